@@ -1,28 +1,255 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
 use cpu::irq::IrqSyncClient;
 use io::timer;
+use savestate::{self, SaveState, StateReader, StateWriter};
+
+/// CPU cycles for each tick of the timers, which run at half the ARM9 clock speed
+pub const CYCLES_PER_TICK: u64 = 2;
+
+/// Something a device wants done once its CPU's clock reaches a given cycle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// The next TIMER overflow
+    Timer = 0,
+    /// Letting the DMA engines catch up with the rest of the system
+    Dma = 1,
+    /// Completion of the command sent to the eMMC controller
+    Emmc = 2,
+    /// Start of the LCDs' vertical blanking period
+    VBlank = 3,
+    /// An ARM11 core's private timer or watchdog reaching zero
+    PrivTimer = 4,
+}
+
+const NUM_EVENTS: usize = 5;
+
+const EVENTS: [Event; NUM_EVENTS] = [
+    Event::Timer, Event::Dma, Event::Emmc, Event::VBlank, Event::PrivTimer
+];
+
+/// Cycle counter of one CPU, along with the absolute cycles at which pending events are due.
+/// Devices keep a handle to it to schedule their own events.
+#[derive(Clone)]
+pub struct Scheduler {
+    cycles: Rc<Cell<u64>>,
+    deadlines: Rc<Cell<[Option<u64>; NUM_EVENTS]>>,
+    /// Earliest of the deadlines, or !0 if nothing is scheduled
+    next: Rc<Cell<u64>>,
+    /// Set whenever a device reads the clock, since what it returns may then change
+    /// with time alone
+    observed: Rc<Cell<bool>>,
+}
+
+impl Scheduler {
+    fn new() -> Scheduler {
+        Scheduler {
+            cycles: Rc::new(Cell::new(0)),
+            deadlines: Rc::new(Cell::new([None; NUM_EVENTS])),
+            next: Rc::new(Cell::new(!0)),
+            observed: Rc::new(Cell::new(false)),
+        }
+    }
+
+    /// CPU cycles counted so far, for a device to compute what it returns
+    pub fn now(&self) -> u64 {
+        self.observed.set(true);
+        self.cycles.get()
+    }
+
+    /// Whether a device read the clock since the last call
+    pub(crate) fn take_observed(&self) -> bool {
+        self.observed.replace(false)
+    }
+
+    /// Makes `event` happen once the clock reaches cycle `at`, replacing any earlier deadline
+    pub fn schedule(&self, event: Event, at: u64) {
+        self.set_deadline(event, Some(at));
+    }
+
+    /// Makes `event` happen `delay` cycles from now
+    pub fn schedule_in(&self, event: Event, delay: u64) {
+        let at = self.cycles.get() + delay;
+        self.schedule(event, at);
+    }
+
+    /// Makes `event` happen no later than `delay` cycles from now, keeping its current
+    /// deadline if that is sooner
+    pub fn schedule_within(&self, event: Event, delay: u64) {
+        let at = self.cycles.get() + delay;
+        match self.deadline(event) {
+            Some(deadline) if deadline <= at => {}
+            _ => self.schedule(event, at)
+        }
+    }
+
+    pub fn cancel(&self, event: Event) {
+        self.set_deadline(event, None);
+    }
+
+    pub fn deadline(&self, event: Event) -> Option<u64> {
+        self.deadlines.get()[event as usize]
+    }
+
+    /// Cycle at which the next event is due, or !0 if nothing is scheduled
+    pub fn next_deadline(&self) -> u64 {
+        self.next.get()
+    }
+
+    fn set_deadline(&self, event: Event, at: Option<u64>) {
+        let mut deadlines = self.deadlines.get();
+        deadlines[event as usize] = at;
+        self.deadlines.set(deadlines);
+        self.next.set(deadlines.iter().filter_map(|&d| d).min().unwrap_or(!0));
+    }
+
+    /// Unschedules and returns the earliest event that is due by now
+    fn take_due(&self) -> Option<Event> {
+        let now = self.cycles.get();
+        if self.next.get() > now {
+            return None
+        }
+        let deadlines = self.deadlines.get();
+        let due = EVENTS.iter()
+            .filter(|&&event| deadlines[event as usize].map_or(false, |d| d <= now))
+            .min_by_key(|&&event| deadlines[event as usize])
+            .cloned();
+        if let Some(event) = due {
+            self.cancel(event);
+        }
+        due
+    }
+}
+
+impl SaveState for Scheduler {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_u64(self.cycles.get());
+        for deadline in self.deadlines.get().iter() {
+            match *deadline {
+                Some(at) => { w.put_bool(true); w.put_u64(at) }
+                None => { w.put_bool(false); w.put_u64(0) }
+            }
+        }
+        w.put_bool(self.observed.get());
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.cycles.set(r.get_u64()?);
+        for &event in EVENTS.iter() {
+            let scheduled = r.get_bool()?;
+            let at = r.get_u64()?;
+            self.set_deadline(event, if scheduled { Some(at) } else { None });
+        }
+        self.observed.set(r.get_bool()?);
+        Ok(())
+    }
+}
+
+type EventHandler = Box<dyn FnMut()>;
 
 #[derive(Clone)]
 pub struct SysClock {
     pub timer_states: timer::TimerStates,
     pub irq_tx: IrqSyncClient,
+    pub sched: Scheduler,
+    /// What to do for each event other than TIMER overflows, set by the devices
+    handlers: Rc<RefCell<[Option<EventHandler>; NUM_EVENTS]>>,
 }
 
 
 
 impl SysClock {
+    /// Advances by `by` CPU cycles, handling any events that became due
     pub fn increment(&mut self, by: u64) {
-        timer::handle_clock_update(&self.timer_states, by, &mut self.irq_tx);
+        let cycles = self.sched.cycles.get() + by;
+        self.sched.cycles.set(cycles);
+        if cycles >= self.sched.next.get() {
+            self.handle_due_events();
+        }
     }
 
+    /// CPU cycles counted so far
     pub fn get(&self) -> u64 {
-        self.timer_states.global_counter.get()
+        self.sched.cycles.get()
+    }
+
+    /// Sets what gets done whenever `event` is due
+    pub fn set_handler<F: FnMut() + 'static>(&self, event: Event, handler: F) {
+        assert!(event != Event::Timer, "TIMER overflows are handled by the clock itself");
+        self.handlers.borrow_mut()[event as usize] = Some(Box::new(handler));
+    }
+
+    fn handle_due_events(&mut self) {
+        while let Some(event) = self.sched.take_due() {
+            trace!("Handling {:?} event at cycle {}", event, self.get());
+            if let Event::Timer = event {
+                timer::handle_deadline(&self.timer_states, &mut self.irq_tx);
+                continue
+            }
+            match self.handlers.borrow_mut()[event as usize] {
+                Some(ref mut handler) => handler(),
+                None => warn!("No handler for {:?} event", event)
+            }
+        }
+    }
+}
+
+/// The TIMER state is saved along with the ARM9's TimerDevice
+impl SaveState for SysClock {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        self.sched.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.sched.load_state(r)
     }
 }
 
 pub fn make_channel(irq_tx: IrqSyncClient) -> SysClock {
-    let timer_states = timer::TimerStates::new();
+    let sched = Scheduler::new();
+    let timer_states = timer::TimerStates::new(sched.clone());
     SysClock {
         timer_states: timer_states,
-        irq_tx: irq_tx
+        irq_tx: irq_tx,
+        sched: sched,
+        handlers: Rc::new(RefCell::new([None, None, None, None, None])),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cpu::irq::IrqSubsys;
+
+    #[test]
+    fn events_in_order() {
+        let irq = IrqSubsys::create();
+        let mut clk = make_channel(irq.sync_tx.clone());
+        let fired = Rc::new(RefCell::new(Vec::new()));
+
+        for &event in [Event::Dma, Event::Emmc].iter() {
+            let fired = fired.clone();
+            let sched = clk.sched.clone();
+            clk.set_handler(event, move || fired.borrow_mut().push((event, sched.now())));
+        }
+
+        clk.sched.schedule(Event::Emmc, 10);
+        clk.sched.schedule_in(Event::Dma, 30);
+        clk.sched.schedule_within(Event::Dma, 5);
+        assert_eq!(clk.sched.next_deadline(), 5);
+
+        clk.increment(4);
+        assert!(fired.borrow().is_empty());
+        clk.increment(20);
+        assert_eq!(*fired.borrow(), vec![(Event::Dma, 24), (Event::Emmc, 24)]);
+        assert_eq!(clk.sched.next_deadline(), !0);
+
+        clk.sched.schedule_in(Event::Dma, 1);
+        clk.sched.cancel(Event::Dma);
+        clk.increment(100);
+        assert_eq!(fired.borrow().len(), 2);
     }
 }
@@ -0,0 +1,703 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use log::LogLevel;
+
+use cpu::BusErrorMode;
+use dbgcore::{self, ActiveCpu};
+use dbgexpr::Condition;
+use hwcore;
+use record;
+use utils::from_hex;
+
+/// Receives the messages printed by debugger commands
+pub trait Output {
+    fn print(&mut self, level: LogLevel, msg: &str);
+}
+
+/// Prints command output through the logger
+pub struct LogOutput;
+
+impl Output for LogOutput {
+    fn print(&mut self, level: LogLevel, msg: &str) {
+        log!(level, "{}", msg);
+    }
+}
+
+macro_rules! out_info {
+    ($out:expr, $($arg:tt)*) => { $out.print(LogLevel::Info, &format!($($arg)*)) };
+}
+
+macro_rules! out_error {
+    ($out:expr, $($arg:tt)*) => { $out.print(LogLevel::Error, &format!($($arg)*)) };
+}
+
+/// Prints disassembly for the next instruction
+/// Command format: "asm [address hex]"
+///
+/// `args`: Iterator over &str items
+fn cmd_asm<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    use capstone::Capstone;
+    use capstone::arch::BuildsCapstone;
+    use capstone::arch::arm::ArchMode;
+    let _ = args;
+
+    let mut hw = ctx.hw();
+
+    let pause_addr = match args.next().map(from_hex) {
+        Some(Ok(x)) => x,
+        Some(Err(_)) => { out_error!(out, "Could not parse hex value!"); return }
+        None => hw.pause_addr(),
+    };
+
+    let cpu_mode = if hw.is_thumb() {
+        ArchMode::Thumb
+    } else {
+        ArchMode::Arm
+    };
+
+    let cs = Capstone::new()
+        .arm()
+        .mode(cpu_mode)
+        .build();
+
+    if let Ok(mut cs) = cs {
+        let mut inst_bytes = [0u8; 4];
+        if let Err(e) = hw.read_mem(pause_addr, &mut inst_bytes) {
+            out_error!(out, "{}", e);
+            return;
+        }
+
+        match cs.disasm_count(&inst_bytes, pause_addr as u64, 1) {
+            Ok(insts) => {
+                let inst = insts.iter().next().unwrap();
+                out_info!(out, "{:X}: {} {}", pause_addr,
+                                     inst.mnemonic().unwrap(),
+                                     inst.op_str().unwrap())
+            }
+            Err(_) => out_error!(out, "Failed to disassemble instruction at 0x{:X}", pause_addr),
+        }
+    } else {
+        out_error!(out, "Could not initialize capstone!");
+    }
+}
+
+/// Manages CPU breakpoints
+/// Command format:
+///   "brk <address hex> [if <condition>]"
+///   "brk list"
+///   "brk del <id>"
+///   "brk <enable|disable> <id>"
+///   "brk ignore <id> <count>"
+///
+/// `args`: Iterator over &str items
+fn cmd_brk<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let usage = |out: &mut dyn Output| out_info!(out, "Usage: `brk <addr> [if <cond>]|list|del <id>|enable <id>|disable <id>|ignore <id> <count>`");
+
+    let op = match args.next() {
+        Some(arg) => arg,
+        None => { usage(out); return }
+    };
+
+    let mut hw = ctx.hw();
+
+    if op == "list" {
+        let breakpoints = hw.breakpoints();
+        if breakpoints.is_empty() {
+            out_info!(out, "No breakpoints set");
+        }
+        for bkpt in breakpoints {
+            let condition = match bkpt.condition {
+                Some(ref condition) => format!(" if {}", condition),
+                None => String::new()
+            };
+            out_info!(out, "#{}: 0x{:08X}{} {}, {} hits, ignoring next {}", bkpt.id, bkpt.addr, condition,
+                  if bkpt.enabled { "enabled" } else { "disabled" }, bkpt.hits, bkpt.ignore_count);
+        }
+        return
+    }
+
+    let id = match op {
+        "del" | "enable" | "disable" | "ignore" => match args.next().map(str::parse::<u32>) {
+            Some(Ok(id)) => id,
+            Some(Err(_)) => { out_error!(out, "Could not parse breakpoint id!"); return }
+            None => { usage(out); return }
+        },
+        addr_str => {
+            let addr = match from_hex(addr_str) {
+                Ok(x) => x,
+                _ => { out_error!(out, "Could not parse hex value!"); return }
+            };
+            let condition = match args.next() {
+                None => None,
+                Some("if") => {
+                    let text = args.collect::<Vec<_>>().join(" ");
+                    match Condition::parse(&text) {
+                        Ok(condition) => Some(condition),
+                        Err(e) => { out_error!(out, "Invalid condition `{}`: {}", text, e); return }
+                    }
+                }
+                Some(_) => { usage(out); return }
+            };
+            let id = hw.set_breakpoint(addr);
+            match condition {
+                Some(ref condition) => out_info!(out, "Breakpoint #{} at 0x{:X} if {}", id, addr, condition),
+                None => out_info!(out, "Breakpoint #{} at 0x{:X}", id, addr),
+            }
+            hw.set_breakpoint_condition(id, condition);
+            return
+        }
+    };
+
+    let found = match op {
+        "del" => hw.del_breakpoint_id(id),
+        "enable" => hw.enable_breakpoint(id, true),
+        "disable" => hw.enable_breakpoint(id, false),
+        "ignore" => {
+            let count = match args.next().map(str::parse::<u32>) {
+                Some(Ok(count)) => count,
+                Some(Err(_)) => { out_error!(out, "Could not parse ignore count!"); return }
+                None => { usage(out); return }
+            };
+            hw.ignore_breakpoint(id, count)
+        }
+        _ => unreachable!()
+    };
+
+    if !found {
+        out_error!(out, "No breakpoint #{}", id);
+    }
+}
+
+/// Toggles or displays button state
+/// Command format: "btn [button name] [up/down]"
+///
+/// `args`: Iterator over &str items
+fn cmd_btn<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+    use io::hid;
+
+    let btn_map = [
+        ("a", hid::Button::A),
+        ("b", hid::Button::B),
+        ("x", hid::Button::X),
+        ("y", hid::Button::Y),
+        ("l", hid::Button::L),
+        ("r", hid::Button::R),
+        ("up", hid::Button::Up),
+        ("down", hid::Button::Down),
+        ("left", hid::Button::Left),
+        ("right", hid::Button::Right),
+        ("start", hid::Button::Start),
+        ("select", hid::Button::Select)
+    ];
+    let mut btn_map = btn_map.iter();
+
+    if let Some(button) = args.next() {
+        let press = match args.next() {
+            Some("up") => hid::ButtonState::Released,
+            Some("down") => hid::ButtonState::Pressed,
+            _ => {
+                out_error!(out, "Specify whether button `{}` should be `up`/`down`", button);
+                return
+            }
+        };
+
+        if let Some((_, btn)) = btn_map.find(|tup| button.eq_ignore_ascii_case(tup.0)) {
+            ctx.update_buttons(press(*btn));
+        } else {
+            out_error!(out, "Button `{}` does not exist!", button);
+        }
+    } else {
+        let hw = ctx.hw11();
+        let pad = hid::pad(&mut hw.io_shared_devices().hid.lock());
+        let mut pressed = Vec::new();
+
+        for (label, btn) in btn_map {
+            if pad & (1 << (*btn as usize)) != 0 {
+                pressed.push(label);
+            }
+        }
+
+        out_info!(out, "Pressed buttons: {:?}", pressed);
+    }
+}
+
+/// Dumps framebuffer to file
+/// Command format: "fbdmp"
+///
+/// `args`: Unused
+fn cmd_fbdmp<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, _: It)
+    where It: Iterator<Item=&'a str> {
+
+    use io::gpu;
+
+    let fb_state = {
+        let hw = ctx.hw11();
+        let gpu = &hw.io11_devices().gpu;
+        let fb_state = gpu::fb_state(&*gpu.borrow());
+        fb_state
+    };
+
+    let mut fbs = hwcore::Framebuffers::default();
+    ctx.hwcore().copy_framebuffers(&mut fbs, &fb_state);
+
+    out_info!(out, "Dumping framebuffers to disk in CWD...");
+
+    let dumps = [("./fb-top.bin", &fbs.top_screen), ("./fb-bot.bin", &fbs.bot_screen)];
+    for &(filename, data) in dumps.iter() {
+        let res = File::create(filename).and_then(|mut file| file.write_all(data));
+        if let Err(e) = res {
+            out_error!(out, "Unable to write framebuffer to `{}`: {:?}", filename, e);
+            return
+        }
+    }
+}
+
+/// Sets AES key-dumping state
+/// Command format: "keydmp"
+///
+/// `args`: Unused
+fn cmd_keydmp<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, _: It)
+    where It: Iterator<Item=&'a str> {
+
+    use io::aes;
+
+    let hw = ctx.hw9();
+    let key_slots = {
+        let aes = &hw.io9_devices().aes;
+        aes::dump_keys(&*aes.borrow())
+    };
+
+    out_info!(out, "Dumping AES keys to disk...");
+
+    use fs;
+    let res = fs::create_file(fs::LlamaFile::AesKeyDb, |file| {
+        for k in key_slots.iter() {
+            if let Err(x) = file.write_all(&k.data) {
+                out_error!(out, "Failed to write to aeskeydb file; {:?}", x);
+                return
+            }
+        }
+    });
+    if let Err(e) = res {
+        out_error!(out, "{}", e);
+    }
+}
+
+/// Triggers the specified IRQ
+/// Command format: "irq <type>"
+///
+/// `args`: Iterator over &str items
+fn cmd_irq<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let irq_ty = match args.next() {
+        Some(arg) => arg.to_lowercase(),
+        None => { out_info!(out, "Usage: `irq <type>"); return }
+    };
+
+    let irq = match irq_ty.as_str() {
+        "timer0" => dbgcore::IrqType9::Timer0,
+        "timer1" => dbgcore::IrqType9::Timer1,
+        "timer2" => dbgcore::IrqType9::Timer2,
+        "timer3" => dbgcore::IrqType9::Timer3,
+        _ => { out_error!(out, "Unimplemented/unknown IRQ type `{}`", irq_ty); return }
+    };
+
+    out_info!(out, "Triggering IRQ {}", irq_ty);
+
+    ctx.trigger_irq(irq);
+}
+
+/// Drives the FIQ line of the active CPU
+/// Command format: "fiq [on|off]"
+///
+/// `args`: Iterator over &str items
+fn cmd_fiq<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let cpu = ctx.active_cpu();
+    match args.next() {
+        Some("on") => ctx.set_fiq(cpu, true),
+        Some("off") => ctx.set_fiq(cpu, false),
+        None => {}
+        _ => { out_error!(out, "Expected `fiq [on|off]`"); return }
+    }
+
+    if ctx.fiq_asserted(cpu) {
+        out_info!(out, "FIQ line of {:?} is asserted", cpu);
+    } else {
+        out_info!(out, "FIQ line of {:?} is deasserted", cpu);
+    }
+}
+
+/// Most bytes `mem` reads at once
+const MAX_MEM_BYTES: u32 = 0x10000;
+
+/// Prints memory to the screen based on provided address, number of bytes
+/// Command format: "mem <start address hex> [# bytes hex]"
+///
+/// `args`: Iterator over &str items
+fn cmd_mem<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    // Tuple: (u32: start, u32: num)
+    let arg_res = match (args.next(), args.next()) {
+        (Some(ss), Some(ns)) => from_hex(ss).and_then(|s| Ok((s, from_hex(ns)?))),
+        (Some(ss), None) => from_hex(ss).and_then(|s| Ok((s, 1))),
+        (None, _) => { out_info!(out, "Usage: `mem <start> [num] [outfile.bin]"); return }
+    };
+
+    // Check for from_hex errors, validate `num` input
+    let (start, num) = match arg_res {
+        Ok((_, n)) if n > MAX_MEM_BYTES => {
+            out_error!(out, "Can print at most 0x{:X} bytes at once!", MAX_MEM_BYTES);
+            return
+        }
+        Ok((s, n)) if n > 0 => (s, n),
+        Ok((s, _)) => (s, 1),
+        _ => { out_error!(out, "Could not parse hex value!"); return }
+    };
+
+    trace!("Printing {} bytes of RAM starting at 0x{:08X}", num, start);
+
+    let mut hw = ctx.hw();
+
+    let mut mem_bytes = vec![0u8; num as usize];
+    if let Err(e) = hw.read_mem(start, &mut mem_bytes) {
+        out_error!(out, "{}", e);
+        return;
+    } else {
+        let mut strbuf = String::new();
+        strbuf.push_str(&format!("{:02X}", mem_bytes[0]));
+        for i in 1 .. num as usize {
+            strbuf.push_str(&format!(" {:02X}", mem_bytes[i]));
+        }
+        out_info!(out, "{}", &strbuf);
+    }
+
+    if let Some(filename) = args.next() {
+        let file = File::create(filename);
+        let mut file = match file {
+            Ok(file) => file,
+            Err(e) => {
+                out_error!(out, "Unable to open file `{}` for dumping memory: {:?}!", filename, e);
+                return;
+            }
+        };
+
+        if let Err(e) = file.write_all(mem_bytes.as_slice()) {
+            out_error!(out, "Unable to write into file `{}`: {:?}", filename, e);
+            return;
+        }
+        out_info!(out, "Wrote 0x{:X} bytes to `{}`", num, filename);
+    }
+}
+
+/// Prints registers to the screen based on provided register name
+/// Command format: "reg [register name]"
+///
+/// `args`: Iterator over &str items
+fn cmd_reg<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+    let hw = ctx.hw();
+
+    let print_reg = |out: &mut dyn Output, reg_num| {
+        out_info!(out, "R{} = 0x{:08X}", reg_num, hw.read_reg(reg_num))
+    };
+    let print_cpsr = |out: &mut dyn Output| out_info!(out, "CPSR = 0x{:08X}", hw.read_cpsr());
+
+    let reg_str = match args.next() {
+        Some(arg) => arg.to_owned().to_lowercase(),
+        None => {
+            for i in 0..16 {
+                print_reg(out, i);
+            }
+            print_cpsr(out);
+            return;
+        }
+    };
+
+    match reg_str.as_str() {
+        "r0" => print_reg(out, 0),
+        "r1" => print_reg(out, 1),
+        "r2" => print_reg(out, 2),
+        "r3" => print_reg(out, 3),
+        "r4" => print_reg(out, 4),
+        "r5" => print_reg(out, 5),
+        "r6" => print_reg(out, 6),
+        "r7" => print_reg(out, 7),
+        "r8" => print_reg(out, 8),
+        "r9" => print_reg(out, 9),
+        "r10" => print_reg(out, 10),
+        "r11" => print_reg(out, 11),
+        "r12" => print_reg(out, 12),
+        "sp" | "r13" => print_reg(out, 13),
+        "lr" | "r14" => print_reg(out, 14),
+        "pc" | "r15" => print_reg(out, 15),
+        "cpsr" => print_cpsr(out),
+        _ => out_error!(out, "Unrecognized register!"),
+    }
+}
+
+/// Controls record mode, which lets GDB run the machine backwards
+/// Command format: "record <on [interval] [max checkpoints]|off|status>"
+///
+/// `args`: Iterator over &str items
+fn cmd_record<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let usage = |out: &mut dyn Output| out_info!(out, "Usage: `record <on [interval] [max checkpoints]|off|status>`");
+
+    let hwcore = ctx.hwcore_mut();
+    match args.next() {
+        Some("on") => {
+            let interval = match args.next().map(str::parse::<u64>) {
+                Some(Ok(interval)) => interval,
+                None => record::DEFAULT_INTERVAL,
+                Some(Err(_)) => { usage(out); return }
+            };
+            let max_checkpoints = match args.next().map(str::parse::<usize>) {
+                Some(Ok(max)) => max,
+                None => record::DEFAULT_MAX_CHECKPOINTS,
+                Some(Err(_)) => { usage(out); return }
+            };
+            match hwcore.start_recording(interval, max_checkpoints) {
+                Ok(()) => out_info!(out, "Recording, with a checkpoint every {} steps",
+                                    hwcore.recorder().as_ref().map_or(0, |rec| rec.interval())),
+                Err(e) => out_error!(out, "Could not start recording: {}", e),
+            }
+        }
+        Some("off") => {
+            hwcore.stop_recording();
+            out_info!(out, "Stopped recording");
+        }
+        Some("status") | None => {
+            let pos = hwcore.position();
+            match *hwcore.recorder() {
+                Some(ref rec) => {
+                    let start = rec.start().unwrap_or_default();
+                    out_info!(out, "Recording at ARM9 step {}, ARM11 step {}", pos.arm9, pos.arm11);
+                    out_info!(out, "{} of {} checkpoints ({} MiB), going back to ARM9 step {}, ARM11 step {}",
+                              rec.num_checkpoints(), rec.max_checkpoints(), rec.size() >> 20,
+                              start.arm9, start.arm11);
+                }
+                None => out_info!(out, "Not recording"),
+            }
+        }
+        _ => usage(out)
+    }
+}
+
+/// Chooses whether the CPUs run on their own threads or interleaved on one
+/// Command format: "sched [threads|single [quantum]]"
+///
+/// `args`: Iterator over &str items
+fn cmd_sched<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let hwcore = ctx.hwcore_mut();
+    match args.next() {
+        Some("threads") => hwcore.set_quantum(None),
+        Some("single") => {
+            let quantum = match args.next().map(str::parse::<u64>) {
+                Some(Ok(quantum)) if quantum > 0 => quantum,
+                None => hwcore::DEFAULT_QUANTUM,
+                Some(_) => { out_error!(out, "Expected a nonzero quantum"); return }
+            };
+            hwcore.set_quantum(Some(quantum))
+        }
+        None => {}
+        _ => { out_error!(out, "Expected `sched [threads|single [quantum]]`"); return }
+    }
+
+    match hwcore.quantum() {
+        Some(quantum) => out_info!(out, "CPUs interleaved on one thread every {} ARM9 cycles", quantum),
+        None => out_info!(out, "CPUs running on their own threads"),
+    }
+}
+
+/// Saves or restores the whole machine state
+/// Command format: "state <save|load> <file>"
+///
+/// `args`: Iterator over &str items
+fn cmd_state<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let (op, filename) = match (args.next(), args.next()) {
+        (Some(op), Some(filename)) => (op, filename),
+        _ => { out_info!(out, "Usage: `state <save|load> <file>"); return }
+    };
+
+    let hwcore = ctx.hwcore_mut();
+    let res = match op {
+        "save" => hwcore.save_state(Path::new(filename)),
+        "load" => hwcore.load_state(Path::new(filename)),
+        _ => { out_error!(out, "Expected `state <save|load> <file>"); return }
+    };
+
+    match res {
+        Ok(()) => out_info!(out, "{} machine state {} `{}`",
+                        if op == "save" { "Saved" } else { "Loaded" },
+                        if op == "save" { "to" } else { "from" }, filename),
+        Err(e) => out_error!(out, "Could not {} state: {}", op, e),
+    }
+}
+
+/// Runs one instruction on the CPU
+/// Command format: "step"
+///
+/// `args`: Unused
+fn cmd_step<'a, It>(ctx: &mut dbgcore::DbgContext, _: &mut dyn Output, args: It)
+    where It: Iterator<Item=&'a str> {
+    let _ = args;
+    let cpu = ctx.active_cpu();
+
+    ctx.step(cpu);
+}
+
+/// Chooses what undefined instructions do on the active CPU
+/// Command format: "undef [halt|raise]"
+///
+/// `args`: Iterator over &str items
+fn cmd_undef<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let mut hw = ctx.hw();
+    match args.next() {
+        Some("halt") => hw.set_halt_on_undefined(true),
+        Some("raise") => hw.set_halt_on_undefined(false),
+        None => {}
+        _ => { out_error!(out, "Expected `undef [halt|raise]`"); return }
+    }
+
+    if hw.halts_on_undefined() {
+        out_info!(out, "Undefined instructions halt into the debugger");
+    } else {
+        out_info!(out, "Undefined instructions raise the exception");
+    }
+}
+
+/// Chooses what accesses to unmapped memory do on the active CPU
+/// Command format: "buserr [abort|openbus|halt]"
+///
+/// `args`: Iterator over &str items
+fn cmd_buserr<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let mut hw = ctx.hw();
+    match args.next() {
+        Some("abort") => hw.set_bus_error_mode(BusErrorMode::Abort),
+        Some("openbus") => hw.set_bus_error_mode(BusErrorMode::OpenBus),
+        Some("halt") => hw.set_bus_error_mode(BusErrorMode::Halt),
+        None => {}
+        _ => { out_error!(out, "Expected `buserr [abort|openbus|halt]`"); return }
+    }
+
+    match hw.bus_error_mode() {
+        BusErrorMode::Abort => out_info!(out, "Unmapped accesses raise an external abort"),
+        BusErrorMode::OpenBus => out_info!(out, "Unmapped reads return zeroes and writes are dropped"),
+        BusErrorMode::Halt => out_info!(out, "Unmapped accesses halt into the debugger"),
+    }
+}
+
+/// Manages data watchpoints
+/// Command format:
+///   "watch <address hex> [# bytes hex] [r|w|rw]"
+///   "watch del <address hex> [# bytes hex] [r|w|rw]"
+///   "watch list"
+///
+/// `args`: Iterator over &str items
+fn cmd_watch<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut args: It)
+    where It: Iterator<Item=&'a str> {
+
+    let usage = |out: &mut dyn Output| out_info!(out, "Usage: `watch [del] <addr> [len] [r|w|rw]` or `watch list`");
+
+    let mut hw = ctx.hw();
+
+    let (delete, addr_str) = match args.next() {
+        Some("list") => {
+            let watchpoints = hw.watchpoints();
+            if watchpoints.is_empty() {
+                out_info!(out, "No watchpoints set");
+            }
+            for wp in watchpoints {
+                out_info!(out, "0x{:08X}-0x{:08X} ({:?})", wp.addr, wp.addr as u64 + wp.len as u64 - 1, wp.kind);
+            }
+            return
+        }
+        Some("del") => match args.next() {
+            Some(arg) => (true, arg),
+            None => { usage(out); return }
+        },
+        Some(arg) => (false, arg),
+        None => { usage(out); return }
+    };
+
+    let (addr, len) = match (from_hex(addr_str), args.next().map(from_hex)) {
+        (Ok(addr), None) => (addr, 4),
+        (Ok(addr), Some(Ok(len))) if len > 0 => (addr, len),
+        _ => { out_error!(out, "Could not parse hex value!"); return }
+    };
+
+    let kind = match args.next() {
+        None | Some("w") => dbgcore::WatchKind::Write,
+        Some("r") => dbgcore::WatchKind::Read,
+        Some("rw") => dbgcore::WatchKind::Access,
+        Some(x) => { out_error!(out, "Unknown watchpoint type `{}`, expected r/w/rw", x); return }
+    };
+
+    if delete {
+        if !hw.del_watchpoint(addr, len, kind) {
+            out_error!(out, "No such watchpoint");
+        }
+    } else {
+        out_info!(out, "Watching 0x{:X} bytes at 0x{:X} ({:?})", len, addr, kind);
+        hw.set_watchpoint(addr, len, kind);
+    }
+}
+
+/// Controls debugger behavior based on user-provided commands
+///
+/// `ctx`: Debugger context, whose active CPU the `cpu` command switches
+/// `out`: Receives the command output
+/// `command`: Iterator over &str items
+pub fn handle<'a, It>(ctx: &mut dbgcore::DbgContext, out: &mut dyn Output, mut command: It)
+    where It: Iterator<Item=&'a str> {
+
+    match command.next() {
+        Some("asm") => cmd_asm(ctx, out, command),
+        Some("brk") => cmd_brk(ctx, out, command),
+        Some("btn") => cmd_btn(ctx, out, command),
+        Some("buserr") => cmd_buserr(ctx, out, command),
+        Some("fbdmp") => cmd_fbdmp(ctx, out, command),
+        Some("fiq") => cmd_fiq(ctx, out, command),
+        Some("irq") => cmd_irq(ctx, out, command),
+        Some("keydmp") => cmd_keydmp(ctx, out, command),
+        Some("mem") => cmd_mem(ctx, out, command),
+        Some("record") => cmd_record(ctx, out, command),
+        Some("reg") => cmd_reg(ctx, out, command),
+        Some("sched") => cmd_sched(ctx, out, command),
+        Some("run") => ctx.resume(),
+        Some("state") => cmd_state(ctx, out, command),
+        Some("step") => cmd_step(ctx, out, command),
+        Some("undef") => cmd_undef(ctx, out, command),
+        Some("watch") => cmd_watch(ctx, out, command),
+
+        Some("cpu") => {
+            match command.next() {
+                Some("arm9") => ctx.set_active_cpu(ActiveCpu::Arm9),
+                Some("arm11") => ctx.set_active_cpu(ActiveCpu::Arm11),
+                _ => out_error!(out, "Expected `cpu <arm9|arm11>")
+            }
+        }
+        None => {},
+        Some(unk_cmd @ _) => out_error!(out, "Unrecognized command `{}`", unk_cmd),
+    }
+}
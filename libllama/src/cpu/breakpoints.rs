@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+
+use dbgexpr::Condition;
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub id: u32,
+    pub addr: u32,
+    pub enabled: bool,
+    /// Number of times execution reached this breakpoint while it was enabled
+    pub hits: u32,
+    /// Number of upcoming hits that will not halt the CPU
+    pub ignore_count: u32,
+    /// Hits are only counted while this holds
+    pub condition: Option<Condition>,
+}
+
+/// Instruction breakpoints for a single CPU
+///
+/// Breakpoints stay armed after they are hit. The address that last halted the CPU
+/// is skipped once, so that resuming or stepping executes the instruction instead of
+/// halting on it again.
+#[derive(Clone)]
+pub struct Breakpoints {
+    by_addr: HashMap<u32, Breakpoint>,
+    next_id: u32,
+    resume_addr: Option<u32>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Breakpoints {
+        Breakpoints {
+            by_addr: HashMap::new(),
+            next_id: 1,
+            resume_addr: None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.by_addr.is_empty()
+    }
+
+    /// Adds a breakpoint at `addr`, returning its id
+    /// If one already exists there, its id is returned instead
+    pub fn insert(&mut self, addr: u32) -> u32 {
+        if let Some(bkpt) = self.by_addr.get(&addr) {
+            return bkpt.id
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.by_addr.insert(addr, Breakpoint {
+            id: id,
+            addr: addr,
+            enabled: true,
+            hits: 0,
+            ignore_count: 0,
+            condition: None,
+        });
+        id
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        self.by_addr.contains_key(&addr)
+    }
+
+    pub fn remove(&mut self, addr: u32) -> Option<Breakpoint> {
+        self.by_addr.remove(&addr)
+    }
+
+    pub fn remove_id(&mut self, id: u32) -> Option<Breakpoint> {
+        let addr = self.get_id(id)?.addr;
+        self.remove(addr)
+    }
+
+    pub fn get_id(&self, id: u32) -> Option<&Breakpoint> {
+        self.by_addr.values().find(|bkpt| bkpt.id == id)
+    }
+
+    pub fn get_id_mut(&mut self, id: u32) -> Option<&mut Breakpoint> {
+        self.by_addr.values_mut().find(|bkpt| bkpt.id == id)
+    }
+
+    /// All breakpoints, ordered by id
+    pub fn list(&self) -> Vec<Breakpoint> {
+        let mut list: Vec<Breakpoint> = self.by_addr.values().cloned().collect();
+        list.sort_by_key(|bkpt| bkpt.id);
+        list
+    }
+
+    /// Finds the enabled breakpoint that executing the instruction at `addr` reaches,
+    /// whose condition must then be checked before calling `hit`
+    pub fn armed_at(&mut self, addr: u32) -> Option<&Breakpoint> {
+        if self.resume_addr.take() == Some(addr) {
+            return None
+        }
+        self.by_addr.get(&addr).filter(|bkpt| bkpt.enabled)
+    }
+
+    /// Sets the address that is skipped once, as if the CPU had just halted there
+    pub fn set_resume_addr(&mut self, addr: Option<u32>) {
+        self.resume_addr = addr;
+    }
+
+    /// Counts a hit of the breakpoint at `addr`, returning whether the CPU should halt
+    pub fn hit(&mut self, addr: u32) -> bool {
+        let bkpt = match self.by_addr.get_mut(&addr) {
+            Some(bkpt) => bkpt,
+            None => return false
+        };
+
+        bkpt.hits += 1;
+        if bkpt.ignore_count > 0 {
+            bkpt.ignore_count -= 1;
+            return false
+        }
+
+        self.resume_addr = Some(addr);
+        true
+    }
+
+    /// Same as `armed_at` followed by `hit`, for breakpoints without conditions
+    pub fn should_break(&mut self, addr: u32) -> bool {
+        self.armed_at(addr).is_some() && self.hit(addr)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+impl WatchKind {
+    fn covers(self, access: WatchKind) -> bool {
+        self == WatchKind::Access || self == access
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    pub addr: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn overlaps(&self, addr: u32, size: u32) -> bool {
+        (addr as u64) < self.addr as u64 + self.len as u64
+            && (self.addr as u64) < addr as u64 + size as u64
+    }
+}
+
+/// Data watchpoints for a single CPU
+///
+/// Accesses are checked as they happen; the first matching access is latched
+/// until the CPU picks it up after finishing the current instruction.
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    hit: Option<(u32, WatchKind)>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints {
+            list: Vec::new(),
+            hit: None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn insert(&mut self, addr: u32, len: u32, kind: WatchKind) {
+        if !self.list.iter().any(|wp| wp.addr == addr && wp.len == len && wp.kind == kind) {
+            self.list.push(Watchpoint { addr: addr, len: len, kind: kind });
+        }
+    }
+
+    pub fn remove(&mut self, addr: u32, len: u32, kind: WatchKind) -> bool {
+        let old_len = self.list.len();
+        self.list.retain(|wp| !(wp.addr == addr && wp.len == len && wp.kind == kind));
+        self.list.len() != old_len
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.list
+    }
+
+    /// Records an access of `size` bytes at `addr`, where `access` is either
+    /// `WatchKind::Read` or `WatchKind::Write`
+    pub fn check(&mut self, addr: u32, size: u32, access: WatchKind) {
+        if self.hit.is_some() {
+            return
+        }
+        let hit = self.list.iter()
+            .find(|wp| wp.kind.covers(access) && wp.overlaps(addr, size));
+        if let Some(wp) = hit {
+            self.hit = Some((addr, wp.kind));
+        }
+    }
+
+    #[inline(always)]
+    pub fn take_hit(&mut self) -> Option<(u32, WatchKind)> {
+        self.hit.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rearm_after_step() {
+        let mut bkpts = Breakpoints::new();
+        bkpts.insert(0x100);
+
+        assert!(bkpts.should_break(0x100));
+        // Resuming executes the instruction it stopped on
+        assert!(!bkpts.should_break(0x100));
+        assert!(!bkpts.should_break(0x104));
+        assert!(bkpts.should_break(0x100));
+        assert_eq!(bkpts.list()[0].hits, 2);
+    }
+
+    #[test]
+    fn ignore_and_disable() {
+        let mut bkpts = Breakpoints::new();
+        let id = bkpts.insert(0x100);
+        bkpts.get_id_mut(id).unwrap().ignore_count = 2;
+
+        assert!(!bkpts.should_break(0x100));
+        assert!(!bkpts.should_break(0x100));
+        assert!(bkpts.should_break(0x100));
+        assert_eq!(bkpts.get_id(id).unwrap().hits, 3);
+
+        bkpts.get_id_mut(id).unwrap().enabled = false;
+        assert!(!bkpts.should_break(0x104));
+        assert!(!bkpts.should_break(0x100));
+        assert_eq!(bkpts.get_id(id).unwrap().hits, 3);
+    }
+
+    #[test]
+    fn ids() {
+        let mut bkpts = Breakpoints::new();
+        let a = bkpts.insert(0x100);
+        let b = bkpts.insert(0x200);
+        assert_eq!(bkpts.insert(0x100), a);
+        assert_ne!(a, b);
+
+        assert_eq!(bkpts.remove_id(a).unwrap().addr, 0x100);
+        assert!(!bkpts.contains(0x100));
+        assert!(bkpts.remove_id(a).is_none());
+        assert_eq!(bkpts.insert(0x100), b + 1);
+    }
+
+    #[test]
+    fn watch_ranges() {
+        let mut wps = Watchpoints::new();
+        wps.insert(0x1000, 8, WatchKind::Write);
+        wps.insert(0x2000, 4, WatchKind::Access);
+
+        wps.check(0x1000, 4, WatchKind::Read);
+        wps.check(0x0FFC, 4, WatchKind::Write);
+        wps.check(0x1008, 1, WatchKind::Write);
+        assert!(wps.take_hit().is_none());
+
+        wps.check(0x0FFE, 4, WatchKind::Write);
+        assert_eq!(wps.take_hit(), Some((0x0FFE, WatchKind::Write)));
+
+        wps.check(0x2003, 1, WatchKind::Read);
+        assert_eq!(wps.take_hit(), Some((0x2003, WatchKind::Access)));
+
+        assert!(wps.remove(0x2000, 4, WatchKind::Access));
+        assert!(!wps.remove(0x2000, 4, WatchKind::Access));
+        wps.check(0x2000, 4, WatchKind::Write);
+        assert!(wps.take_hit().is_none());
+    }
+}
@@ -1,7 +1,15 @@
+use std::cmp;
+
+use utils::bytes;
 use utils::cache::TinyCache;
 use mem;
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 use cpu::{Version, v5};
+use cpu::breakpoints::WatchKind;
+
+/// Cycles a line fill takes on top of the wait states of the words read. Hits are free.
+const CACHE_MISS_CYCLES: u32 = 2;
 
 pub struct MemCache(TinyCache<[u32; 8], mem::MemController>);
 impl MemCache {
@@ -34,26 +42,105 @@ impl MemCache {
         unsafe { *(((line.as_mut_ptr() as usize) + offs) as *mut T) = val; }
     }
 
+    /// Charges for a line fill and keeps lines filled from unmapped memory out of the cache
+    #[inline]
+    fn finish_fill(&mut self, line_base: u32, filled: bool, fallback_mem: &mem::MemController) {
+        if !filled {
+            return
+        }
+        fallback_mem.add_wait_cycles(CACHE_MISS_CYCLES);
+        if fallback_mem.bus_error_pending() {
+            self.0.discard(line_base);
+        }
+    }
+
     #[inline]
     fn read<T: Copy>(&mut self, addr: u32, fallback_mem: &mut mem::MemController) -> T {
         let (line_base, line_rem) = Self::decompose_addr(addr);
-        let buf = self.0.get_or(line_base, fallback_mem);
-        Self::piece_of_line(&buf, line_rem as usize)
+        let filled = !self.0.contains(line_base);
+        let val = Self::piece_of_line(self.0.get_or(line_base, fallback_mem), line_rem as usize);
+        self.finish_fill(line_base, filled, fallback_mem);
+        val
     }
 
     #[inline]
     fn write<T: Copy>(&mut self, addr: u32, val: T, fallback_mem: &mut mem::MemController) {
         let (line_base, line_rem) = Self::decompose_addr(addr);
+        let filled = !self.0.contains(line_base);
         let updater_fn = |_, line: &mut [u32; 8]| Self::put_in_line(line, line_rem as usize, val);
         self.0.update_or(line_base, updater_fn, fallback_mem);
+        self.finish_fill(line_base, filled, fallback_mem);
     }
 
     pub fn invalidate(&mut self, fallback_mem: &mut mem::MemController) {
         self.0.invalidate(fallback_mem);
     }
+
+    /// Copies `buf.len()` bytes at `addr` out of a resident line, which must hold all of them
+    fn peek(&self, addr: u32, buf: &mut [u8]) -> bool {
+        let (line_base, line_rem) = Self::decompose_addr(addr);
+        match self.0.peek(line_base) {
+            Some(line) => {
+                let line_bytes = unsafe { bytes::from_val(line) };
+                buf.copy_from_slice(&line_bytes[line_rem as usize..][..buf.len()]);
+                true
+            }
+            None => false
+        }
+    }
 }
 
 
+/// A memory access, as the MPU or MMU checks it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Access {
+    Fetch,
+    Read,
+    Write
+}
+
+/// Fault status for an address outside every enabled MPU region
+pub const FSR_BACKGROUND: u32 = 0b0000;
+/// Fault status for an access refused by the permissions of its MPU region or section
+pub const FSR_PERMISSION: u32 = 0b1101;
+pub const FSR_PERMISSION_PAGE: u32 = 0b1111;
+/// Fault status for an address without a valid first-level descriptor
+pub const FSR_TRANSLATION_SECTION: u32 = 0b0101;
+/// Fault status for an address without a valid second-level descriptor
+pub const FSR_TRANSLATION_PAGE: u32 = 0b0111;
+/// Fault status for a mapping in a domain without access
+pub const FSR_DOMAIN_SECTION: u32 = 0b1001;
+pub const FSR_DOMAIN_PAGE: u32 = 0b1011;
+/// Fault status for an access to unmapped physical memory
+pub const FSR_EXTERNAL: u32 = 0b1000;
+
+/// An access refused by the MPU or MMU, or one that reached unmapped memory, which
+/// the CPU turns into an abort
+#[derive(Copy, Clone, Debug)]
+pub struct Fault {
+    pub addr: u32,
+    pub access: Access,
+    /// Encoded like the status bits of the fault status registers
+    pub status: u32
+}
+
+/// Whether an access is allowed by the 4-bit extended access permissions of an MPU
+/// region, or equivalently the APX:AP bits of an ARMv6 mapping
+fn perms_allow(perms: u32, privileged: bool, access: Access) -> bool {
+    let write = access == Access::Write;
+    match (perms, privileged) {
+        (0b0001, true) | (0b0010, true) | (0b0011, _) => true,
+        (0b0010, false) | (0b0101, true) | (0b0110, _) | (0b0111, _) => !write,
+        _ => false
+    }
+}
+
+/// What faulting reads return; the aborting instruction never gets to use it
+#[inline]
+fn faulted_value<T: Copy>() -> T {
+    unsafe { std::mem::zeroed() }
+}
+
 pub trait Ops {
     fn set_enabled(&mut self, enabled: bool);
     fn imem_read<T: Copy>(&mut self, addr: u32) -> T;
@@ -78,6 +165,12 @@ pub struct Mpu {
     pub region_use_dcache: u8,
     pub region_base_sigbits: [u32; 8],
     pub region_size_exp: [u32; 8],
+    /// Extended access permissions, four bits per region
+    pub region_data_perms: u32,
+    pub region_instr_perms: u32,
+
+    privileged: bool,
+    fault: Option<Fault>,
 
     pub memory: mem::MemController,
     pub icache: MemCache,
@@ -96,6 +189,11 @@ impl Mpu {
             region_use_dcache: 0,
             region_base_sigbits: [0; 8],
             region_size_exp: [0; 8],
+            region_data_perms: 0,
+            region_instr_perms: 0,
+
+            privileged: true,
+            fault: None,
 
             memory: memory,
             icache: MemCache::new(),
@@ -103,18 +201,32 @@ impl Mpu {
         }
     }
 
-    fn region_mask(&self, addr: u32) -> u8 {
-        let mut mask = 0;
-        for i in (0..8).rev() {
-            let bit = 1 << i;
-            if (self.region_enabled & bit != 0) && ((addr >> self.region_size_exp[i]) == self.region_base_sigbits[i]) {
-                mask |= bit;
-                break;
+    /// Finds the highest-numbered enabled region containing `addr`
+    fn region_of(&self, addr: u32) -> Option<usize> {
+        (0..8).rev().find(|&i| {
+            (self.region_enabled & (1 << i) != 0) && ((addr >> self.region_size_exp[i]) == self.region_base_sigbits[i])
+        })
+    }
+
+    /// Checks an access against the permissions of its region, returning the region's
+    /// bit if allowed. Refused accesses latch a fault for the CPU to take.
+    fn check_access(&mut self, addr: u32, access: Access) -> Option<u8> {
+        let (status, region) = match self.region_of(addr) {
+            Some(i) => {
+                let perms = if access == Access::Fetch { self.region_instr_perms } else { self.region_data_perms };
+                if perms_allow((perms >> (i * 4)) & 0b1111, self.privileged, access) {
+                    return Some(1 << i)
+                }
+                (FSR_PERMISSION, Some(i))
             }
-        }
+            None => (FSR_BACKGROUND, None)
+        };
 
-        if mask == 0 { panic!("Attempted to read memory from 0x{:08X} in nonexistent MpuRegion!", addr) };
-        mask
+        trace!("MPU refused {:?} of 0x{:08X} in region {:?}", access, addr, region);
+        if self.fault.is_none() {
+            self.fault = Some(Fault { addr: addr, access: access, status: status });
+        }
+        None
     }
 
     fn icache_enabled(&self) -> bool {
@@ -134,7 +246,15 @@ impl Ops for Mpu {
     fn imem_read<T: Copy>(&mut self, addr: u32) -> T {
         assert!( (addr as usize) % std::mem::size_of::<T>() == 0 );
 
-        if self.icache_enabled() && (self.region_use_icache & self.region_mask(addr)) != 0 {
+        if !self.enabled {
+            return self.memory.read(addr)
+        }
+        let region = match self.check_access(addr, Access::Fetch) {
+            Some(region) => region,
+            None => return faulted_value()
+        };
+
+        if self.icache_enabled() && (self.region_use_icache & region) != 0 {
             self.icache.read(addr, &mut self.memory)
         } else {
             self.memory.read(addr)
@@ -152,7 +272,15 @@ impl Ops for Mpu {
     fn dmem_read<T: Copy>(&mut self, addr: u32) -> T {
         assert!( (addr as usize) % std::mem::size_of::<T>() == 0 );
 
-        if self.dcache_enabled() && (self.region_use_dcache & self.region_mask(addr)) != 0 {
+        if !self.enabled {
+            return self.memory.read(addr)
+        }
+        let region = match self.check_access(addr, Access::Read) {
+            Some(region) => region,
+            None => return faulted_value()
+        };
+
+        if self.dcache_enabled() && (self.region_use_dcache & region) != 0 {
             self.dcache.read(addr, &mut self.memory)
         } else {
             self.memory.read(addr)
@@ -162,7 +290,15 @@ impl Ops for Mpu {
     fn dmem_write<T: Copy>(&mut self, addr: u32, val: T) {
         assert!( (addr as usize) % std::mem::size_of::<T>() == 0 );
 
-        if self.dcache_enabled() && (self.region_use_dcache & self.region_mask(addr)) != 0 {
+        if !self.enabled {
+            return self.memory.write(addr, val)
+        }
+        let region = match self.check_access(addr, Access::Write) {
+            Some(region) => region,
+            None => return
+        };
+
+        if self.dcache_enabled() && (self.region_use_dcache & region) != 0 {
             self.dcache.write(addr, val, &mut self.memory);
         } else {
             self.memory.write(addr, val);
@@ -185,7 +321,110 @@ impl Ops for Mpu {
     }
 }
 
+impl SaveState for Mpu {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        // Write back dirty lines so that they end up in the saved RAM
+        self.icache_invalidate();
+        self.dcache_invalidate();
+
+        w.put_bool(self.enabled);
+        w.put_bool(self.icache_enabled);
+        w.put_bool(self.dcache_enabled);
+        w.put_u8(self.region_enabled);
+        w.put_u8(self.region_use_icache);
+        w.put_u8(self.region_use_dcache);
+        for i in 0..8 {
+            w.put_u32(self.region_base_sigbits[i]);
+            w.put_u32(self.region_size_exp[i]);
+        }
+        w.put_u32(self.region_data_perms);
+        w.put_u32(self.region_instr_perms);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        // Nothing cached may survive into the loaded state
+        self.icache_invalidate();
+        self.dcache_invalidate();
+
+        self.enabled = r.get_bool()?;
+        self.icache_enabled = r.get_bool()?;
+        self.dcache_enabled = r.get_bool()?;
+        self.region_enabled = r.get_u8()?;
+        self.region_use_icache = r.get_u8()?;
+        self.region_use_dcache = r.get_u8()?;
+        for i in 0..8 {
+            self.region_base_sigbits[i] = r.get_u32()?;
+            self.region_size_exp[i] = r.get_u32()?;
+        }
+        self.region_data_perms = r.get_u32()?;
+        self.region_instr_perms = r.get_u32()?;
+        Ok(())
+    }
+}
+
+
+/// A translation cached for one 4KiB page, whatever size of mapping it came from
+#[derive(Copy, Clone)]
+struct TlbEntry {
+    vpage: u32,
+    ppage: u32,
+    /// log2 of the size of the mapping, for invalidating it as a whole
+    size_exp: u32,
+    /// None for global mappings
+    asid: Option<u8>,
+    domain: u32,
+    /// APX:AP for each 1KiB subpage
+    perms: [u8; 4],
+    xn: bool,
+    is_page: bool
+}
+
+impl TlbEntry {
+    fn matches(&self, vaddr: u32, asid: u8) -> bool {
+        self.vpage == vaddr >> 12 && self.asid.map_or(true, |a| a == asid)
+    }
+
+    fn covers(&self, vaddr: u32) -> bool {
+        (self.vpage << 12) >> self.size_exp == vaddr >> self.size_exp
+    }
+}
+
+const TLB_SIZE: usize = 64;
+
+struct Tlb {
+    entries: [Option<TlbEntry>; TLB_SIZE]
+}
 
+impl Tlb {
+    fn new() -> Tlb {
+        Tlb { entries: [None; TLB_SIZE] }
+    }
+
+    #[inline]
+    fn slot(vaddr: u32) -> usize {
+        (vaddr >> 12) as usize % TLB_SIZE
+    }
+
+    fn lookup(&self, vaddr: u32, asid: u8) -> Option<TlbEntry> {
+        match self.entries[Self::slot(vaddr)] {
+            Some(entry) if entry.matches(vaddr, asid) => Some(entry),
+            _ => None
+        }
+    }
+
+    fn insert(&mut self, entry: TlbEntry) {
+        self.entries[Self::slot(entry.vpage << 12)] = Some(entry);
+    }
+
+    fn invalidate_if<F: Fn(&TlbEntry) -> bool>(&mut self, pred: F) {
+        for slot in self.entries.iter_mut() {
+            if slot.as_ref().map_or(false, |entry| pred(entry)) {
+                *slot = None;
+            }
+        }
+    }
+}
 
 pub struct Mmu {
     enabled: bool,
@@ -195,6 +434,13 @@ pub struct Mmu {
     pub(crate) pagesel: usize,
     pub(crate) page_tables: [u32; 2],
     pub(crate) backcompat_walk: bool,
+    /// Domain access control, two bits per domain
+    pub(crate) domains: u32,
+    pub(crate) asid: u8,
+
+    privileged: bool,
+    fault: Option<Fault>,
+    tlb: Tlb,
 
     pub memory: mem::MemController,
     pub icache: MemCache,
@@ -211,6 +457,12 @@ impl Mmu {
             pagesel: 0,
             page_tables: [0u32; 2],
             backcompat_walk: true,
+            domains: 0,
+            asid: 0,
+
+            privileged: true,
+            fault: None,
+            tlb: Tlb::new(),
 
             memory: memory,
             icache: MemCache::new(),
@@ -226,6 +478,24 @@ impl Mmu {
         self.enabled & self.dcache_enabled
     }
 
+    pub fn invalidate_tlb(&mut self) {
+        self.tlb.invalidate_if(|_| true);
+    }
+
+    /// Invalidates the mapping of the MVA in bits 12-31 of `mva_asid`, if global or
+    /// tagged with the ASID in bits 0-7
+    pub fn invalidate_tlb_mva(&mut self, mva_asid: u32) {
+        let asid = mva_asid as u8;
+        self.tlb.invalidate_if(|entry| {
+            entry.covers(mva_asid) && entry.asid.map_or(true, |a| a == asid)
+        });
+    }
+
+    /// Invalidates the non-global mappings tagged with `asid`
+    pub fn invalidate_tlb_asid(&mut self, asid: u8) {
+        self.tlb.invalidate_if(|entry| entry.asid == Some(asid));
+    }
+
     fn select_page_table(&self, vaddr: u32) -> u32 {
         if self.pagesel == 0 || bits!(vaddr, (32-self.pagesel) : 31) == 0 {
             self.page_tables[0]
@@ -234,7 +504,30 @@ impl Mmu {
         }
     }
 
-    fn walk_l2(&self, table: u32, vaddr: u32) -> u32 {
+    /// Reads a translation table descriptor. Debug walks return None for unmapped
+    /// descriptors instead of charging wait states or raising a bus error.
+    fn read_desc(&self, addr: u32, debug: bool) -> Option<u32> {
+        if !debug {
+            return Some(self.memory.read(addr))
+        }
+        let mut desc = [0u8; 4];
+        self.memory.debug_read_buf(addr, &mut desc).ok()?;
+        Some(u32::from_le_bytes(desc))
+    }
+
+    fn walk_l2(&self, table: u32, vaddr: u32, domain: u32, debug: bool) -> Result<TlbEntry, u32> {
+        let mut entry = TlbEntry {
+            vpage: vaddr >> 12,
+            ppage: 0,
+            size_exp: 12,
+            asid: None,
+            domain: domain,
+            perms: [0; 4],
+            xn: false,
+            is_page: true
+        };
+        let fault = FSR_TRANSLATION_PAGE | domain << 4;
+
         if self.backcompat_walk {
             const DESC_TYPE_FAULT: u32 = 0;
             const DESC_TYPE_LARGE_PAGE: u32 = 1;
@@ -242,65 +535,87 @@ impl Mmu {
             const DESC_TYPE_EXT_PAGE: u32 = 3;
             bf!(Descriptor[u32] {
                 ty: 0:1,
+                ext_page_ap: 4:5,
+                subpage_aps: 4:11,
                 large_page_base: 16:31,
                 small_page_base: 12:31,
-                ext_page_base: 12:31,
             });
 
             let desc_addr = table + 4 * bits!(vaddr, 12:19);
-            let desc = Descriptor::new(self.memory.read(desc_addr));
+            let desc = Descriptor::new(self.read_desc(desc_addr, debug).ok_or(fault)?);
 
+            let aps = desc.subpage_aps.get();
             match desc.ty.get() {
-                DESC_TYPE_FAULT => unimplemented!(),
+                DESC_TYPE_FAULT => return Err(fault),
                 DESC_TYPE_LARGE_PAGE => {
-                    let base = desc.large_page_base.get() << 16;
-                    let offs = bits!(vaddr, 0:15);
-                    base | offs
+                    entry.ppage = (desc.large_page_base.get() << 4) | bits!(vaddr, 12:15);
+                    entry.size_exp = 16;
+                    // Subpages are 16KiB, so a 4KiB entry only needs one
+                    let ap = (aps >> (bits!(vaddr, 14:15) * 2)) & 0b11;
+                    entry.perms = [ap as u8; 4];
                 }
-                DESC_TYPE_SMALL_PAGE | DESC_TYPE_EXT_PAGE => {
-                    let base = desc.large_page_base.get() << 12;
-                    let offs = bits!(vaddr, 0:11);
-                    base | offs
+                DESC_TYPE_SMALL_PAGE => {
+                    entry.ppage = desc.small_page_base.get();
+                    for i in 0..4 {
+                        entry.perms[i] = ((aps >> (i * 2)) & 0b11) as u8;
+                    }
+                }
+                DESC_TYPE_EXT_PAGE => {
+                    entry.ppage = desc.small_page_base.get();
+                    entry.perms = [desc.ext_page_ap.get() as u8; 4];
                 }
                 _ => unreachable!()
             }
         } else {
             bf!(Descriptor[u32] {
                 is_large_page: 0:0,
+                xn_small: 0:0,
                 is_small_page: 1:1,
+                ap: 4:5,
+                apx: 9:9,
+                not_global: 11:11,
+                xn_large: 15:15,
 
                 large_page_base: 16:31,
                 small_page_base: 12:31,
             });
 
             let desc_addr = table + 4 * bits!(vaddr, 12:19);
-            let desc = Descriptor::new(self.memory.read(desc_addr));
-            
+            let desc = Descriptor::new(self.read_desc(desc_addr, debug).ok_or(fault)?);
+
             match (desc.is_large_page.get(), desc.is_small_page.get()) {
-                (0, 0) => unimplemented!(),
+                (0, 0) => return Err(fault),
                 (1, 0) => {
-                    let base = desc.large_page_base.get() << 16;
-                    let offs = bits!(vaddr, 0:15);
-                    base | offs
+                    entry.ppage = (desc.large_page_base.get() << 4) | bits!(vaddr, 12:15);
+                    entry.size_exp = 16;
+                    entry.xn = desc.xn_large.get() == 1;
                 }
-                (_xn, 1) => {
-                    let base = desc.small_page_base.get() << 12;
-                    let offs = bits!(vaddr, 0:11);
-                    base | offs
+                (_, 1) => {
+                    entry.ppage = desc.small_page_base.get();
+                    entry.xn = desc.xn_small.get() == 1;
                 }
                 _ => unreachable!()
             }
+            entry.perms = [(desc.apx.get() << 2 | desc.ap.get()) as u8; 4];
+            if desc.not_global.get() == 1 {
+                entry.asid = Some(self.asid);
+            }
         }
+        Ok(entry)
     }
 
-    fn walk_l1(&self, table: u32, vaddr: u32) -> u32 {
+    fn walk_l1(&self, table: u32, vaddr: u32, debug: bool) -> Result<TlbEntry, u32> {
         const DESC_TYPE_FAULT: u32 = 0;
         const DESC_TYPE_L2: u32 = 1;
         const DESC_TYPE_SECTION: u32 = 2;
         const DESC_TYPE_RESERVED: u32 = 3;
         bf!(Descriptor[u32] {
             ty: 0:1,
-            _domain: 5:8,
+            xn: 4:4,
+            domain: 5:8,
+            ap: 10:11,
+            apx: 15:15,
+            not_global: 17:17,
             l2_base: 10:31,
             is_supersection: 18:18,
             section_base: 20:31,
@@ -308,37 +623,116 @@ impl Mmu {
         });
 
         let desc_addr = table + 4 * bits!(vaddr, 20:31);
-        let desc = Descriptor::new(self.memory.read(desc_addr));
+        let desc = Descriptor::new(self.read_desc(desc_addr, debug).ok_or(FSR_TRANSLATION_SECTION)?);
 
+        let domain = desc.domain.get();
         match desc.ty.get() {
-            DESC_TYPE_FAULT => unimplemented!(),
+            DESC_TYPE_FAULT | DESC_TYPE_RESERVED => Err(FSR_TRANSLATION_SECTION),
             DESC_TYPE_L2 => {
                 let l2_table = desc.l2_base.get() << 10;
-                self.walk_l2(l2_table, vaddr)
+                self.walk_l2(l2_table, vaddr, domain, debug)
             }
             DESC_TYPE_SECTION => {
+                let mut entry = TlbEntry {
+                    vpage: vaddr >> 12,
+                    ppage: 0,
+                    size_exp: 20,
+                    asid: None,
+                    domain: domain,
+                    perms: [desc.ap.get() as u8; 4],
+                    xn: false,
+                    is_page: false
+                };
                 if desc.is_supersection.get() == 1 {
-                    let base = desc.supersection_base.get() << 24;
-                    let offs = bits!(vaddr, 0:23);
-                    base | offs
+                    entry.ppage = (desc.supersection_base.get() << 12) | bits!(vaddr, 12:23);
+                    entry.size_exp = 24;
+                    // Supersections have no domain field and always use domain 0
+                    entry.domain = 0;
                 } else {
-                    let base = desc.section_base.get() << 20;
-                    let offs = bits!(vaddr, 0:19);
-                    base | offs
+                    entry.ppage = (desc.section_base.get() << 8) | bits!(vaddr, 12:19);
+                }
+
+                if !self.backcompat_walk {
+                    entry.perms = [(desc.apx.get() << 2 | desc.ap.get()) as u8; 4];
+                    entry.xn = desc.xn.get() == 1;
+                    if desc.not_global.get() == 1 {
+                        entry.asid = Some(self.asid);
+                    }
                 }
+                Ok(entry)
             }
-            DESC_TYPE_RESERVED | _ => unreachable!()
+            _ => unreachable!()
         }
     }
 
-    fn translate_addr(&self, vaddr: u32) -> u32 {
-        if !self.enabled {
-            vaddr
+    /// Checks an access against the domain and permissions of its mapping, returning
+    /// the fault status if refused
+    fn check_access(&self, entry: &TlbEntry, vaddr: u32, access: Access) -> Result<(), u32> {
+        let (domain_fault, perm_fault) = if entry.is_page {
+            (FSR_DOMAIN_PAGE, FSR_PERMISSION_PAGE)
         } else {
-            let page_table = self.select_page_table(vaddr);
-            self.walk_l1(page_table, vaddr)
+            (FSR_DOMAIN_SECTION, FSR_PERMISSION)
+        };
+        let domain_bits = entry.domain << 4;
+
+        match (self.domains >> (entry.domain * 2)) & 0b11 {
+            // Managers bypass the permission checks
+            0b11 => Ok(()),
+            0b01 => {
+                let perms = entry.perms[bits!(vaddr, 10:11) as usize] as u32;
+                let executable = !entry.xn || access != Access::Fetch;
+                if executable && perms_allow(perms, self.privileged, access) {
+                    Ok(())
+                } else {
+                    Err(perm_fault | domain_bits)
+                }
+            }
+            _ => Err(domain_fault | domain_bits)
         }
     }
+
+    /// Translates `vaddr` through the TLB, walking the page tables on a miss. Refused
+    /// accesses latch a fault for the CPU to take.
+    fn translate_addr(&mut self, vaddr: u32, access: Access) -> Option<u32> {
+        if !self.enabled {
+            return Some(vaddr)
+        }
+
+        let entry = match self.tlb.lookup(vaddr, self.asid) {
+            Some(entry) => Ok(entry),
+            None => {
+                let page_table = self.select_page_table(vaddr);
+                let walked = self.walk_l1(page_table, vaddr, false);
+                if let Ok(entry) = walked {
+                    self.tlb.insert(entry);
+                }
+                walked
+            }
+        };
+
+        let status = match entry.and_then(|entry| self.check_access(&entry, vaddr, access).map(|_| entry)) {
+            Ok(entry) => return Some((entry.ppage << 12) | bits!(vaddr, 0:11)),
+            Err(status) => status
+        };
+
+        trace!("MMU refused {:?} of 0x{:08X}, status {:#X}", access, vaddr, status);
+        if self.fault.is_none() {
+            self.fault = Some(Fault { addr: vaddr, access: access, status: status });
+        }
+        None
+    }
+
+    /// Translates `vaddr` for a debugger, ignoring permissions and leaving the TLB alone
+    fn debug_translate(&self, vaddr: u32) -> Option<u32> {
+        if !self.enabled {
+            return Some(vaddr)
+        }
+        let entry = match self.tlb.lookup(vaddr, self.asid) {
+            Some(entry) => entry,
+            None => self.walk_l1(self.select_page_table(vaddr), vaddr, true).ok()?
+        };
+        Some((entry.ppage << 12) | bits!(vaddr, 0:11))
+    }
 }
 
 impl Ops for Mmu {
@@ -349,8 +743,10 @@ impl Ops for Mmu {
     fn imem_read<T: Copy>(&mut self, vaddr: u32) -> T {
         assert!( (vaddr as usize) % std::mem::size_of::<T>() == 0 );
 
-        let paddr = self.translate_addr(vaddr);
-        self.memory.read(paddr)
+        match self.translate_addr(vaddr, Access::Fetch) {
+            Some(paddr) => self.memory.read(paddr),
+            None => faulted_value()
+        }
     }
 
     fn icache_invalidate(&mut self) {
@@ -364,15 +760,18 @@ impl Ops for Mmu {
     fn dmem_read<T: Copy>(&mut self, vaddr: u32) -> T {
         assert!( (vaddr as usize) % std::mem::size_of::<T>() == 0 );
 
-        let paddr = self.translate_addr(vaddr);
-        self.memory.read(paddr)
+        match self.translate_addr(vaddr, Access::Read) {
+            Some(paddr) => self.memory.read(paddr),
+            None => faulted_value()
+        }
     }
 
     fn dmem_write<T: Copy>(&mut self, vaddr: u32, val: T) {
         assert!( (vaddr as usize) % std::mem::size_of::<T>() == 0 );
 
-        let paddr = self.translate_addr(vaddr);
-        self.memory.write(paddr, val);
+        if let Some(paddr) = self.translate_addr(vaddr, Access::Write) {
+            self.memory.write(paddr, val);
+        }
     }
 
     fn dcache_invalidate(&mut self) {
@@ -391,7 +790,42 @@ impl Ops for Mmu {
     }
 }
 
-
+impl SaveState for Mmu {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        // Write back dirty lines so that they end up in the saved RAM
+        self.icache_invalidate();
+        self.dcache_invalidate();
+
+        w.put_bool(self.enabled);
+        w.put_bool(self.icache_enabled);
+        w.put_bool(self.dcache_enabled);
+        w.put_u32(self.pagesel as u32);
+        w.put_u32(self.page_tables[0]);
+        w.put_u32(self.page_tables[1]);
+        w.put_bool(self.backcompat_walk);
+        w.put_u32(self.domains);
+        w.put_u8(self.asid);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        // Nothing cached may survive into the loaded state
+        self.icache_invalidate();
+        self.dcache_invalidate();
+        self.invalidate_tlb();
+
+        self.enabled = r.get_bool()?;
+        self.icache_enabled = r.get_bool()?;
+        self.dcache_enabled = r.get_bool()?;
+        self.pagesel = r.get_u32()? as usize;
+        self.page_tables[0] = r.get_u32()?;
+        self.page_tables[1] = r.get_u32()?;
+        self.backcompat_walk = r.get_bool()?;
+        self.domains = r.get_u32()?;
+        self.asid = r.get_u8()?;
+        Ok(())
+    }
+}
 
 
 
@@ -409,6 +843,106 @@ impl MemMgr {
             MemMgr::Mmu(Mmu::new(memory))
         }
     }
+
+    #[inline(always)]
+    fn check_watchpoints<T: Copy>(&mut self, addr: u32, access: WatchKind) {
+        let watchpoints = &mut self.main_mem_mut().watchpoints;
+        if !watchpoints.is_empty() {
+            watchpoints.check(addr, std::mem::size_of::<T>() as u32, access);
+        }
+    }
+
+    /// Returns the first watched access made since the last call
+    #[inline(always)]
+    pub fn take_watchpoint_hit(&mut self) -> Option<(u32, WatchKind)> {
+        self.main_mem_mut().watchpoints.take_hit()
+    }
+
+    /// Latches an access to `addr` that reached unmapped memory as an external abort,
+    /// unless the access already faulted
+    #[inline(always)]
+    fn check_bus_error(&mut self, addr: u32, access: Access) {
+        if !self.main_mem().bus_error_pending() {
+            return
+        }
+        self.main_mem().take_bus_error();
+        let fault = match *self {
+            MemMgr::Mpu(ref mut mpu) => &mut mpu.fault,
+            MemMgr::Mmu(ref mut mmu) => &mut mmu.fault
+        };
+        if fault.is_none() {
+            *fault = Some(Fault { addr: addr, access: access, status: FSR_EXTERNAL });
+        }
+    }
+
+    /// Sets whether accesses are checked against privileged or user mode permissions
+    #[inline(always)]
+    pub fn set_privileged(&mut self, privileged: bool) {
+        match *self {
+            MemMgr::Mpu(ref mut mpu) => mpu.privileged = privileged,
+            MemMgr::Mmu(ref mut mmu) => mmu.privileged = privileged
+        }
+    }
+
+    /// Whether an access made since the last `take_fault` was refused
+    #[inline(always)]
+    pub fn fault_pending(&self) -> bool {
+        match *self {
+            MemMgr::Mpu(ref mpu) => mpu.fault.is_some(),
+            MemMgr::Mmu(ref mmu) => mmu.fault.is_some()
+        }
+    }
+
+    /// First refused access made since the last `take_fault`
+    #[inline(always)]
+    pub fn pending_fault(&self) -> Option<&Fault> {
+        match *self {
+            MemMgr::Mpu(ref mpu) => mpu.fault.as_ref(),
+            MemMgr::Mmu(ref mmu) => mmu.fault.as_ref()
+        }
+    }
+
+    /// Returns the first refused access made since the last call
+    #[inline(always)]
+    pub fn take_fault(&mut self) -> Option<Fault> {
+        match *self {
+            MemMgr::Mpu(ref mut mpu) => mpu.fault.take(),
+            MemMgr::Mmu(ref mut mmu) => mmu.fault.take()
+        }
+    }
+
+    /// Translates `vaddr` without checking permissions, filling the TLB or latching a fault
+    pub fn physical_addr(&self, vaddr: u32) -> Option<u32> {
+        match *self {
+            MemMgr::Mpu(_) => Some(vaddr),
+            MemMgr::Mmu(ref mmu) => mmu.debug_translate(vaddr)
+        }
+    }
+
+    /// Reads memory at a virtual address for a debugger, seeing dirty lines in the data
+    /// cache. Unlike the CPU's accesses, this never fills or flushes a cache line, walks
+    /// into the TLB, checks permissions or watchpoints, or latches a fault.
+    pub fn debug_read_buf(&self, mut vaddr: u32, mut buf: &mut [u8]) -> Result<(), String> {
+        while !buf.is_empty() {
+            // Cache lines never straddle pages, so one translation covers each chunk
+            let chunk_len = cmp::min(buf.len(), 32 - (vaddr & 31) as usize);
+            let (chunk, rest) = {buf}.split_at_mut(chunk_len);
+
+            let paddr = self.physical_addr(vaddr)
+                .ok_or(format!("Could not translate address 0x{:X}", vaddr))?;
+            let dcache = match *self {
+                MemMgr::Mpu(ref mpu) => &mpu.dcache,
+                MemMgr::Mmu(ref mmu) => &mmu.dcache
+            };
+            if !dcache.peek(paddr, chunk) {
+                self.main_mem().debug_read_buf(paddr, chunk)?;
+            }
+
+            vaddr = vaddr.wrapping_add(chunk_len as u32);
+            buf = rest;
+        }
+        Ok(())
+    }
 }
 
 macro_rules! match_mgr {
@@ -420,19 +954,36 @@ macro_rules! match_mgr {
     }
 }
 
+impl SaveState for MemMgr {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        match_mgr!(self, +mut save_state(w))
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        match_mgr!(self, +mut load_state(r))
+    }
+}
+
 impl Ops for MemMgr {
     fn set_enabled(&mut self, enabled: bool) {
         match_mgr!(self, +mut set_enabled(enabled))
     }
 
     fn imem_read<T: Copy>(&mut self, addr: u32) -> T {
-        match_mgr!(self, +mut imem_read(addr))
+        let val = match_mgr!(self, +mut imem_read(addr));
+        self.check_bus_error(addr, Access::Fetch);
+        val
     }
     fn dmem_read<T: Copy>(&mut self, addr: u32) -> T {
-        match_mgr!(self, +mut dmem_read(addr))
+        self.check_watchpoints::<T>(addr, WatchKind::Read);
+        let val = match_mgr!(self, +mut dmem_read(addr));
+        self.check_bus_error(addr, Access::Read);
+        val
     }
     fn dmem_write<T: Copy>(&mut self, addr: u32, val: T) {
-        match_mgr!(self, +mut dmem_write(addr, val))
+        self.check_watchpoints::<T>(addr, WatchKind::Write);
+        self.main_mem().mark_stored();
+        match_mgr!(self, +mut dmem_write(addr, val));
+        self.check_bus_error(addr, Access::Write);
     }
 
     fn icache_set_enabled(&mut self, enabled: bool) {
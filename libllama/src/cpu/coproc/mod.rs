@@ -1,11 +1,20 @@
 mod sys_control;
+mod vfp;
 
 use cpu;
 pub use self::sys_control::*;
+pub use self::vfp::*;
 
 pub type CpEffect<V> = Box<dyn Fn(&mut cpu::Cpu<V>)>;
 
 pub trait Coprocessor<V: cpu::Version> {
-    fn move_in(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize, val: u32) -> CpEffect<V>;
-    fn move_out(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize) -> u32;
+    /// Whether a register transfer may reach the coprocessor; if not, the instruction is
+    /// undefined
+    fn allows_access(&self, _cpreg1: usize, _op1: usize, _privileged: bool) -> bool {
+        true
+    }
+    /// Both transfers return None for registers the coprocessor does not have, which
+    /// makes the instruction undefined
+    fn move_in(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize, val: u32) -> Option<CpEffect<V>>;
+    fn move_out(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize) -> Option<u32>;
 }
@@ -1,6 +1,7 @@
 use cpu::coproc::{CpEffect, Coprocessor};
-use cpu::caches::{Ops, MemMgr};
+use cpu::caches::{Access, Fault, Ops, MemMgr};
 use cpu::{Version, v5, v6};
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 bf!(RegControl[u32] {
     use_mpu: 0:0,
@@ -33,46 +34,171 @@ bf!(MpuRegion[u32] {
 pub struct SysControl {
     r1_control: RegControl::Bf,
     r1_auxctrl: u32,
+    r1_coproc_access: u32,
     r2_dcacheability: u32,
     r2_icacheability: u32,
+    r2_ttbr: [u32; 2],
+    r2_ttbcr: u32,
     r3_bufferability: u32,
     r3_domain_access: u32,
     r5_daccessperms: u32,
     r5_iaccessperms: u32,
+    r5_dfault_status: u32,
+    r5_ifault_status: u32,
     r6_memregions: [MpuRegion::Bf; 8],
+    r6_fault_addr: u32,
     r9_dcache_lockdown: u32,
     r9_icache_lockdown: u32,
     r9_dtcm_size: u32,
     r9_itcm_size: u32,
+    r13_fcse_pid: u32,
+    r13_context_id: u32,
+    r13_thread_ids: [u32; 3],
     r15_perfmon_ctrl: u32,
+    /// Which MPCore core this is; fixed, so not saved
+    cpu_id: u32,
+}
+
+impl SaveState for SysControl {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_u32(self.r1_control.val);
+        w.put_u32(self.r1_auxctrl);
+        w.put_u32(self.r1_coproc_access);
+        w.put_u32(self.r2_dcacheability);
+        w.put_u32(self.r2_icacheability);
+        w.put_u32(self.r2_ttbr[0]);
+        w.put_u32(self.r2_ttbr[1]);
+        w.put_u32(self.r2_ttbcr);
+        w.put_u32(self.r3_bufferability);
+        w.put_u32(self.r3_domain_access);
+        w.put_u32(self.r5_daccessperms);
+        w.put_u32(self.r5_iaccessperms);
+        w.put_u32(self.r5_dfault_status);
+        w.put_u32(self.r5_ifault_status);
+        for region in self.r6_memregions.iter() {
+            w.put_u32(region.val);
+        }
+        w.put_u32(self.r6_fault_addr);
+        w.put_u32(self.r9_dcache_lockdown);
+        w.put_u32(self.r9_icache_lockdown);
+        w.put_u32(self.r9_dtcm_size);
+        w.put_u32(self.r9_itcm_size);
+        w.put_u32(self.r13_fcse_pid);
+        w.put_u32(self.r13_context_id);
+        for id in self.r13_thread_ids.iter() {
+            w.put_u32(*id);
+        }
+        w.put_u32(self.r15_perfmon_ctrl);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.r1_control.val = r.get_u32()?;
+        self.r1_auxctrl = r.get_u32()?;
+        self.r1_coproc_access = r.get_u32()?;
+        self.r2_dcacheability = r.get_u32()?;
+        self.r2_icacheability = r.get_u32()?;
+        self.r2_ttbr[0] = r.get_u32()?;
+        self.r2_ttbr[1] = r.get_u32()?;
+        self.r2_ttbcr = r.get_u32()?;
+        self.r3_bufferability = r.get_u32()?;
+        self.r3_domain_access = r.get_u32()?;
+        self.r5_daccessperms = r.get_u32()?;
+        self.r5_iaccessperms = r.get_u32()?;
+        self.r5_dfault_status = r.get_u32()?;
+        self.r5_ifault_status = r.get_u32()?;
+        for region in self.r6_memregions.iter_mut() {
+            region.val = r.get_u32()?;
+        }
+        self.r6_fault_addr = r.get_u32()?;
+        self.r9_dcache_lockdown = r.get_u32()?;
+        self.r9_icache_lockdown = r.get_u32()?;
+        self.r9_dtcm_size = r.get_u32()?;
+        self.r9_itcm_size = r.get_u32()?;
+        self.r13_fcse_pid = r.get_u32()?;
+        self.r13_context_id = r.get_u32()?;
+        for id in self.r13_thread_ids.iter_mut() {
+            *id = r.get_u32()?;
+        }
+        self.r15_perfmon_ctrl = r.get_u32()?;
+        Ok(())
+    }
 }
 
 fn mknop<V: Version>() -> CpEffect<V> {
     Box::new(|_| {})
 }
 
+/// Widens the 2-bit fields of the standard MPU access permission registers to the
+/// 4-bit fields of the extended ones
+fn extend_perms(val: u32) -> u32 {
+    (0..8).fold(0, |acc, i| acc | ((val >> (i * 2)) & 0b11) << (i * 4))
+}
+
+fn compact_perms(val: u32) -> u32 {
+    (0..8).fold(0, |acc, i| acc | ((val >> (i * 4)) & 0b11) << (i * 2))
+}
+
 impl SysControl {
     pub fn new() -> SysControl {
         SysControl {
             r1_control: RegControl::new(0),
             r1_auxctrl: 0,
+            r1_coproc_access: 0,
             r2_dcacheability: 0,
             r2_icacheability: 0,
+            r2_ttbr: [0; 2],
+            r2_ttbcr: 0,
             r3_bufferability: 0,
             r3_domain_access: 0,
             r5_daccessperms: 0,
             r5_iaccessperms: 0,
+            r5_dfault_status: 0,
+            r5_ifault_status: 0,
             r6_memregions: [MpuRegion::new(0); 8],
+            r6_fault_addr: 0,
             r9_dcache_lockdown: 0,
             r9_icache_lockdown: 0,
             r9_dtcm_size: 0,
             r9_itcm_size: 0,
+            r13_fcse_pid: 0,
+            r13_context_id: 0,
+            r13_thread_ids: [0; 3],
             r15_perfmon_ctrl: 0,
+            cpu_id: 0,
         }
     }
 
-    fn write_c1<V: Version>(&mut self, op2: usize, val: u32) -> CpEffect<V> {
-        match op2 {
+    pub fn set_cpu_id(&mut self, id: u32) {
+        self.cpu_id = id;
+    }
+
+    /// Access rights for each of coprocessors 0-13, two bits apiece
+    pub fn coproc_access(&self) -> u32 {
+        self.r1_coproc_access
+    }
+
+    /// Latches the status and address of an abort. The ARM9 has no registers to
+    /// read them back from, so there they are only visible to the debugger.
+    pub fn record_fault<V: Version>(&mut self, fault: &Fault) {
+        match fault.access {
+            // Only data aborts report the domain
+            Access::Fetch => self.r5_ifault_status = fault.status & 0b1111,
+            Access::Read | Access::Write => {
+                let write_bit = (V::is::<v6>() && fault.access == Access::Write) as u32;
+                self.r5_dfault_status = fault.status | (write_bit << 11);
+                self.r6_fault_addr = fault.addr;
+            }
+        }
+    }
+
+    /// Data fault status, instruction fault status and fault address
+    pub fn fault_regs(&self) -> (u32, u32, u32) {
+        (self.r5_dfault_status, self.r5_ifault_status, self.r6_fault_addr)
+    }
+
+    fn write_c1<V: Version>(&mut self, op2: usize, val: u32) -> Option<CpEffect<V>> {
+        let effect: CpEffect<V> = match op2 {
             0b000 => {
                 warn!("STUBBED: System control register write");
                 self.r1_control.val = val;
@@ -83,7 +209,12 @@ impl SysControl {
                     cpu.mpu.icache_set_enabled(control.use_icache.get() == 1);
                     cpu.mpu.dcache_set_enabled(control.use_dcache.get() == 1);
                     if let MemMgr::Mmu(ref mut mmu) = cpu.mpu {
-                        mmu.backcompat_walk = control.disable_subpage_ap.get() == 0;
+                        // The TLB holds descriptors as decoded for the old format
+                        let backcompat_walk = control.disable_subpage_ap.get() == 0;
+                        if mmu.backcompat_walk != backcompat_walk {
+                            mmu.invalidate_tlb();
+                        }
+                        mmu.backcompat_walk = backcompat_walk;
                     }
                 })
             }
@@ -93,15 +224,17 @@ impl SysControl {
                 mknop()
             }
             0b010 => {
-                warn!("STUBBED: Coproc control register write");
+                trace!("Coproc access control register write: {:08X}", val);
+                self.r1_coproc_access = val & 0x0FFFFFFF;
                 mknop()
             },
-            _ => unreachable!()
-        }
+            _ => return None
+        };
+        Some(effect)
     }
 
-    fn write_c2_arm9<V: Version>(&mut self, op2: usize, val: u32) -> CpEffect<V> {
-        match op2 {
+    fn write_c2_arm9<V: Version>(&mut self, op2: usize, val: u32) -> Option<CpEffect<V>> {
+        let effect: CpEffect<V> = match op2 {
             0 => {
                 trace!("DCache cacheability register write");
                 self.r2_dcacheability = val;
@@ -122,14 +255,16 @@ impl SysControl {
                     }
                 })
             }
-            _ => unreachable!()
-        }
+            _ => return None
+        };
+        Some(effect)
     }
 
-    fn write_c2_arm11<V: Version>(&mut self, op2: usize, val: u32) -> CpEffect<V> {
-        match op2 {
+    fn write_c2_arm11<V: Version>(&mut self, op2: usize, val: u32) -> Option<CpEffect<V>> {
+        let effect: CpEffect<V> = match op2 {
             0 => {
                 trace!("Trans. table base 0 register write: {:08X}", val);
+                self.r2_ttbr[0] = val;
                 Box::new(move |cpu| {
                     if let MemMgr::Mmu(ref mut mmu) = cpu.mpu {
                         mmu.page_tables[0] = val & !0b11111;
@@ -138,6 +273,7 @@ impl SysControl {
             }
             1 => {
                 trace!("Trans. table base 1 register write: {:08X}", val);
+                self.r2_ttbr[1] = val;
                 Box::new(move |cpu| {
                     if let MemMgr::Mmu(ref mut mmu) = cpu.mpu {
                         mmu.page_tables[1] = val & !0b11111;
@@ -146,14 +282,16 @@ impl SysControl {
             }
             2 => {
                 trace!("Trans. table base ctrl. register write: {:08X}", val);
+                self.r2_ttbcr = val & 0b111;
                 Box::new(move |cpu| {
                     if let MemMgr::Mmu(ref mut mmu) = cpu.mpu {
-                        mmu.pagesel = val as usize;
+                        mmu.pagesel = (val & 0b111) as usize;
                     }
                 })
             }
-            _ => unreachable!()
-        }
+            _ => return None
+        };
+        Some(effect)
     }
 
     fn write_c3_arm9(&mut self, val: u32) {
@@ -161,59 +299,92 @@ impl SysControl {
         self.r3_bufferability = val;
     }
 
-    fn write_c3_arm11(&mut self, val: u32) {
-        warn!("STUBBED: Domain access control register write");
+    fn write_c3_arm11<V: Version>(&mut self, val: u32) -> CpEffect<V> {
+        trace!("Domain access control register write: {:08X}", val);
         self.r3_domain_access = val;
+        Box::new(move |cpu| {
+            if let MemMgr::Mmu(ref mut mmu) = cpu.mpu {
+                mmu.domains = val;
+            }
+        })
     }
 
-    fn write_c5_arm9(&mut self, op2: usize, val: u32) {
+    fn write_c5_arm11(&mut self, op2: usize, val: u32) -> Option<()> {
         match op2 {
-            0 | 2 => { // TODO: verify
-                warn!("STUBBED: Data access perms register write");
-                self.r5_daccessperms = val;
-            }
-            1 | 3 => { // TODO: verify
-                warn!("STUBBED: Instr access perms register write");
-                self.r5_iaccessperms = val;
-            }
-            _ => unreachable!()
+            0 => self.r5_dfault_status = val,
+            1 => self.r5_ifault_status = val,
+            _ => return None
         }
+        Some(())
     }
 
-    fn write_c6_arm9<V: Version>(&mut self, cpreg2: usize, val: u32) -> CpEffect<V> {
+    fn write_c6_arm11(&mut self, op2: usize, val: u32) {
+        match op2 {
+            0 => self.r6_fault_addr = val,
+            _ => warn!("STUBBED: CP15 c6 write with op2={}", op2)
+        }
+    }
+
+    fn write_c5_arm9<V: Version>(&mut self, op2: usize, val: u32) -> Option<CpEffect<V>> {
+        trace!("Access perms register {} write: {:08X}", op2, val);
+        match op2 {
+            0 => self.r5_daccessperms = extend_perms(val),
+            1 => self.r5_iaccessperms = extend_perms(val),
+            2 => self.r5_daccessperms = val,
+            3 => self.r5_iaccessperms = val,
+            _ => return None
+        }
+
+        let (dperms, iperms) = (self.r5_daccessperms, self.r5_iaccessperms);
+        Some(Box::new(move |cpu| {
+            if let MemMgr::Mpu(ref mut mpu) = cpu.mpu {
+                mpu.region_data_perms = dperms;
+                mpu.region_instr_perms = iperms;
+            }
+        }))
+    }
+
+    fn write_c6_arm9<V: Version>(&mut self, cpreg2: usize, val: u32) -> Option<CpEffect<V>> {
         trace!("MPU region {} register write", cpreg2);
         let index = cpreg2;
-        self.r6_memregions[index].val = val;
+        self.r6_memregions.get_mut(index)?.val = val;
 
         let region_data = self.r6_memregions[index];
-        Box::new(move |cpu| {
+        Some(Box::new(move |cpu| {
             if let MemMgr::Mpu(ref mut mpu) = cpu.mpu {
                 let size_exp = region_data.size.get() + 1;
                 mpu.region_size_exp[index] = size_exp;
                 mpu.region_base_sigbits[index] = region_data.base_shr_12.get() << 12 >> size_exp;
+                mpu.region_enabled &= !(1 << index);
                 mpu.region_enabled |= (region_data.enabled.get() << index) as u8;
             }
-        })
+        }))
     }
 
+    /// Cache operations. Cleaning goes through invalidation, which writes dirty lines
+    /// back first, and single-line operations act on the whole cache.
     fn write_c7<V: Version>(&mut self, op2: usize, cpreg2: usize) -> CpEffect<V> {
         match (cpreg2, op2) {
+            (0, 4) => Box::new(move |cpu| cpu.wait_for_interrupt()),
+            // The ARM9's alternative wait for interrupt encoding
+            (8, 2) if V::is::<v5>() => Box::new(move |cpu| cpu.wait_for_interrupt()),
             (5, 0..=2) => Box::new(move |cpu| cpu.mpu.icache_invalidate()),
             (6, 0..=2) => Box::new(move |cpu| cpu.mpu.dcache_invalidate()),
             (7, 0) => Box::new(move |cpu| {
                 cpu.mpu.icache_invalidate();
                 cpu.mpu.dcache_invalidate();
             }),
-            (7, 1..=2) => unimplemented!(),
-            (10, 0..=2) => Box::new(move |cpu| cpu.mpu.dcache_invalidate()),
-            (11, 0..=2) => unimplemented!(),
+            (7, 1..=2) | (15, 0..=2) => Box::new(move |cpu| {
+                cpu.mpu.icache_invalidate();
+                cpu.mpu.dcache_invalidate();
+            }),
+            (10, 0..=2) | (11, 0..=2) => Box::new(move |cpu| cpu.mpu.dcache_invalidate()),
             (14, 0..=2) => Box::new(move |cpu| cpu.mpu.dcache_invalidate()),
-            (15, 0..=2) => unimplemented!(),
             _ => { warn!("STUBBED: Cache control register write; reg2={}, op2={}", cpreg2, op2); mknop() },
         }
     }
 
-    fn write_c9_arm9(&mut self, op2: usize, cpreg2: usize, val: u32) {
+    fn write_c9_arm9(&mut self, op2: usize, cpreg2: usize, val: u32) -> Option<()> {
         match (cpreg2, op2) {
             (0, 0) => {
                 warn!("STUBBED: DCache lockdown register write");
@@ -231,36 +402,38 @@ impl SysControl {
                 warn!("STUBBED: ITCM size register write");
                 self.r9_itcm_size = val;
             }
-            _ => unreachable!()
+            _ => return None
         }
+        Some(())
     }
 
-    fn write_c15_arm9(&mut self, op1: usize, op2: usize, cpreg2: usize, val: u32) {
+    fn write_c15_arm9(&mut self, op1: usize, op2: usize, cpreg2: usize, val: u32) -> Option<()> {
         match op1 {
             3 => warn!("STUBBED: Cache debug CP15 write! reg2={}, op2={}, val={:08X}", cpreg2, op2, val),
-            _ => unimplemented!(),
+            _ => return None,
         }
+        Some(())
     }
 
 
 
-    fn read_c0_arm9(&self, op2: usize) -> u32 {
+    fn read_c0_arm9(&self, op2: usize) -> Option<u32> {
         match op2 {
-            1 => 0x0F0D2112, // On the 3DS: 4k, 4-way dcache; 8k, 4-day icache
-            _ => unimplemented!(),
+            1 => Some(0x0F0D2112), // On the 3DS: 4k, 4-way dcache; 8k, 4-day icache
+            _ => None,
         }
     }
 
-    fn read_c0_arm11(&self, op2: usize) -> u32 {
+    fn read_c0_arm11(&self, op2: usize) -> Option<u32> {
         match op2 {
-            0 => 0x41060361, // TODO: Educated guess, hopefully works?
-            5 => 0x00000000, // On the 3DS: 2xMPCore
-            n => panic!("Unimplemented access to CP15 c0 with op2={}", n),
+            0 => Some(0x41060361), // TODO: Educated guess, hopefully works?
+            5 => Some(self.cpu_id), // Cluster 0
+            _ => None,
         }
     }
 
-    fn read_c1(&self, op2: usize) -> u32 {
-        match op2 {
+    fn read_c1(&self, op2: usize) -> Option<u32> {
+        let val = match op2 {
             0b000 => {
                 warn!("STUBBED: System control register read");
                 self.r1_control.val
@@ -269,13 +442,14 @@ impl SysControl {
                 warn!("STUBBED: Auxiliary control register read");
                 self.r1_auxctrl
             }
-            0b010 => unimplemented!(),
-            _ => unreachable!()
-        }
+            0b010 => self.r1_coproc_access,
+            _ => return None
+        };
+        Some(val)
     }
 
-    fn read_c2_arm9(&self, op2: usize) -> u32 {
-        match op2 {
+    fn read_c2_arm9(&self, op2: usize) -> Option<u32> {
+        let val = match op2 {
             0 => {
                 warn!("STUBBED: DCache cacheability register read");
                 self.r2_dcacheability
@@ -284,8 +458,9 @@ impl SysControl {
                 warn!("STUBBED: ICache cacheability register read");
                 self.r2_icacheability
             }
-            _ => unreachable!()
-        }
+            _ => return None
+        };
+        Some(val)
     }
 
     fn read_c3_arm9(&self) -> u32 {
@@ -293,35 +468,99 @@ impl SysControl {
         self.r3_bufferability
     }
 
-    fn read_c5_arm9(&self, op2: usize) -> u32 {
+    fn read_c5_arm9(&self, op2: usize) -> Option<u32> {
         match op2 {
-            0 | 2 => { // TODO: verify
-                warn!("STUBBED: Instr access perms register read");
-                self.r5_daccessperms
-            }
-            1 | 3 => { // TODO: verify
-                warn!("STUBBED: Data access perms register read");
-                self.r5_iaccessperms
-            }
-            _ => unreachable!()
+            0 => Some(compact_perms(self.r5_daccessperms)),
+            1 => Some(compact_perms(self.r5_iaccessperms)),
+            2 => Some(self.r5_daccessperms),
+            3 => Some(self.r5_iaccessperms),
+            _ => None
         }
     }
 
-    fn read_c6_arm9(&self, cpreg2: usize) -> u32 {
+    fn read_c6_arm9(&self, cpreg2: usize) -> Option<u32> {
         warn!("STUBBED: MPU region {} register read", cpreg2);
-        self.r6_memregions[cpreg2].val
+        self.r6_memregions.get(cpreg2).map(|region| region.val)
     }
 
-    fn read_c7_arm9(&self) -> u32 {
-        panic!("Cannot read from cache control register!")
+    /// TLB maintenance; the instruction, data and unified TLB operations all act on
+    /// our single TLB
+    fn write_c8_arm11<V: Version>(&mut self, op2: usize, cpreg2: usize, val: u32) -> CpEffect<V> {
+        trace!("TLB operation: reg2={}, op2={}, val={:08X}", cpreg2, op2, val);
+        match (cpreg2, op2) {
+            (5..=7, 0) => Box::new(move |cpu| {
+                if let MemMgr::Mmu(ref mut mmu) = cpu.mpu {
+                    mmu.invalidate_tlb();
+                }
+            }),
+            (5..=7, 1) => Box::new(move |cpu| {
+                if let MemMgr::Mmu(ref mut mmu) = cpu.mpu {
+                    mmu.invalidate_tlb_mva(val);
+                }
+            }),
+            (5..=7, 2) => Box::new(move |cpu| {
+                if let MemMgr::Mmu(ref mut mmu) = cpu.mpu {
+                    mmu.invalidate_tlb_asid(val as u8);
+                }
+            }),
+            _ => { warn!("STUBBED: TLB control register write; reg2={}, op2={}", cpreg2, op2); mknop() }
+        }
     }
 
-    fn write_c8_arm11(&mut self, op2: usize, cpreg2: usize, val: u32) {
-        warn!("STUBBED: ARM11 TLB Control write! reg2={}, op2={}, val={:08X}", cpreg2, op2, val);
+    fn write_c13_arm11<V: Version>(&mut self, op2: usize, val: u32) -> Option<CpEffect<V>> {
+        let effect: CpEffect<V> = match op2 {
+            0 => {
+                if val != 0 {
+                    warn!("STUBBED: FCSE PID register write: {:08X}", val);
+                }
+                self.r13_fcse_pid = val;
+                mknop()
+            }
+            1 => {
+                trace!("Context ID register write: {:08X}", val);
+                self.r13_context_id = val;
+                Box::new(move |cpu| {
+                    if let MemMgr::Mmu(ref mut mmu) = cpu.mpu {
+                        mmu.asid = val as u8;
+                    }
+                })
+            }
+            2..=4 => {
+                self.r13_thread_ids[op2 - 2] = val;
+                mknop()
+            }
+            _ => return None
+        };
+        Some(effect)
     }
 
-    fn read_c9_arm9(&self, op2: usize, cpreg2: usize) -> u32 {
-        match (cpreg2, op2) {
+    fn read_c2_arm11(&self, op2: usize) -> Option<u32> {
+        match op2 {
+            0 | 1 => Some(self.r2_ttbr[op2]),
+            2 => Some(self.r2_ttbcr),
+            _ => None
+        }
+    }
+
+    fn read_c5_arm11(&self, op2: usize) -> Option<u32> {
+        match op2 {
+            0 => Some(self.r5_dfault_status),
+            1 => Some(self.r5_ifault_status),
+            _ => None
+        }
+    }
+
+    fn read_c13_arm11(&self, op2: usize) -> Option<u32> {
+        match op2 {
+            0 => Some(self.r13_fcse_pid),
+            1 => Some(self.r13_context_id),
+            2..=4 => Some(self.r13_thread_ids[op2 - 2]),
+            _ => None
+        }
+    }
+
+    fn read_c9_arm9(&self, op2: usize, cpreg2: usize) -> Option<u32> {
+        let val = match (cpreg2, op2) {
             (0, 0) => {
                 warn!("STUBBED: DCache lockdown register read");
                 self.r9_dcache_lockdown
@@ -338,22 +577,24 @@ impl SysControl {
                 warn!("STUBBED: ITCM size register read");
                 self.r9_itcm_size
             }
-            _ => unreachable!()
-        }
+            _ => return None
+        };
+        Some(val)
     }
 
-    fn write_c15_arm11(&mut self, op2: usize, cpreg2: usize, val: u32) {
+    fn write_c15_arm11(&mut self, op2: usize, cpreg2: usize, val: u32) -> Option<()> {
         match (cpreg2, op2) {
             (12, 0) => {
                 warn!("STUBBED: ARM11 Performance Monitor Control CP15 write! reg2={}, op2={}, val={:08X}", cpreg2, op2, val);
                 self.r15_perfmon_ctrl = val;
             }
-            _ => unimplemented!()
+            _ => return None
         }
+        Some(())
     }
 
-    fn read_c15_arm11(&self, op2: usize, cpreg2: usize) -> u32 {
-        match (cpreg2, op2) {
+    fn read_c15_arm11(&self, op2: usize, cpreg2: usize) -> Option<u32> {
+        let val = match (cpreg2, op2) {
             (12, 0) => {
                 warn!("STUBBED: ARM11 Performance Monitor Control read");
                 self.r15_perfmon_ctrl
@@ -370,8 +611,9 @@ impl SysControl {
                 warn!("STUBBED: ARM11 Performance Monitor Misc. counter 0 read");
                 0
             }
-            _ => panic!("Unimplemented ARM11 C15 read cpreg2={} op2={}", cpreg2, op2)
-        }
+            _ => return None
+        };
+        Some(val)
     }
 }
 
@@ -379,70 +621,83 @@ impl SysControl {
 
 
 impl<V: Version> Coprocessor<V> for SysControl {
-    fn move_in(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize, val: u32) -> CpEffect<V> {
-        let mut effect: CpEffect<V> = Box::new(move |_cpu| {});
+    fn allows_access(&self, cpreg1: usize, _op1: usize, privileged: bool) -> bool {
+        // User mode only gets at the ARM11's barrier and prefetch flush operations, and
+        // its thread ID registers
+        privileged || (V::is::<v6>() && (cpreg1 == 7 || cpreg1 == 13))
+    }
 
-        if cpreg1 != 15 {
-            assert_eq!(op1, 0);
+    fn move_in(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize, val: u32) -> Option<CpEffect<V>> {
+        if cpreg1 != 15 && op1 != 0 {
+            return None
         }
 
-        if V::is::<v5>() {
+        let effect = if V::is::<v5>() {
             match cpreg1 {
-                1 => effect = self.write_c1(op2, val),
-                2 => effect = self.write_c2_arm9(op2, val),
-                3 => self.write_c3_arm9(val),
-                5 => self.write_c5_arm9(op2, val),
-                6 => effect = self.write_c6_arm9(cpreg2, val),
-                7 => effect = self.write_c7(op2, cpreg2),
-                9 => self.write_c9_arm9(op2, cpreg2, val),
-                15 => self.write_c15_arm9(op1, op2, cpreg2, val),
-                _ => panic!("Unimplemented CP15 write to coproc reg {}", cpreg1)
-            };
+                1 => self.write_c1(op2, val)?,
+                2 => self.write_c2_arm9(op2, val)?,
+                3 => { self.write_c3_arm9(val); mknop() }
+                5 => self.write_c5_arm9(op2, val)?,
+                6 => self.write_c6_arm9(cpreg2, val)?,
+                7 => self.write_c7(op2, cpreg2),
+                9 => { self.write_c9_arm9(op2, cpreg2, val)?; mknop() }
+                15 => { self.write_c15_arm9(op1, op2, cpreg2, val)?; mknop() }
+                _ => return None
+            }
         } else if V::is::<v6>() {
             match cpreg1 {
-                1 => effect = self.write_c1(op2, val),
+                1 => self.write_c1(op2, val)?,
+                2 => self.write_c2_arm11(op2, val)?,
                 3 => self.write_c3_arm11(val),
-                2 => effect = self.write_c2_arm11(op2, val),
-                7 => effect = self.write_c7(op2, cpreg2),
+                5 => { self.write_c5_arm11(op2, val)?; mknop() }
+                6 => { self.write_c6_arm11(op2, val); mknop() }
+                7 => self.write_c7(op2, cpreg2),
                 8 => self.write_c8_arm11(op2, cpreg2, val),
-                15 => self.write_c15_arm11(op2, cpreg2, val),
-                _ => panic!("Unimplemented CP15 write to coproc reg {}", cpreg1)
+                13 => self.write_c13_arm11(op2, val)?,
+                15 => { self.write_c15_arm11(op2, cpreg2, val)?; mknop() }
+                _ => return None
             }
         } else {
             unreachable!()
         };
 
         info!("Write 0x{:08X} to CP15 reg {}; reg2={}, op2={}", val, cpreg1, cpreg2, op2);
-        effect
+        Some(effect)
     }
 
-    fn move_out(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize) -> u32 {
-        assert_eq!(op1, 0);
+    fn move_out(&mut self, cpreg1: usize, cpreg2: usize, op1: usize, op2: usize) -> Option<u32> {
+        if op1 != 0 {
+            return None
+        }
 
         let res = if V::is::<v5>() {
             match cpreg1 {
-                0 => self.read_c0_arm9(op2),
-                1 => self.read_c1(op2),
-                2 => self.read_c2_arm9(op2),
+                0 => self.read_c0_arm9(op2)?,
+                1 => self.read_c1(op2)?,
+                2 => self.read_c2_arm9(op2)?,
                 3 => self.read_c3_arm9(),
-                5 => self.read_c5_arm9(op2),
-                6 => self.read_c6_arm9(cpreg2),
-                7 => self.read_c7_arm9(),
-                9 => self.read_c9_arm9(op2, cpreg2),
-                _ => panic!("Unimplemented CP15 read from coproc reg {}", cpreg1)
+                5 => self.read_c5_arm9(op2)?,
+                6 => self.read_c6_arm9(cpreg2)?,
+                9 => self.read_c9_arm9(op2, cpreg2)?,
+                _ => return None
             }
         } else if V::is::<v6>() {
             match cpreg1 {
-                0 => self.read_c0_arm11(op2),
-                1 => self.read_c1(op2),
-                15 => self.read_c15_arm11(op2, cpreg2),
-                _ => unimplemented!()
+                0 => self.read_c0_arm11(op2)?,
+                1 => self.read_c1(op2)?,
+                2 => self.read_c2_arm11(op2)?,
+                3 => self.r3_domain_access,
+                5 => self.read_c5_arm11(op2)?,
+                6 => self.r6_fault_addr,
+                13 => self.read_c13_arm11(op2)?,
+                15 => self.read_c15_arm11(op2, cpreg2)?,
+                _ => return None
             }
         } else {
             unreachable!()
         };
 
         info!("Read from CP15 reg {}; reg2={}, op2={}", cpreg1, cpreg2, op2);
-        res
+        Some(res)
     }
 }
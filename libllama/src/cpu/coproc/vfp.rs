@@ -0,0 +1,721 @@
+//! VFPv2 floating point coprocessor (CP10 for single, CP11 for double precision)
+//!
+//! Floating point exceptions are never trapped, only accumulated in the FPSCR's
+//! cumulative flags.
+
+use std::cmp::Ordering;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use cpu::coproc::{CpEffect, Coprocessor};
+use cpu::Version;
+use savestate::{self, SaveState, StateReader, StateWriter};
+
+bf!(Fpscr[u32] {
+    len: 16:18,
+    stride: 20:21,
+    rmode: 22:23,
+    fz: 24:24,
+    dn: 25:25,
+    nzcv: 28:31
+});
+
+/// VFP11, as found in the ARM11 MPCore
+const FPSID: u32 = 0x410120B4;
+/// Writable bits of the FPSCR
+const FPSCR_MASK: u32 = 0xF3F79F9F;
+const FPEXC_EN: u32 = 1 << 30;
+
+// System registers, as numbered by FMXR/FMRX
+const REG_FPSID: usize = 0b0000;
+const REG_FPSCR: usize = 0b0001;
+const REG_FPEXC: usize = 0b1000;
+const REG_FPINST: usize = 0b1001;
+const REG_FPINST2: usize = 0b1010;
+
+// Cumulative exception flags of the FPSCR
+pub const IOC: u32 = 1 << 0;
+pub const DZC: u32 = 1 << 1;
+pub const OFC: u32 = 1 << 2;
+pub const UFC: u32 = 1 << 3;
+pub const IXC: u32 = 1 << 4;
+pub const IDC: u32 = 1 << 7;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Rounding {
+    Nearest,
+    PlusInf,
+    MinusInf,
+    Zero
+}
+
+/// A VFP operand precision, mostly handled through its raw encoding
+pub trait Float: Copy + PartialOrd + Add<Output = Self> + Sub<Output = Self>
+               + Mul<Output = Self> + Div<Output = Self> + Neg<Output = Self> {
+    const DOUBLE: bool;
+    const SIGN: u64;
+    /// Mask of the exponent field; one less is the largest finite value
+    const EXP: u64;
+    /// Top bit of the fraction, set for quiet NaNs
+    const QUIET: u64;
+    const FRAC_BITS: u32;
+
+    fn to_raw(self) -> u64;
+    fn from_raw(raw: u64) -> Self;
+    fn to_f64(self) -> f64;
+    /// Converts with round to nearest
+    fn from_f64(val: f64) -> Self;
+    fn square_root(self) -> Self;
+}
+
+macro_rules! impl_float {
+    ($t:ident, $bits:ident, $double:expr, $sign:expr, $exp:expr, $frac_bits:expr) => {
+        impl Float for $t {
+            const DOUBLE: bool = $double;
+            const SIGN: u64 = $sign;
+            const EXP: u64 = $exp;
+            const QUIET: u64 = 1 << ($frac_bits - 1);
+            const FRAC_BITS: u32 = $frac_bits;
+
+            fn to_raw(self) -> u64 { self.to_bits() as u64 }
+            fn from_raw(raw: u64) -> $t { $t::from_bits(raw as $bits) }
+            fn to_f64(self) -> f64 { self as f64 }
+            fn from_f64(val: f64) -> $t { val as $t }
+            fn square_root(self) -> $t { self.sqrt() }
+        }
+    }
+}
+
+impl_float!(f32, u32, false, 1 << 31, 0x7F800000, 23);
+impl_float!(f64, u64, true, 1 << 63, 0x7FF0000000000000, 52);
+
+fn is_negative<F: Float>(x: F) -> bool {
+    x.to_raw() & F::SIGN != 0
+}
+
+fn is_zero<F: Float>(x: F) -> bool {
+    x.to_raw() & !F::SIGN == 0
+}
+
+fn is_inf<F: Float>(x: F) -> bool {
+    x.to_raw() & !F::SIGN == F::EXP
+}
+
+fn is_nan<F: Float>(x: F) -> bool {
+    x.to_raw() & !F::SIGN > F::EXP
+}
+
+fn is_snan<F: Float>(x: F) -> bool {
+    is_nan(x) && x.to_raw() & F::QUIET == 0
+}
+
+fn is_denormal<F: Float>(x: F) -> bool {
+    x.to_raw() & F::EXP == 0 && !is_zero(x)
+}
+
+fn signed<F: Float>(raw: u64, negative: bool) -> F {
+    F::from_raw(raw | if negative { F::SIGN } else { 0 })
+}
+
+fn default_nan<F: Float>() -> F {
+    F::from_raw(F::EXP | F::QUIET)
+}
+
+/// The neighbour of `x` towards positive infinity if `up` is set, negative infinity otherwise
+fn step<F: Float>(x: F, up: bool) -> F {
+    if is_zero(x) {
+        signed(1, !up)
+    } else if is_negative(x) == up {
+        F::from_raw(x.to_raw() - 1)
+    } else {
+        F::from_raw(x.to_raw() + 1)
+    }
+}
+
+/// Splits a finite value's magnitude into an integer significand and exponent
+fn decompose<F: Float>(x: F) -> (u128, i32) {
+    let raw = x.to_raw() & !F::SIGN;
+    let bias = (F::EXP >> F::FRAC_BITS) as i32 >> 1;
+    let exp = (raw >> F::FRAC_BITS) as i32;
+    let frac = raw & ((1 << F::FRAC_BITS) - 1);
+    if exp == 0 {
+        (frac as u128, 1 - bias - F::FRAC_BITS as i32)
+    } else {
+        ((frac | 1 << F::FRAC_BITS) as u128, exp - bias - F::FRAC_BITS as i32)
+    }
+}
+
+/// Compares m1 * 2^e1 with m2 * 2^e2
+fn compare_scaled(m1: u128, e1: i32, m2: u128, e2: i32) -> Ordering {
+    if m1 == 0 || m2 == 0 {
+        return m1.cmp(&m2)
+    }
+    // Equal top bit positions keep the aligning shift below from overflowing
+    let top1 = 128 - m1.leading_zeros() as i32 + e1;
+    let top2 = 128 - m2.leading_zeros() as i32 + e2;
+    if top1 != top2 {
+        top1.cmp(&top2)
+    } else if e1 > e2 {
+        (m1 << (e1 - e2)).cmp(&m2)
+    } else {
+        m1.cmp(&(m2 << (e2 - e1)))
+    }
+}
+
+/// How an exact result of magnitude `m * 2^e` compares to its rounded value `r`, which
+/// has the same sign
+fn compare_exact<F: Float>(m: u128, e: i32, r: F) -> Ordering {
+    let (mr, er) = decompose(r);
+    let ord = compare_scaled(m, e, mr, er);
+    if is_negative(r) { ord.reverse() } else { ord }
+}
+
+fn compare_zero<F: Float>(x: F) -> Ordering {
+    x.partial_cmp(&F::from_raw(0)).unwrap_or(Ordering::Equal)
+}
+
+/// Rounds to an integral value, with ties to even
+fn round_even(val: f64) -> f64 {
+    let floor = val.floor();
+    let diff = val - floor;
+    if diff > 0.5 || (diff == 0.5 && floor % 2.0 != 0.0) {
+        floor + 1.0
+    } else {
+        floor
+    }
+}
+
+pub struct Vfp {
+    regs: [u32; 32],
+    fpscr: Fpscr::Bf,
+    fpexc: u32,
+    fpinst: u32,
+    fpinst2: u32,
+}
+
+impl Vfp {
+    pub fn new() -> Vfp {
+        Vfp {
+            regs: [0; 32],
+            fpscr: Fpscr::new(0),
+            fpexc: 0,
+            fpinst: 0,
+            fpinst2: 0,
+        }
+    }
+
+    /// Whether FPEXC allows VFP instructions other than system register transfers
+    pub fn enabled(&self) -> bool {
+        self.fpexc & FPEXC_EN != 0
+    }
+
+    /// Sets the FPSCR condition flags, as done by the comparisons
+    pub fn set_nzcv(&mut self, nzcv: u32) {
+        self.fpscr.nzcv.set(nzcv);
+    }
+
+    /// Length and register stride of short vectors, from the FPSCR LEN and STRIDE fields
+    pub fn vector_shape(&self) -> (usize, usize) {
+        let stride = if self.fpscr.stride.get() == 0b11 { 2 } else { 1 };
+        (self.fpscr.len.get() as usize + 1, stride)
+    }
+
+    /// Raw 32-bit word `index` of the register file, which is single register `index`
+    /// or half of double register `index / 2`
+    pub fn word(&self, index: usize) -> u32 {
+        self.regs[index]
+    }
+
+    pub fn set_word(&mut self, index: usize, val: u32) {
+        self.regs[index] = val;
+    }
+
+    /// Reads single or double register `reg`, depending on `F`
+    pub fn get<F: Float>(&self, reg: usize) -> F {
+        if F::DOUBLE {
+            F::from_raw((self.regs[reg * 2 + 1] as u64) << 32 | self.regs[reg * 2] as u64)
+        } else {
+            F::from_raw(self.regs[reg] as u64)
+        }
+    }
+
+    pub fn set<F: Float>(&mut self, reg: usize, val: F) {
+        let raw = val.to_raw();
+        if F::DOUBLE {
+            self.regs[reg * 2] = raw as u32;
+            self.regs[reg * 2 + 1] = (raw >> 32) as u32;
+        } else {
+            self.regs[reg] = raw as u32;
+        }
+    }
+
+    fn raise(&mut self, flags: u32) {
+        self.fpscr.val |= flags;
+    }
+
+    fn rounding(&self) -> Rounding {
+        match self.fpscr.rmode.get() {
+            0b00 => Rounding::Nearest,
+            0b01 => Rounding::PlusInf,
+            0b10 => Rounding::MinusInf,
+            _ => Rounding::Zero
+        }
+    }
+
+    /// Flushes a denormal operand to zero in flush-to-zero mode
+    fn operand<F: Float>(&mut self, x: F) -> F {
+        if self.fpscr.fz.get() == 1 && is_denormal(x) {
+            self.raise(IDC);
+            signed(0, is_negative(x))
+        } else {
+            x
+        }
+    }
+
+    /// The result of an operation on `ops` if any of them is a NaN
+    fn nans<F: Float>(&mut self, ops: &[F]) -> Option<F> {
+        let nan = ops.iter().cloned().find(|&x| is_snan(x))
+            .or_else(|| ops.iter().cloned().find(|&x| is_nan(x)))?;
+        if is_snan(nan) {
+            self.raise(IOC);
+        }
+        Some(self.nan_result(nan))
+    }
+
+    fn nan_result<F: Float>(&self, nan: F) -> F {
+        if self.fpscr.dn.get() == 1 {
+            default_nan()
+        } else {
+            F::from_raw(nan.to_raw() | F::QUIET)
+        }
+    }
+
+    fn invalid<F: Float>(&mut self) -> F {
+        self.raise(IOC);
+        default_nan()
+    }
+
+    /// Applies the rounding mode to `r`, the round-to-nearest result of an operation on
+    /// finite operands, given how the exact result compares to it
+    fn round<F: Float>(&mut self, r: F, exact: Ordering) -> F {
+        let mode = self.rounding();
+        if is_inf(r) {
+            self.raise(OFC | IXC);
+            let negative = is_negative(r);
+            let to_max = match mode {
+                Rounding::Nearest => false,
+                Rounding::PlusInf => negative,
+                Rounding::MinusInf => !negative,
+                Rounding::Zero => true
+            };
+            return if to_max { signed(F::EXP - 1, negative) } else { r }
+        }
+
+        let mut res = r;
+        if exact != Ordering::Equal {
+            self.raise(IXC);
+            let up = exact == Ordering::Greater;
+            let stepped = match mode {
+                Rounding::Nearest => false,
+                Rounding::PlusInf => up,
+                Rounding::MinusInf => !up,
+                Rounding::Zero => !is_zero(r) && is_negative(r) == up
+            };
+            if stepped {
+                res = step(r, up);
+                if is_inf(res) {
+                    self.raise(OFC);
+                }
+            }
+        }
+
+        let tiny = is_denormal(res) || (is_zero(res) && exact != Ordering::Equal);
+        if tiny && self.fpscr.fz.get() == 1 {
+            self.raise(UFC);
+            return signed(0, is_negative(res))
+        } else if tiny && exact != Ordering::Equal {
+            self.raise(UFC);
+        }
+        res
+    }
+
+    pub fn add<F: Float>(&mut self, a: F, b: F) -> F {
+        let (a, b) = (self.operand(a), self.operand(b));
+        if let Some(nan) = self.nans(&[a, b]) {
+            return nan
+        }
+        if is_inf(a) && is_inf(b) && is_negative(a) != is_negative(b) {
+            return self.invalid()
+        }
+
+        let r = a + b;
+        if is_inf(a) || is_inf(b) {
+            return r
+        }
+        if is_zero(r) {
+            // Exact zero sums are only negative when rounding towards minus infinity
+            let negative = match self.rounding() {
+                Rounding::MinusInf => is_negative(a) || is_negative(b),
+                _ => is_negative(a) && is_negative(b)
+            };
+            return signed(0, negative)
+        }
+
+        // Error-free transformation (TwoSum) gives the exact rounding error
+        let b_virtual = r - a;
+        let err = (a - (r - b_virtual)) + (b - b_virtual);
+        self.round(r, compare_zero(err))
+    }
+
+    pub fn sub<F: Float>(&mut self, a: F, b: F) -> F {
+        if let Some(nan) = self.nans(&[a, b]) {
+            return nan
+        }
+        self.add(a, -b)
+    }
+
+    pub fn mul<F: Float>(&mut self, a: F, b: F) -> F {
+        let (a, b) = (self.operand(a), self.operand(b));
+        if let Some(nan) = self.nans(&[a, b]) {
+            return nan
+        }
+        if (is_inf(a) && is_zero(b)) || (is_zero(a) && is_inf(b)) {
+            return self.invalid()
+        }
+
+        let r = a * b;
+        if is_inf(a) || is_inf(b) {
+            return r
+        }
+        let ((ma, ea), (mb, eb)) = (decompose(a), decompose(b));
+        self.round(r, compare_exact(ma * mb, ea + eb, r))
+    }
+
+    pub fn div<F: Float>(&mut self, a: F, b: F) -> F {
+        let (a, b) = (self.operand(a), self.operand(b));
+        if let Some(nan) = self.nans(&[a, b]) {
+            return nan
+        }
+        if (is_inf(a) && is_inf(b)) || (is_zero(a) && is_zero(b)) {
+            return self.invalid()
+        }
+
+        let r = a / b;
+        if is_zero(b) && !is_inf(a) {
+            self.raise(DZC);
+            return r
+        }
+        if is_inf(a) || is_inf(b) {
+            return r
+        }
+        // |a / b| compares to |r| as |a| does to |r * b|
+        let ((ma, ea), (mb, eb), (mr, er)) = (decompose(a), decompose(b), decompose(r));
+        let ord = compare_scaled(ma, ea, mr * mb, er + eb);
+        self.round(r, if is_negative(r) { ord.reverse() } else { ord })
+    }
+
+    pub fn sqrt<F: Float>(&mut self, a: F) -> F {
+        let a = self.operand(a);
+        if let Some(nan) = self.nans(&[a]) {
+            return nan
+        }
+        if is_negative(a) && !is_zero(a) {
+            return self.invalid()
+        }
+
+        let r = a.square_root();
+        if is_inf(a) || is_zero(a) {
+            return r
+        }
+        let ((ma, ea), (mr, er)) = (decompose(a), decompose(r));
+        self.round(r, compare_scaled(ma, ea, mr * mr, er * 2))
+    }
+
+    /// Converts between precisions
+    pub fn convert<F: Float, T: Float>(&mut self, x: F) -> T {
+        let x = self.operand(x);
+        if is_nan(x) {
+            if is_snan(x) {
+                self.raise(IOC);
+            }
+            return self.nan_result(T::from_f64(x.to_f64()))
+        }
+
+        let wide = x.to_f64();
+        let r = T::from_f64(wide);
+        if is_inf(x) {
+            return r
+        }
+        self.round(r, wide.partial_cmp(&r.to_f64()).unwrap_or(Ordering::Equal))
+    }
+
+    pub fn from_int<T: Float>(&mut self, val: u32, signed: bool) -> T {
+        let wide = if signed { val as i32 as f64 } else { val as f64 };
+        let r = T::from_f64(wide);
+        self.round(r, wide.partial_cmp(&r.to_f64()).unwrap_or(Ordering::Equal))
+    }
+
+    /// Converts to a saturated integer, rounding towards zero if `round_zero` is set and
+    /// according to the FPSCR otherwise
+    pub fn to_int<F: Float>(&mut self, x: F, signed: bool, round_zero: bool) -> u32 {
+        let x = self.operand(x);
+        if is_nan(x) {
+            self.raise(IOC);
+            return 0
+        }
+
+        let val = x.to_f64();
+        let mode = if round_zero { Rounding::Zero } else { self.rounding() };
+        let rounded = match mode {
+            Rounding::Nearest => round_even(val),
+            Rounding::PlusInf => val.ceil(),
+            Rounding::MinusInf => val.floor(),
+            Rounding::Zero => val.trunc()
+        };
+
+        let (min, max) = if signed {
+            (i32::min_value() as f64, i32::max_value() as f64)
+        } else {
+            (0.0, u32::max_value() as f64)
+        };
+        if rounded < min || rounded > max {
+            self.raise(IOC);
+            let clamped = if rounded < min { min } else { max };
+            return if signed { clamped as i32 as u32 } else { clamped as u32 }
+        }
+        if rounded != val {
+            self.raise(IXC);
+        }
+        if signed { rounded as i32 as u32 } else { rounded as u32 }
+    }
+
+    /// Compares two values, returning the NZCV flags; unordered comparisons are invalid
+    /// for quiet NaNs too if `signal_nans` is set
+    pub fn compare<F: Float>(&mut self, a: F, b: F, signal_nans: bool) -> u32 {
+        let (a, b) = (self.operand(a), self.operand(b));
+        if is_nan(a) || is_nan(b) {
+            if signal_nans || is_snan(a) || is_snan(b) {
+                self.raise(IOC);
+            }
+            return 0b0011
+        }
+        match a.partial_cmp(&b) {
+            Some(Ordering::Equal) => 0b0110,
+            Some(Ordering::Less) => 0b1000,
+            _ => 0b0010
+        }
+    }
+
+    pub fn neg<F: Float>(&self, x: F) -> F {
+        F::from_raw(x.to_raw() ^ F::SIGN)
+    }
+
+    pub fn abs<F: Float>(&self, x: F) -> F {
+        F::from_raw(x.to_raw() & !F::SIGN)
+    }
+}
+
+impl SaveState for Vfp {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        for reg in self.regs.iter() {
+            w.put_u32(*reg);
+        }
+        w.put_u32(self.fpscr.val);
+        w.put_u32(self.fpexc);
+        w.put_u32(self.fpinst);
+        w.put_u32(self.fpinst2);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        for reg in self.regs.iter_mut() {
+            *reg = r.get_u32()?;
+        }
+        self.fpscr.val = r.get_u32()?;
+        self.fpexc = r.get_u32()?;
+        self.fpinst = r.get_u32()?;
+        self.fpinst2 = r.get_u32()?;
+        Ok(())
+    }
+}
+
+/// Register transfers reaching the coprocessor interface are FMXR and FMRX; the others
+/// are decoded as VFP instructions
+impl<V: Version> Coprocessor<V> for Vfp {
+    fn allows_access(&self, cpreg1: usize, op1: usize, privileged: bool) -> bool {
+        if op1 != 7 {
+            return false
+        }
+        match cpreg1 {
+            REG_FPSID => true,
+            REG_FPSCR => self.enabled(),
+            REG_FPEXC | REG_FPINST | REG_FPINST2 => privileged,
+            _ => false
+        }
+    }
+
+    fn move_in(&mut self, cpreg1: usize, _cpreg2: usize, _op1: usize, _op2: usize, val: u32) -> Option<CpEffect<V>> {
+        match cpreg1 {
+            REG_FPSID => {}
+            REG_FPSCR => self.fpscr.val = val & FPSCR_MASK,
+            REG_FPEXC => self.fpexc = val,
+            REG_FPINST => self.fpinst = val,
+            REG_FPINST2 => self.fpinst2 = val,
+            _ => return None
+        }
+        Some(Box::new(|_| {}))
+    }
+
+    fn move_out(&mut self, cpreg1: usize, _cpreg2: usize, _op1: usize, _op2: usize) -> Option<u32> {
+        match cpreg1 {
+            REG_FPSID => Some(FPSID),
+            REG_FPSCR => Some(self.fpscr.val),
+            REG_FPEXC => Some(self.fpexc),
+            REG_FPINST => Some(self.fpinst),
+            REG_FPINST2 => Some(self.fpinst2),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn with_fpscr(fpscr: u32) -> Vfp {
+        let mut vfp = Vfp::new();
+        vfp.fpscr.val = fpscr;
+        vfp
+    }
+
+    const RP: u32 = 0b01 << 22;
+    const RM: u32 = 0b10 << 22;
+    const RZ: u32 = 0b11 << 22;
+    const FZ: u32 = 1 << 24;
+    const DN: u32 = 1 << 25;
+
+    #[test]
+    fn rounding_modes() {
+        let third = |fpscr| {
+            let mut vfp = with_fpscr(fpscr);
+            let res: f32 = vfp.div(1.0, 3.0);
+            assert_eq!(vfp.fpscr.val & IXC, IXC);
+            res.to_bits()
+        };
+        assert_eq!(third(0), 0x3EAAAAAB);
+        assert_eq!(third(RP), 0x3EAAAAAB);
+        assert_eq!(third(RM), 0x3EAAAAAA);
+        assert_eq!(third(RZ), 0x3EAAAAAA);
+
+        let mut vfp = with_fpscr(RM);
+        let res: f64 = vfp.add(1.0, 1e-30);
+        assert_eq!(res, 1.0);
+        vfp.fpscr.val = RP;
+        let res: f64 = vfp.add(1.0, 1e-30);
+        assert_eq!(res.to_bits(), 1f64.to_bits() + 1);
+
+        // x - x is -0 only when rounding towards minus infinity
+        let res: f32 = vfp.sub(2.5, 2.5);
+        assert_eq!(res.to_bits(), 0);
+        vfp.fpscr.val = RM;
+        let res: f32 = vfp.sub(2.5, 2.5);
+        assert_eq!(res.to_bits(), 0x80000000);
+
+        let mut vfp = with_fpscr(0);
+        let res: f64 = vfp.sqrt(4.0);
+        assert_eq!(res, 2.0);
+        assert_eq!(vfp.fpscr.val & IXC, 0);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut vfp = with_fpscr(RZ);
+        let res: f32 = vfp.mul(3e38, 10.0);
+        assert_eq!(res, ::std::f32::MAX);
+        assert_eq!(vfp.fpscr.val & (OFC | IXC), OFC | IXC);
+
+        vfp.fpscr.val = 0;
+        let res: f32 = vfp.mul(3e38, -10.0);
+        assert_eq!(res, ::std::f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn special_values() {
+        let mut vfp = with_fpscr(0);
+        let res: f32 = vfp.div(1.0, 0.0);
+        assert_eq!(res, ::std::f32::INFINITY);
+        assert_eq!(vfp.fpscr.val, DZC);
+
+        let mut vfp = with_fpscr(0);
+        let snan = f32::from_bits(0x7F800001);
+        let res: f32 = vfp.add(1.0, snan);
+        assert_eq!(res.to_bits(), 0x7FC00001);
+        assert_eq!(vfp.fpscr.val, IOC);
+
+        let mut vfp = with_fpscr(DN);
+        let res: f32 = vfp.add(1.0, f32::from_bits(0x7FC00005));
+        assert_eq!(res.to_bits(), 0x7FC00000);
+        assert_eq!(vfp.fpscr.val, DN);
+
+        let mut vfp = with_fpscr(0);
+        let res: f64 = vfp.sqrt(-1.0);
+        assert!(res.is_nan());
+        assert_eq!(vfp.fpscr.val, IOC);
+    }
+
+    #[test]
+    fn flush_to_zero() {
+        let denormal = f32::from_bits(0x00000010);
+        let mut vfp = with_fpscr(FZ);
+        let res: f32 = vfp.add(denormal, 0.0);
+        assert_eq!(res.to_bits(), 0);
+        assert_eq!(vfp.fpscr.val & IDC, IDC);
+
+        let mut vfp = with_fpscr(FZ);
+        let res: f32 = vfp.mul(1e-20, 1e-20);
+        assert_eq!(res.to_bits(), 0);
+        assert_eq!(vfp.fpscr.val & UFC, UFC);
+
+        let mut vfp = with_fpscr(0);
+        let res: f32 = vfp.mul(1e-20, 1e-20);
+        assert!(is_denormal(res));
+        assert_eq!(vfp.fpscr.val & (UFC | IXC), UFC | IXC);
+    }
+
+    #[test]
+    fn conversions() {
+        let mut vfp = with_fpscr(0);
+        assert_eq!(vfp.to_int(2.5f32, true, false), 2);
+        assert_eq!(vfp.to_int(3.5f32, true, false), 4);
+        assert_eq!(vfp.to_int(-2.7f64, true, true), -2i32 as u32);
+        assert_eq!(vfp.fpscr.val, IXC);
+
+        let mut vfp = with_fpscr(RM);
+        assert_eq!(vfp.to_int(-0.5f64, true, false), -1i32 as u32);
+        assert_eq!(vfp.to_int(-0.5f64, false, false), 0);
+        assert_eq!(vfp.to_int(5e9f64, false, true), u32::max_value());
+        assert_eq!(vfp.fpscr.val & IOC, IOC);
+
+        let mut vfp = with_fpscr(RZ);
+        let res: f32 = vfp.from_int(0xFFFFFFFF, false);
+        assert_eq!(res.to_bits(), 0x4F7FFFFF);
+        let res: f64 = vfp.from_int(0xFFFFFFFF, true);
+        assert_eq!(res, -1.0);
+
+        let res: f32 = vfp.convert(1.0f64 / 3.0);
+        assert_eq!(res.to_bits(), 0x3EAAAAAA);
+        let res: f64 = vfp.convert(0.1f32);
+        assert_eq!(res, 0.1f32 as f64);
+    }
+
+    #[test]
+    fn comparisons() {
+        let mut vfp = with_fpscr(0);
+        assert_eq!(vfp.compare(1.0f32, 2.0, false), 0b1000);
+        assert_eq!(vfp.compare(2.0f64, 2.0, false), 0b0110);
+        assert_eq!(vfp.compare(3.0f32, 2.0, false), 0b0010);
+        assert_eq!(vfp.compare(::std::f32::NAN, 2.0, false), 0b0011);
+        assert_eq!(vfp.fpscr.val, 0);
+        assert_eq!(vfp.compare(::std::f32::NAN, 2.0, true), 0b0011);
+        assert_eq!(vfp.fpscr.val, IOC);
+    }
+}
@@ -1,16 +1,18 @@
 use clock;
 use cpu;
 use cpu::InstrStatus;
-use cpu::caches;
+use cpu::breakpoints::{Breakpoints, WatchKind};
+use cpu::caches::{self, Ops};
 use cpu::coproc;
 use cpu::irq;
 use cpu::regs::{GpRegs, Psr};
+use cpu::timing;
+use dbgcore::{CpuMut, HwCtx};
 use mem;
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 use utils::cache::TinyCache;
 
-use std::collections::HashSet;
-
 use arraydeque::{ArrayDeque, Wrapping};
 
 #[derive(Copy, Clone, Debug)]
@@ -47,9 +49,16 @@ pub trait Version: 'static + ::std::fmt::Debug {
         use std::any::TypeId;
         TypeId::of::<T>() == TypeId::of::<Self>()
     }
+
+    /// Gives the debugger's view of a CPU of this version
+    fn dbg_mut(cpu: &mut Cpu<Self>) -> CpuMut<'_> where Self: Sized;
+}
+impl Version for v5 {
+    fn dbg_mut(cpu: &mut Cpu<v5>) -> CpuMut<'_> { CpuMut::v5(cpu) }
+}
+impl Version for v6 {
+    fn dbg_mut(cpu: &mut Cpu<v6>) -> CpuMut<'_> { CpuMut::v6(cpu) }
 }
-impl Version for v5 {}
-impl Version for v6 {}
 
 pub struct Cpu<V: Version> {
     pub regs: GpRegs,
@@ -61,17 +70,35 @@ pub struct Cpu<V: Version> {
     pub spsr_und: Psr::Bf,
 
     coproc_syscnt: coproc::SysControl,
+    pub(crate) coproc_vfp: coproc::Vfp,
     pub mpu: caches::MemMgr,
 
     irq_line: irq::IrqLine,
+    pub(crate) fiq_line: irq::FiqLine,
     sys_clk: clock::SysClock,
 
-    pub(crate) thumb_decode_cache: TinyCache<cpu::thumb::InstFn<V>, ()>,
-    pub(crate) arm_decode_cache: TinyCache<cpu::arm::InstFn<V>, ()>,
+    /// Decoded instructions along with their cost in cycles
+    pub(crate) thumb_decode_cache: TinyCache<(cpu::thumb::InstFn<V>, u8), ()>,
+    pub(crate) arm_decode_cache: TinyCache<(cpu::arm::InstFn<V>, u8), ()>,
 
     pub last_instructions: ArrayDeque<[u32; 1024], Wrapping>,
+    /// Instructions executed, exceptions entered and idle steps taken by `run`, used as
+    /// the position of this core in record mode
+    pub steps: u64,
 
-    pub breakpoints: HashSet<u32>, // addr, is_triggered
+    pub breakpoints: Breakpoints,
+    /// Stop in the debugger on undefined instructions instead of taking the exception
+    pub halt_on_undefined: bool,
+    pub on_bus_error: BusErrorMode,
+    pending_break: Option<BreakReason>,
+
+    /// Local exclusive monitor: the address tagged by the last LDREX, if still open
+    exclusive_tag: Option<u32>,
+
+    /// Asleep after a wait-for-interrupt, until an IRQ or FIQ comes in
+    waiting_for_irq: bool,
+    poll_loop: PollLoop,
+    abort_undo: AbortUndo,
 
     pub(crate) _version: V
 }
@@ -80,12 +107,91 @@ pub struct Cpu<V: Version> {
 pub enum BreakReason {
     LimitReached,
     Breakpoint,
+    Watchpoint { addr: u32, kind: WatchKind },
     Trapped,
-    WFI
+    WFI,
+    /// Address and encoding of an undefined instruction, with `halt_on_undefined` set
+    UndefinedInstruction(u32, u32),
+    /// Access to unmapped memory by the instruction at `pc`, with `BusErrorMode::Halt`
+    BusError { pc: u32, addr: u32, access: caches::Access }
+}
+
+/// What the CPU does about accesses to unmapped memory
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BusErrorMode {
+    /// Take an external abort
+    Abort,
+    /// Let reads return zeroes and drop writes
+    OpenBus,
+    /// Same as `OpenBus`, but stop in the debugger after the instruction
+    Halt
 }
 
 const ASYNC_IRQ_CYCLE_MASK: u64 = 0xFFF;
 
+/// Longest loop, in steps, that can be taken for a polling loop
+const POLL_LOOP_MAX_STEPS: u64 = 32;
+
+/// Times a loop has to come around unchanged before the clock skips ahead
+const POLL_LOOP_REPEATS: u32 = 2;
+
+/// The last loop entered through a backward branch, to spot cores polling memory or IO
+/// registers for something only an event can change. Such a loop stores nothing, reads
+/// nothing derived from the clock, and finishes each pass with the registers it started
+/// with, so the core may as well skip ahead to the next event.
+#[derive(Clone, Default)]
+struct PollLoop {
+    /// Branch target at the top of the loop
+    start: u32,
+    regs: [u32; 16],
+    cpsr: u32,
+    /// Value of `steps` when the loop last came around
+    steps: u64,
+    /// Consecutive passes that changed nothing
+    repeats: u32,
+}
+
+/// Registers the instruction being executed overwrote, saved by the instructions that
+/// access memory so that an aborted access leaves them as they were
+struct AbortUndo {
+    /// Value of `steps` while that instruction ran
+    step: u64,
+    /// Registers saved so far
+    mask: u32,
+    regs: [u32; 16],
+    cpsr: u32,
+}
+
+impl AbortUndo {
+    fn new() -> AbortUndo {
+        AbortUndo { step: !0, mask: 0, regs: [0; 16], cpsr: 0 }
+    }
+}
+
+impl SaveState for PollLoop {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_u32(self.start);
+        for reg in self.regs.iter() {
+            w.put_u32(*reg);
+        }
+        w.put_u32(self.cpsr);
+        w.put_u64(self.steps);
+        w.put_u32(self.repeats);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.start = r.get_u32()?;
+        for reg in self.regs.iter_mut() {
+            *reg = r.get_u32()?;
+        }
+        self.cpsr = r.get_u32()?;
+        self.steps = r.get_u64()?;
+        self.repeats = r.get_u32()?;
+        Ok(())
+    }
+}
+
 impl<V: Version> Cpu<V> {
     pub fn new(version: V, memory: mem::MemController, irq_line: irq::IrqLine, clk: clock::SysClock) -> Cpu<V> {
         Cpu {
@@ -98,21 +204,31 @@ impl<V: Version> Cpu<V> {
             spsr_und: Psr::new(0),
 
             coproc_syscnt: coproc::SysControl::new(),
+            coproc_vfp: coproc::Vfp::new(),
             mpu: caches::MemMgr::new::<V>(memory),
 
             irq_line: irq_line,
+            fiq_line: irq::FiqLine::new(),
             sys_clk: clk,
  
             thumb_decode_cache: TinyCache::new(
-                |_, k| cpu::thumb::decode(k as u16), |_, _, _| {}
+                |_, k| (cpu::thumb::decode(k as u16), timing::thumb_cycles(k as u16)), |_, _, _| {}
             ),
             arm_decode_cache: TinyCache::new(
-                |_, k| cpu::arm::decode(k), |_, _, _| {}
+                |_, k| (cpu::arm::decode(k), timing::arm_cycles(k)), |_, _, _| {}
             ),
 
             last_instructions: ArrayDeque::new(),
+            steps: 0,
 
-            breakpoints: HashSet::new(),
+            breakpoints: Breakpoints::new(),
+            halt_on_undefined: false,
+            on_bus_error: BusErrorMode::Abort,
+            pending_break: None,
+            exclusive_tag: None,
+            waiting_for_irq: false,
+            poll_loop: PollLoop::default(),
+            abort_undo: AbortUndo::new(),
             _version: version
         }
     }
@@ -123,6 +239,8 @@ impl<V: Version> Cpu<V> {
         self.cpsr.thumb_bit.set(0b0);
         self.cpsr.disable_fiq_bit.set(0b1);
         self.cpsr.disable_irq_bit.set(0b1);
+        self.clear_exclusive();
+        self.waiting_for_irq = false;
 
         self.regs[15] = entry + Self::pc_offset(0);
     }
@@ -147,24 +265,88 @@ impl<V: Version> Cpu<V> {
         assert_eq!(self.regs[15] & (Self::instr_size(thumb_bit) - 1), 0);
     }
 
-    pub fn get_coprocessor(&mut self, cp_index: usize) -> &mut dyn coproc::Coprocessor<V> {
+    /// Returns None if this CPU has no such coprocessor
+    pub fn get_coprocessor(&mut self, cp_index: usize) -> Option<&mut dyn coproc::Coprocessor<V>> {
         match cp_index {
-            15 => &mut self.coproc_syscnt,
-            _ => panic!("Tried to access unknown CP{}", cp_index),
+            10 | 11 if V::is::<v6>() => Some(&mut self.coproc_vfp),
+            15 => Some(&mut self.coproc_syscnt),
+            _ => None,
         }
     }
 
-    pub fn get_current_spsr(&mut self) -> &mut Psr::Bf {
-        match Mode::from_num(self.cpsr.mode.get()) {
-            Mode::Fiq => &mut self.spsr_fiq,
-            Mode::Irq => &mut self.spsr_irq,
-            Mode::Svc => &mut self.spsr_svc,
-            Mode::Abt => &mut self.spsr_abt,
-            Mode::Und => &mut self.spsr_und,
-            _ => panic!("Attempted to access non-existent SPSR!"),
+    /// Cycles counted by this core's system clock
+    pub fn cycles(&self) -> u64 {
+        self.sys_clk.get()
+    }
+
+    /// Puts the core to sleep until an interrupt comes in, whether or not the CPSR masks it
+    pub fn wait_for_interrupt(&mut self) {
+        trace!("ARM{:?} waiting for interrupt", self._version);
+        self.waiting_for_irq = true;
+    }
+
+    pub fn is_waiting_for_irq(&self) -> bool {
+        self.waiting_for_irq
+    }
+
+    /// Sets the index this core reports in the MPCore CPU ID register
+    pub fn set_cpu_id(&mut self, id: u32) {
+        self.coproc_syscnt.set_cpu_id(id);
+    }
+
+    /// Whether coprocessor `cp_index` exists and the coprocessor access control register
+    /// lets the current mode use it
+    pub fn coproc_enabled(&self, cp_index: usize) -> bool {
+        match cp_index {
+            15 => true,
+            10 | 11 if V::is::<v6>() => {
+                let access = (self.coproc_syscnt.coproc_access() >> (cp_index * 2)) & 0b11;
+                match access {
+                    0b01 => self.cpsr.mode.get() != Mode::Usr as u32,
+                    0b11 => true,
+                    _ => false
+                }
+            }
+            _ => false
+        }
+    }
+
+    /// Takes the undefined instruction exception for the instruction being executed, or
+    /// leaves the PC on it and halts `run` when `halt_on_undefined` is set
+    pub fn undefined_instr(&mut self) -> InstrStatus {
+        let thumb_bit = self.cpsr.thumb_bit.get();
+        let addr = self.regs[15] - Self::pc_offset(thumb_bit);
+        if self.halt_on_undefined {
+            let opcode = if thumb_bit == 0 {
+                self.mpu.imem_read::<u32>(addr)
+            } else {
+                self.mpu.imem_read::<u16>(addr) as u32
+            };
+            self.pending_break = Some(BreakReason::UndefinedInstruction(addr, opcode));
+            return InstrStatus::Branched
+        }
+
+        trace!("Undefined instruction @ {:#X} on ARM{:?}", addr, self._version);
+        self.enter_exception(addr + Self::instr_size(thumb_bit), Mode::Und);
+        InstrStatus::Branched
+    }
+
+    pub fn get_spsr(&mut self, mode: Mode) -> Option<&mut Psr::Bf> {
+        match mode {
+            Mode::Fiq => Some(&mut self.spsr_fiq),
+            Mode::Irq => Some(&mut self.spsr_irq),
+            Mode::Svc => Some(&mut self.spsr_svc),
+            Mode::Abt => Some(&mut self.spsr_abt),
+            Mode::Und => Some(&mut self.spsr_und),
+            Mode::Usr | Mode::Sys => None,
         }
     }
 
+    pub fn get_current_spsr(&mut self) -> &mut Psr::Bf {
+        let mode = Mode::from_num(self.cpsr.mode.get());
+        self.get_spsr(mode).expect("Attempted to access non-existent SPSR!")
+    }
+
     pub fn spsr_make_current(&mut self) {
         self.cpsr = self.get_current_spsr().clone();
         self.regs.swap(cpu::Mode::from_num(self.cpsr.mode.get()));
@@ -178,66 +360,247 @@ impl<V: Version> Cpu<V> {
     }
 
     pub fn run(&mut self, num_instrs: u32) -> BreakReason {
+        self.run_until(num_instrs, !0)
+    }
+
+    /// Same as `run`, but also stops with `LimitReached` once `cycles()` reaches `end_cycle`.
+    ///
+    /// While waiting for an interrupt, each of the `num_instrs` steps skips the clock ahead
+    /// to the next scheduled event or `end_cycle`. With neither of them to wait for, only
+    /// the other threads can wake the core up, so it idles through the remaining steps and
+    /// stops with `WFI`.
+    pub fn run_until(&mut self, num_instrs: u32, end_cycle: u64) -> BreakReason {
         let mut irq_known_pending = false;
         let mut thumb_bit = self.cpsr.thumb_bit.get();
         self.check_alignment(thumb_bit);
 
-        for _ in 0..num_instrs {
+        for step in 0..num_instrs {
+            if self.sys_clk.get() >= end_cycle {
+                break
+            }
             let addr = self.regs[15] - Self::pc_offset(thumb_bit);
 
-            self.sys_clk.increment(8); // Probably speeds up time but w/e
-
             irq_known_pending |= self.irq_line.is_high_sync();
             if self.sys_clk.get() & ASYNC_IRQ_CYCLE_MASK != 0 {
                 // Amortize the cost of checking for async IRQs
                 irq_known_pending |= self.irq_line.is_high_async();
             }
 
+            if self.waiting_for_irq {
+                if self.irq_line.is_high() || self.fiq_line.is_high() {
+                    self.waiting_for_irq = false;
+                    irq_known_pending = true;
+                } else {
+                    let wake_cycle = self.sys_clk.sched.next_deadline().min(end_cycle);
+                    if wake_cycle == !0 {
+                        self.steps += (num_instrs - step) as u64;
+                        return BreakReason::WFI;
+                    }
+                    self.steps += 1;
+                    let now = self.sys_clk.get();
+                    self.sys_clk.increment(wake_cycle.saturating_sub(now));
+                    continue
+                }
+            }
+
+            if self.cpsr.disable_fiq_bit.get() == 0 && self.fiq_line.is_high() {
+                trace!("Entering FIQ for ARM{:?}!", self._version);
+                self.enter_exception(addr+4, Mode::Fiq);
+                thumb_bit = 0;
+                self.steps += 1;
+                self.sys_clk.increment(timing::EXCEPTION_CYCLES as u64);
+                continue
+            }
+
             if irq_known_pending && self.cpsr.disable_irq_bit.get() == 0 && self.irq_line.is_high() {
                 trace!("Entering exception for ARM{:?}!", self._version);
                 self.enter_exception(addr+4, Mode::Irq);
                 thumb_bit = 0;
                 irq_known_pending = false;
+                self.steps += 1;
+                self.sys_clk.increment(timing::EXCEPTION_CYCLES as u64);
                 continue
             }
 
-            if self.find_toggle_breakpoint(addr) {
+            if self.find_breakpoint(addr) {
                 return BreakReason::Breakpoint;
             }
 
             self.last_instructions.push_back(addr);
+            self.mpu.set_privileged(self.cpsr.mode.get() != Mode::Usr as u32);
 
-            let status = if thumb_bit == 0 {
+            let (status, mut cycles) = if thumb_bit == 0 {
                 cpu::arm::interpret_next(self, addr)
             } else {
                 cpu::thumb::interpret_next(self, addr)
             };
+            let mut branched = false;
             match status {
                 InstrStatus::InBlock => self.regs[15] += Self::instr_size(thumb_bit),
-                InstrStatus::Branched => thumb_bit = self.cpsr.thumb_bit.get(),
+                InstrStatus::Branched => {
+                    thumb_bit = self.cpsr.thumb_bit.get();
+                    branched = true;
+                }
+            }
+            let mut bus_error = None;
+            if let Some(fault) = self.mpu.take_fault() {
+                let external = fault.status == caches::FSR_EXTERNAL;
+                if external {
+                    bus_error = Some(fault);
+                }
+                if !external || self.on_bus_error == BusErrorMode::Abort {
+                    self.undo_for_abort();
+                    self.abort(addr, fault);
+                    thumb_bit = 0;
+                    branched = true;
+                }
+            }
+            if let Some(reason) = self.pending_break.take() {
+                return reason;
+            }
+            self.steps += 1;
+
+            if branched {
+                cycles += timing::BRANCH_PENALTY;
+            }
+            cycles += self.mpu.main_mem().take_wait_cycles();
+            self.sys_clk.increment(cycles as u64);
+
+            if let Some(reason) = bus_error.and_then(|fault| self.bus_error(addr, fault)) {
+                return reason;
+            }
+
+            if let Some((addr, kind)) = self.mpu.take_watchpoint_hit() {
+                return BreakReason::Watchpoint { addr: addr, kind: kind };
+            }
+
+            let looped = branched && self.regs[15] - Self::pc_offset(thumb_bit) <= addr;
+            if looped && self.in_poll_loop(thumb_bit) {
+                let wake_cycle = self.sys_clk.sched.next_deadline().min(end_cycle);
+                let now = self.sys_clk.get();
+                if wake_cycle != !0 && wake_cycle > now {
+                    trace!("ARM{:?} polling @ 0x{:X}, skipping to cycle {}", self._version, addr, wake_cycle);
+                    self.sys_clk.increment(wake_cycle - now);
+                }
+                self.poll_loop.repeats = 0;
             }
         }
 
         BreakReason::LimitReached
     }
 
+    /// Checks the loop just entered by a backward branch against the last one, returning
+    /// whether it keeps coming around without changing anything
+    fn in_poll_loop(&mut self, thumb_bit: u32) -> bool {
+        let start = self.regs[15] - Self::pc_offset(thumb_bit);
+        let mut regs = [0u32; 16];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            *reg = self.regs[i];
+        }
+        // Both flags have to be taken every time, to only cover the last pass
+        let stored = self.mpu.main_mem().take_stored();
+        let observed = self.sys_clk.sched.take_observed();
+
+        let poll_loop = &mut self.poll_loop;
+        let unchanged = !stored && !observed
+            && poll_loop.start == start
+            && self.steps - poll_loop.steps <= POLL_LOOP_MAX_STEPS
+            && poll_loop.regs == regs
+            && poll_loop.cpsr == self.cpsr.val;
+        poll_loop.repeats = if unchanged { poll_loop.repeats + 1 } else { 0 };
+        poll_loop.start = start;
+        poll_loop.regs = regs;
+        poll_loop.cpsr = self.cpsr.val;
+        poll_loop.steps = self.steps;
+        poll_loop.repeats >= POLL_LOOP_REPEATS
+    }
+
     pub fn enter_exception(&mut self, return_loc: u32, mode: Mode) {
-        let r14_exc = return_loc;
-        let spsr_exc = self.cpsr;
+        let vector_addr = Self::exception_vector(mode);
+        self.enter_exception_at(return_loc, mode, vector_addr);
+    }
 
-        self.regs.swap(mode);
-        self.cpsr.mode.set(mode as u32);
+    /// Takes a prefetch abort, which enters abort mode like a data abort but through
+    /// the vector just before it
+    pub fn enter_prefetch_abort(&mut self, return_loc: u32) {
+        let vector_addr = Self::exception_vector(Mode::Abt) - 8;
+        self.enter_exception_at(return_loc, Mode::Abt, vector_addr);
+    }
 
-        self.regs[14] = r14_exc;
-        *self.get_current_spsr() = spsr_exc;
-        self.cpsr.thumb_bit.set(0);
-        self.cpsr.disable_irq_bit.set(1);
+    /// Saves the registers in `mask`, along with the CPSR, before the instruction being
+    /// executed overwrites them, so that they can be put back if one of its data accesses
+    /// gets aborted
+    #[inline]
+    pub fn save_for_abort(&mut self, mask: u32) {
+        let undo = &mut self.abort_undo;
+        if undo.step != self.steps {
+            undo.step = self.steps;
+            undo.mask = 0;
+            undo.cpsr = self.cpsr.val;
+        }
+        let mut new = mask & !undo.mask;
+        undo.mask |= mask;
+        while new != 0 {
+            let reg = new.trailing_zeros() as usize;
+            undo.regs[reg] = self.regs[reg];
+            new &= new - 1;
+        }
+    }
+
+    /// Whether the instruction being executed made an access that will be aborted, after
+    /// which it should leave the registers alone
+    pub fn access_aborted(&self) -> bool {
+        match self.mpu.pending_fault() {
+            Some(fault) => fault.status != caches::FSR_EXTERNAL || self.on_bus_error == BusErrorMode::Abort,
+            None => false
+        }
+    }
+
+    /// Puts back what the instruction being executed saved with `save_for_abort`
+    fn undo_for_abort(&mut self) {
+        let undo = &self.abort_undo;
+        if undo.step != self.steps {
+            return
+        }
+        for reg in 0..16 {
+            if undo.mask & (1 << reg) != 0 {
+                self.regs[reg] = undo.regs[reg];
+            }
+        }
+        self.cpsr.val = undo.cpsr;
+    }
+
+    /// Takes the abort for an access refused during the instruction at `addr`
+    fn abort(&mut self, addr: u32, fault: caches::Fault) {
+        trace!("{:?} abort @ 0x{:X} accessing 0x{:X} on ARM{:?}", fault.access, addr, fault.addr, self._version);
+        self.coproc_syscnt.record_fault::<V>(&fault);
+        match fault.access {
+            caches::Access::Fetch => self.enter_prefetch_abort(addr + 4),
+            caches::Access::Read | caches::Access::Write => self.enter_exception(addr + 8, Mode::Abt)
+        }
+    }
+
+    /// Reports an access to unmapped memory by the instruction at `addr`, returning the
+    /// reason to halt with if `on_bus_error` asks for it
+    fn bus_error(&self, addr: u32, fault: caches::Fault) -> Option<BreakReason> {
+        if self.on_bus_error == BusErrorMode::Halt {
+            return Some(BreakReason::BusError { pc: addr, addr: fault.addr, access: fault.access })
+        }
+        warn!("{:?} of unmapped address 0x{:X} @ 0x{:X} on ARM{:?}", fault.access, fault.addr, addr, self._version);
+        None
+    }
+
+    /// Data fault status, instruction fault status and fault address of the last aborts
+    pub fn fault_regs(&self) -> (u32, u32, u32) {
+        self.coproc_syscnt.fault_regs()
+    }
 
-        let vector_addr = if V::is::<v5>() {
+    fn exception_vector(mode: Mode) -> u32 {
+        if V::is::<v5>() {
             // These vectors look like 0x080000XX because that's where the bootrom redirects them
             match mode {
                 Mode::Irq => 0x08000000,
-                Mode::Fiq => unimplemented!(),
+                Mode::Fiq => 0x08000008,
                 Mode::Svc => 0x08000010,
                 Mode::Und => 0x08000018,
                 Mode::Abt => 0x08000028,
@@ -253,11 +616,539 @@ impl<V: Version> Cpu<V> {
                 Mode::Abt => 0x1FFFFFC8,
                 Mode::Sys | Mode::Usr => panic!("No exception associated with {:?}", mode)
             }
-        };
+        }
+    }
+
+    fn enter_exception_at(&mut self, return_loc: u32, mode: Mode, vector_addr: u32) {
+        let r14_exc = return_loc;
+        let spsr_exc = self.cpsr;
+
+        self.regs.swap(mode);
+        self.cpsr.mode.set(mode as u32);
+
+        self.regs[14] = r14_exc;
+        *self.get_current_spsr() = spsr_exc;
+        self.cpsr.thumb_bit.set(0);
+        self.cpsr.disable_irq_bit.set(1);
+        if let Mode::Fiq = mode {
+            self.cpsr.disable_fiq_bit.set(1);
+        }
+        self.clear_exclusive();
+
         self.branch(vector_addr);
     }
 
-    pub fn find_toggle_breakpoint(&mut self, addr: u32) -> bool {
-        !self.breakpoints.is_empty() && self.breakpoints.remove(&addr)
+    /// Opens an exclusive access to `addr` in the local and global monitors, for LDREX.
+    /// Both monitors tag the physical address.
+    pub fn mark_exclusive(&mut self, addr: u32) {
+        match self.mpu.physical_addr(addr) {
+            Some(paddr) => self.mark_physical_exclusive(paddr),
+            None => self.clear_exclusive()
+        }
+    }
+
+    fn mark_physical_exclusive(&mut self, paddr: u32) {
+        self.exclusive_tag = Some(paddr);
+        self.mpu.main_mem().monitor.mark(paddr);
+    }
+
+    /// Closes the exclusive access for a STREX to `addr`, returning whether the store
+    /// may go ahead
+    pub fn check_exclusive(&mut self, addr: u32) -> bool {
+        let tag = self.exclusive_tag.take();
+        let paddr = match self.mpu.physical_addr(addr) {
+            Some(paddr) => paddr,
+            None => {
+                self.mpu.main_mem().monitor.clear();
+                return false
+            }
+        };
+        let global = self.mpu.main_mem().monitor.check_and_clear(paddr);
+        tag == Some(paddr) && global
+    }
+
+    /// Drops any open exclusive access, as done by CLREX and exception entry
+    pub fn clear_exclusive(&mut self) {
+        self.exclusive_tag = None;
+        self.mpu.main_mem().monitor.clear();
+    }
+
+    #[inline(always)]
+    pub fn find_breakpoint(&mut self, addr: u32) -> bool {
+        !self.breakpoints.is_empty() && self.check_breakpoint(addr)
+    }
+
+    fn check_breakpoint(&mut self, addr: u32) -> bool {
+        let condition = match self.breakpoints.armed_at(addr) {
+            Some(bkpt) => bkpt.condition.clone(),
+            None => return false
+        };
+        if let Some(condition) = condition {
+            match V::dbg_mut(self).eval_condition(&condition) {
+                Ok(false) => return false,
+                Ok(true) => {}
+                Err(e) => warn!("Halting on breakpoint @ 0x{:X}, could not evaluate `{}`: {}",
+                                addr, condition, e),
+            }
+        }
+        self.breakpoints.hit(addr)
+    }
+}
+
+/// Breakpoints and the instruction history are debugger state, and are not saved
+impl<V: Version> SaveState for Cpu<V> {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        self.regs.save_state(w)?;
+        w.put_u32(self.cpsr.val);
+        w.put_u32(self.spsr_fiq.val);
+        w.put_u32(self.spsr_irq.val);
+        w.put_u32(self.spsr_svc.val);
+        w.put_u32(self.spsr_abt.val);
+        w.put_u32(self.spsr_und.val);
+
+        w.put_bool(self.exclusive_tag.is_some());
+        w.put_u32(self.exclusive_tag.unwrap_or(0));
+        w.put_bool(self.fiq_line.is_high());
+        w.put_bool(self.waiting_for_irq);
+        self.poll_loop.save_state(w)?;
+        w.put_bool(self.mpu.main_mem().stored_pending());
+
+        self.coproc_syscnt.save_state(w)?;
+        self.coproc_vfp.save_state(w)?;
+        self.mpu.save_state(w)?;
+        self.sys_clk.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.regs.load_state(r)?;
+        self.cpsr.val = r.get_u32()?;
+        self.spsr_fiq.val = r.get_u32()?;
+        self.spsr_irq.val = r.get_u32()?;
+        self.spsr_svc.val = r.get_u32()?;
+        self.spsr_abt.val = r.get_u32()?;
+        self.spsr_und.val = r.get_u32()?;
+
+        let has_tag = r.get_bool()?;
+        let tag = r.get_u32()?;
+        if has_tag {
+            self.mark_physical_exclusive(tag);
+        } else {
+            self.clear_exclusive();
+        }
+        self.fiq_line.set_high(r.get_bool()?);
+        self.waiting_for_irq = r.get_bool()?;
+        self.poll_loop.load_state(r)?;
+        self.abort_undo = AbortUndo::new();
+        self.mpu.main_mem().take_stored();
+        if r.get_bool()? {
+            self.mpu.main_mem().mark_stored();
+        }
+
+        self.coproc_syscnt.load_state(r)?;
+        self.coproc_vfp.load_state(r)?;
+        self.mpu.load_state(r)?;
+        self.sys_clk.load_state(r)?;
+
+        self.last_instructions.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::irq::{IrqClient, IrqSubsys, IrqType11};
+    use mem::{AddressBlock, MemController, UniqueMemoryBlock};
+
+    const UDF: u32 = 0xE7F000F0;
+
+    fn cpu_at_udf<V: Version>(version: V) -> Cpu<V> {
+        let mut cpu = cpu::test_cpu(version);
+        cpu.mpu.dmem_write::<u32>(0x100, UDF);
+        cpu
+    }
+
+    #[test]
+    fn unknown_coprocessor_registers() {
+        for &instr in [0xEE140F10, 0xEE140E10, 0xEE040F10].iter() { // mrc p15 c4, mrc p14 c4, mcr p15 c4
+            let mut cpu = cpu_at_udf(v5);
+            cpu.mpu.dmem_write::<u32>(0x100, instr);
+            cpu.run(1);
+            assert_eq!(cpu.cpsr.mode.get(), Mode::Und as u32);
+            assert_eq!(cpu.regs[14], 0x104);
+        }
+    }
+
+    #[test]
+    fn undefined_raises() {
+        let mut cpu = cpu_at_udf(v5);
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Und as u32);
+        assert_eq!(cpu.regs[14], 0x104);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000018);
+
+        let mut cpu = cpu_at_udf(v6);
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Und as u32);
+        assert_eq!(cpu.regs[14], 0x104);
+    }
+
+    #[test]
+    fn undefined_halts() {
+        let mut cpu = cpu_at_udf(v6);
+        cpu.halt_on_undefined = true;
+        match cpu.run(10) {
+            BreakReason::UndefinedInstruction(addr, opcode) => {
+                assert_eq!(addr, 0x100);
+                assert_eq!(opcode, UDF);
+            }
+            _ => panic!("Expected an undefined instruction halt")
+        }
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Svc as u32);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x100);
+        assert_eq!(cpu.steps, 0);
+    }
+
+    #[test]
+    fn instruction_timing() {
+        let mut cpu = cpu_at_udf(v5);
+        cpu.mpu.main_mem_mut().map_region(0x10000, AddressBlock::UniqueRam(UniqueMemoryBlock::new(1)));
+        cpu.mpu.main_mem_mut().set_wait_states(0x10000, 3);
+        cpu.mpu.dmem_write::<u32>(0x100, 0xE5901000); // ldr r1, [r0]
+        cpu.mpu.dmem_write::<u32>(0x104, 0xEAFFFFFE); // b .
+        cpu.regs[0] = 0x10000;
+
+        let start = cpu.cycles();
+        cpu.run(1);
+        assert_eq!(cpu.cycles() - start, 1 + 3);
+        cpu.run(1);
+        assert_eq!(cpu.cycles() - start, 4 + 1 + timing::BRANCH_PENALTY as u64);
+
+        // Stops at the first instruction boundary past the deadline
+        let end = cpu.cycles() + 1;
+        assert!(match cpu.run_until(100, end) { BreakReason::LimitReached => true, _ => false });
+        assert_eq!(cpu.steps, 3);
+    }
+
+    #[test]
+    fn wait_for_interrupt() {
+        let irq = IrqSubsys::create();
+        irq.agg.set_enabled(1u128 << (IrqType11::VBlankTop as u32));
+        let clk = clock::make_channel(irq.sync_tx.clone());
+        let mut memory = MemController::new();
+        memory.map_region(0, AddressBlock::UniqueRam(UniqueMemoryBlock::new(1)));
+        let mut cpu = Cpu::new(v6, memory, irq.line.clone(), clk.clone());
+        cpu.mpu.dmem_write::<u32>(0x100, 0xE320F003); // wfi
+        cpu.mpu.dmem_write::<u32>(0x104, 0xEAFFFFFE); // b .
+        cpu.reset(0x100);
+
+        cpu.run(1);
+        assert!(cpu.is_waiting_for_irq());
+
+        // Nothing scheduled, so it idles through the steps without the clock moving
+        let cycles = cpu.cycles();
+        assert!(match cpu.run(10) { BreakReason::WFI => true, _ => false });
+        assert_eq!(cpu.steps, 11);
+        assert_eq!(cpu.cycles(), cycles);
+
+        let mut irq_tx = clk.irq_tx.clone();
+        clk.set_handler(clock::Event::VBlank, move || irq_tx.assert(IrqType11::VBlankTop));
+        clk.sched.schedule(clock::Event::VBlank, 5000);
+
+        // Sleeps straight through to the event
+        cpu.run(1);
+        assert_eq!(cpu.cycles(), 5000);
+
+        // Woken up even though the IRQ is masked, and carries on after the WFI
+        cpu.run(1);
+        assert!(!cpu.is_waiting_for_irq());
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x104);
+    }
+
+    #[test]
+    fn polling_loops() {
+        let mut cpu = cpu_at_udf(v5);
+        cpu.mpu.dmem_write::<u32>(0x100, 0xE5901000); // ldr r1, [r0]
+        cpu.mpu.dmem_write::<u32>(0x104, 0xE3110001); // tst r1, #1
+        cpu.mpu.dmem_write::<u32>(0x108, 0x0AFFFFFC); // beq 0x100
+        cpu.regs[0] = 0x200;
+        cpu.sys_clk.set_handler(clock::Event::VBlank, || {});
+        cpu.sys_clk.sched.schedule(clock::Event::VBlank, 100000);
+
+        // Skips ahead to the event once the loop came around unchanged
+        cpu.run(12);
+        assert!(cpu.cycles() >= 100000 && cpu.cycles() < 100100);
+
+        // A loop that stores keeps running at its own pace
+        let mut cpu = cpu_at_udf(v5);
+        cpu.mpu.dmem_write::<u32>(0x100, STR_R1_R0);
+        cpu.mpu.dmem_write::<u32>(0x104, 0xEAFFFFFD); // b 0x100
+        cpu.regs[0] = 0x200;
+        cpu.sys_clk.set_handler(clock::Event::VBlank, || {});
+        cpu.sys_clk.sched.schedule(clock::Event::VBlank, 100000);
+        cpu.run(12);
+        assert!(cpu.cycles() < 1000);
+    }
+
+    #[test]
+    fn fiq_entry() {
+        let mut cpu = cpu_at_udf(v5);
+        cpu.regs[8] = 0x88;
+        cpu.fiq_line.set_high(true);
+
+        // Masked by reset
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Und as u32);
+
+        let mut cpu = cpu_at_udf(v5);
+        cpu.regs[8] = 0x88;
+        cpu.cpsr.disable_fiq_bit.set(0);
+        cpu.fiq_line.set_high(true);
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Fiq as u32);
+        assert_eq!(cpu.cpsr.disable_fiq_bit.get(), 1);
+        assert_eq!(cpu.cpsr.disable_irq_bit.get(), 1);
+        assert_eq!(cpu.regs[14], 0x104);
+        assert_eq!(cpu.regs[8], 0);
+        assert_eq!(cpu.regs.banked(Mode::Svc, 8), 0x88);
+        assert_eq!(cpu.spsr_fiq.mode.get(), Mode::Svc as u32);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000008);
+    }
+
+    const STR_R1_R0: u32 = 0xE5801000;
+    const LDR_R1_R0: u32 = 0xE5901000;
+
+    fn mpu_cpu() -> Cpu<v5> {
+        let mut cpu = cpu_at_udf(v5);
+        cpu.mpu.dmem_write::<u32>(0x100, STR_R1_R0);
+        cpu.mpu.dmem_write::<u32>(0x104, LDR_R1_R0);
+
+        let mut cp15_write = |cpreg1, op2, val| {
+            let effect = cpu.get_coprocessor(15).unwrap().move_in(cpreg1, 0, 0, op2, val).unwrap();
+            effect(&mut cpu);
+        };
+        cp15_write(6, 0, (11 << 1) | 1); // Region 0: 4KiB at 0
+        cp15_write(5, 2, 0b0110); // Read-only data
+        cp15_write(5, 3, 0b0011); // Full access to instructions
+        cp15_write(1, 0, 1); // Enable the MPU
+        cpu
+    }
+
+    #[test]
+    fn mpu_data_abort() {
+        let mut cpu = mpu_cpu();
+        cpu.regs[0] = 0x200;
+        cpu.regs[1] = 0x1234;
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Abt as u32);
+        assert_eq!(cpu.regs[14], 0x108);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000028);
+        assert_eq!(cpu.fault_regs().0, caches::FSR_PERMISSION);
+        assert_eq!(cpu.fault_regs().2, 0x200);
+        cpu.mpu.set_enabled(false);
+        assert_eq!(cpu.mpu.dmem_read::<u32>(0x200), 0);
+
+        // Reads are allowed, outside of user mode
+        let mut cpu = mpu_cpu();
+        cpu.regs[0] = 0x200;
+        cpu.branch(0x104);
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Svc as u32);
+    }
+
+    #[test]
+    fn mpu_prefetch_abort() {
+        let mut cpu = mpu_cpu();
+        cpu.branch(0x2000);
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Abt as u32);
+        assert_eq!(cpu.regs[14], 0x2004);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000020);
+        assert_eq!(cpu.fault_regs().1, caches::FSR_BACKGROUND);
+    }
+
+    #[test]
+    fn bus_errors() {
+        let bus_error_cpu = |mode| {
+            let mut cpu = cpu_at_udf(v6);
+            cpu.mpu.dmem_write::<u32>(0x100, LDR_R1_R0);
+            cpu.regs[0] = 0x10000000;
+            cpu.regs[1] = 0x1234;
+            cpu.on_bus_error = mode;
+            cpu
+        };
+
+        let mut cpu = bus_error_cpu(BusErrorMode::Abort);
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Abt as u32);
+        assert_eq!(cpu.regs[14], 0x108);
+        assert_eq!(cpu.fault_regs().0, caches::FSR_EXTERNAL);
+        assert_eq!(cpu.fault_regs().2, 0x10000000);
+
+        let mut cpu = bus_error_cpu(BusErrorMode::OpenBus);
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Svc as u32);
+        assert_eq!(cpu.regs[1], 0);
+
+        let mut cpu = bus_error_cpu(BusErrorMode::Halt);
+        match cpu.run(10) {
+            BreakReason::BusError { pc, addr, access } => {
+                assert_eq!(pc, 0x100);
+                assert_eq!(addr, 0x10000000);
+                assert_eq!(access, caches::Access::Read);
+            }
+            _ => panic!("Expected a bus error halt")
+        }
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x104);
+        assert_eq!(cpu.steps, 1);
+    }
+
+    fn mmu_cpu() -> Cpu<v6> {
+        let mut cpu = cpu::test_cpu(v6);
+        cpu.mpu.dmem_write::<u32>(0x100, STR_R1_R0);
+        cpu.mpu.dmem_write::<u32>(0x104, LDR_R1_R0);
+        cpu.mpu.dmem_write::<u32>(0x4000, 0x8001); // 0x00000000: Coarse table, domain 0
+        cpu.mpu.dmem_write::<u32>(0x4004, 0x0C22); // 0x00100000: Section, domain 1
+        cpu.mpu.dmem_write::<u32>(0x8000, 0x0032); // 0x0000: Full access
+        cpu.mpu.dmem_write::<u32>(0x8004, 0x1212); // 0x1000: Privileged read-only
+        cpu.mpu.dmem_write::<u32>(0x800C, 0x0033); // 0x3000: Execute-never
+
+        let mut cp15_write = |cpreg1, op2, val| {
+            let effect = cpu.get_coprocessor(15).unwrap().move_in(cpreg1, 0, 0, op2, val).unwrap();
+            effect(&mut cpu);
+        };
+        cp15_write(2, 0, 0x4000);
+        cp15_write(3, 0, 0b01); // Client of domain 0 only
+        cp15_write(1, 0, (1 << 23) | 1); // ARMv6 descriptors, MMU on
+        cpu
+    }
+
+    fn data_fault(cpu: &mut Cpu<v6>, addr: u32) -> Option<(u32, u32)> {
+        cpu.reset(0x104);
+        cpu.regs[0] = addr;
+        cpu.run(1);
+        if cpu.cpsr.mode.get() != Mode::Abt as u32 {
+            return None
+        }
+        assert_eq!(cpu.regs[14], 0x10C);
+        let (dfsr, _, far) = cpu.fault_regs();
+        Some((dfsr, far))
+    }
+
+    #[test]
+    fn mmu_faults() {
+        let mut cpu = mmu_cpu();
+        assert_eq!(data_fault(&mut cpu, 0x0200), None);
+        assert_eq!(data_fault(&mut cpu, 0x1000), None);
+        assert_eq!(data_fault(&mut cpu, 0x2000), Some((caches::FSR_TRANSLATION_PAGE, 0x2000)));
+        assert_eq!(data_fault(&mut cpu, 0x00100000), Some((caches::FSR_DOMAIN_SECTION | 1 << 4, 0x00100000)));
+
+        cpu.reset(0x100);
+        cpu.regs[0] = 0x1000;
+        cpu.run(1);
+        assert_eq!(cpu.fault_regs().0, caches::FSR_PERMISSION_PAGE | 1 << 11);
+
+        cpu.reset(0x3000);
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Abt as u32);
+        assert_eq!(cpu.regs[14], 0x3004);
+        assert_eq!(cpu.fault_regs().1, caches::FSR_PERMISSION_PAGE);
+    }
+
+    #[test]
+    fn aborts_skip_writeback() {
+        // Permission fault on an MPU-protected store
+        let mut cpu = mpu_cpu();
+        cpu.mpu.dmem_write::<u32>(0x100, 0xE4801004); // str r1, [r0], #4
+        cpu.regs[0] = 0x200;
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Abt as u32);
+        assert_eq!(cpu.regs[0], 0x200);
+
+        // Translation fault on a load from an unmapped page
+        let mut cpu = mmu_cpu();
+        cpu.mpu.dmem_write::<u32>(0x108, 0xE5B01004); // ldr r1, [r0, #4]!
+        cpu.reset(0x108);
+        cpu.regs[0] = 0x1FFC;
+        cpu.regs[1] = 0x1234;
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Abt as u32);
+        assert_eq!(cpu.fault_regs(), (caches::FSR_TRANSLATION_PAGE, 0, 0x2000));
+        assert_eq!(cpu.regs[0], 0x1FFC);
+        assert_eq!(cpu.regs[1], 0x1234);
+        assert_eq!(cpu.regs[14], 0x110);
+
+        // Fault partway through a block load
+        let mut cpu = mmu_cpu();
+        cpu.mpu.dmem_write::<u32>(0x10C, 0xE8B00006); // ldmia r0!, {r1, r2}
+        cpu.reset(0x10C);
+        cpu.regs[0] = 0x1FFC;
+        cpu.regs[1] = 0x1234;
+        cpu.regs[2] = 0x5678;
+        cpu.run(1);
+        assert_eq!(cpu.cpsr.mode.get(), Mode::Abt as u32);
+        assert_eq!(cpu.fault_regs().2, 0x2000);
+        assert_eq!((cpu.regs[0], cpu.regs[1], cpu.regs[2]), (0x1FFC, 0x1234, 0x5678));
+    }
+
+    #[test]
+    fn debug_reads() {
+        // Dirty data cache lines are visible, and stay dirty
+        let mut cpu = mpu_cpu();
+        let mut cp15_write = |cpreg1, op2, val| {
+            let effect = cpu.get_coprocessor(15).unwrap().move_in(cpreg1, 0, 0, op2, val).unwrap();
+            effect(&mut cpu);
+        };
+        cp15_write(5, 2, 0b0011); // Full access to data
+        cp15_write(2, 0, 1); // Region 0 is cacheable
+        cp15_write(1, 0, (1 << 2) | 1); // Enable the data cache
+        cpu.mpu.dmem_write::<u32>(0x200, 0x1234);
+        let mut buf = [0u8; 4];
+        cpu.mpu.debug_read_buf(0x200, &mut buf).unwrap();
+        assert_eq!(u32::from_le_bytes(buf), 0x1234);
+        assert_eq!(cpu.mpu.main_mem().read::<u32>(0x200), 0);
+
+        // Reads go through the page tables, whatever the permissions, without faulting
+        // or filling the TLB
+        let mut cpu = mmu_cpu();
+        cpu.mpu.debug_read_buf(0x00100100, &mut buf).unwrap();
+        assert_eq!(u32::from_le_bytes(buf), STR_R1_R0);
+        assert!(cpu.mpu.debug_read_buf(0x2000, &mut buf).is_err());
+        assert!(!cpu.mpu.fault_pending());
+        cpu.mpu.debug_read_buf(0x1000, &mut buf).unwrap();
+        cpu.mpu.main_mem_mut().write::<u32>(0x8004, 0);
+        assert_eq!(data_fault(&mut cpu, 0x1000), Some((caches::FSR_TRANSLATION_PAGE, 0x1000)));
+    }
+
+    #[test]
+    fn exclusive_physical_addresses() {
+        const LDREX_R2_R0: u32 = 0xE1902F9F;
+        let mut cpu = mmu_cpu();
+
+        // A load refused in a domain without access leaves no reservation
+        cpu.regs[0] = 0x00100100;
+        cpu::interpreter_arm::decode::<v6>(LDREX_R2_R0)(&mut cpu, LDREX_R2_R0);
+        assert!(cpu.mpu.take_fault().is_some());
+        assert!(!cpu.check_exclusive(0x00100100));
+
+        // Reservations follow the memory, whichever mapping reaches it
+        let effect = cpu.get_coprocessor(15).unwrap().move_in(3, 0, 0, 0, 0b0101).unwrap();
+        effect(&mut cpu);
+        cpu::interpreter_arm::decode::<v6>(LDREX_R2_R0)(&mut cpu, LDREX_R2_R0);
+        assert!(!cpu.mpu.fault_pending());
+        assert!(cpu.check_exclusive(0x100));
+    }
+
+    #[test]
+    fn tlb_invalidate() {
+        let mut cpu = mmu_cpu();
+        assert_eq!(data_fault(&mut cpu, 0x1000), None);
+
+        // Stale until invalidated
+        cpu.mpu.main_mem_mut().write::<u32>(0x8004, 0);
+        assert_eq!(data_fault(&mut cpu, 0x1000), None);
+        let effect = cpu.get_coprocessor(15).unwrap().move_in(8, 7, 0, 1, 0x1000).unwrap();
+        effect(&mut cpu);
+        assert_eq!(data_fault(&mut cpu, 0x1000), Some((caches::FSR_TRANSLATION_PAGE, 0x1000)));
     }
 }
@@ -1,6 +1,14 @@
 use cpu::{self, Cpu, Version};
 use cpu::interpreter_arm as arm;
 
+/// Register transfers to disabled coprocessors, or to registers the coprocessor
+/// refuses in the current mode, are undefined
+fn transfer_allowed<V: Version>(cpu: &mut Cpu<V>, cp_num: usize, crn: usize, opcode_1: usize) -> bool {
+    let privileged = cpu.cpsr.mode.get() != cpu::Mode::Usr as u32;
+    cpu.coproc_enabled(cp_num) && cpu.get_coprocessor(cp_num)
+        .map_or(false, |coproc| coproc.allows_access(crn, opcode_1, privileged))
+}
+
 pub fn mcr<V: Version>(cpu: &mut Cpu<V>, data: arm::Mcr::Bf) -> cpu::InstrStatus {
     if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
@@ -11,12 +19,17 @@ pub fn mcr<V: Version>(cpu: &mut Cpu<V>, data: arm::Mcr::Bf) -> cpu::InstrStatus
     let crm = data.crm.get() as usize;
     let opcode_1 = data.opcode_1.get() as usize;
     let opcode_2 = data.opcode_2.get() as usize;
+    let cp_num = data.cp_num.get() as usize;
+    if !transfer_allowed(cpu, cp_num, crn, opcode_1) {
+        return cpu.undefined_instr();
+    }
 
-    let cp_effect = {
-        let coproc = cpu.get_coprocessor(data.cp_num.get() as usize);
-        coproc.move_in(crn, crm, opcode_1, opcode_2, src_val)
-    };
-    cp_effect(cpu);
+    let cp_effect = cpu.get_coprocessor(cp_num)
+        .and_then(|coproc| coproc.move_in(crn, crm, opcode_1, opcode_2, src_val));
+    match cp_effect {
+        Some(cp_effect) => cp_effect(cpu),
+        None => return cpu.undefined_instr()
+    }
 
     cpu::InstrStatus::InBlock
 }
@@ -31,10 +44,16 @@ pub fn mrc<V: Version>(cpu: &mut Cpu<V>, data: arm::Mrc::Bf) -> cpu::InstrStatus
     let opcode_1 = data.opcode_1.get() as usize;
     let opcode_2 = data.opcode_2.get() as usize;
     let rd = data.rd.get();
+    let cp_num = data.cp_num.get() as usize;
+    if !transfer_allowed(cpu, cp_num, crn, opcode_1) {
+        return cpu.undefined_instr();
+    }
 
-    let retval = {
-        let coproc = cpu.get_coprocessor(data.cp_num.get() as usize);
-        coproc.move_out(crn, crm, opcode_1, opcode_2)
+    let retval = cpu.get_coprocessor(cp_num)
+        .and_then(|coproc| coproc.move_out(crn, crm, opcode_1, opcode_2));
+    let retval = match retval {
+        Some(retval) => retval,
+        None => return cpu.undefined_instr()
     };
 
     if rd == 15 {
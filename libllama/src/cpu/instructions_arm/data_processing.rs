@@ -519,6 +519,28 @@ pub fn tst<V: Version>(cpu: &mut Cpu<V>, data: arm::Tst::Bf) -> cpu::InstrStatus
     instr_test(cpu, data, false)
 }
 
+pub fn umaal<V: Version>(cpu: &mut Cpu<V>, data: arm::Umaal::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let base_val = cpu.regs[data.rm.get() as usize] as u64;
+    let multiplier = cpu.regs[data.rs.get() as usize] as u64;
+    let rd_hi = data.rd_hi.get() as usize;
+    let rd_lo = data.rd_lo.get() as usize;
+
+    // Cannot overflow: (2^32-1)^2 + 2 * (2^32-1) == 2^64-1
+    let val = base_val * multiplier + cpu.regs[rd_hi] as u64 + cpu.regs[rd_lo] as u64;
+
+    cpu.regs[rd_hi] = (val >> 32) as u32;
+    cpu.regs[rd_lo] = val as u32;
+
+    cpu::InstrStatus::InBlock
+}
+
 pub fn umlal<V: Version>(cpu: &mut Cpu<V>, data: arm::Umlal::Bf) -> cpu::InstrStatus {
     instr_mul64_accumulate(cpu, data, false)
 }
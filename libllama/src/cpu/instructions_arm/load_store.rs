@@ -118,6 +118,7 @@ fn instr_load<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldr::Bf, byte: bool) -> c
 
     let rd = data.rd.get();
     let (addr, wb) = addressing::decode_normal(data.val, cpu);
+    cpu.save_for_abort((1 << data.rn.get()) | (1 << rd));
 
     let val = if byte {
         cpu.mpu.dmem_read::<u8>(addr.0) as u32
@@ -150,6 +151,8 @@ fn instr_load_misc<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldrh::Bf, ty: MiscLs
 
     let rd = data.rd.get() as usize;
     let (addr, wb) = addressing::decode_misc(data.val, cpu);
+    let dest = if let MiscLsType::Doubleword = ty { 0b11 << rd } else { 1 << rd };
+    cpu.save_for_abort((1 << data.rn.get()) | dest);
     // Writeback
     cpu.regs[data.rn.get() as usize] = wb.0;
 
@@ -187,6 +190,7 @@ fn instr_store<V: Version>(cpu: &mut Cpu<V>, data: arm::Str::Bf, byte: bool) ->
 
     let (addr, wb) = addressing::decode_normal(data.val, cpu);
     let val = cpu.regs[data.rd.get() as usize];
+    cpu.save_for_abort(1 << data.rn.get());
 
     // Writeback
     cpu.regs[data.rn.get() as usize] = wb.0;
@@ -210,6 +214,7 @@ fn instr_store_misc<V: Version>(cpu: &mut Cpu<V>, data: arm::Strh::Bf, ty: MiscL
 
     let (addr, wb) = addressing::decode_misc(data.val, cpu);
     let rd = data.rd.get() as usize;
+    cpu.save_for_abort(1 << data.rn.get());
 
     // Writeback
     cpu.regs[data.rn.get() as usize] = wb.0;
@@ -231,9 +236,79 @@ fn instr_store_misc<V: Version>(cpu: &mut Cpu<V>, data: arm::Strh::Bf, ty: MiscL
     cpu::InstrStatus::InBlock
 }
 
-pub fn clrex<V: Version>(_cpu: &mut Cpu<V>, _data: arm::Clrex::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v6>());
-    warn!("clrex: implemented as no-op!");
+enum ExclusiveType {
+    Byte,
+    Halfword,
+    Word,
+    Doubleword
+}
+
+fn instr_load_exclusive<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldrex::Bf, ty: ExclusiveType) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let addr = cpu.regs[data.rn.get() as usize];
+    let rd = data.rd.get() as usize;
+    cpu.save_for_abort(if let ExclusiveType::Doubleword = ty { 0b11 << rd } else { 1 << rd });
+
+    match ty {
+        ExclusiveType::Byte => cpu.regs[rd] = cpu.mpu.dmem_read::<u8>(addr) as u32,
+        ExclusiveType::Halfword => cpu.regs[rd] = cpu.mpu.dmem_read::<u16>(addr) as u32,
+        ExclusiveType::Word => cpu.regs[rd] = cpu.mpu.dmem_read::<u32>(addr),
+        ExclusiveType::Doubleword => {
+            cpu.regs[rd] = cpu.mpu.dmem_read::<u32>(addr);
+            cpu.regs[rd + 1] = cpu.mpu.dmem_read::<u32>(addr + 4);
+        }
+    }
+    // Faulting loads are restarted after the abort, so they reserve nothing
+    if !cpu.mpu.fault_pending() {
+        cpu.mark_exclusive(addr);
+    }
+
+    cpu::InstrStatus::InBlock
+}
+
+fn instr_store_exclusive<V: Version>(cpu: &mut Cpu<V>, data: arm::Strex::Bf, ty: ExclusiveType) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let addr = cpu.regs[data.rn.get() as usize];
+    let rm = data.rm.get() as usize;
+    cpu.save_for_abort(1 << data.rd.get());
+
+    let passed = cpu.check_exclusive(addr);
+    if passed {
+        match ty {
+            ExclusiveType::Byte => cpu.mpu.dmem_write::<u8>(addr, cpu.regs[rm] as u8),
+            ExclusiveType::Halfword => cpu.mpu.dmem_write::<u16>(addr, cpu.regs[rm] as u16),
+            ExclusiveType::Word => cpu.mpu.dmem_write::<u32>(addr, cpu.regs[rm]),
+            ExclusiveType::Doubleword => {
+                cpu.mpu.dmem_write::<u32>(addr, cpu.regs[rm]);
+                cpu.mpu.dmem_write::<u32>(addr + 4, cpu.regs[rm + 1]);
+            }
+        }
+    }
+    // Status is 0 if the store went ahead
+    cpu.regs[data.rd.get() as usize] = !passed as u32;
+
+    cpu::InstrStatus::InBlock
+}
+
+pub fn clrex<V: Version>(cpu: &mut Cpu<V>, _data: arm::Clrex::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    cpu.clear_exclusive();
 
     cpu::InstrStatus::InBlock
 }
@@ -247,24 +322,23 @@ pub fn ldrb<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldrb::Bf) -> cpu::InstrStat
 }
 
 pub fn ldrd<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldrd::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v5>());
     instr_load_misc(cpu, arm::Ldrh::new(data.val), MiscLsType::Doubleword)
 }
 
 pub fn ldrex<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldrex::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v6>());
-
-    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
-        return cpu::InstrStatus::InBlock;
-    }
+    instr_load_exclusive(cpu, data, ExclusiveType::Word)
+}
 
-    let addr = cpu.regs[data.rn.get() as usize];
-    let word = cpu.mpu.dmem_read::<u32>(addr);
-    cpu.regs[data.rd.get() as usize] = word;
+pub fn ldrexb<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldrexb::Bf) -> cpu::InstrStatus {
+    instr_load_exclusive(cpu, arm::Ldrex::new(data.val), ExclusiveType::Byte)
+}
 
-    warn!("ldrex: implemented as ldr!");
+pub fn ldrexd<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldrexd::Bf) -> cpu::InstrStatus {
+    instr_load_exclusive(cpu, arm::Ldrex::new(data.val), ExclusiveType::Doubleword)
+}
 
-    cpu::InstrStatus::InBlock
+pub fn ldrexh<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldrexh::Bf) -> cpu::InstrStatus {
+    instr_load_exclusive(cpu, arm::Ldrex::new(data.val), ExclusiveType::Halfword)
 }
 
 pub fn ldrh<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldrh::Bf) -> cpu::InstrStatus {
@@ -288,7 +362,6 @@ pub fn strb<V: Version>(cpu: &mut Cpu<V>, data: arm::Strb::Bf) -> cpu::InstrStat
 }
 
 pub fn strd<V: Version>(cpu: &mut Cpu<V>, data: arm::Strd::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v5>());
     instr_store_misc(cpu, arm::Strh::new(data.val), MiscLsType::Doubleword)
 }
 
@@ -296,8 +369,23 @@ pub fn strh<V: Version>(cpu: &mut Cpu<V>, data: arm::Strh::Bf) -> cpu::InstrStat
     instr_store_misc(cpu, data, MiscLsType::Halfword)
 }
 
+pub fn strex<V: Version>(cpu: &mut Cpu<V>, data: arm::Strex::Bf) -> cpu::InstrStatus {
+    instr_store_exclusive(cpu, data, ExclusiveType::Word)
+}
+
+pub fn strexb<V: Version>(cpu: &mut Cpu<V>, data: arm::Strexb::Bf) -> cpu::InstrStatus {
+    instr_store_exclusive(cpu, arm::Strex::new(data.val), ExclusiveType::Byte)
+}
+
+pub fn strexd<V: Version>(cpu: &mut Cpu<V>, data: arm::Strexd::Bf) -> cpu::InstrStatus {
+    instr_store_exclusive(cpu, arm::Strex::new(data.val), ExclusiveType::Doubleword)
+}
+
+pub fn strexh<V: Version>(cpu: &mut Cpu<V>, data: arm::Strexh::Bf) -> cpu::InstrStatus {
+    instr_store_exclusive(cpu, arm::Strex::new(data.val), ExclusiveType::Halfword)
+}
+
 pub fn swp<V: Version>(cpu: &mut Cpu<V>, data: arm::Swp::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v5>());
     if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
     }
@@ -305,6 +393,7 @@ pub fn swp<V: Version>(cpu: &mut Cpu<V>, data: arm::Swp::Bf) -> cpu::InstrStatus
     // TODO: determine behavior based on CP15 r1 bit_U (22)
     let addr = cpu.regs[data.rn.get() as usize];
     let new_val = cpu.regs[data.rm.get() as usize];
+    cpu.save_for_abort(1 << data.rd.get());
 
     let tmp = cpu.mpu.dmem_read::<u32>(addr);
     cpu.mpu.dmem_write::<u32>(addr, new_val);
@@ -314,7 +403,6 @@ pub fn swp<V: Version>(cpu: &mut Cpu<V>, data: arm::Swp::Bf) -> cpu::InstrStatus
 }
 
 pub fn swpb<V: Version>(cpu: &mut Cpu<V>, data: arm::Swpb::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v5>());
     if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
     }
@@ -322,6 +410,7 @@ pub fn swpb<V: Version>(cpu: &mut Cpu<V>, data: arm::Swpb::Bf) -> cpu::InstrStat
     // TODO: determine behavior based on CP15 r1 bit_U (22)
     let addr = cpu.regs[data.rn.get() as usize];
     let new_val = cpu.regs[data.rm.get() as usize];
+    cpu.save_for_abort(1 << data.rd.get());
 
     let tmp = cpu.mpu.dmem_read::<u8>(addr);
     cpu.mpu.dmem_write::<u8>(addr, new_val as u8);
@@ -329,3 +418,77 @@ pub fn swpb<V: Version>(cpu: &mut Cpu<V>, data: arm::Swpb::Bf) -> cpu::InstrStat
 
     cpu::InstrStatus::InBlock
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::test_cpu;
+
+    fn exec(cpu: &mut Cpu<cpu::v6>, instr: u32) {
+        arm::decode::<cpu::v6>(instr)(cpu, instr);
+    }
+
+    const LDREX_R2_R1: u32 = 0xE1912F9F;
+    const STREX_R3_R2_R1: u32 = 0xE1813F92;
+    const CLREX: u32 = 0xF57FF01F;
+
+    #[test]
+    fn exclusive_pair() {
+        let mut cpu = test_cpu(cpu::v6);
+        cpu.regs[1] = 0x100;
+        cpu.mpu.dmem_write::<u32>(0x100, 5);
+
+        exec(&mut cpu, LDREX_R2_R1);
+        assert_eq!(cpu.regs[2], 5);
+        cpu.regs[2] = 6;
+        exec(&mut cpu, STREX_R3_R2_R1);
+        assert_eq!(cpu.regs[3], 0);
+        assert_eq!(cpu.mpu.dmem_read::<u32>(0x100), 6);
+
+        // The first STREX closed the exclusive access
+        cpu.regs[2] = 7;
+        exec(&mut cpu, STREX_R3_R2_R1);
+        assert_eq!(cpu.regs[3], 1);
+        assert_eq!(cpu.mpu.dmem_read::<u32>(0x100), 6);
+    }
+
+    #[test]
+    fn exclusive_cleared() {
+        let mut cpu = test_cpu(cpu::v6);
+        cpu.regs[1] = 0x100;
+
+        exec(&mut cpu, LDREX_R2_R1);
+        exec(&mut cpu, CLREX);
+        exec(&mut cpu, STREX_R3_R2_R1);
+        assert_eq!(cpu.regs[3], 1);
+
+        exec(&mut cpu, LDREX_R2_R1);
+        cpu.enter_exception(0, cpu::Mode::Irq);
+        exec(&mut cpu, STREX_R3_R2_R1);
+        assert_eq!(cpu.regs[3], 1);
+
+        // Another core's successful STREX takes this core's reservation
+        let other = cpu.mpu.main_mem().monitor.add_core();
+        exec(&mut cpu, LDREX_R2_R1);
+        other.mark(0x100);
+        assert!(other.check_and_clear(0x100));
+        exec(&mut cpu, STREX_R3_R2_R1);
+        assert_eq!(cpu.regs[3], 1);
+    }
+
+    #[test]
+    fn swap_and_doubleword() {
+        let mut cpu = test_cpu(cpu::v6);
+        cpu.regs[1] = 0x100;
+        cpu.regs[3] = 9;
+        cpu.mpu.dmem_write::<u32>(0x100, 5);
+        cpu.mpu.dmem_write::<u32>(0x104, 6);
+
+        exec(&mut cpu, 0xE1012093); // swp r2, r3, [r1]
+        assert_eq!(cpu.regs[2], 5);
+        assert_eq!(cpu.mpu.dmem_read::<u32>(0x100), 9);
+
+        exec(&mut cpu, 0xE1C120D0); // ldrd r2, [r1]
+        assert_eq!((cpu.regs[2], cpu.regs[3]), (9, 6));
+    }
+}
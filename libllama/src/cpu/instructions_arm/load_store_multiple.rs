@@ -43,9 +43,10 @@ pub fn ldm_1<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldm1::Bf) -> cpu::InstrSta
     // TODO: determine behavior based on CP15 r1 bit_U (22)
     assert!( V::is::<cpu::v5>() || addr % 4 == 0 );
 
+    cpu.save_for_abort(register_list | (1 << data.rn.get()));
+
+    // With Rn in the list, the loaded value wins over the writeback
     if data.w_bit.get() == 1 {
-        // TODO: needs more testing
-        assert!(register_list & (1 << data.rn.get()) == 0);
         cpu.regs[data.rn.get() as usize] = writeback;
     }
 
@@ -77,12 +78,23 @@ pub fn ldm_2<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldm2::Bf) -> cpu::InstrSta
     // TODO: determine behavior based on CP15 r1 bit_U (22)
     assert!( V::is::<cpu::v5>() || addr % 4 == 0 );
 
+    let mut vals = [0u32; 15];
+    for i in 0..15 {
+        if bit!(register_list, i) == 1 {
+            vals[i] = cpu.mpu.dmem_read::<u32>(addr);
+            addr += 4;
+        }
+    }
+    // The user registers are banked away from the undo of aborted instructions
+    if cpu.access_aborted() {
+        return cpu::InstrStatus::InBlock;
+    }
+
     let current_mode = cpu::Mode::from_num(cpu.cpsr.mode.get());
     cpu.regs.swap(cpu::Mode::Usr);
     for i in 0..15 {
         if bit!(register_list, i) == 1 {
-            cpu.regs[i] = cpu.mpu.dmem_read::<u32>(addr);
-            addr += 4;
+            cpu.regs[i] = vals[i];
         }
     }
     cpu.regs.swap(current_mode);
@@ -101,6 +113,8 @@ pub fn ldm_3<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldm3::Bf) -> cpu::InstrSta
     // TODO: determine behavior based on CP15 r1 bit_U (22)
     assert!( V::is::<cpu::v5>() || addr % 4 == 0 );
 
+    cpu.save_for_abort(register_list | (1 << data.rn.get()));
+
     if data.w_bit.get() == 1 {
         // TODO: needs more testing
         assert!(register_list & (1 << data.rn.get()) == 0);
@@ -114,14 +128,20 @@ pub fn ldm_3<V: Version>(cpu: &mut Cpu<V>, data: arm::Ldm3::Bf) -> cpu::InstrSta
         }
     }
 
-    cpu.spsr_make_current();
     let dest = cpu.mpu.dmem_read::<u32>(addr);
+    // Switching modes would leave the undo of an aborted instruction in the wrong bank
+    if cpu.access_aborted() {
+        return cpu::InstrStatus::InBlock;
+    }
+    cpu.spsr_make_current();
     cpu.branch(dest & 0xFFFFFFFE);
     cpu::InstrStatus::Branched
 }
 
 pub fn rfe<V: Version>(cpu: &mut Cpu<V>, data: arm::Rfe::Bf) -> cpu::InstrStatus {
-    assert!( V::is::<cpu::v6>() );
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
 
     let p_bit = data.p_bit.get() == 1;
     let u_bit = data.u_bit.get() == 1;
@@ -134,6 +154,9 @@ pub fn rfe<V: Version>(cpu: &mut Cpu<V>, data: arm::Rfe::Bf) -> cpu::InstrStatus
 
     let new_pc = cpu.mpu.dmem_read::<u32>(addr);
     let new_cpsr = cpu.mpu.dmem_read::<u32>(addr+4);
+    if cpu.access_aborted() {
+        return cpu::InstrStatus::InBlock;
+    }
 
     if data.w_bit.get() == 1 {
         cpu.regs[data.rn.get() as usize] = writeback;
@@ -145,7 +168,9 @@ pub fn rfe<V: Version>(cpu: &mut Cpu<V>, data: arm::Rfe::Bf) -> cpu::InstrStatus
 }
 
 pub fn srs<V: Version>(cpu: &mut Cpu<V>, data: arm::Srs::Bf) -> cpu::InstrStatus {
-    assert!( V::is::<cpu::v6>() );
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
 
     let src_lr = cpu.regs[14];
     let src_spsr = cpu.get_current_spsr().val;
@@ -166,7 +191,7 @@ pub fn srs<V: Version>(cpu: &mut Cpu<V>, data: arm::Srs::Bf) -> cpu::InstrStatus
     cpu.mpu.dmem_write::<u32>(addr, src_lr);
     cpu.mpu.dmem_write::<u32>(addr+4, src_spsr);
 
-    if data.w_bit.get() == 1 {
+    if data.w_bit.get() == 1 && !cpu.access_aborted() {
         cpu.regs[13] = writeback;
     }
 
@@ -193,7 +218,7 @@ pub fn stm_1<V: Version>(cpu: &mut Cpu<V>, data: arm::Stm1::Bf) -> cpu::InstrSta
         }
     }
 
-    if data.w_bit.get() == 1 {
+    if data.w_bit.get() == 1 && !cpu.access_aborted() {
         cpu.regs[data.rn.get() as usize] = writeback;
     }
 
@@ -1,8 +1,192 @@
 use cpu::{self, Cpu, Version};
 use cpu::interpreter_arm as arm;
 
+/// Sign or zero extends the low `bits` bits of `val`
+fn extend(val: u32, signed: bool, bits: u32) -> u32 {
+    let shift = 32 - bits;
+    if signed {
+        (((val << shift) as i32) >> shift) as u32
+    } else {
+        (val << shift) >> shift
+    }
+}
+
+/// Clamps `val` to the signed `bits`-bit range, also returning whether it had to
+fn signed_sat(val: i64, bits: u32) -> (i64, bool) {
+    let max = (1i64 << (bits - 1)) - 1;
+    let min = -(1i64 << (bits - 1));
+    if val > max {
+        (max, true)
+    } else if val < min {
+        (min, true)
+    } else {
+        (val, false)
+    }
+}
+
+/// Clamps `val` to the unsigned `bits`-bit range, also returning whether it had to
+fn unsigned_sat(val: i64, bits: u32) -> (i64, bool) {
+    let max = (1i64 << bits) - 1;
+    if val > max {
+        (max, true)
+    } else if val < 0 {
+        (0, true)
+    } else {
+        (val, false)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum LaneOp {
+    Add,
+    Sub
+}
+
+/// Lane-wise arithmetic of the parallel add/subtract instructions
+///
+/// `lanes` gives, lowest lane first, the operation of each `width`-bit lane and the lane of
+/// `rm` it takes its second operand from. The prefix picks signed (S, Q, SH) or unsigned
+/// (U, UQ, UH) lanes and modular, saturating or halving results; only the modular forms
+/// produce GE bits. Returns None for the unallocated prefixes.
+fn parallel_op(prefix: u32, width: u32, rn: u32, rm: u32, lanes: &[(LaneOp, u32)])
+    -> Option<(u32, Option<u32>)> {
+
+    let signed = match prefix {
+        0b001 | 0b010 | 0b011 => true,
+        0b101 | 0b110 | 0b111 => false,
+        _ => return None
+    };
+    let lane = |val: u32, i: u32| extend(val >> (i * width), signed, width) as i32 as i64;
+    let lane_mask = (1u32 << width) - 1;
+    let ge_per_lane = 4 / lanes.len() as u32;
+
+    let mut out = 0;
+    let mut ge = 0;
+    for (i, &(op, m)) in lanes.iter().enumerate() {
+        let i = i as u32;
+        let (a, b) = (lane(rn, i), lane(rm, m));
+        let full = match op {
+            LaneOp::Add => a + b,
+            LaneOp::Sub => a - b
+        };
+
+        let (res, ge_set) = match prefix & 0b11 {
+            0b01 => {
+                let ge_set = match op {
+                    LaneOp::Add if !signed => full >= 1 << width,
+                    _ => full >= 0
+                };
+                (full, ge_set)
+            }
+            0b10 if signed => (signed_sat(full, width).0, false),
+            0b10 => (unsigned_sat(full, width).0, false),
+            _ => (full >> 1, false)
+        };
+
+        out |= (res as u32 & lane_mask) << (i * width);
+        if ge_set {
+            ge |= ((1 << ge_per_lane) - 1) << (i * ge_per_lane);
+        }
+    }
+
+    Some((out, if prefix & 0b11 == 0b01 { Some(ge) } else { None }))
+}
+
+fn instr_parallel<V: Version>(cpu: &mut Cpu<V>, data: arm::Add16::Bf, width: u32,
+                              lanes: &[(LaneOp, u32)]) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rn = cpu.regs[data.rn.get() as usize];
+    let rm = cpu.regs[data.rm.get() as usize];
+
+    let prefix = data.prefix.get();
+    let (val, ge) = match parallel_op(prefix, width, rn, rm, lanes) {
+        Some(res) => res,
+        None => return cpu.undefined_instr()
+    };
+
+    cpu.regs[data.rd.get() as usize] = val;
+    if let Some(ge) = ge {
+        cpu.cpsr.ge_bits.set(ge);
+    }
+
+    cpu::InstrStatus::InBlock
+}
+
+pub fn add16<V: Version>(cpu: &mut Cpu<V>, data: arm::Add16::Bf) -> cpu::InstrStatus {
+    instr_parallel(cpu, data, 16, &[(LaneOp::Add, 0), (LaneOp::Add, 1)])
+}
+
+pub fn add8<V: Version>(cpu: &mut Cpu<V>, data: arm::Add8::Bf) -> cpu::InstrStatus {
+    instr_parallel(cpu, arm::Add16::new(data.val), 8,
+                   &[(LaneOp::Add, 0), (LaneOp::Add, 1), (LaneOp::Add, 2), (LaneOp::Add, 3)])
+}
+
+pub fn asx<V: Version>(cpu: &mut Cpu<V>, data: arm::Asx::Bf) -> cpu::InstrStatus {
+    instr_parallel(cpu, arm::Add16::new(data.val), 16, &[(LaneOp::Sub, 1), (LaneOp::Add, 0)])
+}
+
+pub fn sax<V: Version>(cpu: &mut Cpu<V>, data: arm::Sax::Bf) -> cpu::InstrStatus {
+    instr_parallel(cpu, arm::Add16::new(data.val), 16, &[(LaneOp::Add, 1), (LaneOp::Sub, 0)])
+}
+
+pub fn sub16<V: Version>(cpu: &mut Cpu<V>, data: arm::Sub16::Bf) -> cpu::InstrStatus {
+    instr_parallel(cpu, arm::Add16::new(data.val), 16, &[(LaneOp::Sub, 0), (LaneOp::Sub, 1)])
+}
+
+pub fn sub8<V: Version>(cpu: &mut Cpu<V>, data: arm::Sub8::Bf) -> cpu::InstrStatus {
+    instr_parallel(cpu, arm::Add16::new(data.val), 8,
+                   &[(LaneOp::Sub, 0), (LaneOp::Sub, 1), (LaneOp::Sub, 2), (LaneOp::Sub, 3)])
+}
+
+pub fn pkhbt<V: Version>(cpu: &mut Cpu<V>, data: arm::Pkhbt::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rn = cpu.regs[data.rn.get() as usize];
+    let rm = cpu.regs[data.rm.get() as usize];
+
+    let val = (rn & 0xFFFF) | ((rm << data.shift_imm.get()) & 0xFFFF0000);
+    cpu.regs[data.rd.get() as usize] = val;
+
+    cpu::InstrStatus::InBlock
+}
+
+pub fn pkhtb<V: Version>(cpu: &mut Cpu<V>, data: arm::Pkhtb::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rn = cpu.regs[data.rn.get() as usize];
+    let rm = cpu.regs[data.rm.get() as usize];
+
+    // An immediate of 0 encodes ASR #32, which leaves the same low halfword as ASR #31
+    let shift = match data.shift_imm.get() {
+        0 => 31,
+        n => n
+    };
+    let val = (rn & 0xFFFF0000) | (((rm as i32) >> shift) as u32 & 0xFFFF);
+    cpu.regs[data.rd.get() as usize] = val;
+
+    cpu::InstrStatus::InBlock
+}
+
 pub fn rev<V: Version>(cpu: &mut Cpu<V>, data: arm::Rev::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v6>());
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
     if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
     }
@@ -14,32 +198,552 @@ pub fn rev<V: Version>(cpu: &mut Cpu<V>, data: arm::Rev::Bf) -> cpu::InstrStatus
     cpu::InstrStatus::InBlock
 }
 
-pub fn uxtb<V: Version>(cpu: &mut Cpu<V>, data: arm::Uxtb::Bf) -> cpu::InstrStatus {
-    assert!( V::is::<cpu::v6>() );
+pub fn rev16<V: Version>(cpu: &mut Cpu<V>, data: arm::Rev16::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
     if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
     }
 
     let rm = cpu.regs[data.rm.get() as usize];
-    let rot = 8 * data.rot.get() as usize;
+    let out = ((rm & 0x00FF00FF) << 8) | ((rm & 0xFF00FF00) >> 8);
+    cpu.regs[data.rd.get() as usize] = out;
 
-    let val = (rm >> rot) & 0xFF;
-    cpu.regs[data.rd.get() as usize] = val;
+    cpu::InstrStatus::InBlock
+}
+
+pub fn revsh<V: Version>(cpu: &mut Cpu<V>, data: arm::Revsh::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm = cpu.regs[data.rm.get() as usize];
+    let out = (rm as u16).swap_bytes() as i16 as u32;
+    cpu.regs[data.rd.get() as usize] = out;
 
     cpu::InstrStatus::InBlock
 }
 
-pub fn uxth<V: Version>(cpu: &mut Cpu<V>, data: arm::Uxth::Bf) -> cpu::InstrStatus {
-    assert!( V::is::<cpu::v6>() );
+pub fn sel<V: Version>(cpu: &mut Cpu<V>, data: arm::Sel::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rn = cpu.regs[data.rn.get() as usize];
+    let rm = cpu.regs[data.rm.get() as usize];
+    let ge = cpu.cpsr.ge_bits.get();
+
+    let mut out = 0;
+    for i in 0..4 {
+        let byte_mask = 0xFF << (i * 8);
+        out |= if bit!(ge, i) == 1 { rn & byte_mask } else { rm & byte_mask };
+    }
+    cpu.regs[data.rd.get() as usize] = out;
+
+    cpu::InstrStatus::InBlock
+}
+
+/// Operand of SSAT and USAT: Rm shifted left, or arithmetically right if `sh` is set
+fn sat_operand(rm: u32, shift_imm: u32, sh: u32) -> i64 {
+    if sh == 1 {
+        let shift = if shift_imm == 0 { 31 } else { shift_imm };
+        ((rm as i32) >> shift) as i64
+    } else {
+        (rm << shift_imm) as i32 as i64
+    }
+}
+
+pub fn ssat<V: Version>(cpu: &mut Cpu<V>, data: arm::Ssat::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm = cpu.regs[data.rm.get() as usize];
+    let operand = sat_operand(rm, data.shift_imm.get(), data.sh.get());
+
+    let (val, saturated) = signed_sat(operand, data.sat_imm.get() + 1);
+    cpu.regs[data.rd.get() as usize] = val as u32;
+    if saturated {
+        cpu.cpsr.q_bit.set(1);
+    }
+
+    cpu::InstrStatus::InBlock
+}
+
+pub fn ssat16<V: Version>(cpu: &mut Cpu<V>, data: arm::Ssat16::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm = cpu.regs[data.rm.get() as usize];
+    let bits = data.sat_imm.get() + 1;
+
+    let (lo, lo_sat) = signed_sat(rm as i16 as i64, bits);
+    let (hi, hi_sat) = signed_sat((rm >> 16) as i16 as i64, bits);
+    cpu.regs[data.rd.get() as usize] = ((hi as u32) << 16) | (lo as u32 & 0xFFFF);
+    if lo_sat || hi_sat {
+        cpu.cpsr.q_bit.set(1);
+    }
+
+    cpu::InstrStatus::InBlock
+}
+
+pub fn usat<V: Version>(cpu: &mut Cpu<V>, data: arm::Usat::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm = cpu.regs[data.rm.get() as usize];
+    let operand = sat_operand(rm, data.shift_imm.get(), data.sh.get());
+
+    let (val, saturated) = unsigned_sat(operand, data.sat_imm.get());
+    cpu.regs[data.rd.get() as usize] = val as u32;
+    if saturated {
+        cpu.cpsr.q_bit.set(1);
+    }
+
+    cpu::InstrStatus::InBlock
+}
+
+pub fn usat16<V: Version>(cpu: &mut Cpu<V>, data: arm::Usat16::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm = cpu.regs[data.rm.get() as usize];
+    let bits = data.sat_imm.get();
+
+    let (lo, lo_sat) = unsigned_sat(rm as i16 as i64, bits);
+    let (hi, hi_sat) = unsigned_sat((rm >> 16) as i16 as i64, bits);
+    cpu.regs[data.rd.get() as usize] = ((hi as u32) << 16) | lo as u32;
+    if lo_sat || hi_sat {
+        cpu.cpsr.q_bit.set(1);
+    }
+
+    cpu::InstrStatus::InBlock
+}
+
+#[derive(Clone, Copy)]
+enum Extend {
+    Byte,
+    Half,
+    DualByte
+}
+
+/// Shared by the extend instructions; an Rn of 15 encodes the forms without accumulate
+fn instr_extend<V: Version>(cpu: &mut Cpu<V>, data: arm::Sxtab::Bf, kind: Extend,
+                            signed: bool) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
     if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
     }
 
     let rm = cpu.regs[data.rm.get() as usize];
     let rot = 8 * data.rot.get();
+    let operand = rm.rotate_right(rot);
+
+    let acc = match data.rn.get() {
+        15 => 0,
+        rn => cpu.regs[rn as usize]
+    };
 
-    let val = rm.rotate_right(rot) & 0xFFFF;
+    let val = match kind {
+        Extend::Byte => acc.wrapping_add(extend(operand, signed, 8)),
+        Extend::Half => acc.wrapping_add(extend(operand, signed, 16)),
+        Extend::DualByte => {
+            let lo = acc.wrapping_add(extend(operand, signed, 8)) & 0xFFFF;
+            let hi = (acc >> 16).wrapping_add(extend(operand >> 16, signed, 8)) & 0xFFFF;
+            (hi << 16) | lo
+        }
+    };
     cpu.regs[data.rd.get() as usize] = val;
 
     cpu::InstrStatus::InBlock
 }
+
+pub fn sxtab<V: Version>(cpu: &mut Cpu<V>, data: arm::Sxtab::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, data, Extend::Byte, true)
+}
+
+pub fn sxtab16<V: Version>(cpu: &mut Cpu<V>, data: arm::Sxtab16::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::Sxtab::new(data.val), Extend::DualByte, true)
+}
+
+pub fn sxtah<V: Version>(cpu: &mut Cpu<V>, data: arm::Sxtah::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::Sxtab::new(data.val), Extend::Half, true)
+}
+
+pub fn sxtb<V: Version>(cpu: &mut Cpu<V>, data: arm::Sxtb::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::Sxtab::new(data.val), Extend::Byte, true)
+}
+
+pub fn sxtb16<V: Version>(cpu: &mut Cpu<V>, data: arm::Sxtb16::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::Sxtab::new(data.val), Extend::DualByte, true)
+}
+
+pub fn sxth<V: Version>(cpu: &mut Cpu<V>, data: arm::Sxth::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::Sxtab::new(data.val), Extend::Half, true)
+}
+
+pub fn uxtab<V: Version>(cpu: &mut Cpu<V>, data: arm::Uxtab::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::Sxtab::new(data.val), Extend::Byte, false)
+}
+
+pub fn uxtab16<V: Version>(cpu: &mut Cpu<V>, data: arm::Uxtab16::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::Sxtab::new(data.val), Extend::DualByte, false)
+}
+
+pub fn uxtah<V: Version>(cpu: &mut Cpu<V>, data: arm::Uxtah::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::Sxtab::new(data.val), Extend::Half, false)
+}
+
+pub fn uxtb<V: Version>(cpu: &mut Cpu<V>, data: arm::Uxtb::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::Sxtab::new(data.val), Extend::Byte, false)
+}
+
+pub fn uxtb16<V: Version>(cpu: &mut Cpu<V>, data: arm::Uxtb16::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::Sxtab::new(data.val), Extend::DualByte, false)
+}
+
+pub fn uxth<V: Version>(cpu: &mut Cpu<V>, data: arm::Uxth::Bf) -> cpu::InstrStatus {
+    instr_extend(cpu, arm::Sxtab::new(data.val), Extend::Half, false)
+}
+
+/// Signed products of the bottom and top halfwords, with Rs's halves swapped if `swap` is set
+fn dual_products(rm: u32, rs: u32, swap: bool) -> (i64, i64) {
+    let rs = if swap { rs.rotate_right(16) } else { rs };
+    let lo = (rm as i16 as i64) * (rs as i16 as i64);
+    let hi = ((rm >> 16) as i16 as i64) * ((rs >> 16) as i16 as i64);
+    (lo, hi)
+}
+
+/// Shared by SMLAD, SMLSD, SMUAD and SMUSD; an Rn of 15 encodes the forms without accumulate
+fn instr_dual_mul<V: Version>(cpu: &mut Cpu<V>, data: arm::Smlad::Bf, subtract: bool) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm = cpu.regs[data.rm.get() as usize];
+    let rs = cpu.regs[data.rs.get() as usize];
+    let (lo, hi) = dual_products(rm, rs, data.x_bit.get() == 1);
+
+    let acc = match data.rn.get() {
+        15 => 0,
+        rn => cpu.regs[rn as usize] as i32 as i64
+    };
+    let val = acc + if subtract { lo - hi } else { lo + hi };
+
+    cpu.regs[data.rd.get() as usize] = val as u32;
+    if val != val as i32 as i64 {
+        cpu.cpsr.q_bit.set(1);
+    }
+
+    cpu::InstrStatus::InBlock
+}
+
+pub fn smlad<V: Version>(cpu: &mut Cpu<V>, data: arm::Smlad::Bf) -> cpu::InstrStatus {
+    instr_dual_mul(cpu, data, false)
+}
+
+pub fn smlsd<V: Version>(cpu: &mut Cpu<V>, data: arm::Smlsd::Bf) -> cpu::InstrStatus {
+    instr_dual_mul(cpu, arm::Smlad::new(data.val), true)
+}
+
+pub fn smuad<V: Version>(cpu: &mut Cpu<V>, data: arm::Smuad::Bf) -> cpu::InstrStatus {
+    instr_dual_mul(cpu, arm::Smlad::new(data.val), false)
+}
+
+pub fn smusd<V: Version>(cpu: &mut Cpu<V>, data: arm::Smusd::Bf) -> cpu::InstrStatus {
+    instr_dual_mul(cpu, arm::Smlad::new(data.val), true)
+}
+
+fn instr_dual_mul_long<V: Version>(cpu: &mut Cpu<V>, data: arm::Smlald::Bf, subtract: bool) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm = cpu.regs[data.rm.get() as usize];
+    let rs = cpu.regs[data.rs.get() as usize];
+    let (lo, hi) = dual_products(rm, rs, data.x_bit.get() == 1);
+
+    let rd_hi = data.rd_hi.get() as usize;
+    let rd_lo = data.rd_lo.get() as usize;
+    let acc = ((cpu.regs[rd_hi] as u64) << 32 | cpu.regs[rd_lo] as u64) as i64;
+    let val = acc.wrapping_add(if subtract { lo - hi } else { lo + hi });
+
+    cpu.regs[rd_hi] = (val >> 32) as u32;
+    cpu.regs[rd_lo] = val as u32;
+
+    cpu::InstrStatus::InBlock
+}
+
+pub fn smlald<V: Version>(cpu: &mut Cpu<V>, data: arm::Smlald::Bf) -> cpu::InstrStatus {
+    instr_dual_mul_long(cpu, data, false)
+}
+
+pub fn smlsld<V: Version>(cpu: &mut Cpu<V>, data: arm::Smlsld::Bf) -> cpu::InstrStatus {
+    instr_dual_mul_long(cpu, arm::Smlald::new(data.val), true)
+}
+
+/// Shared by SMMLA, SMMLS and SMMUL; an Rn of 15 encodes SMMUL
+fn instr_mul_most_significant<V: Version>(cpu: &mut Cpu<V>, data: arm::Smmla::Bf, subtract: bool)
+    -> cpu::InstrStatus {
+
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm = cpu.regs[data.rm.get() as usize] as i32 as i64;
+    let rs = cpu.regs[data.rs.get() as usize] as i32 as i64;
+    let product = rm * rs;
+
+    let acc = match data.rn.get() {
+        15 => 0,
+        rn => ((cpu.regs[rn as usize] as u64) << 32) as i64
+    };
+    let round = if data.r_bit.get() == 1 { 0x80000000 } else { 0 };
+    let val = if subtract { acc.wrapping_sub(product) } else { acc.wrapping_add(product) };
+
+    cpu.regs[data.rd.get() as usize] = (val.wrapping_add(round) >> 32) as u32;
+
+    cpu::InstrStatus::InBlock
+}
+
+pub fn smmla<V: Version>(cpu: &mut Cpu<V>, data: arm::Smmla::Bf) -> cpu::InstrStatus {
+    instr_mul_most_significant(cpu, data, false)
+}
+
+pub fn smmls<V: Version>(cpu: &mut Cpu<V>, data: arm::Smmls::Bf) -> cpu::InstrStatus {
+    instr_mul_most_significant(cpu, arm::Smmla::new(data.val), true)
+}
+
+pub fn smmul<V: Version>(cpu: &mut Cpu<V>, data: arm::Smmul::Bf) -> cpu::InstrStatus {
+    instr_mul_most_significant(cpu, arm::Smmla::new(data.val), false)
+}
+
+pub fn usad8<V: Version>(cpu: &mut Cpu<V>, data: arm::Usad8::Bf) -> cpu::InstrStatus {
+    usada8(cpu, arm::Usada8::new(data.val))
+}
+
+pub fn usada8<V: Version>(cpu: &mut Cpu<V>, data: arm::Usada8::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    let rm = cpu.regs[data.rm.get() as usize];
+    let rs = cpu.regs[data.rs.get() as usize];
+    let acc = match data.rn.get() {
+        15 => 0,
+        rn => cpu.regs[rn as usize]
+    };
+
+    let diffs = (0..4).map(|i| {
+        let a = (rm >> (i * 8)) & 0xFF;
+        let b = (rs >> (i * 8)) & 0xFF;
+        if a > b { a - b } else { b - a }
+    });
+    cpu.regs[data.rd.get() as usize] = diffs.fold(acc, u32::wrapping_add);
+
+    cpu::InstrStatus::InBlock
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::test_cpu;
+
+    /// Runs a single ARM instruction with the given register values, returning the CPU
+    fn exec(instr: u32, regs: &[(usize, u32)]) -> Cpu<cpu::v6> {
+        let mut cpu = test_cpu(cpu::v6);
+        for &(reg, val) in regs {
+            cpu.regs[reg] = val;
+        }
+        arm::decode::<cpu::v6>(instr)(&mut cpu, instr);
+        cpu
+    }
+
+    #[test]
+    fn parallel_add_sub() {
+        // uadd8 r0, r1, r2
+        let cpu = exec(0xE6510F92, &[(1, 0x80FF0102), (2, 0x80010304)]);
+        assert_eq!(cpu.regs[0], 0x00000406);
+        assert_eq!(cpu.cpsr.ge_bits.get(), 0b1100);
+
+        // sadd16 r0, r1, r2
+        let cpu = exec(0xE6110F12, &[(1, 0x7FFF0001), (2, 0x0001FFFE)]);
+        assert_eq!(cpu.regs[0], 0x8000FFFF);
+        assert_eq!(cpu.cpsr.ge_bits.get(), 0b1100);
+
+        // usub16 r0, r1, r2
+        let cpu = exec(0xE6510F72, &[(1, 0x00050005), (2, 0x00060004)]);
+        assert_eq!(cpu.regs[0], 0xFFFF0001);
+        assert_eq!(cpu.cpsr.ge_bits.get(), 0b0011);
+
+        // qsub8 r0, r1, r2
+        let cpu = exec(0xE6210FF2, &[(1, 0x80007F10), (2, 0x017F8120)]);
+        assert_eq!(cpu.regs[0], 0x80817FF0);
+
+        // uqadd16 r0, r1, r2
+        let cpu = exec(0xE6610F12, &[(1, 0xFFF00010), (2, 0x00200020)]);
+        assert_eq!(cpu.regs[0], 0xFFFF0030);
+
+        // shasx r0, r1, r2: top = (-2 + 4) >> 1, bottom = (10 - 3) >> 1
+        let cpu = exec(0xE6310F32, &[(1, 0xFFFE000A), (2, 0x00030004)]);
+        assert_eq!(cpu.regs[0], 0x00010003);
+
+        // uhsax r0, r1, r2: top = (4 - 6) >> 1, bottom = (1 + 2) >> 1
+        let cpu = exec(0xE6710F52, &[(1, 0x00040001), (2, 0x00020006)]);
+        assert_eq!(cpu.regs[0], 0xFFFF0001);
+    }
+
+    #[test]
+    fn select() {
+        // usub8 r3, r1, r2; sel r0, r1, r2 picks the larger unsigned bytes
+        let mut cpu = exec(0xE6513FF2, &[(1, 0x10802030), (2, 0x20700040)]);
+        arm::decode::<cpu::v6>(0xE6810FB2)(&mut cpu, 0xE6810FB2);
+        assert_eq!(cpu.cpsr.ge_bits.get(), 0b0110);
+        assert_eq!(cpu.regs[0], 0x20802040);
+    }
+
+    #[test]
+    fn pack() {
+        // pkhbt r0, r1, r2, lsl #8
+        let cpu = exec(0xE6810412, &[(1, 0xAAAA1234), (2, 0x00567800)]);
+        assert_eq!(cpu.regs[0], 0x56781234);
+
+        // pkhtb r0, r1, r2, asr #32
+        let cpu = exec(0xE6810052, &[(1, 0x1234AAAA), (2, 0x80000000)]);
+        assert_eq!(cpu.regs[0], 0x1234FFFF);
+    }
+
+    #[test]
+    fn saturate() {
+        // ssat r0, #8, r1
+        let cpu = exec(0xE6A70011, &[(1, 0x00000100)]);
+        assert_eq!(cpu.regs[0], 0x7F);
+        assert_eq!(cpu.cpsr.q_bit.get(), 1);
+
+        // ssat r0, #16, r1, asr #4
+        let cpu = exec(0xE6AF0251, &[(1, 0xFFFFFF00)]);
+        assert_eq!(cpu.regs[0], 0xFFFFFFF0);
+        assert_eq!(cpu.cpsr.q_bit.get(), 0);
+
+        // usat r0, #8, r1
+        let cpu = exec(0xE6E80011, &[(1, 0xFFFFFFFF)]);
+        assert_eq!(cpu.regs[0], 0);
+        assert_eq!(cpu.cpsr.q_bit.get(), 1);
+
+        // ssat16 r0, #8, r1
+        let cpu = exec(0xE6A70F31, &[(1, 0x8000007F)]);
+        assert_eq!(cpu.regs[0], 0xFF80007F);
+        assert_eq!(cpu.cpsr.q_bit.get(), 1);
+
+        // usat16 r0, #4, r1
+        let cpu = exec(0xE6E40F31, &[(1, 0x0010000F)]);
+        assert_eq!(cpu.regs[0], 0x000F000F);
+        assert_eq!(cpu.cpsr.q_bit.get(), 1);
+    }
+
+    #[test]
+    fn reverse() {
+        // rev16 r0, r1
+        let cpu = exec(0xE6BF0FB1, &[(1, 0x11223344)]);
+        assert_eq!(cpu.regs[0], 0x22114433);
+
+        // revsh r0, r1
+        let cpu = exec(0xE6FF0FB1, &[(1, 0x11223380)]);
+        assert_eq!(cpu.regs[0], 0xFFFF8033);
+    }
+
+    #[test]
+    fn extend_ops() {
+        // sxtb r0, r1, ror #8
+        let cpu = exec(0xE6AF0471, &[(1, 0x000080FF)]);
+        assert_eq!(cpu.regs[0], 0xFFFFFF80);
+
+        // sxtah r0, r2, r1
+        let cpu = exec(0xE6B20071, &[(1, 0x0000FFFF), (2, 10)]);
+        assert_eq!(cpu.regs[0], 9);
+
+        // uxtab16 r0, r2, r1
+        let cpu = exec(0xE6C20071, &[(1, 0x00FF00FF), (2, 0x0001FFFF)]);
+        assert_eq!(cpu.regs[0], 0x010000FE);
+
+        // sxtb16 r0, r1
+        let cpu = exec(0xE68F0071, &[(1, 0x00800001)]);
+        assert_eq!(cpu.regs[0], 0xFF800001);
+
+        // uxth r0, r1, ror #24
+        let cpu = exec(0xE6FF0C71, &[(1, 0x12345678)]);
+        assert_eq!(cpu.regs[0], 0x7812);
+    }
+
+    #[test]
+    fn multiplies() {
+        // smuad r0, r1, r2
+        let cpu = exec(0xE700F211, &[(1, 0x00030002), (2, 0x00050004)]);
+        assert_eq!(cpu.regs[0], 23);
+
+        // smlsdx r0, r1, r2, r3
+        let cpu = exec(0xE7003271, &[(1, 0x00030002), (2, 0x00050004), (3, 100)]);
+        assert_eq!(cpu.regs[0], 98);
+
+        // smlad r0, r1, r2, r3 overflowing into Q
+        let cpu = exec(0xE7003211, &[(1, 0x80008000), (2, 0x80008000), (3, 0)]);
+        assert_eq!(cpu.regs[0], 0x80000000);
+        assert_eq!(cpu.cpsr.q_bit.get(), 1);
+
+        // smlald r0, r1, r2, r3
+        let cpu = exec(0xE7410312, &[(0, 0xFFFFFFFF), (1, 0), (2, 0x00010001), (3, 0x00010001)]);
+        assert_eq!((cpu.regs[1], cpu.regs[0]), (1, 1));
+
+        // smmulr r0, r1, r2
+        let cpu = exec(0xE750F231, &[(1, 0x40000000), (2, 3)]);
+        assert_eq!(cpu.regs[0], 1);
+
+        // smmls r0, r1, r2, r3
+        let cpu = exec(0xE75032D1, &[(1, 0x10000000), (2, 0x10), (3, 5)]);
+        assert_eq!(cpu.regs[0], 4);
+
+        // usada8 r0, r1, r2, r3
+        let cpu = exec(0xE7803211, &[(1, 0x01FF0010), (2, 0x02000100), (3, 7)]);
+        assert_eq!(cpu.regs[0], 7 + 1 + 0xFF + 1 + 0x10);
+
+        // umaal r0, r1, r2, r3
+        let cpu = exec(0xE0410392, &[(0, 0xFFFFFFFF), (1, 0xFFFFFFFF), (2, 0xFFFFFFFF), (3, 0xFFFFFFFF)]);
+        assert_eq!((cpu.regs[1], cpu.regs[0]), (0xFFFFFFFF, 0xFFFFFFFF));
+    }
+}
@@ -2,7 +2,6 @@ use cpu::{self, Cpu, Version};
 use cpu::interpreter_arm as arm;
 
 pub fn swi<V: Version>(cpu: &mut Cpu<V>, data: arm::Swi::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v5>());
     if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
     }
@@ -12,7 +11,12 @@ pub fn swi<V: Version>(cpu: &mut Cpu<V>, data: arm::Swi::Bf) -> cpu::InstrStatus
     cpu::InstrStatus::Branched
 }
 
-pub fn bkpt<V: Version>(_cpu: &mut Cpu<V>, data: arm::Bkpt::Bf) -> cpu::InstrStatus {
+/// Without debug hardware, BKPT is a prefetch abort returning to the next instruction
+pub fn bkpt<V: Version>(cpu: &mut Cpu<V>, data: arm::Bkpt::Bf) -> cpu::InstrStatus {
     let brk_num = data.immed_lo.get() | (data.immed_hi.get() << 4);
-    panic!("Hit breakpoint instruction! (#{})", brk_num);
+    let addr = cpu.regs[15] - cpu.get_pc_offset();
+    warn!("Hit breakpoint instruction #{} at {:08X}!", brk_num, addr);
+
+    cpu.enter_prefetch_abort(addr + 4);
+    cpu::InstrStatus::Branched
 }
@@ -6,6 +6,7 @@ mod load_store_multiple;
 mod media;
 mod misc;
 mod program_status;
+mod vfp;
 
 pub use self::branch::*;
 pub use self::coprocessor::*;
@@ -15,3 +16,4 @@ pub use self::load_store_multiple::*;
 pub use self::media::*;
 pub use self::misc::*;
 pub use self::program_status::*;
+pub use self::vfp::*;
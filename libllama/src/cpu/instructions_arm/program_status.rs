@@ -73,7 +73,9 @@ pub fn instr_msr<V: Version>(cpu: &mut Cpu<V>, data: arm::Msr1::Bf, immediate: b
 }
 
 pub fn cps<V: Version>(cpu: &mut Cpu<V>, data: arm::Cps::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<v6>());
+    if !V::is::<v6>() {
+        return cpu.undefined_instr();
+    }
     
     if let cpu::Mode::Usr = cpu::Mode::from_num(cpu.cpsr.mode.get()) {
         return cpu::InstrStatus::InBlock
@@ -93,7 +95,37 @@ pub fn cps<V: Version>(cpu: &mut Cpu<V>, data: arm::Cps::Bf) -> cpu::InstrStatus
     cpu::InstrStatus::InBlock
 }
 
+pub fn setend<V: Version>(cpu: &mut Cpu<V>, data: arm::Setend::Bf) -> cpu::InstrStatus {
+    if !V::is::<v6>() {
+        return cpu.undefined_instr();
+    }
+
+    if data.e_bit.get() == 1 {
+        warn!("STUBBED: Big-endian data accesses");
+    }
+    cpu.cpsr.e_bit.set(data.e_bit.get());
+    cpu::InstrStatus::InBlock
+}
+
+/// ARMv6K hints, which take the encoding of an MSR to the CPSR that writes no fields
+fn hint<V: Version>(cpu: &mut Cpu<V>, data: arm::Msr1::Bf) -> cpu::InstrStatus {
+    if !cpu::cond_passed(data.cond.get(), &cpu.cpsr) {
+        return cpu::InstrStatus::InBlock;
+    }
+
+    match data.shifter_operand.get() {
+        3 => cpu.wait_for_interrupt(), // WFI
+        // NOP, YIELD, and WFE and SEV, since waking up early from a WFE is allowed
+        0..=2 | 4 => {}
+        op => warn!("Unknown hint instruction #{}", op)
+    }
+    cpu::InstrStatus::InBlock
+}
+
 pub fn msr_1<V: Version>(cpu: &mut Cpu<V>, data: arm::Msr1::Bf) -> cpu::InstrStatus {
+    if V::is::<v6>() && data.r_bit.get() == 0 && data.field_mask.get() == 0 {
+        return hint(cpu, data)
+    }
     instr_msr(cpu, data, true)
 }
 
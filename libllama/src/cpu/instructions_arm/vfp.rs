@@ -0,0 +1,577 @@
+use cpu::{self, Cpu, Version};
+use cpu::caches::Ops;
+use cpu::coproc::{Float, Vfp};
+use cpu::interpreter_arm as arm;
+
+/// Checks the condition and coprocessor access for a VFP instruction, returning the
+/// status to bail out with if it should not execute
+fn check<V: Version>(cpu: &mut Cpu<V>, cond: u32, double: bool) -> Option<cpu::InstrStatus> {
+    if !cpu::cond_passed(cond, &cpu.cpsr) {
+        return Some(cpu::InstrStatus::InBlock)
+    }
+    if !cpu.coproc_enabled(10 + double as usize) || !cpu.coproc_vfp.enabled() {
+        return Some(cpu.undefined_instr())
+    }
+    None
+}
+
+/// Register number from a 4-bit field and its extra bit, which is the low bit of single
+/// registers and should be clear for doubles
+fn reg<F: Float>(field: u32, bit: u32) -> usize {
+    if F::DOUBLE {
+        field as usize
+    } else {
+        (field << 1 | bit) as usize
+    }
+}
+
+/// Whether `reg` is in the first bank, which makes it a scalar in short vector operations
+fn scalar_bank<F: Float>(reg: usize) -> bool {
+    reg < if F::DOUBLE { 4 } else { 8 }
+}
+
+/// Register `reg` of element `i` of a short vector, which wraps around within its bank
+fn vector_reg<F: Float>(reg: usize, i: usize, stride: usize) -> usize {
+    let bank_size = if F::DOUBLE { 4 } else { 8 };
+    let bank = reg - reg % bank_size;
+    bank + (reg % bank_size + i * stride) % bank_size
+}
+
+/// The registers of each element of a data processing operation. Destinations in the
+/// first bank make it scalar; otherwise it runs over short vectors as set up in the
+/// FPSCR, with a second operand in the first bank used as a scalar for every element.
+fn elements<F: Float>(vfp: &Vfp, d: usize, n: usize, m: usize) -> impl Iterator<Item = (usize, usize, usize)> {
+    let (len, stride) = vfp.vector_shape();
+    let len = if scalar_bank::<F>(d) { 1 } else { len };
+    let m_stride = if scalar_bank::<F>(m) { 0 } else { stride };
+    (0..len).map(move |i| {
+        (vector_reg::<F>(d, i, stride), vector_reg::<F>(n, i, stride), vector_reg::<F>(m, i, m_stride))
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Arith {
+    Mac,
+    Nmac,
+    Msc,
+    Nmsc,
+    Mul,
+    Nmul,
+    Add,
+    Sub,
+    Div
+}
+
+fn arith<F: Float>(vfp: &mut Vfp, op: Arith, data: arm::Fmac::Bf) {
+    let d = reg::<F>(data.vd.get(), data.d_bit.get());
+    let n = reg::<F>(data.vn.get(), data.n_bit.get());
+    let m = reg::<F>(data.vm.get(), data.m_bit.get());
+
+    for (d, n, m) in elements::<F>(vfp, d, n, m) {
+        let a = vfp.get::<F>(n);
+        let b = vfp.get::<F>(m);
+        let acc = vfp.get::<F>(d);
+
+        let res = match op {
+            Arith::Mac => { let p = vfp.mul(a, b); vfp.add(acc, p) }
+            Arith::Nmac => { let p = vfp.mul(a, b); vfp.sub(acc, p) }
+            Arith::Msc => { let p = vfp.mul(a, b); vfp.sub(p, acc) }
+            Arith::Nmsc => { let p = vfp.mul(a, b); let p = vfp.neg(p); vfp.sub(p, acc) }
+            Arith::Mul => vfp.mul(a, b),
+            Arith::Nmul => { let p = vfp.mul(a, b); vfp.neg(p) }
+            Arith::Add => vfp.add(a, b),
+            Arith::Sub => vfp.sub(a, b),
+            Arith::Div => vfp.div(a, b),
+        };
+        vfp.set(d, res);
+    }
+}
+
+fn instr_arith<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmac::Bf, op: Arith) -> cpu::InstrStatus {
+    let double = data.sz_bit.get() == 1;
+    if let Some(status) = check(cpu, data.cond.get(), double) {
+        return status
+    }
+
+    if double {
+        arith::<f64>(&mut cpu.coproc_vfp, op, data);
+    } else {
+        arith::<f32>(&mut cpu.coproc_vfp, op, data);
+    }
+    cpu::InstrStatus::InBlock
+}
+
+#[derive(Clone, Copy)]
+enum Unary {
+    Cpy,
+    Abs,
+    Neg,
+    Sqrt
+}
+
+fn unary<F: Float>(vfp: &mut Vfp, op: Unary, data: arm::Fcpy::Bf) {
+    let d = reg::<F>(data.vd.get(), data.d_bit.get());
+    let m = reg::<F>(data.vm.get(), data.m_bit.get());
+
+    for (d, _, m) in elements::<F>(vfp, d, d, m) {
+        let x = vfp.get::<F>(m);
+        let res = match op {
+            Unary::Cpy => x,
+            Unary::Abs => vfp.abs(x),
+            Unary::Neg => vfp.neg(x),
+            Unary::Sqrt => vfp.sqrt(x),
+        };
+        vfp.set(d, res);
+    }
+}
+
+fn instr_unary<V: Version>(cpu: &mut Cpu<V>, data: arm::Fcpy::Bf, op: Unary) -> cpu::InstrStatus {
+    let double = data.sz_bit.get() == 1;
+    if let Some(status) = check(cpu, data.cond.get(), double) {
+        return status
+    }
+
+    if double {
+        unary::<f64>(&mut cpu.coproc_vfp, op, data);
+    } else {
+        unary::<f32>(&mut cpu.coproc_vfp, op, data);
+    }
+    cpu::InstrStatus::InBlock
+}
+
+fn compare<F: Float>(vfp: &mut Vfp, d: usize, m: Option<usize>, signal_nans: bool) {
+    let a = vfp.get::<F>(d);
+    let b = match m {
+        Some(m) => vfp.get::<F>(m),
+        None => F::from_raw(0)
+    };
+    let nzcv = vfp.compare(a, b, signal_nans);
+    vfp.set_nzcv(nzcv);
+}
+
+pub fn fmac<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmac::Bf) -> cpu::InstrStatus {
+    instr_arith(cpu, data, Arith::Mac)
+}
+
+pub fn fnmac<V: Version>(cpu: &mut Cpu<V>, data: arm::Fnmac::Bf) -> cpu::InstrStatus {
+    instr_arith(cpu, arm::Fmac::new(data.val), Arith::Nmac)
+}
+
+pub fn fmsc<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmsc::Bf) -> cpu::InstrStatus {
+    instr_arith(cpu, arm::Fmac::new(data.val), Arith::Msc)
+}
+
+pub fn fnmsc<V: Version>(cpu: &mut Cpu<V>, data: arm::Fnmsc::Bf) -> cpu::InstrStatus {
+    instr_arith(cpu, arm::Fmac::new(data.val), Arith::Nmsc)
+}
+
+pub fn fmul<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmul::Bf) -> cpu::InstrStatus {
+    instr_arith(cpu, arm::Fmac::new(data.val), Arith::Mul)
+}
+
+pub fn fnmul<V: Version>(cpu: &mut Cpu<V>, data: arm::Fnmul::Bf) -> cpu::InstrStatus {
+    instr_arith(cpu, arm::Fmac::new(data.val), Arith::Nmul)
+}
+
+pub fn fadd<V: Version>(cpu: &mut Cpu<V>, data: arm::Fadd::Bf) -> cpu::InstrStatus {
+    instr_arith(cpu, arm::Fmac::new(data.val), Arith::Add)
+}
+
+pub fn fsub<V: Version>(cpu: &mut Cpu<V>, data: arm::Fsub::Bf) -> cpu::InstrStatus {
+    instr_arith(cpu, arm::Fmac::new(data.val), Arith::Sub)
+}
+
+pub fn fdiv<V: Version>(cpu: &mut Cpu<V>, data: arm::Fdiv::Bf) -> cpu::InstrStatus {
+    instr_arith(cpu, arm::Fmac::new(data.val), Arith::Div)
+}
+
+pub fn fcpy<V: Version>(cpu: &mut Cpu<V>, data: arm::Fcpy::Bf) -> cpu::InstrStatus {
+    instr_unary(cpu, data, Unary::Cpy)
+}
+
+pub fn fabs<V: Version>(cpu: &mut Cpu<V>, data: arm::Fabs::Bf) -> cpu::InstrStatus {
+    instr_unary(cpu, arm::Fcpy::new(data.val), Unary::Abs)
+}
+
+pub fn fneg<V: Version>(cpu: &mut Cpu<V>, data: arm::Fneg::Bf) -> cpu::InstrStatus {
+    instr_unary(cpu, arm::Fcpy::new(data.val), Unary::Neg)
+}
+
+pub fn fsqrt<V: Version>(cpu: &mut Cpu<V>, data: arm::Fsqrt::Bf) -> cpu::InstrStatus {
+    instr_unary(cpu, arm::Fcpy::new(data.val), Unary::Sqrt)
+}
+
+pub fn fcmp<V: Version>(cpu: &mut Cpu<V>, data: arm::Fcmp::Bf) -> cpu::InstrStatus {
+    let double = data.sz_bit.get() == 1;
+    if let Some(status) = check(cpu, data.cond.get(), double) {
+        return status
+    }
+
+    let signal_nans = data.e_bit.get() == 1;
+    let vfp = &mut cpu.coproc_vfp;
+    if double {
+        let m = reg::<f64>(data.vm.get(), data.m_bit.get());
+        compare::<f64>(vfp, reg::<f64>(data.vd.get(), data.d_bit.get()), Some(m), signal_nans);
+    } else {
+        let m = reg::<f32>(data.vm.get(), data.m_bit.get());
+        compare::<f32>(vfp, reg::<f32>(data.vd.get(), data.d_bit.get()), Some(m), signal_nans);
+    }
+    cpu::InstrStatus::InBlock
+}
+
+pub fn fcmpz<V: Version>(cpu: &mut Cpu<V>, data: arm::Fcmpz::Bf) -> cpu::InstrStatus {
+    let double = data.sz_bit.get() == 1;
+    if let Some(status) = check(cpu, data.cond.get(), double) {
+        return status
+    }
+
+    let signal_nans = data.e_bit.get() == 1;
+    let vfp = &mut cpu.coproc_vfp;
+    if double {
+        compare::<f64>(vfp, reg::<f64>(data.vd.get(), data.d_bit.get()), None, signal_nans);
+    } else {
+        compare::<f32>(vfp, reg::<f32>(data.vd.get(), data.d_bit.get()), None, signal_nans);
+    }
+    cpu::InstrStatus::InBlock
+}
+
+/// FCVTDS when the size bit is clear, FCVTSD when set. Like the other conversions and
+/// the comparisons, this is always scalar.
+pub fn fcvt<V: Version>(cpu: &mut Cpu<V>, data: arm::Fcvt::Bf) -> cpu::InstrStatus {
+    let double = data.sz_bit.get() == 1;
+    if let Some(status) = check(cpu, data.cond.get(), double) {
+        return status
+    }
+
+    let vfp = &mut cpu.coproc_vfp;
+    if double {
+        let x = vfp.get::<f64>(reg::<f64>(data.vm.get(), data.m_bit.get()));
+        let res: f32 = vfp.convert(x);
+        vfp.set(reg::<f32>(data.vd.get(), data.d_bit.get()), res);
+    } else {
+        let x = vfp.get::<f32>(reg::<f32>(data.vm.get(), data.m_bit.get()));
+        let res: f64 = vfp.convert(x);
+        vfp.set(reg::<f64>(data.vd.get(), data.d_bit.get()), res);
+    }
+    cpu::InstrStatus::InBlock
+}
+
+/// FUITO/FSITO: converts the integer in a single register
+pub fn fito<V: Version>(cpu: &mut Cpu<V>, data: arm::Fito::Bf) -> cpu::InstrStatus {
+    let double = data.sz_bit.get() == 1;
+    if let Some(status) = check(cpu, data.cond.get(), double) {
+        return status
+    }
+
+    let vfp = &mut cpu.coproc_vfp;
+    let val = vfp.word(reg::<f32>(data.vm.get(), data.m_bit.get()));
+    let signed = data.signed_bit.get() == 1;
+    if double {
+        let res: f64 = vfp.from_int(val, signed);
+        vfp.set(reg::<f64>(data.vd.get(), data.d_bit.get()), res);
+    } else {
+        let res: f32 = vfp.from_int(val, signed);
+        vfp.set(reg::<f32>(data.vd.get(), data.d_bit.get()), res);
+    }
+    cpu::InstrStatus::InBlock
+}
+
+/// FTOUI/FTOSI: converts to an integer in a single register
+pub fn ftoi<V: Version>(cpu: &mut Cpu<V>, data: arm::Ftoi::Bf) -> cpu::InstrStatus {
+    let double = data.sz_bit.get() == 1;
+    if let Some(status) = check(cpu, data.cond.get(), double) {
+        return status
+    }
+
+    let vfp = &mut cpu.coproc_vfp;
+    let signed = data.signed_bit.get() == 1;
+    let round_zero = data.z_bit.get() == 1;
+    let res = if double {
+        let x = vfp.get::<f64>(reg::<f64>(data.vm.get(), data.m_bit.get()));
+        vfp.to_int(x, signed, round_zero)
+    } else {
+        let x = vfp.get::<f32>(reg::<f32>(data.vm.get(), data.m_bit.get()));
+        vfp.to_int(x, signed, round_zero)
+    };
+    vfp.set_word(reg::<f32>(data.vd.get(), data.d_bit.get()), res);
+    cpu::InstrStatus::InBlock
+}
+
+pub fn fmsr<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmsr::Bf) -> cpu::InstrStatus {
+    if let Some(status) = check(cpu, data.cond.get(), false) {
+        return status
+    }
+    let val = cpu.regs[data.rd.get() as usize];
+    cpu.coproc_vfp.set_word(reg::<f32>(data.vn.get(), data.n_bit.get()), val);
+    cpu::InstrStatus::InBlock
+}
+
+pub fn fmrs<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmrs::Bf) -> cpu::InstrStatus {
+    if let Some(status) = check(cpu, data.cond.get(), false) {
+        return status
+    }
+    cpu.regs[data.rd.get() as usize] = cpu.coproc_vfp.word(reg::<f32>(data.vn.get(), data.n_bit.get()));
+    cpu::InstrStatus::InBlock
+}
+
+/// Moves between a core register and half of a double register, the high half if `high`
+fn instr_transfer_half<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmdlr::Bf, high: bool, to_vfp: bool)
+    -> cpu::InstrStatus {
+
+    if let Some(status) = check(cpu, data.cond.get(), true) {
+        return status
+    }
+    let index = data.vn.get() as usize * 2 + high as usize;
+    let rd = data.rd.get() as usize;
+    if to_vfp {
+        let val = cpu.regs[rd];
+        cpu.coproc_vfp.set_word(index, val);
+    } else {
+        cpu.regs[rd] = cpu.coproc_vfp.word(index);
+    }
+    cpu::InstrStatus::InBlock
+}
+
+pub fn fmdlr<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmdlr::Bf) -> cpu::InstrStatus {
+    instr_transfer_half(cpu, data, false, true)
+}
+
+pub fn fmdhr<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmdhr::Bf) -> cpu::InstrStatus {
+    instr_transfer_half(cpu, arm::Fmdlr::new(data.val), true, true)
+}
+
+pub fn fmrdl<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmrdl::Bf) -> cpu::InstrStatus {
+    instr_transfer_half(cpu, arm::Fmdlr::new(data.val), false, false)
+}
+
+pub fn fmrdh<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmrdh::Bf) -> cpu::InstrStatus {
+    instr_transfer_half(cpu, arm::Fmdlr::new(data.val), true, false)
+}
+
+/// Moves between Rd/Rn and two consecutive words of the register file, starting at `index`
+fn instr_transfer_pair<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmdrr::Bf, index: usize, double: bool,
+                                   to_vfp: bool) -> cpu::InstrStatus {
+    if let Some(status) = check(cpu, data.cond.get(), double) {
+        return status
+    }
+    let rd = data.rd.get() as usize;
+    let rn = data.rn.get() as usize;
+    if to_vfp {
+        let (lo, hi) = (cpu.regs[rd], cpu.regs[rn]);
+        cpu.coproc_vfp.set_word(index, lo);
+        cpu.coproc_vfp.set_word(index + 1, hi);
+    } else {
+        cpu.regs[rd] = cpu.coproc_vfp.word(index);
+        cpu.regs[rn] = cpu.coproc_vfp.word(index + 1);
+    }
+    cpu::InstrStatus::InBlock
+}
+
+pub fn fmsrr<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmsrr::Bf) -> cpu::InstrStatus {
+    let index = reg::<f32>(data.vm.get(), data.m_bit.get());
+    instr_transfer_pair(cpu, arm::Fmdrr::new(data.val), index, false, true)
+}
+
+pub fn fmrrs<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmrrs::Bf) -> cpu::InstrStatus {
+    let index = reg::<f32>(data.vm.get(), data.m_bit.get());
+    instr_transfer_pair(cpu, arm::Fmdrr::new(data.val), index, false, false)
+}
+
+pub fn fmdrr<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmdrr::Bf) -> cpu::InstrStatus {
+    let index = data.vm.get() as usize * 2;
+    instr_transfer_pair(cpu, data, index, true, true)
+}
+
+pub fn fmrrd<V: Version>(cpu: &mut Cpu<V>, data: arm::Fmrrd::Bf) -> cpu::InstrStatus {
+    let index = data.vm.get() as usize * 2;
+    instr_transfer_pair(cpu, arm::Fmdrr::new(data.val), index, true, false)
+}
+
+/// First word of the register file named by a load/store, and how many words a transfer
+/// of `count` registers covers
+fn transfer_words(data: arm::Fld::Bf, count: u32) -> (usize, usize) {
+    if data.sz_bit.get() == 1 {
+        (data.vd.get() as usize * 2, count as usize * 2)
+    } else {
+        (reg::<f32>(data.vd.get(), data.d_bit.get()), count as usize)
+    }
+}
+
+fn instr_load_store<V: Version>(cpu: &mut Cpu<V>, data: arm::Fld::Bf, load: bool) -> cpu::InstrStatus {
+    if let Some(status) = check(cpu, data.cond.get(), data.sz_bit.get() == 1) {
+        return status
+    }
+
+    let base = cpu.regs[data.rn.get() as usize];
+    let offset = data.offset.get() << 2;
+    let addr = if data.u_bit.get() == 1 { base.wrapping_add(offset) } else { base.wrapping_sub(offset) };
+
+    let (first, words) = transfer_words(data, 1);
+    for i in 0..words {
+        let word_addr = addr + i as u32 * 4;
+        if load {
+            let val = cpu.mpu.dmem_read::<u32>(word_addr);
+            cpu.coproc_vfp.set_word(first + i, val);
+        } else {
+            let val = cpu.coproc_vfp.word(first + i);
+            cpu.mpu.dmem_write::<u32>(word_addr, val);
+        }
+    }
+    cpu::InstrStatus::InBlock
+}
+
+fn instr_load_store_multiple<V: Version>(cpu: &mut Cpu<V>, data: arm::Fldm::Bf, load: bool) -> cpu::InstrStatus {
+    if let Some(status) = check(cpu, data.cond.get(), data.sz_bit.get() == 1) {
+        return status
+    }
+
+    let rn = data.rn.get() as usize;
+    let base = cpu.regs[rn];
+    let offset = data.offset.get() << 2;
+    let (start, writeback) = match (data.p_bit.get(), data.u_bit.get(), data.w_bit.get()) {
+        (0, 1, _) => (base, base.wrapping_add(offset)),
+        (1, 0, 1) => (base.wrapping_sub(offset), base.wrapping_sub(offset)),
+        _ => return cpu.undefined_instr()
+    };
+
+    // Double transfers with an odd offset are FLDMX/FSTMX, whose extra word is skipped
+    let count = if data.sz_bit.get() == 1 { data.offset.get() / 2 } else { data.offset.get() };
+    let (first, words) = transfer_words(arm::Fld::new(data.val), count);
+    let words = words.min(32 - first);
+    for i in 0..words {
+        let word_addr = start + i as u32 * 4;
+        if load {
+            let val = cpu.mpu.dmem_read::<u32>(word_addr);
+            cpu.coproc_vfp.set_word(first + i, val);
+        } else {
+            let val = cpu.coproc_vfp.word(first + i);
+            cpu.mpu.dmem_write::<u32>(word_addr, val);
+        }
+    }
+
+    if data.w_bit.get() == 1 && !cpu.access_aborted() {
+        cpu.regs[rn] = writeback;
+    }
+    cpu::InstrStatus::InBlock
+}
+
+pub fn fld<V: Version>(cpu: &mut Cpu<V>, data: arm::Fld::Bf) -> cpu::InstrStatus {
+    instr_load_store(cpu, data, true)
+}
+
+pub fn fst<V: Version>(cpu: &mut Cpu<V>, data: arm::Fst::Bf) -> cpu::InstrStatus {
+    instr_load_store(cpu, arm::Fld::new(data.val), false)
+}
+
+pub fn fldm<V: Version>(cpu: &mut Cpu<V>, data: arm::Fldm::Bf) -> cpu::InstrStatus {
+    instr_load_store_multiple(cpu, data, true)
+}
+
+pub fn fstm<V: Version>(cpu: &mut Cpu<V>, data: arm::Fstm::Bf) -> cpu::InstrStatus {
+    instr_load_store_multiple(cpu, arm::Fldm::new(data.val), false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::test_cpu;
+
+    fn exec(cpu: &mut Cpu<cpu::v6>, instr: u32) {
+        arm::decode::<cpu::v6>(instr)(cpu, instr);
+    }
+
+    const MCR_CPACR_R0: u32 = 0xEE010F50;
+    const FMXR_FPEXC_R0: u32 = 0xEEE80A10;
+    const FMXR_FPSCR_R0: u32 = 0xEEE10A10;
+    const FADDS_S0_S1_S2: u32 = 0xEE300A81;
+    const FCMPS_S0_S1: u32 = 0xEEB40A60;
+    const FMSTAT: u32 = 0xEEF1FA10;
+    const FSTS_S0_R1: u32 = 0xED810A00;
+    const FLDMIAS_R1_S4_S5: u32 = 0xEC912A02;
+    const FADDS_S8_S16_S24: u32 = 0xEE384A0C;
+    const FADDS_S8_S16_S0: u32 = 0xEE384A00;
+    const FADDS_S0_S8_S16: u32 = 0xEE340A08;
+    const FNEGD_D4_D10: u32 = 0xEEB14B4A;
+
+    fn enable_vfp(cpu: &mut Cpu<cpu::v6>) {
+        cpu.regs[0] = 0b1111 << 20;
+        exec(cpu, MCR_CPACR_R0);
+        cpu.regs[0] = 1 << 30;
+        exec(cpu, FMXR_FPEXC_R0);
+    }
+
+    #[test]
+    fn access_denied() {
+        let mut cpu = test_cpu(cpu::v6);
+        exec(&mut cpu, FADDS_S0_S1_S2);
+        assert_eq!(cpu.cpsr.mode.get(), cpu::Mode::Und as u32);
+        assert_eq!(cpu.regs[14], 0x104);
+
+        // Granting access through the CPACR still leaves VFP disabled in FPEXC
+        cpu.reset(0x100);
+        cpu.regs[0] = 0b1111 << 20;
+        exec(&mut cpu, MCR_CPACR_R0);
+        exec(&mut cpu, FADDS_S0_S1_S2);
+        assert_eq!(cpu.cpsr.mode.get(), cpu::Mode::Und as u32);
+    }
+
+    #[test]
+    fn execute() {
+        let mut cpu = test_cpu(cpu::v6);
+        enable_vfp(&mut cpu);
+        cpu.coproc_vfp.set_word(1, 1.5f32.to_bits());
+        cpu.coproc_vfp.set_word(2, 2.25f32.to_bits());
+        exec(&mut cpu, FADDS_S0_S1_S2);
+        assert_eq!(cpu.coproc_vfp.word(0), 3.75f32.to_bits());
+
+        exec(&mut cpu, FCMPS_S0_S1);
+        exec(&mut cpu, FMSTAT);
+        assert_eq!(cpu.cpsr.val >> 28, 0b0010);
+
+        cpu.regs[1] = 0x200;
+        cpu.mpu.dmem_write::<u32>(0x204, 7);
+        exec(&mut cpu, FSTS_S0_R1);
+        exec(&mut cpu, FLDMIAS_R1_S4_S5);
+        assert_eq!(cpu.coproc_vfp.word(4), 3.75f32.to_bits());
+        assert_eq!(cpu.coproc_vfp.word(5), 7);
+        assert_eq!(cpu.regs[1], 0x200);
+    }
+
+    #[test]
+    fn short_vectors() {
+        let mut cpu = test_cpu(cpu::v6);
+        enable_vfp(&mut cpu);
+        for i in 0..32 {
+            cpu.coproc_vfp.set_word(i, (i as f32).to_bits());
+        }
+        // LEN 3, STRIDE 1
+        cpu.regs[0] = 0b010 << 16;
+        exec(&mut cpu, FMXR_FPSCR_R0);
+
+        exec(&mut cpu, FADDS_S8_S16_S24);
+        for i in 0..3 {
+            assert_eq!(cpu.coproc_vfp.word(8 + i), (40.0 + 2.0 * i as f32).to_bits());
+        }
+        assert_eq!(cpu.coproc_vfp.word(11), 11f32.to_bits());
+
+        // Second operands in the first bank are scalars
+        exec(&mut cpu, FADDS_S8_S16_S0);
+        for i in 0..3 {
+            assert_eq!(cpu.coproc_vfp.word(8 + i), (16.0 + i as f32).to_bits());
+        }
+
+        // And so are whole operations with their destination there
+        exec(&mut cpu, FADDS_S0_S8_S16);
+        assert_eq!(cpu.coproc_vfp.word(0), 32f32.to_bits());
+        assert_eq!(cpu.coproc_vfp.word(1), 1f32.to_bits());
+
+        // LEN 2, STRIDE 2 over doubles, wrapping around from d10 to d8
+        cpu.coproc_vfp.set(10, 1.5f64);
+        cpu.coproc_vfp.set(8, -2.5f64);
+        cpu.regs[0] = 0b11 << 20 | 0b001 << 16;
+        exec(&mut cpu, FMXR_FPSCR_R0);
+        exec(&mut cpu, FNEGD_D4_D10);
+        assert_eq!(cpu.coproc_vfp.get::<f64>(4), -1.5);
+        assert_eq!(cpu.coproc_vfp.get::<f64>(6), 2.5);
+    }
+}
@@ -6,6 +6,13 @@ pub fn b_1<V: Version>(cpu: &mut Cpu<V>, data: thumb::B1::Bf) -> cpu::InstrStatu
     let offset_8 = data.signed_imm_8.get();
     let cond = data.cond.get();
 
+    // Condition 0b1111 is SWI, which shares this encoding; 0b1110 is undefined
+    match cond {
+        0b1110 => return cpu.undefined_instr(),
+        0b1111 => return cpu::instructions_thumb::swi(cpu, thumb::Swi::new(data.val)),
+        _ => {}
+    }
+
     if !cpu::cond_passed(cond as u32, &cpu.cpsr) {
         return cpu::InstrStatus::InBlock;
     }
@@ -24,13 +31,6 @@ pub fn branch<V: Version>(cpu: &mut Cpu<V>, data: thumb::Branch::Bf) -> cpu::Ins
             cpu.branch(addr);
             cpu::InstrStatus::Branched
         },
-        0b01 => {
-            let addr = (cpu.regs[14] + (offset_11 << 1) as u32) & 0xFFFFFFFC;
-            cpu.regs[14] = (cpu.regs[15] - 2) as u32 | 1;
-            cpu.cpsr.thumb_bit.set(0);
-            cpu.branch(addr);
-            cpu::InstrStatus::Branched
-        },
         0b10 => {
             cpu.regs[14] = (cpu.regs[15] as i32 + (sign_extend32(offset_11 as u32, 11) << 12)) as u32;
             cpu::InstrStatus::InBlock
@@ -45,6 +45,20 @@ pub fn branch<V: Version>(cpu: &mut Cpu<V>, data: thumb::Branch::Bf) -> cpu::Ins
     }
 }
 
+/// Second half of a BL prefix/suffix pair that switches to ARM state
+pub fn blx_1<V: Version>(cpu: &mut Cpu<V>, data: thumb::Blx1::Bf) -> cpu::InstrStatus {
+    let offset_11 = data.offset_11.get();
+    if offset_11 & 1 != 0 {
+        return cpu.undefined_instr();
+    }
+
+    let addr = (cpu.regs[14] + (offset_11 << 1) as u32) & 0xFFFFFFFC;
+    cpu.regs[14] = (cpu.regs[15] - 2) as u32 | 1;
+    cpu.cpsr.thumb_bit.set(0);
+    cpu.branch(addr);
+    cpu::InstrStatus::Branched
+}
+
 pub fn blx_2<V: Version>(cpu: &mut Cpu<V>, data: thumb::Blx2::Bf) -> cpu::InstrStatus {
     let rm = data.rm.get() | (data.h2.get() << 3);
     let addr = cpu.regs[rm as usize];
@@ -62,3 +76,40 @@ pub fn bx<V: Version>(cpu: &mut Cpu<V>, data: thumb::Bx::Bf) -> cpu::InstrStatus
     cpu.branch(addr & 0xFFFFFFFE);
     cpu::InstrStatus::Branched
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::test_thumb_cpu;
+
+    fn exec<V: Version>(cpu: &mut Cpu<V>, instr: u16) {
+        thumb::decode::<V>(instr)(cpu, instr);
+    }
+
+    #[test]
+    fn bl_blx_pair() {
+        let mut cpu = test_thumb_cpu(cpu::v5);
+        exec(&mut cpu, 0xF000); // bl prefix
+        assert_eq!(cpu.regs[14], 0x104);
+
+        cpu.regs[15] = 0x106;
+        exec(&mut cpu, 0xE812); // blx suffix
+        assert_eq!(cpu.cpsr.thumb_bit.get(), 0);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x128);
+        assert_eq!(cpu.regs[14], 0x105);
+
+        let mut cpu = test_thumb_cpu(cpu::v5);
+        exec(&mut cpu, 0xF000);
+        cpu.regs[15] = 0x106;
+        exec(&mut cpu, 0xF812); // bl suffix
+        assert_eq!(cpu.cpsr.thumb_bit.get(), 1);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x128);
+    }
+
+    #[test]
+    fn blx_odd_offset() {
+        let mut cpu = test_thumb_cpu(cpu::v6);
+        exec(&mut cpu, 0xE813);
+        assert_eq!(cpu.cpsr.mode.get(), cpu::Mode::Und as u32);
+    }
+}
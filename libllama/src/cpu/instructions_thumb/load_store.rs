@@ -22,6 +22,7 @@ pub fn ldr_1<V: Version>(cpu: &mut Cpu<V>, data: thumb::Ldr1::Bf) -> cpu::InstrS
     let addr = base_val + immed_5 * 4;
     assert!( V::is::<cpu::v5>() || addr % 4 == 0 );
     // TODO: determine behavior based on CP15 r1 bit_U (22)
+    cpu.save_for_abort(1 << data.rd.get());
     cpu.regs[data.rd.get() as usize] = cpu.mpu.dmem_read::<u32>(addr);
 
     cpu::InstrStatus::InBlock
@@ -39,6 +40,7 @@ pub fn ldr_3<V: Version>(cpu: &mut Cpu<V>, data: thumb::Ldr3::Bf) -> cpu::InstrS
     let immed_8 = data.immed_8.get() as u32;
     let addr = (cpu.regs[15] & 0xFFFFFFFC) + immed_8 * 4;
     assert!( V::is::<cpu::v5>() || addr % 4 == 0 );
+    cpu.save_for_abort(1 << data.rd.get());
     cpu.regs[data.rd.get() as usize] = cpu.mpu.dmem_read::<u32>(addr);
 
     cpu::InstrStatus::InBlock
@@ -180,3 +182,40 @@ pub fn strh_2<V: Version>(cpu: &mut Cpu<V>, data: thumb::Strh2::Bf) -> cpu::Inst
                                                          | ((data.rm.get() as u32) << 0);
     cpu::instructions_arm::strh(cpu, arm::Strh::new(arminst))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::test_thumb_cpu;
+
+    fn exec(cpu: &mut Cpu<cpu::v5>, instr: u16) {
+        thumb::decode::<cpu::v5>(instr)(cpu, instr);
+    }
+
+    #[test]
+    fn ldmia_writeback() {
+        let mut cpu = test_thumb_cpu(cpu::v5);
+        cpu.mpu.dmem_write::<u32>(0x10, 0xAAAA);
+        cpu.mpu.dmem_write::<u32>(0x14, 0xBBBB);
+
+        cpu.regs[2] = 0x10;
+        exec(&mut cpu, 0xCA03); // ldmia r2!, {r0, r1}
+        assert_eq!((cpu.regs[0], cpu.regs[1], cpu.regs[2]), (0xAAAA, 0xBBBB, 0x18));
+
+        // The base is in the list, so it takes the loaded value instead of writeback
+        cpu.regs[0] = 0x10;
+        exec(&mut cpu, 0xC803); // ldmia r0!, {r0, r1}
+        assert_eq!((cpu.regs[0], cpu.regs[1]), (0xAAAA, 0xBBBB));
+    }
+
+    #[test]
+    fn stmia_writeback() {
+        let mut cpu = test_thumb_cpu(cpu::v5);
+        cpu.regs[0] = 0x20;
+        cpu.regs[1] = 0x1234;
+        exec(&mut cpu, 0xC003); // stmia r0!, {r0, r1}
+        assert_eq!(cpu.mpu.dmem_read::<u32>(0x20), 0x20);
+        assert_eq!(cpu.mpu.dmem_read::<u32>(0x24), 0x1234);
+        assert_eq!(cpu.regs[0], 0x28);
+    }
+}
@@ -1,15 +1,59 @@
 use cpu::{self, arm, thumb, Cpu, Version};
 
 pub fn rev<V: Version>(cpu: &mut Cpu<V>, data: thumb::Rev::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v6>());
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
     let arminst: u32 = 0b1110011010111111_0000_11110011_0000
                                           | ((data.rd.get() as u32) << 12)
                                                         | ((data.rn.get() as u32) << 0);
     cpu::instructions_arm::rev(cpu, arm::Rev::new(arminst))
 }
 
+pub fn rev16<V: Version>(cpu: &mut Cpu<V>, data: thumb::Rev16::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    let arminst: u32 = 0b1110011010111111_0000_11111011_0000
+                                          | ((data.rd.get() as u32) << 12)
+                                                        | ((data.rm.get() as u32) << 0);
+    cpu::instructions_arm::rev16(cpu, arm::Rev16::new(arminst))
+}
+
+pub fn revsh<V: Version>(cpu: &mut Cpu<V>, data: thumb::Revsh::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    let arminst: u32 = 0b1110011011111111_0000_11111011_0000
+                                          | ((data.rd.get() as u32) << 12)
+                                                        | ((data.rm.get() as u32) << 0);
+    cpu::instructions_arm::revsh(cpu, arm::Revsh::new(arminst))
+}
+
+pub fn sxtb<V: Version>(cpu: &mut Cpu<V>, data: thumb::Sxtb::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    let arminst: u32 = 0b1110011010101111_0000_00000111_0000
+                                          | ((data.rd.get() as u32) << 12)
+                                                        | ((data.rm.get() as u32) << 0);
+    cpu::instructions_arm::sxtb(cpu, arm::Sxtb::new(arminst))
+}
+
+pub fn sxth<V: Version>(cpu: &mut Cpu<V>, data: thumb::Sxth::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    let arminst: u32 = 0b1110011010111111_0000_00000111_0000
+                                          | ((data.rd.get() as u32) << 12)
+                                                        | ((data.rm.get() as u32) << 0);
+    cpu::instructions_arm::sxth(cpu, arm::Sxth::new(arminst))
+}
+
 pub fn uxtb<V: Version>(cpu: &mut Cpu<V>, data: thumb::Uxtb::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v6>());
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
     let arminst: u32 = 0b1110011011101111_0000_00000111_0000
                                           | ((data.rd.get() as u32) << 12)
                                                         | ((data.rm.get() as u32) << 0);
@@ -17,9 +61,52 @@ pub fn uxtb<V: Version>(cpu: &mut Cpu<V>, data: thumb::Uxtb::Bf) -> cpu::InstrSt
 }
 
 pub fn uxth<V: Version>(cpu: &mut Cpu<V>, data: thumb::Uxth::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v6>());
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
     let arminst: u32 = 0b1110011011111111_0000_00000111_0000
                                           | ((data.rd.get() as u32) << 12)
                                                         | ((data.rm.get() as u32) << 0);
     cpu::instructions_arm::uxth(cpu, arm::Uxth::new(arminst))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::test_thumb_cpu;
+
+    fn exec<V: Version>(cpu: &mut Cpu<V>, instr: u16) {
+        thumb::decode::<V>(instr)(cpu, instr);
+    }
+
+    #[test]
+    fn extend_and_reverse() {
+        let mut cpu = test_thumb_cpu(cpu::v6);
+        cpu.regs[1] = 0x8081_F280;
+        let cases = [
+            (0xB248, 0xFFFF_FF80), // sxtb r0, r1
+            (0xB208, 0xFFFF_F280), // sxth r0, r1
+            (0xB2C8, 0x0000_0080), // uxtb r0, r1
+            (0xB288, 0x0000_F280), // uxth r0, r1
+            (0xBA08, 0x80F2_8180), // rev r0, r1
+            (0xBA48, 0x8180_80F2), // rev16 r0, r1
+            (0xBAC8, 0xFFFF_80F2), // revsh r0, r1
+        ];
+        for &(instr, expected) in cases.iter() {
+            exec(&mut cpu, instr);
+            assert_eq!(cpu.regs[0], expected, "{:04X}", instr);
+        }
+    }
+
+    #[test]
+    fn undefined_on_v5() {
+        for &instr in [0xB248, 0xB208, 0xB2C8, 0xB288, 0xBA08, 0xBA48, 0xBAC8].iter() {
+            let mut cpu = test_thumb_cpu(cpu::v5);
+            cpu.regs[0] = 0x1234;
+            exec(&mut cpu, instr);
+            assert_eq!(cpu.regs[0], 0x1234);
+            assert_eq!(cpu.cpsr.mode.get(), cpu::Mode::Und as u32);
+            assert_eq!(cpu.regs[14], 0x102);
+        }
+    }
+}
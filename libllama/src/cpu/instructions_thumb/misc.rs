@@ -1,7 +1,6 @@
 use cpu::{self, arm, thumb, Cpu, Version};
 
 pub fn bkpt<V: Version>(cpu: &mut Cpu<V>, data: thumb::Bkpt::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v5>());
     let immed_lo = data.immed_8.get() as u32 & 0b1111;
     let immed_hi = data.immed_8.get() as u32 >> 4;
     let arminst: u32 = 0b11100001001000000000_0000_0111_0000
@@ -10,10 +9,79 @@ pub fn bkpt<V: Version>(cpu: &mut Cpu<V>, data: thumb::Bkpt::Bf) -> cpu::InstrSt
     cpu::instructions_arm::bkpt(cpu, arm::Bkpt::new(arminst))
 }
 
+pub fn cps<V: Version>(cpu: &mut Cpu<V>, data: thumb::Cps::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    let arminst: u32 = 0b111100010000_1_0_0_00000000_0_0_0_0_00000
+                                        | ((data.im.get() as u32) << 18)
+                                                      | ((data.a_bit.get() as u32) << 8)
+                                                        | ((data.i_bit.get() as u32) << 7)
+                                                          | ((data.f_bit.get() as u32) << 6);
+    cpu::instructions_arm::cps(cpu, arm::Cps::new(arminst))
+}
+
+pub fn setend<V: Version>(cpu: &mut Cpu<V>, data: thumb::Setend::Bf) -> cpu::InstrStatus {
+    if !V::is::<cpu::v6>() {
+        return cpu.undefined_instr();
+    }
+    let arminst: u32 = 0b1111000100000001000000_0_000000000
+                                                | ((data.e_bit.get() as u32) << 9);
+    cpu::instructions_arm::setend(cpu, arm::Setend::new(arminst))
+}
+
 pub fn swi<V: Version>(cpu: &mut Cpu<V>, data: thumb::Swi::Bf) -> cpu::InstrStatus {
-    assert!(V::is::<cpu::v5>());
     let arminst: u32 = 0b111011110000000000000000_00000000
                                                   | ((data.immed_8.get() as u32) << 0);
     cpu::instructions_arm::swi(cpu, arm::Swi::new(arminst))
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::test_thumb_cpu;
+
+    fn exec<V: Version>(cpu: &mut Cpu<V>, instr: u16) {
+        thumb::decode::<V>(instr)(cpu, instr);
+    }
+
+    #[test]
+    fn cps_setend() {
+        let mut cpu = test_thumb_cpu(cpu::v6);
+        exec(&mut cpu, 0xB662); // cpsie i
+        assert_eq!(cpu.cpsr.disable_irq_bit.get(), 0);
+        assert_eq!(cpu.cpsr.disable_fiq_bit.get(), 1);
+        exec(&mut cpu, 0xB673); // cpsid if
+        assert_eq!(cpu.cpsr.disable_irq_bit.get(), 1);
+        assert_eq!(cpu.cpsr.disable_fiq_bit.get(), 1);
+
+        exec(&mut cpu, 0xB658); // setend be
+        assert_eq!(cpu.cpsr.e_bit.get(), 1);
+        exec(&mut cpu, 0xB650); // setend le
+        assert_eq!(cpu.cpsr.e_bit.get(), 0);
+
+        let mut cpu = test_thumb_cpu(cpu::v5);
+        exec(&mut cpu, 0xB662);
+        assert_eq!(cpu.cpsr.mode.get(), cpu::Mode::Und as u32);
+
+        let mut cpu = test_thumb_cpu(cpu::v5);
+        exec(&mut cpu, 0xB658);
+        assert_eq!(cpu.cpsr.mode.get(), cpu::Mode::Und as u32);
+        assert_eq!(cpu.cpsr.e_bit.get(), 0);
+    }
+
+    #[test]
+    fn exceptions() {
+        let mut cpu = test_thumb_cpu(cpu::v5);
+        exec(&mut cpu, 0xBE07); // bkpt #7
+        assert_eq!(cpu.cpsr.mode.get(), cpu::Mode::Abt as u32);
+        assert_eq!(cpu.regs[14], 0x104);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x08000020);
+
+        let mut cpu = test_thumb_cpu(cpu::v6);
+        exec(&mut cpu, 0xDF01); // swi #1
+        assert_eq!(cpu.cpsr.mode.get(), cpu::Mode::Svc as u32);
+        assert_eq!(cpu.regs[14], 0x102);
+        assert_eq!(cpu.regs[15] - cpu.get_pc_offset(), 0x1FFFFFB0);
+    }
+}
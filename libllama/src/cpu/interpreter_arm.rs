@@ -44,8 +44,8 @@ mod interpreter {
     use cpu;
     pub use cpu::instructions_arm::*;
 
-    pub fn undef<V: cpu::Version>(cpu: &mut cpu::Cpu<V>, instr: u32) -> cpu::InstrStatus {
-        panic!("Unimplemented instruction! {:#X}: {:#08X}", cpu.regs[15] - cpu.get_pc_offset(), instr)
+    pub fn undef<V: cpu::Version>(cpu: &mut cpu::Cpu<V>, _instr: u32) -> cpu::InstrStatus {
+        cpu.undefined_instr()
     }
 
     #[inline(always)]
@@ -56,11 +56,16 @@ mod interpreter {
 
 include!(concat!(env!("OUT_DIR"), "/arm.decoder.rs"));
 
+/// Executes the instruction at `addr`, returning its cost from `timing::arm_cycles`
 #[inline]
-pub fn interpret_next<V: Version>(cpu: &mut Cpu<V>, addr: u32) -> InstrStatus {
+pub fn interpret_next<V: Version>(cpu: &mut Cpu<V>, addr: u32) -> (InstrStatus, u32) {
     let instr = cpu.mpu.imem_read::<u32>(addr);
-    let inst_fn = *cpu.arm_decode_cache.get_or(instr, &mut ());
+    if cpu.mpu.fault_pending() {
+        // Taken as a prefetch abort once we return
+        return (InstrStatus::InBlock, 1)
+    }
+    let (inst_fn, cycles) = *cpu.arm_decode_cache.get_or(instr, &mut ());
     
     // trace!("ARM{:?} @ {:08X}: {} ({:08X})", cpu._version, addr, ::cpu::arm::disasm(instr), instr);
-    inst_fn(cpu, instr)
+    (inst_fn(cpu, instr), cycles as u32)
 }
@@ -5,18 +5,23 @@ pub type InstFn<V> = fn(&mut Cpu<V>, u16) -> InstrStatus;
 mod interpreter {
     use cpu;
     pub use cpu::instructions_thumb::*;
-    pub fn undef<V: cpu::Version>(cpu: &mut cpu::Cpu<V>, instr: u16) -> cpu::InstrStatus {
-        panic!("Unimplemented instruction! {:#X}: {:?}", cpu.regs[15] - cpu.get_pc_offset(), instr)
+    pub fn undef<V: cpu::Version>(cpu: &mut cpu::Cpu<V>, _instr: u16) -> cpu::InstrStatus {
+        cpu.undefined_instr()
     }
 }
 
 include!(concat!(env!("OUT_DIR"), "/thumb.decoder.rs"));
 
+/// Executes the instruction at `addr`, returning its cost from `timing::thumb_cycles`
 #[inline]
-pub fn interpret_next<V: Version>(cpu: &mut Cpu<V>, addr: u32) -> InstrStatus {
+pub fn interpret_next<V: Version>(cpu: &mut Cpu<V>, addr: u32) -> (InstrStatus, u32) {
     let instr = cpu.mpu.imem_read::<u16>(addr);
-    let inst_fn = *cpu.thumb_decode_cache.get_or(instr as u32, &mut ());
+    if cpu.mpu.fault_pending() {
+        // Taken as a prefetch abort once we return
+        return (InstrStatus::InBlock, 1)
+    }
+    let (inst_fn, cycles) = *cpu.thumb_decode_cache.get_or(instr as u32, &mut ());
 
     // trace!("THUMB{:?} @ {:08X}: {} ({:04X})", cpu._version, addr, ::cpu::thumb::disasm(instr), instr);
-    inst_fn(cpu, instr)
+    (inst_fn(cpu, instr), cycles as u32)
 }
@@ -5,6 +5,8 @@ use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::cell::Cell;
 use std::sync::mpsc;
 
+use savestate::{self, SaveState, StateReader, StateWriter};
+
 pub trait IrqType: fmt::Debug + Copy + Clone {
     fn index(&self) -> u32;
 }
@@ -51,6 +53,8 @@ impl IrqType for IrqType9 {
 
 #[derive(Debug, Copy, Clone)]
 pub enum IrqType11 {
+    VBlankTop    = 0x2A,
+    VBlankBottom = 0x2B,
     PxiSync = 80,
 }
 
@@ -90,6 +94,22 @@ impl IrqLine {
     }
 }
 
+/// CPU end of the FIQ input, which is level-triggered. Nothing on the 3DS drives it,
+/// so only the debugger raises it.
+#[derive(Clone)]
+pub struct FiqLine(Arc<AtomicBool>);
+
+impl FiqLine {
+    pub fn new() -> FiqLine {
+        FiqLine(Arc::new(AtomicBool::new(false)))
+    }
+    pub fn is_high(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+    pub fn set_high(&self, high: bool) {
+        self.0.store(high, Ordering::Relaxed)
+    }
+}
 
 
 
@@ -220,6 +240,16 @@ impl Aggregator {
         pending
     }
 
+    /// Makes interrupt `index` pending right away, if enabled, without going
+    /// through a client
+    pub(crate) fn raise(&mut self, index: u32) {
+        if self.sync_enabled.get() & (1u128 << (index as u128)) == 0 {
+            return
+        }
+        self.pending |= 1u128 << (index as u128);
+        self.line.sync.set(true);
+    }
+
     pub(crate) fn acknowledge(&mut self, which: u128) -> u128 {
         self.pending = self.drain_asserts() & !which;
         self.line.sync.set(self.pending != 0);
@@ -228,6 +258,26 @@ impl Aggregator {
     }
 }
 
+impl SaveState for Aggregator {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        let pending = self.drain_asserts();
+        w.put_u128(pending);
+        w.put_u128(self.sync_enabled.get());
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        // Throw away anything asserted since the state was saved
+        self.drain_asserts();
+
+        self.pending = r.get_u128()?;
+        self.set_enabled(r.get_u128()?);
+        self.line.sync.set(self.pending != 0);
+        self.line.async.store(self.pending != 0, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
 pub struct IrqSubsys {
     pub(crate) agg: Aggregator,
     pub(crate) sync_tx: IrqSyncClient,
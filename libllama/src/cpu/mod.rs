@@ -1,5 +1,6 @@
 mod cpu;
 
+pub mod breakpoints;
 pub mod caches;
 mod coproc;
 pub mod interpreter_arm;
@@ -14,8 +15,34 @@ pub mod instructions_arm;
 pub mod instructions_thumb;
 pub mod irq;
 pub mod regs;
+mod timing;
 
 pub enum InstrStatus {
     InBlock, // Advance PC by instruction width
     Branched, // Do not advance PC
 }
+
+/// A CPU with 64KiB of RAM at address 0, reset to run ARM code from 0x100
+#[cfg(test)]
+pub(crate) fn test_cpu<V: Version>(version: V) -> Cpu<V> {
+    use clock;
+    use cpu::irq::IrqSubsys;
+    use mem::{AddressBlock, MemController, UniqueMemoryBlock};
+
+    let irq = IrqSubsys::create();
+    let clk = clock::make_channel(irq.sync_tx.clone());
+    let mut memory = MemController::new();
+    memory.map_region(0, AddressBlock::UniqueRam(UniqueMemoryBlock::new(64)));
+    let mut cpu = Cpu::new(version, memory, irq.line.clone(), clk);
+    cpu.reset(0x100);
+    cpu
+}
+
+/// Same as `test_cpu`, but in Thumb state
+#[cfg(test)]
+pub(crate) fn test_thumb_cpu<V: Version>(version: V) -> Cpu<V> {
+    let mut cpu = test_cpu(version);
+    cpu.cpsr.thumb_bit.set(1);
+    cpu.branch(0x100);
+    cpu
+}
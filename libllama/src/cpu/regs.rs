@@ -1,4 +1,5 @@
 use cpu;
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 use std::iter::Iterator;
 use std::ops;
@@ -10,6 +11,8 @@ bf!(Psr[u32] {
     disable_fiq_bit: 6:6,
     disable_irq_bit: 7:7,
     disable_imp_abt: 8:8,
+    e_bit: 9:9,
+    ge_bits: 16:19,
     q_bit: 27:27,
     v_bit: 28:28,
     c_bit: 29:29,
@@ -57,6 +60,64 @@ impl GpRegs {
         }
         self.mode = mode;
     }
+
+    /// Storage of `reg` as banked for `mode`, which is the active register
+    /// if `mode` shares that bank with the current mode
+    fn bank_slot(&mut self, mode: cpu::Mode, reg: usize) -> &mut u32 {
+        assert!(reg < 15);
+        let current = &*self.banks.build_iter(self.mode).nth(reg).unwrap() as *const u32;
+        let slot = self.banks.build_iter(mode).nth(reg).unwrap();
+        if &*slot as *const u32 == current {
+            &mut self.active[reg]
+        } else {
+            slot
+        }
+    }
+
+    /// Reads r0-r14 as seen from `mode`, regardless of the current mode
+    pub fn banked(&mut self, mode: cpu::Mode, reg: usize) -> u32 {
+        *self.bank_slot(mode, reg)
+    }
+
+    pub fn set_banked(&mut self, mode: cpu::Mode, reg: usize, val: u32) {
+        *self.bank_slot(mode, reg) = val;
+    }
+}
+
+impl SaveState for GpRegs {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_u32(self.mode as u32);
+        let banks = &self.banks;
+        let all = self.active.iter()
+            .chain(banks.basic_bank.iter())
+            .chain(banks.usr_bank.iter())
+            .chain(banks.svc_bank.iter())
+            .chain(banks.abt_bank.iter())
+            .chain(banks.und_bank.iter())
+            .chain(banks.irq_bank.iter())
+            .chain(banks.fiq_bank.iter());
+        for reg in all {
+            w.put_u32(*reg);
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.mode = cpu::Mode::from_num(r.get_u32()?);
+        let banks = &mut self.banks;
+        let all = self.active.iter_mut()
+            .chain(banks.basic_bank.iter_mut())
+            .chain(banks.usr_bank.iter_mut())
+            .chain(banks.svc_bank.iter_mut())
+            .chain(banks.abt_bank.iter_mut())
+            .chain(banks.und_bank.iter_mut())
+            .chain(banks.irq_bank.iter_mut())
+            .chain(banks.fiq_bank.iter_mut());
+        for reg in all {
+            *reg = r.get_u32()?;
+        }
+        Ok(())
+    }
 }
 
 impl ops::Index<usize> for GpRegs {
@@ -97,3 +158,29 @@ impl GpBanks {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn banked_access() {
+        let mut regs = GpRegs::new(cpu::Mode::Svc);
+        regs[13] = 0x100;
+        regs.swap(cpu::Mode::Irq);
+        regs[13] = 0x200;
+        regs[8] = 0x8;
+
+        assert_eq!(regs.banked(cpu::Mode::Svc, 13), 0x100);
+        assert_eq!(regs.banked(cpu::Mode::Irq, 13), 0x200);
+        assert_eq!(regs.banked(cpu::Mode::Usr, 8), 0x8);
+        assert_eq!(regs.banked(cpu::Mode::Fiq, 8), 0);
+
+        regs.set_banked(cpu::Mode::Irq, 14, 0x300);
+        assert_eq!(regs[14], 0x300);
+        regs.set_banked(cpu::Mode::Svc, 14, 0x400);
+        regs.swap(cpu::Mode::Svc);
+        assert_eq!(regs[13], 0x100);
+        assert_eq!(regs[14], 0x400);
+    }
+}
@@ -0,0 +1,72 @@
+//! Rough instruction timings, after the cycle tables of the ARM946E-S manual
+//!
+//! Costs are in CPU cycles and leave out memory wait states, which the memory controller
+//! counts, and the pipeline refill after a branch. The ARM11 uses the same table for now.
+
+/// Cycles to refill the pipeline after anything that changes the PC
+pub const BRANCH_PENALTY: u32 = 2;
+
+/// Cycles to enter an IRQ or FIQ handler
+pub const EXCEPTION_CYCLES: u32 = 3;
+
+/// Registers moved by an LDM/STM/PUSH/POP register list
+fn transfers(reg_list: u32) -> u32 {
+    reg_list.count_ones().max(1)
+}
+
+pub fn arm_cycles(instr: u32) -> u8 {
+    let cycles = match bits!(instr, 25:27) {
+        0b000 if instr & 0x0FC000F0 == 0x00000090 => 2, // MUL, MLA
+        0b000 if instr & 0x0F8000F0 == 0x00800090 => 3, // Long multiplies
+        0b000 if instr & 0x0FB00FF0 == 0x01000090 => 2, // SWP, SWPB
+        0b000 if instr & 0x0E1000F0 == 0x000000D0 => 2, // LDRD
+        0b000 if instr & 0x0E1000F0 == 0x000000F0 => 2, // STRD
+        // Data processing with a register-specified shift reads one more register
+        0b000 if instr & 0x0E000090 == 0x00000010 => 2,
+        0b000 | 0b001 => 1,
+        0b010 | 0b011 => 1,
+        0b100 if bits!(instr, 28:31) != 0b1111 => transfers(bits!(instr, 0:15)) + 1, // LDM, STM
+        0b100 | 0b101 => 1,
+        // Coprocessor loads/stores and register transfers
+        0b110 => 2,
+        0b111 if bit!(instr, 24) == 0 => 2,
+        _ => 1,
+    };
+    cycles as u8
+}
+
+pub fn thumb_cycles(instr: u16) -> u8 {
+    let instr = instr as u32;
+    let cycles = match bits!(instr, 12:15) {
+        0b0100 if instr & 0xFFC0 == 0x4340 => 2, // MUL
+        0b1011 if instr & 0x0600 == 0x0400 => transfers(bits!(instr, 0:8)) + 1, // PUSH, POP
+        0b1100 => transfers(bits!(instr, 0:7)) + 1, // LDMIA, STMIA
+        _ => 1,
+    };
+    cycles as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arm_costs() {
+        assert_eq!(arm_cycles(0xE0821003), 1); // add r1, r2, r3
+        assert_eq!(arm_cycles(0xE0821413), 2); // add r1, r2, r3, lsl r4
+        assert_eq!(arm_cycles(0xE0010392), 2); // mul r1, r2, r3
+        assert_eq!(arm_cycles(0xE0C10392), 3); // smull r0, r1, r2, r3
+        assert_eq!(arm_cycles(0xE5912000), 1); // ldr r2, [r1]
+        assert_eq!(arm_cycles(0xE8BD800F), 6); // ldmia sp!, {r0-r3, pc}
+        assert_eq!(arm_cycles(0xEAFFFFFE), 1); // b .
+        assert_eq!(arm_cycles(0xEE110F10), 2); // mrc p15, 0, r0, c1, c0, 0
+    }
+
+    #[test]
+    fn thumb_costs() {
+        assert_eq!(thumb_cycles(0x1888), 1); // adds r0, r1, r2
+        assert_eq!(thumb_cycles(0x4348), 2); // muls r0, r1
+        assert_eq!(thumb_cycles(0xB5F0), 6); // push {r4-r7, lr}
+        assert_eq!(thumb_cycles(0xC807), 4); // ldmia r0!, {r0-r2}
+    }
+}
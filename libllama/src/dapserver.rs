@@ -0,0 +1,898 @@
+//! Debug Adapter Protocol server, for debugging from editors
+//!
+//! Each core is exposed as a thread (ARM9 = 1, ARM11 = 2) and, as with the GDB stub,
+//! both cores always halt together. There is no symbol information, so breakpoints are
+//! set on addresses through instruction or function breakpoints and apply to both cores.
+
+extern crate json;
+
+use std::io::{self, Read, Write};
+use std::str;
+use std::thread;
+use std::time::Duration;
+
+use log::LogLevel;
+use mio;
+use mio::tcp::{TcpListener, TcpStream};
+
+use self::json::JsonValue;
+
+use commands;
+use cpu::BreakReason;
+use dbgcore::{self, ActiveCpu, HwCtx, WatchKind};
+use dbgexpr::{Condition, Expr};
+use hwcore::Message;
+use msgs;
+use utils;
+
+const DAP_ADDR: &str = "127.0.0.1:4568";
+
+/// Deepest stack trace reported, in case the frame pointer chain loops
+const MAX_FRAMES: usize = 64;
+
+/// Most bytes returned by one `readMemory`; clients ask again for the rest
+const MAX_READ_BYTES: usize = 0x10000;
+
+type ReqResult = ::std::result::Result<JsonValue, String>;
+
+fn thread_id(cpu: ActiveCpu) -> u32 {
+    match cpu {
+        ActiveCpu::Arm9 => 1,
+        ActiveCpu::Arm11 => 2,
+    }
+}
+
+fn parse_thread(id: Option<u32>) -> ::std::result::Result<ActiveCpu, String> {
+    match id {
+        Some(1) => Ok(ActiveCpu::Arm9),
+        Some(2) => Ok(ActiveCpu::Arm11),
+        Some(id) => Err(format!("Unknown thread {}", id)),
+        None => Err("Missing thread id".to_owned()),
+    }
+}
+
+/// Frame ids keep the thread in the upper bits and the frame's depth in the lower ones
+fn frame_id(cpu: ActiveCpu, depth: usize) -> u32 {
+    thread_id(cpu) << 16 | depth as u32
+}
+
+fn parse_address(text: &str) -> ::std::result::Result<u32, String> {
+    utils::from_hex(text.trim()).map_err(|_| format!("Invalid address `{}`", text))
+}
+
+/// Walks the frame pointer chain, returning the address each frame is executing at
+///
+/// ARM code keeps its frame pointer in r11, pointing at the saved lr with the caller's
+/// frame pointer right below it (`push {fp, lr}; add fp, sp, #4`). Thumb code keeps it
+/// in r7, pointing at the caller's frame pointer with the saved lr right above it
+/// (`push {r7, lr}; mov r7, sp`). Whether a caller is Thumb code comes from bit 0
+/// of its return address.
+fn unwind<F>(pc: u32, fp: u32, thumb: bool, mut read_word: F) -> Vec<u32>
+    where F: FnMut(u32) -> Option<u32> {
+
+    let mut frames = vec![pc];
+    let mut fp = fp;
+    let mut thumb = thumb;
+    while frames.len() < MAX_FRAMES {
+        if fp == 0 || fp & 3 != 0 {
+            break
+        }
+        let (lr_addr, fp_addr) = if thumb {
+            (fp.wrapping_add(4), fp)
+        } else {
+            (fp, fp.wrapping_sub(4))
+        };
+        let (lr, next_fp) = match (read_word(lr_addr), read_word(fp_addr)) {
+            (Some(lr), Some(next_fp)) if lr != 0 => (lr, next_fp),
+            _ => break
+        };
+        frames.push(lr & !1);
+        thumb = lr & 1 != 0;
+
+        // The stack grows down, so callers' frames always sit higher up
+        if next_fp <= fp {
+            break
+        }
+        fp = next_fp;
+    }
+    frames
+}
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let mut group = [0u8; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = (group[0] as u32) << 16 | (group[1] as u32) << 8 | group[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_CHARS[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let mut bits = 0u32;
+    let mut num_bits = 0;
+    for c in text.bytes().filter(|c| *c != b'=') {
+        let val = BASE64_CHARS.iter().position(|b| *b == c)? as u32;
+        bits = bits << 6 | val;
+        num_bits += 6;
+        if num_bits >= 8 {
+            num_bits -= 8;
+            out.push((bits >> num_bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Collects the output of debugger commands run from the REPL
+struct ReplOutput(String);
+
+impl commands::Output for ReplOutput {
+    fn print(&mut self, level: LogLevel, msg: &str) {
+        if level == LogLevel::Error {
+            self.0 += "error: ";
+        }
+        self.0 += msg;
+        self.0 += "\n";
+    }
+}
+
+struct Session {
+    seq: u32,
+    /// Events to send once the response to the current request is out
+    events: Vec<JsonValue>,
+    /// Resume once the client is done configuring, as asked for by `launch`
+    resume_on_config: bool,
+    /// Thread used for requests that do not name one, like `readMemory`
+    current: ActiveCpu,
+    /// Addresses installed by `setInstructionBreakpoints`
+    instruction_bkpts: Vec<u32>,
+    /// Addresses installed by `setFunctionBreakpoints`
+    function_bkpts: Vec<u32>,
+    /// Whether the last thing the client heard about the cores is that they stopped
+    stopped: bool,
+    disconnected: bool,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            seq: 0,
+            events: Vec::new(),
+            resume_on_config: false,
+            current: ActiveCpu::Arm9,
+            instruction_bkpts: Vec::new(),
+            function_bkpts: Vec::new(),
+            stopped: false,
+            disconnected: false,
+        }
+    }
+
+    fn queue_event(&mut self, event: &str, body: JsonValue) {
+        let mut msg = JsonValue::new_object();
+        msg["type"] = "event".into();
+        msg["event"] = event.into();
+        if !body.is_null() {
+            msg["body"] = body;
+        }
+        self.events.push(msg);
+    }
+
+    fn queue_stopped(&mut self, cpu: ActiveCpu, reason: &str) {
+        let mut body = JsonValue::new_object();
+        body["reason"] = reason.into();
+        body["threadId"] = thread_id(cpu).into();
+        body["allThreadsStopped"] = true.into();
+        self.current = cpu;
+        self.stopped = true;
+        self.queue_event("stopped", body);
+    }
+
+    fn resume(&mut self, dbg: &mut dbgcore::DbgCore) {
+        dbg.ctx(self.current).resume();
+        self.stopped = false;
+    }
+}
+
+fn stop_reason(reason: &BreakReason) -> &'static str {
+    match *reason {
+        BreakReason::Breakpoint => "breakpoint",
+        BreakReason::Watchpoint { .. } => "data breakpoint",
+        BreakReason::UndefinedInstruction(..) | BreakReason::BusError { .. } => "exception",
+        _ => "pause",
+    }
+}
+
+fn describe_watch(kind: WatchKind) -> &'static str {
+    match kind {
+        WatchKind::Read => "read",
+        WatchKind::Write => "write",
+        WatchKind::Access => "access",
+    }
+}
+
+/// Replaces one kind of breakpoint on both cores
+fn replace_breakpoints(dbg: &mut dbgcore::DbgCore, installed: &mut Vec<u32>,
+                       new: Vec<(u32, Option<Condition>)>) {
+    let mut ctx = dbg.ctx(ActiveCpu::Arm9);
+    for &cpu in [ActiveCpu::Arm9, ActiveCpu::Arm11].iter() {
+        let mut hw = ctx.hw_of(cpu);
+        for addr in installed.iter() {
+            hw.del_breakpoint(*addr);
+        }
+        for &(addr, ref condition) in new.iter() {
+            let id = hw.set_breakpoint(addr);
+            hw.set_breakpoint_condition(id, condition.clone());
+        }
+    }
+    *installed = new.into_iter().map(|(addr, _)| addr).collect();
+}
+
+/// Parses the optional condition of a breakpoint, returning a rejected breakpoint on error
+fn parse_condition(bkpt: &JsonValue) -> ::std::result::Result<Option<Condition>, JsonValue> {
+    match bkpt["condition"].as_str() {
+        None | Some("") => Ok(None),
+        Some(text) => Condition::parse(text).map(Some).map_err(|e| {
+            let mut rejected = JsonValue::new_object();
+            rejected["verified"] = false.into();
+            rejected["message"] = format!("Invalid condition: {}", e).into();
+            rejected
+        })
+    }
+}
+
+fn req_initialize(session: &mut Session) -> ReqResult {
+    let mut caps = JsonValue::new_object();
+    caps["supportsConfigurationDoneRequest"] = true.into();
+    caps["supportsConditionalBreakpoints"] = true.into();
+    caps["supportsFunctionBreakpoints"] = true.into();
+    caps["supportsInstructionBreakpoints"] = true.into();
+    caps["supportsReadMemoryRequest"] = true.into();
+    caps["supportsWriteMemoryRequest"] = true.into();
+    caps["supportsSetVariable"] = true.into();
+    caps["supportsEvaluateForHovers"] = true.into();
+    caps["supportTerminateDebuggee"] = true.into();
+    session.queue_event("initialized", JsonValue::Null);
+    Ok(caps)
+}
+
+/// `launch` and `attach` both debug the already loaded machine; `launch` also starts it
+fn req_launch(args: &JsonValue, launch: bool, dbg: &mut dbgcore::DbgCore, session: &mut Session) -> ReqResult {
+    let stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+    if launch && !stop_on_entry {
+        session.resume_on_config = true;
+    } else if !dbg.ctx(session.current).running() {
+        let cpu = session.current;
+        session.queue_stopped(cpu, if launch { "entry" } else { "pause" });
+    }
+    Ok(JsonValue::Null)
+}
+
+fn req_threads() -> ReqResult {
+    let mut threads = JsonValue::new_array();
+    for &(cpu, name) in [(ActiveCpu::Arm9, "ARM9"), (ActiveCpu::Arm11, "ARM11")].iter() {
+        let mut thread = JsonValue::new_object();
+        thread["id"] = thread_id(cpu).into();
+        thread["name"] = name.into();
+        let _ = threads.push(thread);
+    }
+    let mut body = JsonValue::new_object();
+    body["threads"] = threads;
+    Ok(body)
+}
+
+fn req_set_breakpoints(args: &JsonValue) -> ReqResult {
+    let mut results = JsonValue::new_array();
+    for _ in args["breakpoints"].members() {
+        let mut bkpt = JsonValue::new_object();
+        bkpt["verified"] = false.into();
+        bkpt["message"] = "No source information; use instruction or function breakpoints".into();
+        let _ = results.push(bkpt);
+    }
+    let mut body = JsonValue::new_object();
+    body["breakpoints"] = results;
+    Ok(body)
+}
+
+/// Handles `setInstructionBreakpoints` and `setFunctionBreakpoints`, where function
+/// names have to be addresses
+fn req_set_addr_breakpoints(args: &JsonValue, functions: bool, dbg: &mut dbgcore::DbgCore,
+                            session: &mut Session) -> ReqResult {
+    let mut new = Vec::new();
+    let mut results = JsonValue::new_array();
+    for bkpt in args["breakpoints"].members() {
+        let addr = if functions {
+            parse_address(bkpt["name"].as_str().unwrap_or(""))
+        } else {
+            parse_address(bkpt["instructionReference"].as_str().unwrap_or(""))
+                .map(|addr| addr.wrapping_add(bkpt["offset"].as_i32().unwrap_or(0) as u32))
+        };
+
+        let result = match (addr, parse_condition(bkpt)) {
+            (Ok(addr), Ok(condition)) => {
+                new.push((addr, condition));
+                let mut result = JsonValue::new_object();
+                result["verified"] = true.into();
+                result["instructionReference"] = format!("0x{:08X}", addr).into();
+                result
+            }
+            (Err(e), _) => {
+                let mut result = JsonValue::new_object();
+                result["verified"] = false.into();
+                result["message"] = e.into();
+                result
+            }
+            (_, Err(rejected)) => rejected,
+        };
+        let _ = results.push(result);
+    }
+
+    let installed = if functions { &mut session.function_bkpts } else { &mut session.instruction_bkpts };
+    replace_breakpoints(dbg, installed, new);
+
+    let mut body = JsonValue::new_object();
+    body["breakpoints"] = results;
+    Ok(body)
+}
+
+fn req_stack_trace(args: &JsonValue, dbg: &mut dbgcore::DbgCore, session: &mut Session) -> ReqResult {
+    let cpu = parse_thread(args["threadId"].as_u32())?;
+    session.current = cpu;
+
+    let mut ctx = dbg.ctx(cpu);
+    let mut hw = ctx.hw_of(cpu);
+    let thumb = hw.is_thumb();
+    let fp = hw.read_reg(if thumb { 7 } else { 11 });
+    let frames = unwind(hw.pause_addr(), fp, thumb, |addr| {
+        let mut buf = [0u8; 4];
+        hw.read_mem(addr, &mut buf).ok().map(|_| u32::from_le_bytes(buf))
+    });
+
+    let start = args["startFrame"].as_usize().unwrap_or(0);
+    let levels = match args["levels"].as_usize() {
+        Some(0) | None => frames.len(),
+        Some(levels) => levels,
+    };
+    let mut stack_frames = JsonValue::new_array();
+    for (depth, pc) in frames.iter().enumerate().skip(start).take(levels) {
+        let mut frame = JsonValue::new_object();
+        frame["id"] = frame_id(cpu, depth).into();
+        frame["name"] = format!("0x{:08X}", pc).into();
+        frame["instructionPointerReference"] = format!("0x{:08X}", pc).into();
+        frame["line"] = 0.into();
+        frame["column"] = 0.into();
+        let _ = stack_frames.push(frame);
+    }
+
+    let mut body = JsonValue::new_object();
+    body["stackFrames"] = stack_frames;
+    body["totalFrames"] = frames.len().into();
+    Ok(body)
+}
+
+/// Only the innermost frame has known registers
+fn req_scopes(args: &JsonValue) -> ReqResult {
+    let frame = args["frameId"].as_u32().unwrap_or(0);
+    let cpu = parse_thread(Some(frame >> 16))?;
+
+    let mut scopes = JsonValue::new_array();
+    if frame & 0xFFFF == 0 {
+        let mut scope = JsonValue::new_object();
+        scope["name"] = "Registers".into();
+        scope["variablesReference"] = thread_id(cpu).into();
+        scope["expensive"] = false.into();
+        let _ = scopes.push(scope);
+    }
+    let mut body = JsonValue::new_object();
+    body["scopes"] = scopes;
+    Ok(body)
+}
+
+const REG_NAMES: [&str; 17] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7",
+    "r8", "r9", "r10", "r11", "r12", "sp", "lr", "pc", "cpsr"
+];
+
+fn read_named_reg(hw: &mut dyn HwCtx, index: usize) -> u32 {
+    match index {
+        15 => hw.pause_addr(),
+        16 => hw.read_cpsr(),
+        n => hw.read_reg(n),
+    }
+}
+
+fn register_variable(name: &str, val: u32) -> JsonValue {
+    let mut var = JsonValue::new_object();
+    var["name"] = name.into();
+    var["value"] = format!("0x{:08X}", val).into();
+    var["variablesReference"] = 0.into();
+    var["memoryReference"] = format!("0x{:08X}", val).into();
+    var
+}
+
+fn req_variables(args: &JsonValue, dbg: &mut dbgcore::DbgCore) -> ReqResult {
+    let cpu = parse_thread(args["variablesReference"].as_u32())?;
+    let mut ctx = dbg.ctx(cpu);
+    let mut hw = ctx.hw_of(cpu);
+
+    let mut vars = JsonValue::new_array();
+    for (index, name) in REG_NAMES.iter().enumerate() {
+        let _ = vars.push(register_variable(name, read_named_reg(&mut *hw, index)));
+    }
+    let mut body = JsonValue::new_object();
+    body["variables"] = vars;
+    Ok(body)
+}
+
+fn req_set_variable(args: &JsonValue, dbg: &mut dbgcore::DbgCore) -> ReqResult {
+    let cpu = parse_thread(args["variablesReference"].as_u32())?;
+    let name = args["name"].as_str().unwrap_or("");
+    let index = REG_NAMES.iter().position(|reg| *reg == name)
+        .ok_or(format!("Unknown register `{}`", name))?;
+
+    let mut ctx = dbg.ctx(cpu);
+    let mut hw = ctx.hw_of(cpu);
+    let expr = Expr::parse(args["value"].as_str().unwrap_or(""))?;
+    let val = expr.eval(&mut *hw)?;
+    match index {
+        15 => hw.branch_to(val),
+        16 => hw.write_cpsr(val),
+        n => hw.write_reg(n, val),
+    }
+
+    let mut body = JsonValue::new_object();
+    body["value"] = format!("0x{:08X}", read_named_reg(&mut *hw, index)).into();
+    Ok(body)
+}
+
+fn req_step(args: &JsonValue, dbg: &mut dbgcore::DbgCore, session: &mut Session) -> ReqResult {
+    let cpu = parse_thread(args["threadId"].as_u32())?;
+    let mut ctx = dbg.ctx(cpu);
+    ctx.pause();
+    let (cpu, reason) = ctx.step(cpu);
+    let reason = match reason {
+        BreakReason::LimitReached | BreakReason::Trapped | BreakReason::WFI => "step",
+        ref reason => stop_reason(reason),
+    };
+    session.queue_stopped(cpu, reason);
+    Ok(JsonValue::Null)
+}
+
+fn req_read_memory(args: &JsonValue, dbg: &mut dbgcore::DbgCore, session: &mut Session) -> ReqResult {
+    let base = parse_address(args["memoryReference"].as_str().unwrap_or(""))?;
+    let addr = base.wrapping_add(args["offset"].as_i32().unwrap_or(0) as u32);
+    let count = args["count"].as_usize().unwrap_or(0).min(MAX_READ_BYTES);
+
+    let mut ctx = dbg.ctx(session.current);
+    let mut hw = ctx.hw();
+    let mut data = vec![0u8; count];
+    if hw.read_mem(addr, &mut data).is_err() {
+        // Return whatever is readable up to the first unreadable byte
+        let readable = (0..count)
+            .take_while(|i| hw.read_mem(addr.wrapping_add(*i as u32), &mut data[*i..*i + 1]).is_ok())
+            .count();
+        data.truncate(readable);
+    }
+
+    let mut body = JsonValue::new_object();
+    body["address"] = format!("0x{:08X}", addr).into();
+    body["data"] = base64_encode(&data).into();
+    body["unreadableBytes"] = (count - data.len()).into();
+    Ok(body)
+}
+
+fn req_write_memory(args: &JsonValue, dbg: &mut dbgcore::DbgCore, session: &mut Session) -> ReqResult {
+    let base = parse_address(args["memoryReference"].as_str().unwrap_or(""))?;
+    let addr = base.wrapping_add(args["offset"].as_i32().unwrap_or(0) as u32);
+    let data = base64_decode(args["data"].as_str().unwrap_or(""))
+        .ok_or("Invalid base64 data".to_owned())?;
+
+    let mut ctx = dbg.ctx(session.current);
+    ctx.hw().write_mem(addr, &data)?;
+
+    let mut body = JsonValue::new_object();
+    body["bytesWritten"] = data.len().into();
+    Ok(body)
+}
+
+/// The REPL runs debugger commands; everything else evaluates debugger expressions
+fn req_evaluate(args: &JsonValue, dbg: &mut dbgcore::DbgCore, session: &mut Session) -> ReqResult {
+    let text = args["expression"].as_str().unwrap_or("");
+    let cpu = match args["frameId"].as_u32() {
+        Some(frame) => parse_thread(Some(frame >> 16))?,
+        None => session.current,
+    };
+    let mut ctx = dbg.ctx(cpu);
+    let mut body = JsonValue::new_object();
+
+    if args["context"].as_str() == Some("repl") {
+        let mut output = ReplOutput(String::new());
+        commands::handle(&mut ctx, &mut output, text.split_whitespace());
+        session.current = ctx.active_cpu();
+        body["result"] = output.0.trim_end().into();
+    } else {
+        let val = Expr::parse(text)?.eval(&mut *ctx.hw())?;
+        body["result"] = format!("0x{:08X}", val).into();
+        body["memoryReference"] = format!("0x{:08X}", val).into();
+    }
+    body["variablesReference"] = 0.into();
+    Ok(body)
+}
+
+/// Ends the session, taking out its breakpoints. The machine keeps running unless the
+/// client asked for the debuggee to be terminated, which leaves it paused.
+fn detach(dbg: &mut dbgcore::DbgCore, session: &mut Session, terminate: bool) {
+    replace_breakpoints(dbg, &mut session.instruction_bkpts, Vec::new());
+    replace_breakpoints(dbg, &mut session.function_bkpts, Vec::new());
+    if terminate {
+        dbg.ctx(session.current).pause();
+    } else {
+        session.resume(dbg);
+    }
+    session.disconnected = true;
+}
+
+fn handle_request(req: &JsonValue, dbg: &mut dbgcore::DbgCore, session: &mut Session) -> ReqResult {
+    let args = &req["arguments"];
+    let command = req["command"].as_str().unwrap_or("");
+    match command {
+        "initialize" => req_initialize(session),
+        "launch" => req_launch(args, true, dbg, session),
+        "attach" => req_launch(args, false, dbg, session),
+        "configurationDone" => {
+            if session.resume_on_config {
+                session.resume_on_config = false;
+                session.resume(dbg);
+            }
+            Ok(JsonValue::Null)
+        }
+        "disconnect" => {
+            let terminate = args["terminateDebuggee"].as_bool().unwrap_or(false);
+            detach(dbg, session, terminate);
+            Ok(JsonValue::Null)
+        }
+        "threads" => req_threads(),
+        "setBreakpoints" => req_set_breakpoints(args),
+        "setInstructionBreakpoints" => req_set_addr_breakpoints(args, false, dbg, session),
+        "setFunctionBreakpoints" => req_set_addr_breakpoints(args, true, dbg, session),
+        "setExceptionBreakpoints" => {
+            let mut body = JsonValue::new_object();
+            body["breakpoints"] = JsonValue::new_array();
+            Ok(body)
+        }
+        "stackTrace" => req_stack_trace(args, dbg, session),
+        "scopes" => req_scopes(args),
+        "variables" => req_variables(args, dbg),
+        "setVariable" => req_set_variable(args, dbg),
+        "continue" => {
+            session.resume(dbg);
+            let mut body = JsonValue::new_object();
+            body["allThreadsContinued"] = true.into();
+            Ok(body)
+        }
+        "next" | "stepIn" => req_step(args, dbg, session),
+        "pause" => {
+            let mut ctx = dbg.ctx(session.current);
+            // Already halted cores were reported when they stopped
+            if ctx.running() {
+                ctx.pause();
+                let cpu = parse_thread(args["threadId"].as_u32()).unwrap_or(session.current);
+                session.queue_stopped(cpu, "pause");
+            }
+            Ok(JsonValue::Null)
+        }
+        "readMemory" => req_read_memory(args, dbg, session),
+        "writeMemory" => req_write_memory(args, dbg, session),
+        "evaluate" => req_evaluate(args, dbg, session),
+        _ => Err(format!("Unsupported request `{}`", command)),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ErrorKind {
+    Io(io::Error),
+    Json(json::Error),
+    /// Message headers were not understood
+    Header,
+}
+
+pub type Result<T> = ::std::result::Result<T, ErrorKind>;
+
+fn write_all(stream: &mut TcpStream, mut data: &[u8]) -> io::Result<()> {
+    // The socket is non-blocking, but large responses must still go out whole
+    while !data.is_empty() {
+        match stream.write(data) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => data = &data[n..],
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+            Err(e) => return Err(e),
+        }
+    }
+    stream.flush()
+}
+
+fn send_message(mut msg: JsonValue, stream: &mut TcpStream, session: &mut Session) -> Result<()> {
+    session.seq += 1;
+    msg["seq"] = session.seq.into();
+    let body = msg.dump();
+    trace!("Sending DAP message: {}", body);
+    write_all(stream, format!("Content-Length: {}\r\n\r\n{}", body.len(), body).as_bytes())?;
+    Ok(())
+}
+
+fn flush_events(stream: &mut TcpStream, session: &mut Session) -> Result<()> {
+    for event in session.events.split_off(0) {
+        send_message(event, stream, session)?;
+    }
+    Ok(())
+}
+
+/// Takes the next complete message off the front of `data`
+fn load_message(data: &mut Vec<u8>) -> Result<Option<JsonValue>> {
+    let header_end = match data.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(end) => end,
+        None => return Ok(None)
+    };
+
+    let mut len = None;
+    for line in str::from_utf8(&data[..header_end]).map_err(|_| ErrorKind::Header)?.split("\r\n") {
+        let mut parts = line.splitn(2, ':');
+        if let (Some(name), Some(val)) = (parts.next(), parts.next()) {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                len = Some(val.trim().parse::<usize>().map_err(|_| ErrorKind::Header)?);
+            }
+        }
+    }
+    let len = len.ok_or(ErrorKind::Header)?;
+
+    let body_start = header_end + 4;
+    if data.len() < body_start + len {
+        return Ok(None)
+    }
+    let msg = {
+        let body = str::from_utf8(&data[body_start..body_start + len]).map_err(|_| ErrorKind::Header)?;
+        json::parse(body)?
+    };
+    data.drain(..body_start + len);
+    Ok(Some(msg))
+}
+
+fn handle_messages(data: &mut Vec<u8>, stream: &mut TcpStream, dbg: &mut dbgcore::DbgCore,
+                   session: &mut Session) -> Result<()> {
+    while let Some(req) = load_message(data)? {
+        trace!("Recieved DAP message: {}", req.dump());
+        if req["type"].as_str() != Some("request") {
+            continue
+        }
+
+        let mut response = JsonValue::new_object();
+        response["type"] = "response".into();
+        response["request_seq"] = req["seq"].clone();
+        response["command"] = req["command"].clone();
+        match handle_request(&req, dbg, session) {
+            Ok(body) => {
+                response["success"] = true.into();
+                if !body.is_null() {
+                    response["body"] = body;
+                }
+            }
+            Err(msg) => {
+                response["success"] = false.into();
+                response["message"] = msg.into();
+            }
+        }
+        send_message(response, stream, session)?;
+        flush_events(stream, session)?;
+
+        if session.disconnected {
+            return Ok(())
+        }
+    }
+    Ok(())
+}
+
+struct Connection {
+    socket: TcpStream,
+    /// Received data not yet handled, ending in a partial message
+    pending: Vec<u8>,
+    session: Session,
+}
+
+const TOKEN_LISTENER: mio::Token = mio::Token(0);
+const TOKEN_CLIENT: mio::Token = mio::Token(1);
+
+pub struct DapServer {
+    debugger: dbgcore::DbgCore,
+    dap_thread: Option<thread::JoinHandle<msgs::Client<Message>>>
+}
+
+impl DapServer {
+    pub fn new(msg_client: msgs::Client<Message>, debugger: dbgcore::DbgCore) -> DapServer {
+        let mut server = DapServer {
+            debugger: debugger,
+            dap_thread: None
+        };
+        server.start(msg_client);
+        server
+    }
+
+    pub fn start(&mut self, msg_client: msgs::Client<Message>) {
+        let mut debugger = self.debugger.clone();
+        self.dap_thread = Some(thread::Builder::new().name("DAPServer".to_owned()).spawn(move || {
+            let poll = mio::Poll::new()
+                .expect("Could not create mio polling instance!");
+            let listener = TcpListener::bind(&DAP_ADDR.parse().unwrap())
+                .expect("Could not bind TcpListener to port!");
+            poll.register(&listener, TOKEN_LISTENER, mio::Ready::readable(), mio::PollOpt::edge())
+                .expect("Could not register TcpListener to mio!");
+
+            let mut events = mio::Events::with_capacity(1024);
+            let mut connection: Option<Connection> = None;
+
+            info!("Starting DAP server on {}...", DAP_ADDR);
+
+            't: loop {
+                poll.poll(&mut events, Some(Duration::from_millis(100)))
+                    .expect("Could not poll for network events!");
+
+                for event in &events {
+                    match event.token() {
+                        TOKEN_LISTENER => loop {
+                            match listener.accept() {
+                                Ok((socket, _)) => {
+                                    info!("DAP server accepting connection");
+                                    poll.register(&socket, TOKEN_CLIENT, mio::Ready::readable(),
+                                                  mio::PollOpt::edge())
+                                        .expect("Could not register TCP client to mio!");
+                                    connection = Some(Connection {
+                                        socket: socket,
+                                        pending: Vec::new(),
+                                        session: Session::new(),
+                                    });
+                                }
+                                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                                Err(e) => panic!("DAP server IO error! {:?}", e)
+                            }
+                        },
+                        TOKEN_CLIENT => {
+                            let mut closed = false;
+                            if let Some(ref mut conn) = connection {
+                                let mut buf = [0u8; 4096];
+                                loop {
+                                    match conn.socket.read(&mut buf) {
+                                        Ok(0) => { closed = true; break }
+                                        Ok(l) => conn.pending.extend_from_slice(&buf[..l]),
+                                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                                        Err(e) => {
+                                            warn!("DAP client connection failed: {:?}", e);
+                                            closed = true;
+                                            break
+                                        }
+                                    }
+                                }
+                                if let Err(e) = handle_messages(&mut conn.pending, &mut conn.socket,
+                                                                &mut debugger, &mut conn.session) {
+                                    warn!("Dropping DAP client: {:?}", e);
+                                    closed = true;
+                                }
+                                if closed && !conn.session.disconnected {
+                                    // Going away without a `disconnect` request
+                                    detach(&mut debugger, &mut conn.session, false);
+                                }
+                                closed |= conn.session.disconnected;
+                            }
+                            if closed {
+                                info!("DAP client disconnected");
+                                connection = None;
+                            }
+                        }
+                        _ => unreachable!()
+                    }
+                }
+
+                let mut halted = None;
+                for msg in msg_client.try_iter() {
+                    match msg {
+                        Message::Quit => break 't,
+                        Message::Arm9Halted(reason) => halted = Some((ActiveCpu::Arm9, reason)),
+                        Message::Arm11Halted(reason) => halted = Some((ActiveCpu::Arm11, reason)),
+                        _ => {}
+                    }
+                }
+
+                if let Some((cpu, reason)) = halted {
+                    // Both cores halt together, as with the GDB stub
+                    debugger.ctx(cpu).pause();
+                    if let Some(ref mut conn) = connection {
+                        if let BreakReason::Trapped = reason {
+                            if conn.session.stopped {
+                                // Paused on behalf of a request that reported it already
+                                continue
+                            }
+                        }
+                        if let BreakReason::Watchpoint { addr, kind } = reason {
+                            let mut body = JsonValue::new_object();
+                            body["category"] = "console".into();
+                            body["output"] = format!("Watchpoint: {} at 0x{:08X}\n",
+                                                     describe_watch(kind), addr).into();
+                            conn.session.queue_event("output", body);
+                        }
+                        conn.session.queue_stopped(cpu, stop_reason(&reason));
+                        if let Err(e) = flush_events(&mut conn.socket, &mut conn.session) {
+                            warn!("Could not notify DAP client of halt: {:?}", e);
+                        }
+                    }
+                }
+            }
+
+            if let Some(ref mut conn) = connection {
+                conn.session.queue_event("terminated", JsonValue::Null);
+                let _ = flush_events(&mut conn.socket, &mut conn.session);
+            }
+            msg_client
+        }).unwrap())
+    }
+
+    pub fn wait(&mut self) {
+        if let Some(t) = self.dap_thread.take() {
+            t.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn unwind_frame_pointers() {
+        let mut stack = HashMap::new();
+        // ARM frame at 0x1000 called from ARM code at 0x2000, which was called
+        // from Thumb code whose frame is at 0x1100
+        stack.insert(0x1000, 0x2004);
+        stack.insert(0x0FFC, 0x1020);
+        stack.insert(0x1020, 0x3001);
+        stack.insert(0x101C, 0x1100);
+        stack.insert(0x1100, 0x0);
+        stack.insert(0x1104, 0x4000);
+
+        let frames = unwind(0x5000, 0x1000, false, |addr| stack.get(&addr).cloned());
+        assert_eq!(frames, vec![0x5000, 0x2004, 0x3000, 0x4000]);
+
+        // A chain that does not go up the stack is cut off
+        stack.insert(0x0FFC, 0x0F00);
+        let frames = unwind(0x5000, 0x1000, false, |addr| stack.get(&addr).cloned());
+        assert_eq!(frames, vec![0x5000, 0x2004]);
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        assert_eq!(base64_encode(b"llama"), "bGxhbWE=");
+        assert_eq!(base64_encode(b"ll"), "bGw=");
+        assert_eq!(base64_encode(b""), "");
+        for len in 0..8 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37 + 200) as u8).collect();
+            assert_eq!(base64_decode(&base64_encode(&data)), Some(data));
+        }
+        assert!(base64_decode("bG$h").is_none());
+    }
+
+    #[test]
+    fn split_messages() {
+        let mut data = b"Content-Length: 13\r\n\r\n{\"seq\":1}    Content-Len".to_vec();
+        let msg = load_message(&mut data).unwrap().unwrap();
+        assert_eq!(msg["seq"].as_u32(), Some(1));
+        assert_eq!(data, b"Content-Len");
+        assert!(load_message(&mut data).unwrap().is_none());
+    }
+}
@@ -1,10 +1,15 @@
 use std::sync;
 
-use cpu::{self, v5, v6};
+use cpu::{self, v5, v6, Mode};
+pub use cpu::breakpoints::{Breakpoint, Watchpoint, WatchKind};
 pub use cpu::irq::{IrqType9, IrqClient};
-use cpu::caches::Ops;
+use cpu::caches::{MemMgr, Ops};
+use dbgexpr::Condition;
 use hwcore;
 use io;
+use io::hid::ButtonState;
+use mem;
+use record::{self, Input, Reverse};
 
 #[derive(Clone)]
 pub struct DbgCore {
@@ -91,18 +96,61 @@ impl<'a> DbgContext<'a> {
     }
 
     pub fn hw<'b>(&'b mut self) -> Box<dyn HwCtx + 'b> {
-        match self.active_cpu {
+        let which = self.active_cpu;
+        self.hw_of(which)
+    }
+
+    pub fn hw_of<'b>(&'b mut self, which: ActiveCpu) -> Box<dyn HwCtx + 'b> {
+        match which {
             ActiveCpu::Arm9 => Box::new(self.hw9()),
             ActiveCpu::Arm11 => Box::new(self.hw11())
         }
     }
 
+    pub fn active_cpu(&self) -> ActiveCpu {
+        self.active_cpu
+    }
+
+    pub fn set_active_cpu(&mut self, which: ActiveCpu) {
+        self.active_cpu = which;
+    }
+
     pub fn trigger_irq(&mut self, irq: IrqType9) {
-        self.hwcore_mut().irq_tx.assert(irq);
+        self.hwcore_mut().apply_input(Input::Irq9(irq));
+    }
+
+    pub fn update_buttons(&mut self, change: ButtonState) {
+        self.hwcore_mut().apply_input(Input::Hid(change));
+    }
+
+    /// Drives the FIQ line of `which`, which stays at that level until changed again
+    pub fn set_fiq(&mut self, which: ActiveCpu, high: bool) {
+        self.hwcore_mut().apply_input(Input::Fiq(which, high));
+    }
+
+    pub fn fiq_asserted(&self, which: ActiveCpu) -> bool {
+        self.hwcore().fiq_line(which).is_high()
+    }
+
+    /// Runs one instruction on `which`. In record mode the other core may run first,
+    /// and halt before `which` gets to step.
+    pub fn step(&mut self, which: ActiveCpu) -> (ActiveCpu, cpu::BreakReason) {
+        match self.hwcore.record_step(which) {
+            Some(halt) => halt,
+            None => (which, self.hw_of(which).step())
+        }
+    }
+
+    pub fn reverse_step(&mut self, which: ActiveCpu) -> record::Result<Reverse> {
+        self.hwcore.reverse_step(which)
+    }
+
+    pub fn reverse_continue(&mut self) -> record::Result<Reverse> {
+        self.hwcore.reverse_continue()
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum ActiveCpu {
     Arm9, Arm11
 }
@@ -119,6 +167,75 @@ pub enum CpuMut<'a> {
     v6(&'a mut cpu::Cpu<v6>),
 }
 
+/// Registers the debugger can access beyond what `read_reg`/`read_cpsr` offer
+#[derive(Copy, Clone, Debug)]
+pub enum DbgReg {
+    /// r0-r15 of the current mode, r15 being the address of the current instruction
+    Gp(usize),
+    Cpsr,
+    Spsr(cpu::Mode),
+    /// r0-r14 as banked for the given mode
+    Banked(cpu::Mode, usize),
+    Cp15Control,
+    /// ARM9 only
+    Cp15MpuRegion(usize),
+    /// ARM11 only
+    Cp15Ttbr(usize),
+    /// Status of the last data and prefetch aborts, and the address of the last data abort
+    Cp15Dfsr,
+    Cp15Ifsr,
+    Cp15Far,
+}
+
+/// Modes with their own SPSR, r13 and r14
+const BANKED_MODES: [(&str, Mode); 5] = [
+    ("fiq", Mode::Fiq), ("irq", Mode::Irq), ("svc", Mode::Svc), ("abt", Mode::Abt), ("und", Mode::Und)
+];
+
+/// Registers in the order of the GDB stub's target description, which is also their
+/// GDB number
+pub fn gdb_registers() -> Vec<(String, DbgReg)> {
+    let mut regs = Vec::new();
+    for n in 0..13 {
+        regs.push((format!("r{}", n), DbgReg::Gp(n)));
+    }
+    regs.push(("sp".to_owned(), DbgReg::Gp(13)));
+    regs.push(("lr".to_owned(), DbgReg::Gp(14)));
+    regs.push(("pc".to_owned(), DbgReg::Gp(15)));
+    regs.push(("cpsr".to_owned(), DbgReg::Cpsr));
+
+    for &(name, mode) in BANKED_MODES.iter() {
+        regs.push((format!("spsr_{}", name), DbgReg::Spsr(mode)));
+    }
+    for n in 8..15 {
+        regs.push((format!("r{}_usr", n), DbgReg::Banked(Mode::Usr, n)));
+    }
+    for n in 8..15 {
+        regs.push((format!("r{}_fiq", n), DbgReg::Banked(Mode::Fiq, n)));
+    }
+    for &(name, mode) in BANKED_MODES[1..].iter() {
+        regs.push((format!("r13_{}", name), DbgReg::Banked(mode, 13)));
+        regs.push((format!("r14_{}", name), DbgReg::Banked(mode, 14)));
+    }
+
+    regs.push(("cp15_control".to_owned(), DbgReg::Cp15Control));
+    for n in 0..8 {
+        regs.push((format!("cp15_mpu{}", n), DbgReg::Cp15MpuRegion(n)));
+    }
+    for n in 0..2 {
+        regs.push((format!("cp15_ttbr{}", n), DbgReg::Cp15Ttbr(n)));
+    }
+    regs.push(("cp15_dfsr".to_owned(), DbgReg::Cp15Dfsr));
+    regs.push(("cp15_ifsr".to_owned(), DbgReg::Cp15Ifsr));
+    regs.push(("cp15_far".to_owned(), DbgReg::Cp15Far));
+    regs
+}
+
+/// Looks up a register by its GDB number
+pub fn gdb_register(num: usize) -> Option<DbgReg> {
+    gdb_registers().get(num).map(|&(_, reg)| reg)
+}
+
 macro_rules! any_cpu {
     ($self:expr, mut $ident:ident; $code:block) => {
         match $self.cpu_mut() {
@@ -135,8 +252,8 @@ macro_rules! any_cpu {
 }
 
 pub trait HwCtx {
-    fn cpu_ref(&self) -> CpuRef;
-    fn cpu_mut(&mut self) -> CpuMut;
+    fn cpu_ref(&self) -> CpuRef<'_>;
+    fn cpu_mut(&mut self) -> CpuMut<'_>;
 
     fn read_mem(&mut self, address: u32, bytes: &mut [u8]) -> Result<(), String> {
         any_cpu!(self, mut cpu; {
@@ -146,11 +263,25 @@ pub trait HwCtx {
         })
     }
 
-    fn write_mem(&mut self, address: u32, bytes: &[u8]) {
+    /// Reads memory at a virtual address the way the CPU would see it, without the side
+    /// effects of `read_mem`, so that evaluating breakpoint conditions leaves the run alone
+    fn peek_mem(&self, address: u32, bytes: &mut [u8]) -> Result<(), String> {
+        any_cpu!(self, ref cpu; {
+            cpu.mpu.debug_read_buf(address, bytes)
+        })
+    }
+
+    fn write_mem(&mut self, address: u32, bytes: &[u8]) -> Result<(), String> {
         any_cpu!(self, mut cpu; {
             cpu.mpu.icache_invalidate();
             cpu.mpu.dcache_invalidate();
-            cpu.mpu.main_mem_mut().write_buf(address, bytes);
+            cpu.mpu.main_mem_mut().debug_write_buf(address, bytes)
+        })
+    }
+
+    fn memory_map(&self) -> Vec<mem::MemRegion> {
+        any_cpu!(self, ref cpu; {
+            cpu.mpu.main_mem().region_map()
         })
     }
 
@@ -198,29 +329,193 @@ pub trait HwCtx {
         })
     }
 
-    fn step(&mut self) {
+    fn step(&mut self) -> cpu::BreakReason {
         any_cpu!(self, mut cpu; {
-            cpu.run(1);
+            cpu.run(1)
+        })
+    }
+
+    fn run(&mut self, num_instrs: u32) -> cpu::BreakReason {
+        any_cpu!(self, mut cpu; {
+            cpu.run(num_instrs)
+        })
+    }
+
+    /// Whether undefined instructions halt into the debugger instead of raising the exception
+    fn halts_on_undefined(&self) -> bool {
+        any_cpu!(self, ref cpu; {
+            cpu.halt_on_undefined
+        })
+    }
+
+    fn set_halt_on_undefined(&mut self, halt: bool) {
+        any_cpu!(self, mut cpu; {
+            cpu.halt_on_undefined = halt;
+        })
+    }
+
+    /// What accesses to unmapped memory do
+    fn bus_error_mode(&self) -> cpu::BusErrorMode {
+        any_cpu!(self, ref cpu; {
+            cpu.on_bus_error
         })
     }
 
-    fn set_breakpoint(&mut self, addr: u32) {
+    fn set_bus_error_mode(&mut self, mode: cpu::BusErrorMode) {
         any_cpu!(self, mut cpu; {
-            cpu.breakpoints.insert(addr);
+            cpu.on_bus_error = mode;
+        })
+    }
+
+    /// Returns None if the register does not exist on this CPU
+    fn read_dbg_reg(&mut self, reg: DbgReg) -> Option<u32> {
+        match reg {
+            DbgReg::Gp(15) => return Some(self.pause_addr()),
+            DbgReg::Gp(n) => return Some(self.read_reg(n)),
+            DbgReg::Cpsr => return Some(self.read_cpsr()),
+            _ => {}
+        }
+        any_cpu!(self, mut cpu; {
+            let ttbrs = match cpu.mpu {
+                MemMgr::Mpu(_) => None,
+                MemMgr::Mmu(ref mmu) => Some(mmu.page_tables)
+            };
+            match reg {
+                DbgReg::Spsr(mode) => cpu.get_spsr(mode).map(|spsr| spsr.val),
+                DbgReg::Banked(mode, n) => Some(cpu.regs.banked(mode, n)),
+                DbgReg::Cp15Control => {
+                    cpu.get_coprocessor(15).and_then(|cp15| cp15.move_out(1, 0, 0, 0))
+                }
+                DbgReg::Cp15MpuRegion(n) if ttbrs.is_none() => {
+                    cpu.get_coprocessor(15).and_then(|cp15| cp15.move_out(6, n, 0, 0))
+                }
+                DbgReg::Cp15Ttbr(n) => ttbrs.map(|tables| tables[n]),
+                DbgReg::Cp15Dfsr => Some(cpu.fault_regs().0),
+                DbgReg::Cp15Ifsr => Some(cpu.fault_regs().1),
+                DbgReg::Cp15Far => Some(cpu.fault_regs().2),
+                _ => None
+            }
+        })
+    }
+
+    /// Returns false if the register does not exist on this CPU
+    fn write_dbg_reg(&mut self, reg: DbgReg, value: u32) -> bool {
+        match reg {
+            DbgReg::Gp(15) => { self.branch_to(value); return true }
+            DbgReg::Gp(n) => { self.write_reg(n, value); return true }
+            DbgReg::Cpsr => { self.write_cpsr(value); return true }
+            _ => {}
+        }
+        any_cpu!(self, mut cpu; {
+            let has_mpu = match cpu.mpu {
+                MemMgr::Mpu(_) => true,
+                MemMgr::Mmu(_) => false
+            };
+            // CP15 writes go through the coprocessor so that their side effects apply
+            let (cpreg1, cpreg2, op2) = match reg {
+                DbgReg::Spsr(mode) => {
+                    return cpu.get_spsr(mode).map(|spsr| spsr.val = value).is_some()
+                }
+                DbgReg::Banked(mode, n) => {
+                    cpu.regs.set_banked(mode, n, value);
+                    return true
+                }
+                DbgReg::Cp15Control => (1, 0, 0),
+                DbgReg::Cp15MpuRegion(n) if has_mpu => (6, n, 0),
+                DbgReg::Cp15Ttbr(n) if !has_mpu => (2, 0, n),
+                _ => return false
+            };
+            match cpu.get_coprocessor(15).and_then(|cp15| cp15.move_in(cpreg1, cpreg2, 0, op2, value)) {
+                Some(effect) => { effect(cpu); true }
+                None => false
+            }
+        })
+    }
+
+    fn set_breakpoint(&mut self, addr: u32) -> u32 {
+        any_cpu!(self, mut cpu; {
+            cpu.breakpoints.insert(addr)
         })
     }
 
     fn has_breakpoint(&mut self, addr: u32) -> bool {
         any_cpu!(self, ref cpu; {
-            cpu.breakpoints.contains(&addr)
+            cpu.breakpoints.contains(addr)
         })
     }
 
     fn del_breakpoint(&mut self, addr: u32) {
         any_cpu!(self, mut cpu; {
-            cpu.breakpoints.remove(&addr);
+            cpu.breakpoints.remove(addr);
+        })
+    }
+
+    fn breakpoints(&self) -> Vec<Breakpoint> {
+        any_cpu!(self, ref cpu; {
+            cpu.breakpoints.list()
+        })
+    }
+
+    fn del_breakpoint_id(&mut self, id: u32) -> bool {
+        any_cpu!(self, mut cpu; {
+            cpu.breakpoints.remove_id(id).is_some()
+        })
+    }
+
+    fn enable_breakpoint(&mut self, id: u32, enabled: bool) -> bool {
+        any_cpu!(self, mut cpu; {
+            cpu.breakpoints.get_id_mut(id).map(|bkpt| bkpt.enabled = enabled).is_some()
+        })
+    }
+
+    fn ignore_breakpoint(&mut self, id: u32, count: u32) -> bool {
+        any_cpu!(self, mut cpu; {
+            cpu.breakpoints.get_id_mut(id).map(|bkpt| bkpt.ignore_count = count).is_some()
+        })
+    }
+
+    fn set_breakpoint_condition(&mut self, id: u32, condition: Option<Condition>) -> bool {
+        any_cpu!(self, mut cpu; {
+            cpu.breakpoints.get_id_mut(id).map(|bkpt| bkpt.condition = condition).is_some()
+        })
+    }
+
+    fn eval_condition(&mut self, condition: &Condition) -> Result<bool, String> {
+        condition.eval(self)
+    }
+
+    fn set_watchpoint(&mut self, addr: u32, len: u32, kind: WatchKind) {
+        any_cpu!(self, mut cpu; {
+            cpu.mpu.main_mem_mut().watchpoints.insert(addr, len, kind);
+        })
+    }
+
+    fn del_watchpoint(&mut self, addr: u32, len: u32, kind: WatchKind) -> bool {
+        any_cpu!(self, mut cpu; {
+            cpu.mpu.main_mem_mut().watchpoints.remove(addr, len, kind)
         })
     }
+
+    fn watchpoints(&self) -> Vec<Watchpoint> {
+        any_cpu!(self, ref cpu; {
+            cpu.mpu.main_mem().watchpoints.list().to_vec()
+        })
+    }
+}
+
+impl<'a> HwCtx for CpuMut<'a> {
+    fn cpu_ref(&self) -> CpuRef<'_> {
+        match *self {
+            CpuMut::v5(ref cpu) => CpuRef::v5(cpu),
+            CpuMut::v6(ref cpu) => CpuRef::v6(cpu),
+        }
+    }
+    fn cpu_mut(&mut self) -> CpuMut<'_> {
+        match *self {
+            CpuMut::v5(ref mut cpu) => CpuMut::v5(cpu),
+            CpuMut::v6(ref mut cpu) => CpuMut::v6(cpu),
+        }
+    }
 }
 
 pub struct DbgHw9Context<'a> {
@@ -237,10 +532,10 @@ impl<'a> DbgHw9Context<'a> {
 }
 
 impl<'a> HwCtx for DbgHw9Context<'a> {
-    fn cpu_ref(&self) -> CpuRef {
+    fn cpu_ref(&self) -> CpuRef<'_> {
         CpuRef::v5(&self.hw.arm9)
     }
-    fn cpu_mut(&mut self) -> CpuMut {
+    fn cpu_mut(&mut self) -> CpuMut<'_> {
         CpuMut::v5(&mut self.hw.arm9)
     }
 }
@@ -259,10 +554,18 @@ impl<'a> DbgHw11Context<'a> {
 }
 
 impl<'a> HwCtx for DbgHw11Context<'a> {
-    fn cpu_ref(&self) -> CpuRef {
+    fn cpu_ref(&self) -> CpuRef<'_> {
         CpuRef::v6(&self.hw.arm11)
     }
-    fn cpu_mut(&mut self) -> CpuMut {
+    fn cpu_mut(&mut self) -> CpuMut<'_> {
         CpuMut::v6(&mut self.hw.arm11)
     }
+
+    fn step(&mut self) -> cpu::BreakReason {
+        self.hw.run(1)
+    }
+
+    fn run(&mut self, num_instrs: u32) -> cpu::BreakReason {
+        self.hw.run(num_instrs)
+    }
 }
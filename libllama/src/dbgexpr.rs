@@ -0,0 +1,514 @@
+//! Debugger expressions, used for breakpoint conditions
+//!
+//! The textual syntax is C-like and operates on unsigned 32-bit values:
+//! - numbers in decimal or `0x` hex
+//! - registers `r0`-`r15`, `sp`, `lr`, `pc` and `cpsr`; `pc`/`r15` read as the
+//!   address of the current instruction
+//! - memory reads `[addr]` (32 bits), `u8[addr]`, `u16[addr]` and `u32[addr]`
+//! - unary `-`, `~`, `!`, arithmetic `* / % + -`, shifts `<< >>`, unsigned
+//!   comparisons, `== !=`, bitwise `& ^ |` and short-circuiting `&& ||`
+//!
+//! GDB sends its conditions as agent expression bytecode, which is evaluated here too.
+
+use std::fmt;
+
+use dbgcore::{self, DbgReg, HwCtx};
+
+pub type Result<T> = ::std::result::Result<T, String>;
+
+/// What expressions can observe of the machine
+pub trait EvalTarget {
+    fn eval_reg(&mut self, reg: usize) -> u32;
+    fn eval_cpsr(&mut self) -> u32;
+    /// Returns None if the register does not exist on this CPU
+    fn eval_dbg_reg(&mut self, reg: DbgReg) -> Option<u32>;
+    fn eval_read(&mut self, addr: u32, buf: &mut [u8]) -> Result<()>;
+}
+
+impl<H: HwCtx + ?Sized> EvalTarget for H {
+    fn eval_reg(&mut self, reg: usize) -> u32 {
+        match reg {
+            15 => self.pause_addr(),
+            n => self.read_reg(n)
+        }
+    }
+
+    fn eval_cpsr(&mut self) -> u32 {
+        self.read_cpsr()
+    }
+
+    fn eval_dbg_reg(&mut self, reg: DbgReg) -> Option<u32> {
+        self.read_dbg_reg(reg)
+    }
+
+    fn eval_read(&mut self, addr: u32, buf: &mut [u8]) -> Result<()> {
+        self.peek_mem(addr, buf)
+    }
+}
+
+fn read_le<T: EvalTarget + ?Sized>(target: &mut T, addr: u32, width: usize) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    target.eval_read(addr, &mut buf[..width])?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum UnOp { Neg, Not, LogNot }
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BinOp {
+    Mul, Div, Rem, Add, Sub, Shl, Shr,
+    Lt, Le, Gt, Ge, Eq, Ne,
+    And, Xor, Or, LogAnd, LogOr,
+}
+
+impl BinOp {
+    fn from_token(tok: &str) -> Option<(BinOp, u32)> {
+        Some(match tok {
+            "*" => (BinOp::Mul, 10), "/" => (BinOp::Div, 10), "%" => (BinOp::Rem, 10),
+            "+" => (BinOp::Add, 9), "-" => (BinOp::Sub, 9),
+            "<<" => (BinOp::Shl, 8), ">>" => (BinOp::Shr, 8),
+            "<" => (BinOp::Lt, 7), "<=" => (BinOp::Le, 7),
+            ">" => (BinOp::Gt, 7), ">=" => (BinOp::Ge, 7),
+            "==" => (BinOp::Eq, 6), "!=" => (BinOp::Ne, 6),
+            "&" => (BinOp::And, 5),
+            "^" => (BinOp::Xor, 4),
+            "|" => (BinOp::Or, 3),
+            "&&" => (BinOp::LogAnd, 2),
+            "||" => (BinOp::LogOr, 1),
+            _ => return None
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Const(u32),
+    Reg(usize),
+    Cpsr,
+    Deref(usize, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0 };
+        let expr = parser.expr(0)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(tok) => Err(format!("unexpected `{}`", tok))
+        }
+    }
+
+    pub fn eval<T: EvalTarget + ?Sized>(&self, target: &mut T) -> Result<u32> {
+        Ok(match *self {
+            Expr::Const(val) => val,
+            Expr::Reg(reg) => target.eval_reg(reg),
+            Expr::Cpsr => target.eval_cpsr(),
+            Expr::Deref(width, ref addr) => {
+                let addr = addr.eval(target)?;
+                read_le(target, addr, width)? as u32
+            }
+            Expr::Unary(op, ref e) => {
+                let val = e.eval(target)?;
+                match op {
+                    UnOp::Neg => val.wrapping_neg(),
+                    UnOp::Not => !val,
+                    UnOp::LogNot => (val == 0) as u32,
+                }
+            }
+            Expr::Binary(BinOp::LogAnd, ref a, ref b) =>
+                (a.eval(target)? != 0 && b.eval(target)? != 0) as u32,
+            Expr::Binary(BinOp::LogOr, ref a, ref b) =>
+                (a.eval(target)? != 0 || b.eval(target)? != 0) as u32,
+            Expr::Binary(op, ref a, ref b) => {
+                let (a, b) = (a.eval(target)?, b.eval(target)?);
+                match op {
+                    BinOp::Mul => a.wrapping_mul(b),
+                    BinOp::Div => a.checked_div(b).ok_or("division by zero")?,
+                    BinOp::Rem => a.checked_rem(b).ok_or("division by zero")?,
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::Shl => a.checked_shl(b).unwrap_or(0),
+                    BinOp::Shr => a.checked_shr(b).unwrap_or(0),
+                    BinOp::Lt => (a < b) as u32,
+                    BinOp::Le => (a <= b) as u32,
+                    BinOp::Gt => (a > b) as u32,
+                    BinOp::Ge => (a >= b) as u32,
+                    BinOp::Eq => (a == b) as u32,
+                    BinOp::Ne => (a != b) as u32,
+                    BinOp::And => a & b,
+                    BinOp::Xor => a ^ b,
+                    BinOp::Or => a | b,
+                    BinOp::LogAnd | BinOp::LogOr => unreachable!(),
+                }
+            }
+        })
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>> {
+    const OPS: [&str; 22] = [
+        "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+        "*", "/", "%", "+", "-", "<", ">", "&", "^", "|", "~", "!", "(", ")",
+    ];
+
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let len = if let Some(op) = OPS.iter().find(|op| rest.starts_with(*op)) {
+            op.len()
+        } else if rest.starts_with('[') || rest.starts_with(']') {
+            1
+        } else {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(format!("unexpected character `{}`", rest.chars().next().unwrap()))
+            }
+            len
+        };
+        tokens.push(rest[..len].to_owned());
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|s| s.as_str())
+    }
+
+    fn next(&mut self) -> Result<&'a str> {
+        let tok = self.peek().ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        Ok(tok)
+    }
+
+    fn expect(&mut self, tok: &str) -> Result<()> {
+        match self.next()? {
+            t if t == tok => Ok(()),
+            t => Err(format!("expected `{}`, found `{}`", tok, t))
+        }
+    }
+
+    fn expr(&mut self, min_prec: u32) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Some((op, prec)) = self.peek().and_then(BinOp::from_token) {
+            if prec <= min_prec {
+                break
+            }
+            self.pos += 1;
+            let rhs = self.expr(prec)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let op = match self.peek() {
+            Some("-") => UnOp::Neg,
+            Some("~") => UnOp::Not,
+            Some("!") => UnOp::LogNot,
+            _ => return self.primary()
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn deref(&mut self, width: usize) -> Result<Expr> {
+        self.expect("[")?;
+        let addr = self.expr(0)?;
+        self.expect("]")?;
+        Ok(Expr::Deref(width, Box::new(addr)))
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let tok = self.next()?;
+        let lower = tok.to_lowercase();
+        Ok(match lower.as_str() {
+            "(" => {
+                let e = self.expr(0)?;
+                self.expect(")")?;
+                e
+            }
+            "[" => {
+                self.pos -= 1;
+                self.deref(4)?
+            }
+            "u8" => self.deref(1)?,
+            "u16" => self.deref(2)?,
+            "u32" => self.deref(4)?,
+            "sp" => Expr::Reg(13),
+            "lr" => Expr::Reg(14),
+            "pc" => Expr::Reg(15),
+            "cpsr" => Expr::Cpsr,
+            s if s.starts_with("0x") => {
+                Expr::Const(u32::from_str_radix(&s[2..], 16)
+                    .map_err(|_| format!("bad hex number `{}`", tok))?)
+            }
+            s if s.starts_with(|c: char| c.is_ascii_digit()) => {
+                Expr::Const(s.parse().map_err(|_| format!("bad number `{}`", tok))?)
+            }
+            s if s.starts_with('r') => match s[1..].parse::<usize>() {
+                Ok(n) if n < 16 => Expr::Reg(n),
+                _ => return Err(format!("unknown register `{}`", tok))
+            },
+            _ => return Err(format!("unexpected `{}`", tok))
+        })
+    }
+}
+
+/// Upper bound on executed bytecodes, so that a looping expression cannot hang the CPU
+const AGENT_MAX_STEPS: usize = 0x1000;
+
+/// GDB agent expression bytecode, as found in `Z0` condition lists
+#[derive(Clone, Debug, PartialEq)]
+pub struct AgentExpr(pub Vec<u8>);
+
+impl AgentExpr {
+    pub fn eval<T: EvalTarget + ?Sized>(&self, target: &mut T) -> Result<u64> {
+        let code = &self.0;
+        let mut stack: Vec<u64> = Vec::new();
+        let mut pc = 0;
+
+        macro_rules! pop {
+            () => { stack.pop().ok_or("agent expression stack underflow")? };
+        }
+        macro_rules! imm {
+            ($n:expr) => {{
+                let bytes = code.get(pc .. pc + $n).ok_or("truncated agent expression")?;
+                pc += $n;
+                bytes.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64)
+            }};
+        }
+        macro_rules! binop {
+            (|$a:ident, $b:ident| $e:expr) => {{
+                let $b = pop!();
+                let $a = pop!();
+                stack.push($e);
+            }};
+        }
+
+        for _ in 0..AGENT_MAX_STEPS {
+            let op = *code.get(pc).ok_or("agent expression ran past its end")?;
+            pc += 1;
+            match op {
+                0x02 => binop!(|a, b| a.wrapping_add(b)),
+                0x03 => binop!(|a, b| a.wrapping_sub(b)),
+                0x04 => binop!(|a, b| a.wrapping_mul(b)),
+                0x05 => binop!(|a, b| (a as i64).checked_div(b as i64).ok_or("division by zero")? as u64),
+                0x06 => binop!(|a, b| a.checked_div(b).ok_or("division by zero")?),
+                0x07 => binop!(|a, b| (a as i64).checked_rem(b as i64).ok_or("division by zero")? as u64),
+                0x08 => binop!(|a, b| a.checked_rem(b).ok_or("division by zero")?),
+                0x09 => binop!(|a, b| a.checked_shl(b as u32).unwrap_or(0)),
+                0x0a => binop!(|a, b| (a as i64).checked_shr(b as u32).unwrap_or((a as i64) >> 63) as u64),
+                0x0b => binop!(|a, b| a.checked_shr(b as u32).unwrap_or(0)),
+                0x0e => { let a = pop!(); stack.push((a == 0) as u64) }
+                0x0f => binop!(|a, b| a & b),
+                0x10 => binop!(|a, b| a | b),
+                0x11 => binop!(|a, b| a ^ b),
+                0x12 => { let a = pop!(); stack.push(!a) }
+                0x13 => binop!(|a, b| (a == b) as u64),
+                0x14 => binop!(|a, b| ((a as i64) < (b as i64)) as u64),
+                0x15 => binop!(|a, b| (a < b) as u64),
+                0x16 => {
+                    let shift = 64u32.saturating_sub(imm!(1) as u32);
+                    let a = pop!();
+                    stack.push(if shift >= 64 { 0 } else { (((a << shift) as i64) >> shift) as u64 });
+                }
+                0x17 ..= 0x1a => {
+                    let width = 1 << (op - 0x17);
+                    let addr = pop!();
+                    stack.push(read_le(target, addr as u32, width)?);
+                }
+                0x20 => {
+                    let dest = imm!(2) as usize;
+                    if pop!() != 0 {
+                        pc = dest;
+                    }
+                }
+                0x21 => {
+                    let dest = code.get(pc .. pc + 2).ok_or("truncated agent expression")?;
+                    pc = (dest[0] as usize) << 8 | dest[1] as usize;
+                }
+                0x22 => stack.push(imm!(1)),
+                0x23 => stack.push(imm!(2)),
+                0x24 => stack.push(imm!(4)),
+                0x25 => stack.push(imm!(8)),
+                0x26 => {
+                    // Registers are numbered like in `g` and `p` packets
+                    let num = imm!(2) as usize;
+                    let val = dbgcore::gdb_register(num)
+                        .and_then(|reg| target.eval_dbg_reg(reg))
+                        .ok_or_else(|| format!("agent expression read unknown register {}", num))?;
+                    stack.push(val as u64);
+                }
+                0x27 => return Ok(pop!()),
+                0x28 => { let a = pop!(); stack.push(a); stack.push(a) }
+                0x29 => { pop!(); }
+                0x2a => {
+                    let bits = imm!(1) as u32;
+                    let a = pop!();
+                    stack.push(if bits >= 64 { a } else { a & ((1 << bits) - 1) });
+                }
+                0x2b => { let b = pop!(); let a = pop!(); stack.push(b); stack.push(a) }
+                0x32 => {
+                    let n = imm!(1) as usize;
+                    let val = *stack.iter().rev().nth(n).ok_or("agent expression stack underflow")?;
+                    stack.push(val);
+                }
+                0x33 => {
+                    let c = pop!(); let b = pop!(); let a = pop!();
+                    stack.push(c); stack.push(a); stack.push(b);
+                }
+                op => return Err(format!("unsupported agent expression opcode 0x{:02X}", op))
+            }
+        }
+        Err("agent expression did not terminate".to_owned())
+    }
+}
+
+/// Condition that has to hold for a breakpoint to halt the CPU
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    Expr(String, Expr),
+    /// Any of the expressions must be nonzero
+    Agent(Vec<AgentExpr>),
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition> {
+        Ok(Condition::Expr(text.trim().to_owned(), Expr::parse(text)?))
+    }
+
+    pub fn eval<T: EvalTarget + ?Sized>(&self, target: &mut T) -> Result<bool> {
+        match *self {
+            Condition::Expr(_, ref e) => Ok(e.eval(target)? != 0),
+            Condition::Agent(ref exprs) => {
+                for e in exprs {
+                    if e.eval(target)? != 0 {
+                        return Ok(true)
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Condition::Expr(ref text, _) => write!(f, "{}", text),
+            Condition::Agent(ref exprs) => write!(f, "<{} GDB agent expression(s)>", exprs.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::Mode;
+
+    struct Target {
+        regs: [u32; 16],
+        cpsr: u32,
+        mem: Vec<u8>,
+    }
+
+    impl EvalTarget for Target {
+        fn eval_reg(&mut self, reg: usize) -> u32 {
+            self.regs[reg]
+        }
+        fn eval_cpsr(&mut self) -> u32 {
+            self.cpsr
+        }
+        fn eval_dbg_reg(&mut self, reg: DbgReg) -> Option<u32> {
+            match reg {
+                DbgReg::Gp(n) => Some(self.regs[n]),
+                DbgReg::Cpsr => Some(self.cpsr),
+                DbgReg::Spsr(Mode::Svc) => Some(0x10),
+                _ => None
+            }
+        }
+        fn eval_read(&mut self, addr: u32, buf: &mut [u8]) -> Result<()> {
+            let addr = addr as usize;
+            let bytes = self.mem.get(addr .. addr + buf.len()).ok_or("out of bounds")?;
+            buf.copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    fn target() -> Target {
+        let mut t = Target { regs: [0; 16], cpsr: 0x1F, mem: vec![0; 0x20] };
+        t.regs[0] = 0x1FF80000;
+        t.regs[13] = 0x10;
+        t.mem[0x14 .. 0x18].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        t
+    }
+
+    fn eval(text: &str) -> Result<u32> {
+        Expr::parse(text)?.eval(&mut target())
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(eval("0x10 >> 2 | 1").unwrap(), 5);
+        assert_eq!(eval("-1").unwrap(), 0xFFFFFFFF);
+        assert_eq!(eval("!0 && ~0 == 0xFFFFFFFF").unwrap(), 1);
+        assert_eq!(eval("1 < 2 || 1 / 0").unwrap(), 1);
+        assert!(eval("1 / 0").is_err());
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        assert_eq!(eval("r0 == 0x1FF80000 && [sp+4] != 0").unwrap(), 1);
+        assert_eq!(eval("u8[sp + 4]").unwrap(), 0x78);
+        assert_eq!(eval("u16[r13+6]").unwrap(), 0x1234);
+        assert_eq!(eval("cpsr & 0x1F").unwrap(), 0x1F);
+        assert!(eval("[0x100]").is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Expr::parse("r16").is_err());
+        assert!(Expr::parse("1 +").is_err());
+        assert!(Expr::parse("(1").is_err());
+        assert!(Expr::parse("[1").is_err());
+        assert!(Expr::parse("1 2").is_err());
+        assert!(Expr::parse("r0 $ 1").is_err());
+    }
+
+    #[test]
+    fn agent_bytecode() {
+        // reg 0; const32 0x1FF80000; equal; end
+        let code = vec![0x26, 0x00, 0x00, 0x24, 0x1F, 0xF8, 0x00, 0x00, 0x13, 0x27];
+        let cond = Condition::Agent(vec![AgentExpr(code)]);
+        assert_eq!(cond.eval(&mut target()).unwrap(), true);
+
+        // reg 13; const8 4; add; ref8; const8 0x78; equal; if_goto 16; const8 0; end; const8 1; end
+        let code = vec![0x26, 0x00, 0x0D, 0x22, 0x04, 0x02, 0x17, 0x22, 0x78, 0x13,
+                        0x20, 0x00, 0x10, 0x22, 0x00, 0x27, 0x22, 0x01, 0x27];
+        assert_eq!(AgentExpr(code).eval(&mut target()).unwrap(), 1);
+
+        // reg 16 (cpsr); reg 19 (spsr_svc); add; end
+        let code = vec![0x26, 0x00, 0x10, 0x26, 0x00, 0x13, 0x02, 0x27];
+        assert_eq!(AgentExpr(code).eval(&mut target()).unwrap(), 0x2F);
+        // reg 17 (spsr_fiq) is not available; reg 0x100 does not exist
+        assert!(AgentExpr(vec![0x26, 0x00, 0x11, 0x27]).eval(&mut target()).is_err());
+        assert!(AgentExpr(vec![0x26, 0x01, 0x00, 0x27]).eval(&mut target()).is_err());
+
+        assert!(AgentExpr(vec![0x02]).eval(&mut target()).is_err());
+        assert!(AgentExpr(vec![0x21, 0x00, 0x00]).eval(&mut target()).is_err());
+    }
+}
@@ -4,15 +4,23 @@ use std::str;
 use std::thread;
 use std::time::Duration;
 
+use log::LogLevel;
 use mio;
 use mio::tcp::{TcpListener, TcpStream};
 
+use commands;
 use cpu::BreakReason;
-use dbgcore::{self, ActiveCpu::Arm9};
+use dbgcore::{self, ActiveCpu, DbgReg, WatchKind};
+use dbgexpr::{AgentExpr, Condition};
 use hwcore::Message;
+use mem;
 use msgs;
+use record::Reverse;
 use utils;
 
+/// Largest packet we accept, which GDB also uses to size `m` and `X` requests
+const MAX_PACKET_SIZE: usize = 0x4000;
+
 #[derive(Debug, Error)]
 pub enum ErrorKind {
     Hex(::std::num::ParseIntError),
@@ -35,9 +43,134 @@ fn parse_next_hex<'a, I: Iterator<Item=&'a str>>(it: &mut I) -> Result<u32> {
 }
 
 
-fn cmd_step(ctx: &mut GdbCtx) -> Result<String> {
-    ctx.dbg.hw().step();
-    let break_data = BreakData::new(BreakReason::LimitReached, ctx.dbg);
+/// GDB thread ids of the two cores
+fn thread_id(cpu: ActiveCpu) -> u32 {
+    match cpu {
+        ActiveCpu::Arm9 => 1,
+        ActiveCpu::Arm11 => 2,
+    }
+}
+
+/// Parses a GDB thread id, where `None` stands for any/all threads
+fn parse_thread(id: &str) -> Result<Option<ActiveCpu>> {
+    match id {
+        "-1" | "0" => Ok(None),
+        id => match utils::from_hex(id)? {
+            1 => Ok(Some(ActiveCpu::Arm9)),
+            2 => Ok(Some(ActiveCpu::Arm11)),
+            _ => Err(ErrorKind::Parse)
+        }
+    }
+}
+
+/// Number of registers sent in `g`/`G` packets: r0-r15 and CPSR
+const NUM_G_REGS: usize = 17;
+
+fn target_xml() -> String {
+    let mut xml = String::new();
+    xml += "<?xml version=\"1.0\"?>\n";
+    xml += "<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n";
+    xml += "<target version=\"1.0\">\n";
+    xml += "<architecture>arm</architecture>\n";
+
+    let mut feature = "";
+    for (num, &(ref name, reg)) in dbgcore::gdb_registers().iter().enumerate() {
+        let (reg_feature, ty, group) = match reg {
+            DbgReg::Gp(13) => ("org.gnu.gdb.arm.core", "data_ptr", "general"),
+            DbgReg::Gp(15) => ("org.gnu.gdb.arm.core", "code_ptr", "general"),
+            DbgReg::Gp(_) | DbgReg::Cpsr => ("org.gnu.gdb.arm.core", "uint32", "general"),
+            DbgReg::Spsr(_) | DbgReg::Banked(..) => ("org.llama.arm.banked", "uint32", "system"),
+            _ => ("org.llama.arm.cp15", "uint32", "system"),
+        };
+        if reg_feature != feature {
+            if !feature.is_empty() {
+                xml += "</feature>\n";
+            }
+            xml += &format!("<feature name=\"{}\">\n", reg_feature);
+            feature = reg_feature;
+        }
+        xml += &format!("<reg name=\"{}\" bitsize=\"32\" regnum=\"{}\" type=\"{}\" group=\"{}\"/>\n",
+                        name, num, ty, group);
+    }
+    xml += "</feature>\n";
+    xml += "</target>\n";
+    xml
+}
+
+/// Describes the memory map of the selected CPU
+///
+/// GDB refuses accesses outside of the map unless `set mem inaccessible-by-default off`
+/// is used, which is needed to reach memory only the other core has mapped.
+/// The format has no notion of IO, which is listed as RAM; regions from the map are
+/// uncached by default, so GDB still reads IO registers from the hardware every time.
+fn memory_map_xml(map: &[mem::MemRegion]) -> String {
+    let mut xml = String::new();
+    xml += "<?xml version=\"1.0\"?>\n";
+    xml += "<!DOCTYPE memory-map PUBLIC \"+//IDN gnu.org//DTD GDB Memory Map V1.0//EN\" ";
+    xml += "\"http://sourceware.org/gdb/gdb-memory-map.dtd\">\n";
+    xml += "<memory-map>\n";
+    for region in map {
+        let ty = match region.kind {
+            mem::RegionKind::Ram | mem::RegionKind::Io => "ram",
+            mem::RegionKind::Rom => "rom",
+        };
+        xml += &format!("<memory type=\"{}\" start=\"0x{:X}\" length=\"0x{:X}\"/>\n",
+                        ty, region.start, region.len);
+    }
+    xml += "</memory-map>\n";
+    xml
+}
+
+/// Decodes the escaped binary data of an `X` packet
+fn unescape_binary(data: &str) -> Vec<u8> {
+    let mut bytes = data.chars().map(|c| c as u32 as u8);
+    let mut out = Vec::with_capacity(data.len());
+    while let Some(b) = bytes.next() {
+        match b {
+            b'}' => if let Some(escaped) = bytes.next() {
+                out.push(escaped ^ 0x20)
+            },
+            b => out.push(b)
+        }
+    }
+    out
+}
+
+/// Streams monitor command output to the client as `O` packets
+struct GdbOutput<'a> {
+    stream: &'a mut TcpStream,
+}
+
+impl<'a> commands::Output for GdbOutput<'a> {
+    fn print(&mut self, level: LogLevel, msg: &str) {
+        let line = match level {
+            LogLevel::Error => format!("error: {}\n", msg),
+            _ => format!("{}\n", msg),
+        };
+        let mut packet = String::from("O");
+        for b in line.bytes() {
+            packet += &format!("{:02X}", b);
+        }
+        if let Err(e) = write_gdb_packet(&packet, self.stream) {
+            warn!("Could not send monitor output to GDB client: {:?}", e);
+        }
+    }
+}
+
+fn gdb_register(num: usize) -> Result<DbgReg> {
+    dbgcore::gdb_register(num).ok_or(ErrorKind::Parse)
+}
+
+fn parse_reg_value(regstr: &str) -> Result<u32> {
+    if regstr.len() < 8 {
+        return Err(ErrorKind::Parse)
+    }
+    Ok(utils::from_hex(&regstr[..8])?.swap_bytes())
+}
+
+fn cmd_step(ctx: &mut GdbCtx, cpu: ActiveCpu) -> Result<String> {
+    let (cpu, reason) = ctx.dbg.step(cpu);
+    let break_data = BreakData::new(reason, cpu, ctx.dbg);
     let signal = break_data.to_signal();
     *ctx.last_halt = break_data;
     Ok(signal)
@@ -48,17 +181,50 @@ fn cmd_continue(ctx: &mut GdbCtx) -> Result<String> {
     Err(ErrorKind::NoResponse)
 }
 
+/// Handles `bs` and `bc`, which need record mode to be on
+fn cmd_reverse(ctx: &mut GdbCtx, params: &str) -> Result<String> {
+    let res = match params {
+        "s" => {
+            let cpu = ctx.threads.cont;
+            ctx.dbg.reverse_step(cpu)
+        }
+        "c" => ctx.dbg.reverse_continue(),
+        _ => {
+            warn!("GDB client tried to run unsupported command b{}", params);
+            return Ok(String::new())
+        }
+    };
+
+    let (cpu, reason, history_start) = match res {
+        Ok(Reverse::Halted(cpu, reason)) => (cpu, reason, false),
+        Ok(Reverse::HistoryStart) => (ctx.threads.cont, BreakReason::LimitReached, true),
+        Err(e) => {
+            warn!("Could not run backwards: {}", e);
+            return Ok("E01".to_owned())
+        }
+    };
+    let break_data = BreakData::new(reason, cpu, ctx.dbg);
+    let mut signal = break_data.to_signal();
+    if history_start {
+        signal += "replaylog:begin;";
+    }
+    *ctx.last_halt = break_data;
+    Ok(signal)
+}
+
 struct BreakData {
     reason: BreakReason,
+    thread: ActiveCpu,
     r15: u32,
     r13: u32
 }
 
 impl BreakData {
-    fn new(reason: BreakReason, dbg: &mut dbgcore::DbgContext) -> BreakData {
-        let hw = dbg.hw();
+    fn new(reason: BreakReason, thread: ActiveCpu, dbg: &mut dbgcore::DbgContext) -> BreakData {
+        let hw = dbg.hw_of(thread);
         BreakData {
             reason: reason,
+            thread: thread,
             r15: hw.pause_addr(),
             r13: hw.read_reg(13),
         }
@@ -67,25 +233,108 @@ impl BreakData {
     fn to_signal(&self) -> String {
         let reason_str = match self.reason {
             BreakReason::Breakpoint => format!(";{}:", "swbreak"),
+            BreakReason::Watchpoint { addr, kind } => {
+                let name = match kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+                format!(";{}:{:08X}", name, addr)
+            }
             _ => String::new(),
         };
-        format!("T05{:02X}:{:08X};{:02X}:{:08X}{};", 15, self.r15.swap_bytes(),
-                                                     13, self.r13.swap_bytes(),
-                                                     reason_str)
+        let signal = match self.reason {
+            BreakReason::UndefinedInstruction(..) => 4, // SIGILL
+            BreakReason::BusError { .. } => 10, // SIGBUS
+            _ => 5 // SIGTRAP
+        };
+        format!("T{:02X}thread:{:X};{:02X}:{:08X};{:02X}:{:08X}{};", signal, thread_id(self.thread),
+                                                                             15, self.r15.swap_bytes(),
+                                                                             13, self.r13.swap_bytes(),
+                                                                             reason_str)
+    }
+}
+
+/// Parses the `X<len>,<bytecode>` condition list of a `Z0` packet,
+/// ignoring any breakpoint commands that follow it
+fn parse_conditions<'a, I: Iterator<Item=&'a str>>(sections: I) -> Result<Option<Condition>> {
+    let mut exprs = Vec::new();
+    for section in sections {
+        if !section.starts_with('X') {
+            continue
+        }
+        let mut parts = section[1..].split(',');
+        let len = parse_next_hex(&mut parts)? as usize;
+        let hex = parse_next(&mut parts)?;
+        if hex.len() != len * 2 {
+            return Err(ErrorKind::Parse)
+        }
+        let code = (0..len)
+            .map(|i| u8::from_str_radix(&hex[2*i .. 2*i+2], 16))
+            .collect::<::std::result::Result<Vec<u8>, _>>()?;
+        exprs.push(AgentExpr(code));
     }
+    Ok(if exprs.is_empty() { None } else { Some(Condition::Agent(exprs)) })
 }
 
-fn handle_gdb_cmd_q(cmd: &str, _ctx: &mut GdbCtx) -> Result<String> {
-    let mut s = cmd.splitn(2, ':');
+fn handle_gdb_cmd_q(cmd: &str, ctx: &mut GdbCtx, stream: &mut TcpStream) -> Result<String> {
+    let mut s = cmd.splitn(2, |c| c == ':' || c == ',');
     let ty = parse_next(&mut s)?;
     let mut out = String::new();
     match ty {
-        "fThreadInfo" => out += "m0000000000000001",
+        "fThreadInfo" => out += &format!("m{:X},{:X}", thread_id(ActiveCpu::Arm9),
+                                                      thread_id(ActiveCpu::Arm11)),
         "sThreadInfo" => out += "l",
-        "C" => out += "QC0000000000000001",
+        "C" => out += &format!("QC{:X}", thread_id(ctx.dbg.active_cpu())),
+        "ThreadExtraInfo" => {
+            let name = match parse_thread(parse_next(&mut s)?)? {
+                Some(ActiveCpu::Arm9) => "ARM9",
+                Some(ActiveCpu::Arm11) => "ARM11",
+                None => return Err(ErrorKind::Parse)
+            };
+            for b in name.bytes() {
+                out += &format!("{:02X}", b);
+            }
+        }
         "Attached" => out += "1",
+        "Rcmd" => {
+            let hex = parse_next(&mut s)?;
+            let bytes = (0..hex.len() / 2)
+                .map(|i| u8::from_str_radix(&hex[2*i .. 2*i+2], 16))
+                .collect::<::std::result::Result<Vec<u8>, _>>()?;
+            let command = String::from_utf8_lossy(&bytes).into_owned();
+
+            commands::handle(ctx.dbg, &mut GdbOutput { stream: stream }, command.split_whitespace());
+            ctx.threads.general = ctx.dbg.active_cpu();
+            out += "OK";
+        }
         "Supported" => {
-            out += "PacketSize=400;BreakpointCommands+;swbreak+;vContSupported+";
+            out += &format!("PacketSize={:X};", MAX_PACKET_SIZE);
+            out += "ConditionalBreakpoints+;BreakpointCommands+;swbreak+;vContSupported+";
+            out += ";ReverseStep+;ReverseContinue+";
+            out += ";qXfer:features:read+;qXfer:memory-map:read+";
+        }
+        "Xfer" => {
+            let mut args = parse_next(&mut s)?.split(':');
+            let object = parse_next(&mut args)?;
+            let op = parse_next(&mut args)?;
+            let annex = parse_next(&mut args)?;
+            let doc = match (object, op, annex) {
+                ("features", "read", "target.xml") => target_xml(),
+                ("memory-map", "read", "") => memory_map_xml(&ctx.dbg.hw().memory_map()),
+                _ => {
+                    warn!("GDB client tried to read unsupported object {}:{}", object, annex);
+                    return Ok(out)
+                }
+            };
+
+            let mut range = parse_next(&mut args)?.split(',');
+            let offset = parse_next_hex(&mut range)? as usize;
+            let length = parse_next_hex(&mut range)? as usize;
+            let start = offset.min(doc.len());
+            let end = offset.saturating_add(length).min(doc.len());
+            out += if end == doc.len() { "l" } else { "m" };
+            out += &doc[start..end];
         }
         _ => warn!("GDB client tried to run unsupported `q` command {}", ty)
     }
@@ -98,27 +347,30 @@ fn handle_gdb_cmd_v(cmd: &str, ctx: &mut GdbCtx) -> Result<String> {
     let mut out = String::new();
     match ty {
         "Cont" => {
+            // All-stop: a step only runs the stepped core, anything else resumes both
             let params = parse_next(&mut s)?;
-            let threads = params.split(';');
-            for thread in threads {
-                let mut thread_data = thread.split(':');
-                let action = parse_next(&mut thread_data)?;
-                let thread_name = thread_data.next();
-                if let Some(name) = thread_name {
-                    match (name, utils::from_hex(name)) {
-                        | ("-1", _)
-                        | (_, Ok(0))
-                        | (_, Ok(1)) => {}
-                        
-                        | (s, _) => panic!("Attempted to issue command on invalid thread id {}", s)
-                    }
-                }
+            let mut step = None;
+            let mut cont = false;
+            for action in params.split(';') {
+                let mut action_data = action.split(':');
+                let action = parse_next(&mut action_data)?;
+                let thread = match action_data.next() {
+                    Some(id) => parse_thread(id)?,
+                    None => None
+                };
                 match action {
-                    "c" => return cmd_continue(ctx),
-                    "s" => return cmd_step(ctx),
+                    "c" => cont = true,
+                    "s" => if step.is_none() {
+                        step = Some(thread.unwrap_or(ctx.threads.cont))
+                    },
                     _ => warn!("GDB client tried to run unsupported `vCont` action {}", action)
                 }
             }
+            if let Some(cpu) = step {
+                return cmd_step(ctx, cpu)
+            } else if cont {
+                return cmd_continue(ctx)
+            }
         }
         "Cont?" => {
             let supported = ["c", "s"];
@@ -134,117 +386,167 @@ fn handle_gdb_cmd_v(cmd: &str, ctx: &mut GdbCtx) -> Result<String> {
 }
 
 
-fn handle_gdb_cmd(cmd: &str, ctx: &mut GdbCtx) -> Result<String> {
+fn handle_gdb_cmd(cmd: &str, ctx: &mut GdbCtx, stream: &mut TcpStream) -> Result<String> {
     let ty = parse_next(&mut cmd.chars())?;
     let params = &cmd[1..];
     let mut out = String::new();
     ctx.dbg.pause();
     match ty {
         'g' => {
-            let hw = ctx.dbg.hw();
-            for reg in 0..15 {
-                out += &format!("{:08X}", hw.read_reg(reg).swap_bytes());
-            }
-            out += &format!("{:08X}", hw.pause_addr().swap_bytes());
-            for _ in 0..8 {
-                out += "xxxxxxxxxxxxxxxxxxxxxxxx"; // fX registers (12 bytes each)
+            let mut hw = ctx.dbg.hw();
+            for num in 0..NUM_G_REGS {
+                let val = hw.read_dbg_reg(gdb_register(num)?).unwrap_or(0);
+                out += &format!("{:08X}", val.swap_bytes());
             }
-            out += "xxxxxxxx"; // fps register            
-            out += &format!("{:08X}", hw.read_cpsr().swap_bytes());
         }
         'G' => {
             let mut hw = ctx.dbg.hw();
             let mut regs = params;
-            let next_reg = |regstr: &str| -> Result<u32> {
-                let val = utils::from_hex(&regstr[..8])?;
-                Ok(val.swap_bytes())
-            };
-
-            for reg in 0..15 {
-                hw.write_reg(reg, next_reg(regs)?);
+            for num in 0..NUM_G_REGS {
+                hw.write_dbg_reg(gdb_register(num)?, parse_reg_value(regs)?);
                 regs = &regs[8..];
             }
-            // register at 15: PC
-            hw.branch_to(next_reg(regs)?);
-            regs = &regs[8..];
-
-            // Skip 8 fX registers
-            regs = &regs[8 * 24..];
-
-            // Skip fps register
-            regs = &regs[8..];
-
-            // register at 25: CPSR
-            hw.write_cpsr(next_reg(regs)?);
             out += "OK";
         }
         'H' => {
-            out += "OK";
+            let op = parse_next(&mut params.chars())?;
+            match parse_thread(&params[1..]) {
+                Ok(thread) => {
+                    match (op, thread) {
+                        ('g', Some(cpu)) => ctx.dbg.set_active_cpu(cpu),
+                        ('c', Some(cpu)) => ctx.threads.cont = cpu,
+                        _ => {}
+                    }
+                    ctx.threads.general = ctx.dbg.active_cpu();
+                    out += "OK";
+                }
+                Err(_) => out += "E01",
+            }
+        }
+        'T' => {
+            match parse_thread(params) {
+                Ok(Some(_)) => out += "OK",
+                _ => out += "E01",
+            }
         }
         'm' => {
             let mut hw = ctx.dbg.hw();
             let mut params = params.split(',');
             let addr = parse_next_hex(&mut params)?;
-            let size = parse_next_hex(&mut params)?;
-            let mut buf = [0u8];
-            for b in 0..size {
-                if let Err(_) = hw.read_mem(addr+b, &mut buf) {
-                    out += "00";
-                } else {
-                    out += &format!("{:02X}", buf[0]);
+            let size = parse_next_hex(&mut params)? as usize;
+            if size > MAX_PACKET_SIZE / 2 {
+                return Ok("E01".to_owned())
+            }
+            let mut buf = vec![0u8; size];
+            if let Err(_) = hw.read_mem(addr, &mut buf) {
+                // Zero out only the bytes that cannot be read
+                for (b, byte) in buf.iter_mut().enumerate() {
+                    let mut byte_buf = [0u8];
+                    let _ = hw.read_mem(addr.wrapping_add(b as u32), &mut byte_buf);
+                    *byte = byte_buf[0];
                 }
             }
+            for byte in buf {
+                out += &format!("{:02X}", byte);
+            }
         }
         'M' => {
             let mut hw = ctx.dbg.hw();
             let mut params = params.split(|c| c == ',' || c == ':');
             let addr = parse_next_hex(&mut params)?;
-            let size = parse_next_hex(&mut params)?;
+            let size = parse_next_hex(&mut params)? as usize;
             let data = parse_next(&mut params)?;
-            for b in 0..size {
-                let data_byte_range = 2*(b as usize)..2*((b as usize)+1);
-                let byte = utils::from_hex(&data[data_byte_range])? as u8;
-                hw.write_mem(addr+b, &[byte]);
+            if data.len() != size * 2 {
+                return Err(ErrorKind::Parse)
+            }
+            let bytes = (0..size)
+                .map(|b| u8::from_str_radix(&data[2*b .. 2*b+2], 16))
+                .collect::<::std::result::Result<Vec<u8>, _>>()?;
+            match hw.write_mem(addr, &bytes) {
+                Ok(()) => out += "OK",
+                Err(_) => out += "E01",
+            }
+        }
+        'X' => {
+            let mut hw = ctx.dbg.hw();
+            let data_start = params.find(':').ok_or(ErrorKind::Parse)?;
+            let mut header = params[..data_start].split(',');
+            let addr = parse_next_hex(&mut header)?;
+            let size = parse_next_hex(&mut header)? as usize;
+            let bytes = unescape_binary(&params[data_start + 1..]);
+            if bytes.len() != size {
+                return Err(ErrorKind::Parse)
+            }
+            match hw.write_mem(addr, &bytes) {
+                Ok(()) => out += "OK",
+                Err(_) => out += "E01",
             }
-            out += "OK";
         }
         'p' => {
-            let hw = ctx.dbg.hw();
-            let reg = utils::from_hex(&params)? as usize;
-            let regval = match reg {
-                0 ..= 14 => hw.read_reg(reg),
-                15 => hw.pause_addr(),
-                25 => hw.read_cpsr(),
-                n => {
-                    warn!("GDB requested bad register value {}", n);
-                    0
+            let mut hw = ctx.dbg.hw();
+            let num = utils::from_hex(&params)? as usize;
+            match gdb_register(num).ok().map(|reg| hw.read_dbg_reg(reg)) {
+                Some(Some(val)) => out += &format!("{:08X}", val.swap_bytes()),
+                Some(None) => out += "xxxxxxxx",
+                None => {
+                    warn!("GDB requested bad register value {}", num);
+                    out += "E01";
                 }
-            };
-            out += &format!("{:08X}", regval.swap_bytes());
+            }
+        }
+        'P' => {
+            let mut hw = ctx.dbg.hw();
+            let mut params = params.split('=');
+            let num = parse_next_hex(&mut params)? as usize;
+            let val = parse_reg_value(parse_next(&mut params)?)?;
+            match gdb_register(num) {
+                Ok(reg) if hw.write_dbg_reg(reg, val) => out += "OK",
+                _ => out += "E01",
+            }
         }
         'q' => {
-            return handle_gdb_cmd_q(params, ctx);
+            return handle_gdb_cmd_q(params, ctx, stream);
         }
         's' => {
-            return cmd_step(ctx);
+            let cpu = ctx.threads.cont;
+            return cmd_step(ctx, cpu);
         }
         'c' => {
             return cmd_continue(ctx);
         }
+        'b' => {
+            return cmd_reverse(ctx, params);
+        }
         'v' => {
             return handle_gdb_cmd_v(params, ctx);
         }
         'z' | 'Z' => {
             let mut hw = ctx.dbg.hw();
-            let mut params = params.split(',');
+            let mut sections = params.split(';');
+            let mut params = parse_next(&mut sections)?.split(',');
+            let condition = parse_conditions(sections)?;
             let brk_ty = parse_next(&mut params)?;
             let addr = parse_next_hex(&mut params)?;
-            let _kind = parse_next(&mut params)?;
-            assert!(brk_ty == "0");
-            if ty == 'Z' {
-                hw.set_breakpoint(addr);
-            } else {
-                hw.del_breakpoint(addr);
+            let kind = parse_next_hex(&mut params)?;
+            let watch_kind = match brk_ty {
+                "0" => None,
+                "2" => Some(WatchKind::Write),
+                "3" => Some(WatchKind::Read),
+                "4" => Some(WatchKind::Access),
+                _ => {
+                    // Empty response tells the client this type is unsupported
+                    warn!("GDB client tried to use unsupported breakpoint type {}", brk_ty);
+                    return Ok(out)
+                }
+            };
+            match (watch_kind, ty == 'Z') {
+                (None, true) => {
+                    let id = hw.set_breakpoint(addr);
+                    hw.set_breakpoint_condition(id, condition);
+                }
+                (None, false) => hw.del_breakpoint(addr),
+                (Some(wk), true) => hw.set_watchpoint(addr, kind, wk),
+                (Some(wk), false) => { hw.del_watchpoint(addr, kind, wk); }
             }
             out += "OK";
         }
@@ -278,35 +580,48 @@ enum PacketType {
     AckOk,
     AckErr,
     EndOfPacket,
+    /// The rest of the packet has not arrived yet
+    Incomplete,
     Malformed,
 }
 
-fn load_packet<I: Iterator<Item = u8>>(it: &mut I) -> PacketType {
-    let mut it = it.skip_while(|b| *b != 0x03 && *b != b'$' && *b != b'-' && *b != b'+');
-    match it.next() {
-        Some(0x3) => return PacketType::CtrlC,
-        Some(b'$') => {}
-        Some(b'+') => return PacketType::AckOk,
-        Some(b'-') => return PacketType::AckErr,
-        None => return PacketType::EndOfPacket,
-        _ => return PacketType::Malformed
+/// Parses the packet at the start of `data`, also returning how many bytes were used up
+///
+/// Packet bytes are stored one per char in `PacketType::Command`, so that binary data
+/// survives being treated as a string.
+fn load_packet(data: &[u8]) -> (PacketType, usize) {
+    let start = data.iter().position(|b| *b == 0x03 || *b == b'$' || *b == b'-' || *b == b'+');
+    let start = match start {
+        Some(start) => start,
+        None => return (PacketType::EndOfPacket, data.len())
+    };
+    match data[start] {
+        0x3 => return (PacketType::CtrlC, start + 1),
+        b'+' => return (PacketType::AckOk, start + 1),
+        b'-' => return (PacketType::AckErr, start + 1),
+        _ => {}
     }
 
-    let mut string = String::new();
+    let body = &data[start + 1..];
+    let end = match body.iter().position(|b| *b == b'#') {
+        Some(end) if end + 3 <= body.len() => end,
+        _ => return (PacketType::Incomplete, start)
+    };
+    let len = start + 1 + end + 3;
+
+    let mut string = String::with_capacity(end);
     let mut checksum = Checksum(0);
-    for b in it.by_ref().take_while(|b| *b != b'#') {
+    for &b in &body[..end] {
         string.push(b as char);
         checksum += b;
     }
 
-    if let (Some(top), Some(bot)) = (it.next(), it.next()) {
-        let packet_checksum = str::from_utf8(&[top, bot]).ok()
-            .and_then(|s| utils::from_hex(s).ok());
-        if Some(checksum.0) == packet_checksum {
-            return PacketType::Command(string)
-        }
+    let packet_checksum = str::from_utf8(&body[end + 1..end + 3]).ok()
+        .and_then(|s| utils::from_hex(s).ok());
+    if Some(checksum.0) == packet_checksum {
+        return (PacketType::Command(string), len)
     }
-    return PacketType::Malformed
+    (PacketType::Malformed, len)
 }
 
 fn write_gdb_packet(data: &str, stream: &mut TcpStream) -> Result<()> {
@@ -318,19 +633,23 @@ fn write_gdb_packet(data: &str, stream: &mut TcpStream) -> Result<()> {
     Ok(())
 }
 
-fn handle_gdb_packet(data: &[u8], stream: &mut TcpStream, ctx: &mut GdbCtx) -> Result<()> {
-    trace!("Recieving GDB packet: {}", str::from_utf8(data).unwrap());
-    let mut it = data.iter().cloned();
+/// Handles all complete packets in `data`, leaving any partial packet at the end
+fn handle_gdb_packet(data: &mut Vec<u8>, stream: &mut TcpStream, ctx: &mut GdbCtx) -> Result<()> {
+    trace!("Recieving GDB packet: {}", String::from_utf8_lossy(data));
     loop {
-        match load_packet(&mut it) {
+        let (packet, len) = load_packet(data);
+        data.drain(..len);
+        match packet {
             PacketType::Command(cmd) => {
                 stream.write(b"+")?;
                 stream.flush()?;
-                match handle_gdb_cmd(&cmd, ctx) {
+                match handle_gdb_cmd(&cmd, ctx, stream) {
                     Ok(out) => write_gdb_packet(&out, stream)?,
+                    Err(ErrorKind::NoResponse) => {}
+                    Err(ErrorKind::Io(e)) => return Err(ErrorKind::Io(e)),
                     Err(e) => {
-                        if let ErrorKind::NoResponse = e {}
-                        else { return Err(e) }
+                        warn!("Could not handle GDB command `{}`: {:?}", cmd, e);
+                        write_gdb_packet("E01", stream)?
                     }
                 }
 
@@ -341,22 +660,40 @@ fn handle_gdb_packet(data: &[u8], stream: &mut TcpStream, ctx: &mut GdbCtx) -> R
             }
             PacketType::AckOk => {},
             PacketType::AckErr => error!("GDB client replied with error packet!"),
-            PacketType::EndOfPacket => {
+            PacketType::EndOfPacket | PacketType::Incomplete => {
                 return Ok(())
             }
             PacketType::Malformed => {
-                trace!("Recieved malformed data {:?}", data);
+                trace!("Recieved malformed packet");
                 stream.write(b"-")?;
                 stream.flush()?;
-                return Ok(())
             }
         }
     }
 }
 
+/// Threads selected with `Hg` (registers and memory) and `Hc` (stepping)
+struct GdbThreads {
+    general: ActiveCpu,
+    cont: ActiveCpu,
+}
+
 struct GdbCtx<'a, 'b: 'a> {
     dbg: &'a mut dbgcore::DbgContext<'b>,
     last_halt: &'a mut BreakData,
+    threads: &'a mut GdbThreads,
+}
+
+/// Remembers which core stopped, preferring the one that caused the stop
+/// over one that was merely trapped along with it
+fn merge_halt(halted: &mut Option<(ActiveCpu, BreakReason)>, cpu: ActiveCpu, reason: BreakReason) {
+    let trapped = match reason {
+        BreakReason::Trapped => true,
+        _ => false
+    };
+    if halted.is_none() || !trapped {
+        *halted = Some((cpu, reason));
+    }
 }
 
 const TOKEN_LISTENER: mio::Token = mio::Token(1024);
@@ -394,40 +731,67 @@ impl GdbStub {
                 listener: &listener,
                 poll: poll,
                 socket: None,
+                pending: Vec::new(),
             };
 
             let mut events = Events::with_capacity(1024);
 
             info!("Starting GDB stub on port 4567...");
 
-            let mut last_halt = BreakData::new(BreakReason::Trapped, &mut debugger.ctx(Arm9));
+            let mut last_halt = BreakData::new(BreakReason::Trapped, ActiveCpu::Arm9,
+                                               &mut debugger.ctx(ActiveCpu::Arm9));
+            let mut threads = GdbThreads {
+                general: ActiveCpu::Arm9,
+                cont: ActiveCpu::Arm9,
+            };
             't: loop {
                 connection.poll.poll(&mut events, Some(Duration::from_millis(100)))
                     .expect("Could not poll for network events!");
 
+                let general = threads.general;
                 let mut ctx = GdbCtx {
-                    dbg: &mut debugger.ctx(Arm9),
-                    last_halt: &mut last_halt
+                    dbg: &mut debugger.ctx(general),
+                    last_halt: &mut last_halt,
+                    threads: &mut threads,
                 };
 
                 for event in &events {
                     handle_event(&event, &mut connection, |buf, stream| {
-                        handle_gdb_packet(buf, stream, &mut ctx).unwrap();
+                        if let Err(e) = handle_gdb_packet(buf, stream, &mut ctx) {
+                            error!("Could not reply to GDB client: {:?}", e);
+                        }
                     });
                 }
 
+                let mut halted = None;
                 for msg in msg_client.try_iter() {
                     match msg {
                         Message::Quit => break 't,
-                        Message::Arm9Halted(reason) => {
-                            if let Some(ref mut stream) = connection.socket {
-                                let break_data = BreakData::new(reason, ctx.dbg);
-                                write_gdb_packet(&break_data.to_signal(), stream).unwrap();
-                            }
-                        }
+                        Message::Arm9Halted(reason) => merge_halt(&mut halted, ActiveCpu::Arm9, reason),
+                        Message::Arm11Halted(reason) => merge_halt(&mut halted, ActiveCpu::Arm11, reason),
                         _ => {}
                     }
                 }
+
+                if halted.is_some() {
+                    // Both cores halt together; wait for the other one before reporting
+                    ctx.dbg.pause();
+                    for msg in msg_client.try_iter() {
+                        match msg {
+                            Message::Quit => break 't,
+                            Message::Arm9Halted(reason) => merge_halt(&mut halted, ActiveCpu::Arm9, reason),
+                            Message::Arm11Halted(reason) => merge_halt(&mut halted, ActiveCpu::Arm11, reason),
+                            _ => {}
+                        }
+                    }
+                    let (cpu, reason) = halted.unwrap();
+
+                    if let Some(ref mut stream) = connection.socket {
+                        let break_data = BreakData::new(reason, cpu, ctx.dbg);
+                        write_gdb_packet(&break_data.to_signal(), stream).unwrap();
+                        *ctx.last_halt = break_data;
+                    }
+                }
             }
             msg_client
         }).unwrap())
@@ -444,10 +808,12 @@ struct Connection<'a> {
     listener: &'a TcpListener,
     poll: mio::Poll,
     socket: Option<TcpStream>,
+    /// Received data not yet handled, ending in a partial packet
+    pending: Vec<u8>,
 }
 
 fn handle_event<F>(event: &mio::Event, connection: &mut Connection, mut client_responder: F)
-        where F: FnMut(&[u8], &mut TcpStream) {
+        where F: FnMut(&mut Vec<u8>, &mut TcpStream) {
 
     let mut buf = [0u8; 1024];
     match event.token() {
@@ -459,6 +825,7 @@ fn handle_event<F>(event: &mio::Event, connection: &mut Connection, mut client_r
                                              mio::PollOpt::edge())
                         .expect("Could not register TCP client to mio!");
                     connection.socket = Some(socket);
+                    connection.pending.clear();
                 }
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                     return; // Socket is not ready anymore, stop accepting
@@ -472,7 +839,10 @@ fn handle_event<F>(event: &mio::Event, connection: &mut Connection, mut client_r
                     connection.socket = None;
                     break;
                 }
-                Ok(l) => client_responder(&buf[..l], connection.socket.as_mut().unwrap()),
+                Ok(l) => {
+                    connection.pending.extend_from_slice(&buf[..l]);
+                    client_responder(&mut connection.pending, connection.socket.as_mut().unwrap());
+                }
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
                     continue; // Socket is not ready anymore, stop reading
                 }
@@ -1,18 +1,25 @@
 use std::sync::{self, Arc, Mutex};
 use std::cell::RefCell;
+use std::path::Path;
 use std::rc::Rc;
 use std::thread;
+use std::time::Duration;
 
 use clock;
 use cpu;
+use dbgcore::ActiveCpu;
 use ldr;
 use mem;
 use io;
 use msgs;
 use fs;
+use record::{self, Input, Position, Recorder, Reverse};
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 use cpu::{v5, v6};
+use cpu::breakpoints::{Breakpoints, Watchpoints};
 use cpu::caches::Ops;
+use cpu::irq::IrqClient;
 
 #[derive(Clone)]
 pub enum Message {
@@ -43,11 +50,62 @@ impl msgs::Ident for Message {
 
 
 
+/// RAM only reachable by the ARM9 side
+struct Ram9 {
+    itcm: mem::UniqueMemoryBlock,
+    dtcm: mem::UniqueMemoryBlock,
+    ram: mem::UniqueMemoryBlock,
+}
+
+impl SaveState for Ram9 {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        self.itcm.save_state(w)?;
+        self.dtcm.save_state(w)?;
+        self.ram.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.itcm.load_state(r)?;
+        self.dtcm.load_state(r)?;
+        self.ram.load_state(r)
+    }
+}
+
+/// RAM shared between the ARM9, ARM11 and PICA
+#[derive(Clone)]
+struct SharedRam {
+    vram: mem::SharedMemoryBlock,
+    dsp_ram: mem::SharedMemoryBlock,
+    axi_wram: mem::SharedMemoryBlock,
+    fcram: mem::SharedMemoryBlock,
+}
+
+impl SaveState for SharedRam {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        self.vram.save_state(w)?;
+        self.dsp_ram.save_state(w)?;
+        self.axi_wram.save_state(w)?;
+        self.fcram.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.vram.load_state(r)?;
+        self.dsp_ram.load_state(r)?;
+        self.axi_wram.load_state(r)?;
+        self.fcram.load_state(r)
+    }
+}
+
 struct MemoryRegions {
     mem9: mem::MemController,
     mem11: mem::MemController,
+    /// One for each ARM11 core after the first
+    mem11_others: Vec<mem::MemController>,
     mem_framebuf: mem::MemController,
 
+    ram9: Ram9,
+    shared_ram: SharedRam,
+
     io9_hnd: mem::AddressBlockHandle,
     io9_shared_hnd: mem::AddressBlockHandle,
     io11_shared_hnd: mem::AddressBlockHandle,
@@ -55,11 +113,33 @@ struct MemoryRegions {
     io11_priv_hnd: mem::AddressBlockHandle,
 }
 
+/// Wait states per word accessed for each kind of memory, in cycles of the accessing CPU.
+/// These are estimates rather than measurements; the TCMs and the ARM11 private region
+/// have none.
+struct WaitStates {
+    ram9: u32,
+    vram: u32,
+    dsp_ram: u32,
+    axi_wram: u32,
+    fcram: u32,
+    bootrom: u32,
+    io: u32,
+}
+
+const WAIT_STATES9: WaitStates = WaitStates {
+    ram9: 1, vram: 4, dsp_ram: 4, axi_wram: 2, fcram: 6, bootrom: 1, io: 8,
+};
+
+/// The ARM11 clock runs twice as fast, so it waits twice as many cycles
+const WAIT_STATES11: WaitStates = WaitStates {
+    ram9: 0, vram: 8, dsp_ram: 8, axi_wram: 4, fcram: 12, bootrom: 2, io: 16,
+};
+
 impl MemoryRegions {
     fn map<F>(io_creator: F) -> Self
         where F: 
             FnOnce(mem::MemController, Rc<RefCell<HardwareDma9>>)
-                -> (io::IoRegsArm9, io::IoRegsShared, io::IoRegsArm11, io::IoRegsArm11Priv)
+                -> (io::IoRegsArm9, io::IoRegsShared, io::IoRegsArm11, Vec<io::IoRegsArm11Priv>)
     {
         let arm9_itcm = mem::UniqueMemoryBlock::new(0x20);
         let arm9_ram = mem::UniqueMemoryBlock::new(0x400);
@@ -114,36 +194,80 @@ impl MemoryRegions {
         for i in 0..0x1000 {
             controller9.map_region(i * 0x8000, mem::AddressBlock::UniqueRam(arm9_itcm.clone()));
         }
-        controller9.map_region(0x08000000, mem::AddressBlock::UniqueRam(arm9_ram));
+        controller9.map_region(0x08000000, mem::AddressBlock::UniqueRam(arm9_ram.clone()));
         controller9.map_region(0x18000000, mem::AddressBlock::SharedRam(vram.clone()));
         controller9.map_region(0x1FF00000, mem::AddressBlock::SharedRam(dsp_ram.clone()));
         controller9.map_region(0x1FF80000, mem::AddressBlock::SharedRam(axi_wram.clone()));
         controller9.map_region(0x20000000, mem::AddressBlock::SharedRam(fcram.clone()));
-        controller9.map_region(0xFFF00000, mem::AddressBlock::UniqueRam(arm9_dtcm));
-        controller9.map_region(0xFFFF0000, mem::AddressBlock::UniqueRam(arm9_bootrom));
+        controller9.map_region(0xFFF00000, mem::AddressBlock::UniqueRam(arm9_dtcm.clone()));
+        controller9.map_rom_region(0xFFFF0000, mem::AddressBlock::UniqueRam(arm9_bootrom));
         let io9_hnd         = controller9.map_region(0x10000000, mem::AddressBlock::Io9(arm9_io));
         let io9_shared_hnd  = controller9.map_region(0x10100000, mem::AddressBlock::IoShared(shared_io.clone()));
+        controller9.set_wait_states(0x08000000, WAIT_STATES9.ram9);
+        controller9.set_wait_states(0x18000000, WAIT_STATES9.vram);
+        controller9.set_wait_states(0x1FF00000, WAIT_STATES9.dsp_ram);
+        controller9.set_wait_states(0x1FF80000, WAIT_STATES9.axi_wram);
+        controller9.set_wait_states(0x20000000, WAIT_STATES9.fcram);
+        controller9.set_wait_states(0xFFFF0000, WAIT_STATES9.bootrom);
+        controller9.set_wait_states(0x10000000, WAIT_STATES9.io);
+        controller9.set_wait_states(0x10100000, WAIT_STATES9.io);
 
         /////////////////////////////////////////////////////////////////
         ////////////          ARM11 MEMORY MAPPINGS
         /////////////////////////////////////////////////////////////////
 
-        let mut controller11 = mem::MemController::new();
-        controller11.map_region(0x00000000, mem::AddressBlock::SharedRam(arm11_bootrom.clone()));
-        controller11.map_region(0x00010000, mem::AddressBlock::SharedRam(arm11_bootrom.clone()));
-        controller11.map_region(0x18000000, mem::AddressBlock::SharedRam(vram.clone()));
-        controller11.map_region(0x1FF80000, mem::AddressBlock::SharedRam(axi_wram.clone()));
-        controller11.map_region(0x20000000, mem::AddressBlock::SharedRam(fcram.clone()));
-        controller11.map_region(0xFFFF0000, mem::AddressBlock::SharedRam(arm11_bootrom));
-        let io11_shared_hnd = controller11.map_region(0x10100000, mem::AddressBlock::IoShared(shared_io.clone()));
-        let io11_hnd        = controller11.map_region(0x10200000, mem::AddressBlock::Io11(arm11_io));
-        let io11_priv_hnd   = controller11.map_region(0x17E00000, mem::AddressBlock::IoPriv11(arm11_io_priv));
+        let make_controller11 = |io_priv| {
+            let mut controller11 = mem::MemController::new();
+            controller11.map_rom_region(0x00000000, mem::AddressBlock::SharedRam(arm11_bootrom.clone()));
+            controller11.map_rom_region(0x00010000, mem::AddressBlock::SharedRam(arm11_bootrom.clone()));
+            controller11.map_region(0x18000000, mem::AddressBlock::SharedRam(vram.clone()));
+            controller11.map_region(0x1FF80000, mem::AddressBlock::SharedRam(axi_wram.clone()));
+            controller11.map_region(0x20000000, mem::AddressBlock::SharedRam(fcram.clone()));
+            controller11.map_rom_region(0xFFFF0000, mem::AddressBlock::SharedRam(arm11_bootrom.clone()));
+            let io11_shared_hnd = controller11.map_region(0x10100000, mem::AddressBlock::IoShared(shared_io.clone()));
+            let io11_hnd        = controller11.map_region(0x10200000, mem::AddressBlock::Io11(arm11_io.clone()));
+            let io11_priv_hnd   = controller11.map_region(0x17E00000, mem::AddressBlock::IoPriv11(io_priv));
+            for &addr in [0x00000000, 0x00010000, 0xFFFF0000].iter() {
+                controller11.set_wait_states(addr, WAIT_STATES11.bootrom);
+            }
+            controller11.set_wait_states(0x18000000, WAIT_STATES11.vram);
+            controller11.set_wait_states(0x1FF80000, WAIT_STATES11.axi_wram);
+            controller11.set_wait_states(0x20000000, WAIT_STATES11.fcram);
+            controller11.set_wait_states(0x10100000, WAIT_STATES11.io);
+            controller11.set_wait_states(0x10200000, WAIT_STATES11.io);
+            (controller11, io11_shared_hnd, io11_hnd, io11_priv_hnd)
+        };
+
+        // Every core sees the same memory apart from its private region
+        let mut arm11_io_priv = arm11_io_priv.into_iter();
+        let (controller11, io11_shared_hnd, io11_hnd, io11_priv_hnd)
+            = make_controller11(arm11_io_priv.next().unwrap());
+        let other_controllers11 = arm11_io_priv
+            .map(|io_priv| {
+                let (mut controller, ..) = make_controller11(io_priv);
+                controller.monitor = controller11.monitor.add_core();
+                controller
+            })
+            .collect();
 
         Self {
             mem9: controller9,
             mem11: controller11,
+            mem11_others: other_controllers11,
             mem_framebuf: controller_fbuf,
 
+            ram9: Ram9 {
+                itcm: arm9_itcm,
+                dtcm: arm9_dtcm,
+                ram: arm9_ram,
+            },
+            shared_ram: SharedRam {
+                vram: vram,
+                dsp_ram: dsp_ram,
+                axi_wram: axi_wram,
+                fcram: fcram,
+            },
+
             io9_hnd: io9_hnd,
             io9_shared_hnd: io9_shared_hnd,
             io11_shared_hnd: io11_shared_hnd,
@@ -163,7 +287,7 @@ fn _write_fb_pointers(cpu: &mut cpu::Cpu<v5>) {
     cpu.regs[1] = 0xFFF00000;
 }
 
-fn try_bootrom_load(mem: &mut mem::MemController, llama_file: fs::LlamaFile) {
+fn try_bootrom_read(llama_file: fs::LlamaFile) -> Option<Vec<u8>> {
     use std::io::Read;
 
     loop {
@@ -171,16 +295,17 @@ fn try_bootrom_load(mem: &mut mem::MemController, llama_file: fs::LlamaFile) {
             Ok(x) => x,
             Err(_) => break
         };
-        let mut buf = [0u8; 0x10000];
+        let mut buf = vec![0u8; 0x10000];
         if !file.read_exact(&mut buf).is_ok() { break };
-        mem.write_buf(0xFFFF0000, &buf);
-        return
+        return Some(buf)
     }
     info!("Did not find {:?}; not loading bootrom.", llama_file);
+    None
 }
 
 pub struct Hardware9 {
     pub arm9: cpu::Cpu<v5>,
+    ram: Ram9,
     io_handle: mem::AddressBlockHandle,
     io_shared_handle: mem::AddressBlockHandle,
 }
@@ -208,8 +333,40 @@ impl Hardware9 {
     }
 }
 
+/// The CPU goes first so that its dirty cache lines are written back
+/// before the RAM is saved, and dropped before the RAM is loaded
+impl SaveState for Hardware9 {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.tag(b"CPU9");
+        self.arm9.save_state(w)?;
+        w.tag(b"RAM9");
+        self.ram.save_state(w)?;
+        w.tag(b"IO9 ");
+        self.io9().save_state(w)?;
+        w.tag(b"IOSH");
+        self.io_shared().save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        r.expect_tag(b"CPU9")?;
+        self.arm9.load_state(r)?;
+        r.expect_tag(b"RAM9")?;
+        self.ram.load_state(r)?;
+        r.expect_tag(b"IO9 ")?;
+        self.io9().load_state(r)?;
+        r.expect_tag(b"IOSH")?;
+        self.io_shared().load_state(r)
+    }
+}
+
+/// Instructions the first ARM11 core runs between turns of the other cores
+const ARM11_QUANTUM: u64 = 1000;
+
 pub struct Hardware11 {
-    pub arm11: cpu::Cpu<v6>,    
+    /// Core 0, the one that gets debugged
+    pub arm11: cpu::Cpu<v6>,
+    /// The other MPCore cores, run in turns of `ARM11_QUANTUM` instructions
+    pub other_cores: Vec<cpu::Cpu<v6>>,
     io_shared_handle: mem::AddressBlockHandle,
     io_handle: mem::AddressBlockHandle,
     io_priv_handle: mem::AddressBlockHandle,
@@ -238,13 +395,108 @@ impl Hardware11 {
     }
 
     pub fn io_priv(&self) -> &io::IoRegsArm11Priv {
-        let region = self.arm11.mpu.main_mem().region(&self.io_priv_handle);
+        self.core_io_priv(0)
+    }
+
+    /// Private timers and interrupt interface of core `index`
+    pub fn core_io_priv(&self, index: usize) -> &io::IoRegsArm11Priv {
+        let core = if index == 0 { &self.arm11 } else { &self.other_cores[index - 1] };
+        let region = core.mpu.main_mem().region(&self.io_priv_handle);
         if let mem::AddressBlock::IoPriv11(ref io) = region {
             io
         } else {
             unreachable!()
         }
     }
+
+    pub fn num_cores(&self) -> usize {
+        self.other_cores.len() + 1
+    }
+
+    /// Whether every core is asleep until an interrupt comes in
+    pub fn all_waiting_for_irq(&self) -> bool {
+        self.arm11.is_waiting_for_irq()
+            && self.other_cores.iter().all(|core| core.is_waiting_for_irq())
+    }
+
+    /// Runs core 0 like `Cpu::run`. The other cores take their turn every time its step
+    /// count reaches a multiple of `ARM11_QUANTUM`, so they interleave the same way
+    /// however the run is sliced.
+    pub fn run(&mut self, num_instrs: u32) -> cpu::BreakReason {
+        self.run_until(num_instrs, !0)
+    }
+
+    /// Same as `run`, but also stops once core 0's clock reaches `end_cycle`
+    pub fn run_until(&mut self, num_instrs: u32, end_cycle: u64) -> cpu::BreakReason {
+        if self.other_cores.is_empty() {
+            return self.arm11.run_until(num_instrs, end_cycle)
+        }
+
+        let mut left = num_instrs as u64;
+        while left > 0 && self.arm11.cycles() < end_cycle {
+            let chunk = left.min(ARM11_QUANTUM - self.arm11.steps % ARM11_QUANTUM);
+            let start = self.arm11.steps;
+            let reason = self.arm11.run_until(chunk as u32, end_cycle);
+            if self.arm11.steps != start && self.arm11.steps % ARM11_QUANTUM == 0 {
+                self.run_other_cores();
+            }
+            if let cpu::BreakReason::LimitReached = reason {
+                left -= self.arm11.steps - start;
+            } else {
+                return reason
+            }
+        }
+        cpu::BreakReason::LimitReached
+    }
+
+    fn run_other_cores(&mut self) {
+        for (i, core) in self.other_cores.iter_mut().enumerate() {
+            match core.run(ARM11_QUANTUM as u32) {
+                cpu::BreakReason::LimitReached | cpu::BreakReason::WFI => {}
+                _ => warn!("ARM11 core {} stopped early; only core 0 can be debugged", i + 1)
+            }
+        }
+    }
+}
+
+impl SaveState for Hardware11 {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_u32(self.num_cores() as u32);
+        w.tag(b"CP11");
+        self.arm11.save_state(w)?;
+        for core in self.other_cores.iter_mut() {
+            w.tag(b"CP11");
+            core.save_state(w)?;
+        }
+        w.tag(b"IO11");
+        self.io11().save_state(w)?;
+        for index in 0..self.num_cores() {
+            w.tag(b"PRIV");
+            self.core_io_priv(index).save_state(w)?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        let num_cores = r.get_u32()? as usize;
+        if num_cores != self.num_cores() {
+            return Err(savestate::ErrorKind::Mismatch(format!(
+                "state has {} ARM11 cores, but {} are emulated", num_cores, self.num_cores())))
+        }
+        r.expect_tag(b"CP11")?;
+        self.arm11.load_state(r)?;
+        for core in self.other_cores.iter_mut() {
+            r.expect_tag(b"CP11")?;
+            core.load_state(r)?;
+        }
+        r.expect_tag(b"IO11")?;
+        self.io11().load_state(r)?;
+        for index in 0..self.num_cores() {
+            r.expect_tag(b"PRIV")?;
+            self.core_io_priv(index).load_state(r)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct HardwareDma9 {
@@ -264,11 +516,17 @@ pub struct HwCore {
     client_this: msgs::Client<Message>,
     client_user: Option<msgs::Client<Message>>,
     client_gdb: Option<msgs::Client<Message>>,
+    client_dap: Option<msgs::Client<Message>>,
     _arm9_thread: thread::JoinHandle<()>,
     _arm11_thread: thread::JoinHandle<()>,
 
     mem_framebuf: mem::MemController,
+    shared_ram: SharedRam,
     pub irq_tx: cpu::irq::IrqAsyncClient,
+    fiq9: cpu::irq::FiqLine,
+    fiq11: cpu::irq::FiqLine,
+    record: Arc<Mutex<Option<Recorder>>>,
+    quantum: Arc<Mutex<Option<u64>>>,
 }
 
 /// HwCore will not contain any x-thread references
@@ -284,15 +542,17 @@ pub enum Arm11State {
 impl HwCore {
     pub fn new(loader: &dyn ldr::Loader) -> HwCore {
         let mut msg_spec = msgs::MsgGraph::new(&[
-            ("gdb", &[], &["quit", "arm9halted"]),
+            ("gdb", &[], &["quit", "arm9halted", "arm11halted"]),
+            ("dap", &[], &["quit", "arm9halted", "arm11halted"]),
             ("user", &["quit", "hidupdate"], &["framebufstate"]),
-            ("arm9", &["arm9halted", "arm11halted"], &["quit", "startemu", "suspendemu"]),
-            ("arm11", &["arm9halted", "arm11halted"], &["quit", "startemu", "suspendemu", "hidupdate"]),
+            ("arm9", &["arm9halted", "arm11halted", "suspendemu"], &["quit", "startemu", "suspendemu", "hidupdate"]),
+            ("arm11", &["arm11halted", "suspendemu"], &["quit", "startemu", "suspendemu", "hidupdate"]),
             ("pica", &["framebufstate"], &[]),
             ("hwcore", &["startemu", "suspendemu"], &[]),
         ]);
 
         let client_gdb = msg_spec.client("gdb");
+        let client_dap = msg_spec.client("dap");
         let client_user = msg_spec.client("user");
 
         let client_arm9 = msg_spec.client("arm9").unwrap();
@@ -300,30 +560,51 @@ impl HwCore {
         let client_pica = msg_spec.client("pica").unwrap();
         let client_this = msg_spec.client("hwcore").unwrap();
 
+        let boot9 = try_bootrom_read(fs::LlamaFile::Boot9);
+        let boot11 = try_bootrom_read(fs::LlamaFile::Boot11);
+
+        // The other ARM11 cores wait in the bootrom until core 0 wakes them up
+        let num_cores11 = if boot11.is_some() {
+            loader.arm11_cores()
+        } else {
+            info!("Without the ARM11 bootrom, only one ARM11 core is emulated");
+            1
+        };
+
         let irq_subsys = cpu::irq::IrqSubsys::create();
         let irq_line = irq_subsys.line.clone();
-        let irq11_subsys = cpu::irq::IrqSubsys::create();
-        let irq11_line = irq11_subsys.line.clone();
+        let irq11_subsys: Vec<_> = (0..num_cores11)
+            .map(|_| cpu::irq::IrqSubsys::create())
+            .collect();
+        let mut irq11_lines: Vec<_> = irq11_subsys.iter()
+            .map(|subsys| subsys.line.clone())
+            .collect();
         let irq_async_tx = irq_subsys.async_tx.clone();
 
         let clk_tx = clock::make_channel(irq_subsys.sync_tx.clone());
         let clk_rx = clk_tx.clone();
-        let clk11_tx = clock::make_channel(irq11_subsys.sync_tx.clone());
-        // let clk11_rx = clk11_tx.clone();
+        let mut clk11_txs: Vec<_> = irq11_subsys.iter()
+            .map(|subsys| clock::make_channel(subsys.sync_tx.clone()))
+            .collect();
+        let clk11_rxs = clk11_txs.clone();
 
         let mut mem_regions = MemoryRegions::map(
             |pica_controller, dma9_hw| {
                 let pica_hw = io::gpu::HardwarePica::new(client_pica, pica_controller);
 
-                io::new_devices(irq_subsys, irq11_subsys, clk_rx, pica_hw, dma9_hw)
+                io::new_devices(irq_subsys, irq11_subsys, clk_rx, clk11_rxs, pica_hw, dma9_hw)
             }
         );
 
         loader.load9(&mut mem_regions.mem9);
         loader.load11(&mut mem_regions.mem11);
 
-        try_bootrom_load(&mut mem_regions.mem9, fs::LlamaFile::Boot9);
-        try_bootrom_load(&mut mem_regions.mem11, fs::LlamaFile::Boot11);
+        if let Some(buf) = boot9 {
+            mem_regions.mem9.write_buf(0xFFFF0000, &buf);
+        }
+        if let Some(buf) = boot11 {
+            mem_regions.mem11.write_buf(0xFFFF0000, &buf);
+        }
 
         let mut cpu9 = cpu::Cpu::new(v5, mem_regions.mem9, irq_line, clk_tx);
         cpu9.reset(loader.entrypoint9());
@@ -336,17 +617,36 @@ impl HwCore {
         cpu9.mpu.main_mem_mut().write(0x01FF8004, 0u32);
         cpu9.mpu.main_mem_mut().write_buf(0x01FF8008, b"sdmc:/boot.firm\0");
 
+        // Loading the payload shouldn't count as time the CPUs spent waiting
+        cpu9.mpu.main_mem().take_wait_cycles();
+
+        let fiq9 = cpu9.fiq_line.clone();
         let hardware9 = Hardware9 {
             arm9: cpu9,
+            ram: mem_regions.ram9,
             io_handle: mem_regions.io9_hnd,
             io_shared_handle: mem_regions.io9_shared_hnd,
         };
 
-        let mut cpu11 = cpu::Cpu::new(v6, mem_regions.mem11, irq11_line, clk11_tx);
+        let mut cpu11 = cpu::Cpu::new(v6, mem_regions.mem11, irq11_lines.remove(0), clk11_txs.remove(0));
         cpu11.reset(loader.entrypoint11());
-
+        cpu11.mpu.main_mem().take_wait_cycles();
+
+        let other_cores11 = mem_regions.mem11_others.into_iter()
+            .zip(irq11_lines.into_iter().zip(clk11_txs))
+            .enumerate()
+            .map(|(i, (mem, (irq_line, clk_tx)))| {
+                let mut core = cpu::Cpu::new(v6, mem, irq_line, clk_tx);
+                core.set_cpu_id(i as u32 + 1);
+                core.reset(0xFFFF0000);
+                core
+            })
+            .collect();
+
+        let fiq11 = cpu11.fiq_line.clone();
         let hardware11 = Hardware11 {
             arm11: cpu11,
+            other_cores: other_cores11,
             io_shared_handle: mem_regions.io11_shared_hnd,
             io_handle: mem_regions.io11_hnd,
             io_priv_handle: mem_regions.io11_priv_hnd,
@@ -355,12 +655,48 @@ impl HwCore {
         let hardware9 = Arc::new(Mutex::new(hardware9));
         let hardware11 = Arc::new(Mutex::new(hardware11));
 
+        let record = Arc::new(Mutex::new(None));
+        let quantum = Arc::new(Mutex::new(None));
+
         let hardware = hardware9.clone();
+        let hardware_other = hardware11.clone();
+        let record_arm9 = record.clone();
+        let quantum_arm9 = quantum.clone();
+        let mut shared_ram = mem_regions.shared_ram.clone();
+        let mut irq_tx = irq_async_tx.clone();
         let arm9_thread = thread::Builder::new().name("ARM9".to_owned()).spawn(move || {
             let client = client_arm9;
             loop {
                 if !emu_idle(&client) { break }
-                {
+                let recording = record_arm9.lock().unwrap().is_some();
+                if recording {
+                    let mut hw_guard = hardware.lock().unwrap();
+                    let mut hw_other_guard = hardware_other.lock().unwrap();
+                    let mut rec_guard = record_arm9.lock().unwrap();
+                    if let Some(rec) = rec_guard.as_mut() {
+                        let mut lockstep = Lockstep {
+                            hw9: &mut hw_guard,
+                            hw11: &mut hw_other_guard,
+                            shared_ram: &mut shared_ram,
+                            irq_tx: &mut irq_tx,
+                            rec: rec,
+                        };
+                        if !record_run(&client, &mut lockstep) { break }
+                    }
+                } else if let Some(quantum) = *quantum_arm9.lock().unwrap() {
+                    let mut hw_guard = hardware.lock().unwrap();
+                    let mut hw_other_guard = hardware_other.lock().unwrap();
+                    let mut interleaved = Interleaved {
+                        hw9: &mut hw_guard,
+                        hw11: &mut hw_other_guard,
+                        quantum: quantum,
+                        hid: Vec::new(),
+                    };
+                    let running = interleaved_run(&client, &mut interleaved);
+                    // Nothing latched gets lost while paused
+                    interleaved.apply_hid();
+                    if !running { break }
+                } else {
                     let mut hw_guard = hardware.lock().unwrap();
                     if !arm9_run(&client, &mut hw_guard) { break }
                 }
@@ -368,10 +704,15 @@ impl HwCore {
         }).unwrap();
 
         let hardware = hardware11.clone();
+        let record_arm11 = record.clone();
+        let quantum_arm11 = quantum.clone();
         let arm11_thread = thread::Builder::new().name("ARM11".to_owned()).spawn(move || {
             let client = client_arm11;
             loop {
                 if !emu_idle(&client) { break }
+                // In record mode and with a quantum set, the ARM9 thread runs both cores
+                if record_arm11.lock().unwrap().is_some() { continue }
+                if quantum_arm11.lock().unwrap().is_some() { continue }
                 {
                     let mut hw_guard = hardware.lock().unwrap();
                     if !arm11_run(&client, &mut hw_guard) { break }
@@ -385,11 +726,17 @@ impl HwCore {
             client_this: client_this,
             client_user: client_user,
             client_gdb: client_gdb,
+            client_dap: client_dap,
             _arm9_thread: arm9_thread,
             _arm11_thread: arm11_thread,
 
             mem_framebuf: mem_regions.mem_framebuf,
+            shared_ram: mem_regions.shared_ram,
             irq_tx: irq_async_tx,
+            fiq9: fiq9,
+            fiq11: fiq11,
+            record: record,
+            quantum: quantum,
         }
     }
 
@@ -413,6 +760,202 @@ impl HwCore {
         { let _ = self.hardware11.lock().unwrap(); }
     }
 
+    /// Pauses emulation and writes the state of the whole machine to `path`.
+    /// SD and NAND images are not included.
+    pub fn save_state(&mut self, path: &Path) -> savestate::Result<()> {
+        self.stop();
+
+        let state = write_state(&mut self.hardware9.lock().unwrap(),
+                                &mut self.hardware11.lock().unwrap(), &mut self.shared_ram)?;
+        ::std::fs::write(path, state)?;
+        Ok(())
+    }
+
+    /// Pauses emulation and restores the machine from a state written by `save_state`.
+    /// If this fails, the machine is left as it was. A recording in progress starts over
+    /// from the loaded state.
+    pub fn load_state(&mut self, path: &Path) -> savestate::Result<()> {
+        let state = ::std::fs::read(path)?;
+
+        self.stop();
+
+        {
+            let mut hw9 = self.hardware9.lock().unwrap();
+            let mut hw11 = self.hardware11.lock().unwrap();
+            // Sections load one after the other, so a bad file can fail halfway through
+            let current = write_state(&mut hw9, &mut hw11, &mut self.shared_ram)?;
+            if let Err(e) = read_state(&mut hw9, &mut hw11, &mut self.shared_ram, state) {
+                read_state(&mut hw9, &mut hw11, &mut self.shared_ram, current)
+                    .expect("Could not restore the machine after a failed load!");
+                return Err(e)
+            }
+        }
+
+        let params = self.record.lock().unwrap().as_ref()
+            .map(|rec| (rec.interval(), rec.max_checkpoints()));
+        if let Some((interval, max_checkpoints)) = params {
+            self.begin_recording(Recorder::new(interval, max_checkpoints))?;
+        }
+        Ok(())
+    }
+
+    /// Pauses emulation and starts record mode, see `record`. Checkpoints are taken
+    /// every `interval` steps, keeping at most `max_checkpoints` of them.
+    pub fn start_recording(&mut self, interval: u64, max_checkpoints: usize) -> savestate::Result<()> {
+        self.stop();
+        self.begin_recording(Recorder::new(interval, max_checkpoints))
+    }
+
+    /// Pauses emulation and drops the recording, if any
+    pub fn stop_recording(&mut self) {
+        self.stop();
+        *self.record.lock().unwrap() = None;
+    }
+
+    pub fn recorder(&self) -> sync::MutexGuard<'_, Option<Recorder>> {
+        self.record.lock().unwrap()
+    }
+
+    /// Pauses emulation and picks how the cores get scheduled. With `Some(quantum)`, one
+    /// thread interleaves both cores every `quantum` ARM9 cycles, see `Interleaved`, so
+    /// that identical inputs give identical runs. With `None`, each core runs on its own
+    /// thread. Record mode ignores this and always runs the cores in lockstep.
+    pub fn set_quantum(&mut self, quantum: Option<u64>) {
+        self.stop();
+        *self.quantum.lock().unwrap() = quantum.map(|quantum| quantum.max(1));
+    }
+
+    pub fn quantum(&self) -> Option<u64> {
+        *self.quantum.lock().unwrap()
+    }
+
+    /// Pauses emulation and returns how far each core has run since recording started
+    pub fn position(&mut self) -> Position {
+        self.stop();
+        Position {
+            arm9: self.hardware9.lock().unwrap().arm9.steps,
+            arm11: self.hardware11.lock().unwrap().arm11.steps,
+        }
+    }
+
+    /// Pauses emulation and runs both cores on this thread until one of them halts, either
+    /// core reaches `limit`, or `keep_going` returns false, which is asked between slices.
+    /// The cores are scheduled as with `set_quantum`, using `DEFAULT_QUANTUM` if no
+    /// quantum is set, or in lockstep while recording. Without a halt, this returns
+    /// `LimitReached`.
+    pub fn run_interleaved<F>(&mut self, limit: Position, mut keep_going: F)
+        -> (ActiveCpu, cpu::BreakReason)
+        where F: FnMut() -> bool {
+        let stopped = (ActiveCpu::Arm9, cpu::BreakReason::LimitReached);
+        let reached = |pos: Position| pos.arm9 >= limit.arm9 || pos.arm11 >= limit.arm11;
+
+        let recorded = self.with_lockstep(|lockstep| loop {
+            if reached(lockstep.pos()) || !keep_going() {
+                return stopped
+            }
+            lockstep.checkpoint_if_due();
+            let (cpu, reason) = lockstep.run_slice(limit);
+            if log_halt(cpu, &reason, lockstep.pause_addr(cpu)) {
+                return (cpu, reason)
+            }
+        });
+        if let Some(halt) = recorded {
+            return halt
+        }
+
+        let quantum = self.quantum().unwrap_or(DEFAULT_QUANTUM);
+        let mut hw9 = self.hardware9.lock().unwrap();
+        let mut hw11 = self.hardware11.lock().unwrap();
+        let mut interleaved = Interleaved {
+            hw9: &mut hw9,
+            hw11: &mut hw11,
+            quantum: quantum,
+            hid: Vec::new(),
+        };
+        loop {
+            if reached(interleaved.pos()) || !keep_going() {
+                return stopped
+            }
+            let (cpu, reason) = interleaved.run_slice(limit);
+            if log_halt(cpu, &reason, interleaved.pause_addr(cpu)) {
+                return (cpu, reason)
+            }
+        }
+    }
+
+    fn begin_recording(&mut self, mut rec: Recorder) -> savestate::Result<()> {
+        let mut hw9 = self.hardware9.lock().unwrap();
+        let mut hw11 = self.hardware11.lock().unwrap();
+        hw9.arm9.steps = 0;
+        hw11.arm11.steps = 0;
+
+        let state = write_state(&mut hw9, &mut hw11, &mut self.shared_ram)?;
+        rec.checkpoint(Position::default(), state);
+        *self.record.lock().unwrap() = Some(rec);
+        Ok(())
+    }
+
+    /// Pauses emulation and runs `f` on both cores, if recording
+    fn with_lockstep<R, F>(&mut self, f: F) -> Option<R>
+        where F: FnOnce(&mut Lockstep) -> R {
+        self.stop();
+
+        let mut hw9 = self.hardware9.lock().unwrap();
+        let mut hw11 = self.hardware11.lock().unwrap();
+        let mut rec = self.record.lock().unwrap();
+        let mut lockstep = Lockstep {
+            hw9: &mut hw9,
+            hw11: &mut hw11,
+            shared_ram: &mut self.shared_ram,
+            irq_tx: &mut self.irq_tx,
+            rec: rec.as_mut()?,
+        };
+        Some(f(&mut lockstep))
+    }
+
+    /// Steps `cpu` in record mode, where the other core runs first if the schedule says
+    /// so and may halt before `cpu` gets to step. Returns None if not recording.
+    pub fn record_step(&mut self, cpu: ActiveCpu) -> Option<(ActiveCpu, cpu::BreakReason)> {
+        self.with_lockstep(|lockstep| lockstep.step(cpu))
+    }
+
+    /// Pauses emulation and goes back to just before the last step of `cpu`
+    pub fn reverse_step(&mut self, cpu: ActiveCpu) -> record::Result<Reverse> {
+        self.with_lockstep(|lockstep| lockstep.reverse_step(cpu))
+            .unwrap_or(Err(record::ErrorKind::NotRecording))
+    }
+
+    /// Pauses emulation and goes back to the last breakpoint or watchpoint hit on either core
+    pub fn reverse_continue(&mut self) -> record::Result<Reverse> {
+        self.with_lockstep(|lockstep| lockstep.reverse_continue())
+            .unwrap_or(Err(record::ErrorKind::NotRecording))
+    }
+
+    /// Applies an input coming from the debugger. In record mode, this pauses emulation
+    /// and logs the input so that it gets replayed.
+    pub fn apply_input(&mut self, input: Input) {
+        if self.record.lock().unwrap().is_some() {
+            if self.with_lockstep(|lockstep| lockstep.input(input)).is_some() {
+                return
+            }
+        }
+        match input {
+            Input::Hid(btn) => {
+                let hw11 = self.hardware11.lock().unwrap();
+                io::hid::update_pad(&mut hw11.io_shared().hid.lock(), btn);
+            }
+            Input::Irq9(irq) => self.irq_tx.assert(irq),
+            Input::Fiq(which, high) => self.fiq_line(which).set_high(high),
+        }
+    }
+
+    pub fn fiq_line(&self, which: ActiveCpu) -> &cpu::irq::FiqLine {
+        match which {
+            ActiveCpu::Arm9 => &self.fiq9,
+            ActiveCpu::Arm11 => &self.fiq11,
+        }
+    }
+
     pub fn copy_framebuffers(&self, fbs: &mut Framebuffers, fb_state: &io::gpu::FramebufState) {
         let pixel_depth = |color_fmt: io::gpu::ColorFormat| match color_fmt {
             io::gpu::ColorFormat::Rgb8 => 3,
@@ -437,8 +980,462 @@ impl HwCore {
     pub fn take_client_gdb(&mut self) -> Option<msgs::Client<Message>> {
         self.client_gdb.take()
     }
+
+    pub fn take_client_dap(&mut self) -> Option<msgs::Client<Message>> {
+        self.client_dap.take()
+    }
+}
+
+fn write_state(hw9: &mut Hardware9, hw11: &mut Hardware11, shared_ram: &mut SharedRam)
+    -> savestate::Result<Vec<u8>> {
+    let mut w = StateWriter::new();
+    w.tag(b"HW9 ");
+    hw9.save_state(&mut w)?;
+    w.tag(b"HW11");
+    hw11.save_state(&mut w)?;
+    w.tag(b"SRAM");
+    shared_ram.save_state(&mut w)?;
+    Ok(w.into_bytes())
 }
 
+fn read_state(hw9: &mut Hardware9, hw11: &mut Hardware11, shared_ram: &mut SharedRam,
+              state: Vec<u8>) -> savestate::Result<()> {
+    let mut r = StateReader::from_bytes(state)?;
+    r.expect_tag(b"HW9 ")?;
+    hw9.load_state(&mut r)?;
+    r.expect_tag(b"HW11")?;
+    hw11.load_state(&mut r)?;
+    r.expect_tag(b"SRAM")?;
+    shared_ram.load_state(&mut r)?;
+    r.finish()
+}
+
+/// ARM11 cycles for each ARM9 cycle
+const ARM11_CLOCK_RATIO: u64 = 2;
+
+/// Quantum for deterministic scheduling when none is given, in ARM9 cycles
+pub const DEFAULT_QUANTUM: u64 = 8000;
+
+/// The core to run next and the point on the timeline it runs to, given where both cores
+/// are. Whichever core is behind catches up to the next multiple of `quantum`.
+fn next_quantum(time9: u64, time11: u64, quantum: u64) -> (ActiveCpu, u64) {
+    let end = (time9.min(time11) / quantum + 1) * quantum;
+    if time9 <= time11 {
+        (ActiveCpu::Arm9, end)
+    } else {
+        (ActiveCpu::Arm11, end)
+    }
+}
+
+/// Runs `cpu` for at most `num_instrs` steps, stopping at the first instruction boundary
+/// past `end` on the timeline shared by both cores, counted in ARM9 cycles. This is how
+/// both ways of running the cores on one thread step them.
+fn run_core(hw9: &mut Hardware9, hw11: &mut Hardware11, cpu: ActiveCpu, num_instrs: u32, end: u64)
+    -> cpu::BreakReason {
+    let reason = match cpu {
+        ActiveCpu::Arm9 => hw9.arm9.run_until(num_instrs, end),
+        ActiveCpu::Arm11 => hw11.run_until(num_instrs, end.saturating_mul(ARM11_CLOCK_RATIO)),
+    };
+    match reason {
+        // A core asleep with nothing to wake it up idles through its slice
+        cpu::BreakReason::WFI => cpu::BreakReason::LimitReached,
+        _ => reason
+    }
+}
+
+/// Both cores, for running them on one thread in a reproducible order
+///
+/// The cores share a timeline counted in ARM9 cycles, which only depends on what the
+/// machine executed. The order they run in is decided from it alone, never from how
+/// fast the host gets through each core. Button changes are latched until both cores
+/// reach the same quantum, so the machine only sees them at quantum boundaries.
+struct Interleaved<'a> {
+    hw9: &'a mut Hardware9,
+    hw11: &'a mut Hardware11,
+    quantum: u64,
+    hid: Vec<io::hid::ButtonState>,
+}
+
+impl<'a> Interleaved<'a> {
+    fn pos(&self) -> Position {
+        Position {
+            arm9: self.hw9.arm9.steps,
+            arm11: self.hw11.arm11.steps,
+        }
+    }
+
+    fn time(&self, cpu: ActiveCpu) -> u64 {
+        match cpu {
+            ActiveCpu::Arm9 => self.hw9.arm9.cycles(),
+            ActiveCpu::Arm11 => self.hw11.arm11.cycles() / ARM11_CLOCK_RATIO,
+        }
+    }
+
+    fn pause_addr(&self, cpu: ActiveCpu) -> u32 {
+        match cpu {
+            ActiveCpu::Arm9 => self.hw9.arm9.regs[15] - self.hw9.arm9.get_pc_offset(),
+            ActiveCpu::Arm11 => self.hw11.arm11.regs[15] - self.hw11.arm11.get_pc_offset(),
+        }
+    }
+
+    /// Latches a button change until the next quantum boundary
+    fn input_hid(&mut self, btn: io::hid::ButtonState) {
+        self.hid.push(btn);
+    }
+
+    /// Applies the latched button changes
+    fn apply_hid(&mut self) {
+        let io_shared = &self.hw11.io_shared().hid;
+        for btn in self.hid.drain(..) {
+            io::hid::update_pad(&mut io_shared.lock(), btn);
+        }
+    }
+
+    /// Runs the core that is behind until the end of the current quantum, stopping early
+    /// once `limit` is reached
+    fn run_slice(&mut self, limit: Position) -> (ActiveCpu, cpu::BreakReason) {
+        let (time9, time11) = (self.time(ActiveCpu::Arm9), self.time(ActiveCpu::Arm11));
+        if time9 / self.quantum == time11 / self.quantum {
+            self.apply_hid();
+        }
+        let (cpu, end) = next_quantum(time9, time11, self.quantum);
+        let steps = (limit.of(cpu) - self.pos().of(cpu)).min(!0u32 as u64) as u32;
+        (cpu, run_core(self.hw9, self.hw11, cpu, steps, end))
+    }
+}
+
+/// Both cores and the recording, for running the machine in record mode
+struct Lockstep<'a> {
+    hw9: &'a mut Hardware9,
+    hw11: &'a mut Hardware11,
+    shared_ram: &'a mut SharedRam,
+    irq_tx: &'a mut cpu::irq::IrqAsyncClient,
+    rec: &'a mut Recorder,
+}
+
+impl<'a> Lockstep<'a> {
+    fn pos(&self) -> Position {
+        Position {
+            arm9: self.hw9.arm9.steps,
+            arm11: self.hw11.arm11.steps,
+        }
+    }
+
+    fn pause_addr(&self, cpu: ActiveCpu) -> u32 {
+        match cpu {
+            ActiveCpu::Arm9 => self.hw9.arm9.regs[15] - self.hw9.arm9.get_pc_offset(),
+            ActiveCpu::Arm11 => self.hw11.arm11.regs[15] - self.hw11.arm11.get_pc_offset(),
+        }
+    }
+
+    fn apply(&mut self, input: Input) {
+        match input {
+            Input::Hid(btn) => {
+                let io_shared = &self.hw11.io_shared().hid;
+                io::hid::update_pad(&mut io_shared.lock(), btn);
+            }
+            Input::Irq9(irq) => self.irq_tx.assert(irq),
+            Input::Fiq(ActiveCpu::Arm9, high) => self.hw9.arm9.fiq_line.set_high(high),
+            Input::Fiq(ActiveCpu::Arm11, high) => self.hw11.arm11.fiq_line.set_high(high),
+        }
+    }
+
+    /// Applies an input from outside the machine and logs it
+    fn input(&mut self, input: Input) {
+        self.apply(input);
+        let pos = self.pos();
+        self.rec.log_input(pos, input);
+    }
+
+    fn checkpoint_if_due(&mut self) {
+        let pos = self.pos();
+        if !self.rec.checkpoint_due(pos) {
+            return
+        }
+        match write_state(self.hw9, self.hw11, self.shared_ram) {
+            Ok(state) => self.rec.checkpoint(pos, state),
+            Err(e) => error!("Could not take checkpoint: {}", e),
+        }
+    }
+
+    fn restore(&mut self, index: usize) -> savestate::Result<()> {
+        let pos = self.rec.checkpoint_pos(index);
+        let state = self.rec.restore(index);
+        read_state(self.hw9, self.hw11, self.shared_ram, state)?;
+        self.hw9.arm9.steps = pos.arm9;
+        self.hw11.arm11.steps = pos.arm11;
+        Ok(())
+    }
+
+    /// Runs the next slice of the schedule, stopping early once `limit` is reached
+    fn run_slice(&mut self, limit: Position) -> (ActiveCpu, cpu::BreakReason) {
+        let pos = self.pos();
+        let (cpu, end) = record::next_slice(pos);
+        let steps = (end.min(limit.of(cpu)) - pos.of(cpu)) as u32;
+        (cpu, run_core(self.hw9, self.hw11, cpu, steps, !0))
+    }
+
+    fn step(&mut self, cpu: ActiveCpu) -> (ActiveCpu, cpu::BreakReason) {
+        let target = self.pos().of(cpu) + 1;
+        let limit = Position { arm9: !0, arm11: !0 }.with(cpu, target);
+        loop {
+            self.checkpoint_if_due();
+            match self.run_slice(limit) {
+                (_, cpu::BreakReason::LimitReached) => {}
+                halt => return halt
+            }
+            if self.pos().of(cpu) == target {
+                return (cpu, cpu::BreakReason::LimitReached)
+            }
+        }
+    }
+
+    /// Runs forward to `target`, applying logged inputs on the way,
+    /// and returns early if either core halts
+    fn replay(&mut self, target: Position) -> Option<(ActiveCpu, cpu::BreakReason)> {
+        loop {
+            let pos = self.pos();
+            while let Some((at, input)) = self.rec.pending_input() {
+                if at > pos { break }
+                self.apply(input);
+                self.rec.input_applied();
+            }
+            if pos == target {
+                return None
+            }
+
+            let limit = match self.rec.pending_input() {
+                Some((at, _)) => at.min(target),
+                None => target
+            };
+            match self.run_slice(limit) {
+                (_, cpu::BreakReason::LimitReached) => {}
+                halt => return Some(halt)
+            }
+        }
+    }
+
+    /// Runs `f` with undefined instructions and bus errors handled the way they must have
+    /// been for the recording to get past them, so that replaying never halts on them
+    fn without_fault_halts<R, F>(&mut self, f: F) -> R
+        where F: FnOnce(&mut Self) -> R {
+        let undef9 = ::std::mem::replace(&mut self.hw9.arm9.halt_on_undefined, false);
+        let undef11 = ::std::mem::replace(&mut self.hw11.arm11.halt_on_undefined, false);
+        let bus9 = self.hw9.arm9.on_bus_error;
+        let bus11 = self.hw11.arm11.on_bus_error;
+        if bus9 == cpu::BusErrorMode::Halt {
+            self.hw9.arm9.on_bus_error = cpu::BusErrorMode::OpenBus;
+        }
+        if bus11 == cpu::BusErrorMode::Halt {
+            self.hw11.arm11.on_bus_error = cpu::BusErrorMode::OpenBus;
+        }
+
+        let res = f(self);
+
+        self.hw9.arm9.halt_on_undefined = undef9;
+        self.hw11.arm11.halt_on_undefined = undef11;
+        self.hw9.arm9.on_bus_error = bus9;
+        self.hw11.arm11.on_bus_error = bus11;
+        res
+    }
+
+    /// Replays up to `target` and returns the last halt on the way, along with where it
+    /// happened. A halt that does not let the cores move on ends the replay there.
+    fn replay_halts(&mut self, target: Position) -> Option<(ActiveCpu, cpu::BreakReason, Position)> {
+        let mut last_halt: Option<(ActiveCpu, cpu::BreakReason, Position)> = None;
+        while let Some((cpu, reason)) = self.replay(target) {
+            let pos = self.pos();
+            let stuck = last_halt.map_or(false, |(_, _, at)| at == pos);
+            last_halt = Some((cpu, reason, pos));
+            if stuck {
+                break
+            }
+        }
+        last_halt
+    }
+
+    /// Same as `replay`, with all breakpoints and watchpoints out of the way
+    fn replay_quietly(&mut self, target: Position) {
+        let bkpts9 = ::std::mem::replace(&mut self.hw9.arm9.breakpoints, Breakpoints::new());
+        let bkpts11 = ::std::mem::replace(&mut self.hw11.arm11.breakpoints, Breakpoints::new());
+        let wps9 = ::std::mem::replace(&mut self.hw9.arm9.mpu.main_mem_mut().watchpoints,
+                                       Watchpoints::new());
+        let wps11 = ::std::mem::replace(&mut self.hw11.arm11.mpu.main_mem_mut().watchpoints,
+                                        Watchpoints::new());
+
+        self.without_fault_halts(|lockstep| lockstep.replay_halts(target));
+
+        self.hw9.arm9.breakpoints = bkpts9;
+        self.hw11.arm11.breakpoints = bkpts11;
+        self.hw9.arm9.mpu.main_mem_mut().watchpoints = wps9;
+        self.hw11.arm11.mpu.main_mem_mut().watchpoints = wps11;
+    }
+
+    /// Drops the future that was left behind, so that running forward records anew
+    fn finish_reverse(&mut self) {
+        let pos = self.pos();
+        self.rec.truncate(pos);
+
+        // Resuming executes the current instructions instead of halting on them again
+        let pc9 = self.pause_addr(ActiveCpu::Arm9);
+        let pc11 = self.pause_addr(ActiveCpu::Arm11);
+        self.hw9.arm9.breakpoints.set_resume_addr(Some(pc9));
+        self.hw11.arm11.breakpoints.set_resume_addr(Some(pc11));
+    }
+
+    fn reverse_step(&mut self, cpu: ActiveCpu) -> record::Result<Reverse> {
+        let pos = self.pos();
+        if pos.of(cpu) == 0 {
+            return Ok(Reverse::HistoryStart)
+        }
+        let target = record::latest_point(cpu, pos.of(cpu) - 1, pos);
+        let index = match self.rec.checkpoint_before(target) {
+            Some(index) => index,
+            None => return Ok(Reverse::HistoryStart)
+        };
+
+        self.restore(index)?;
+        self.replay_quietly(target);
+        self.finish_reverse();
+        Ok(Reverse::Halted(cpu, cpu::BreakReason::LimitReached))
+    }
+
+    /// Replays the stretch between each checkpoint and the one after it, newest first,
+    /// until one contains a halt. The last halt found is where execution ends up.
+    fn reverse_continue(&mut self) -> record::Result<Reverse> {
+        let bkpts9 = self.hw9.arm9.breakpoints.clone();
+        let bkpts11 = self.hw11.arm11.breakpoints.clone();
+
+        let mut end = self.pos();
+        let mut index = match self.rec.checkpoint_before(end) {
+            Some(index) => index,
+            None => return Ok(Reverse::HistoryStart)
+        };
+        loop {
+            let start = self.rec.checkpoint_pos(index);
+            if start != end {
+                self.restore(index)?;
+                self.hw9.arm9.breakpoints.set_resume_addr(None);
+                self.hw11.arm11.breakpoints.set_resume_addr(None);
+
+                let last_halt = self.without_fault_halts(|lockstep| lockstep.replay_halts(end));
+
+                // Replaying must not count towards hit and ignore counts
+                self.hw9.arm9.breakpoints = bkpts9.clone();
+                self.hw11.arm11.breakpoints = bkpts11.clone();
+
+                if let Some((cpu, reason, pos)) = last_halt {
+                    self.restore(index)?;
+                    self.replay_quietly(pos);
+                    self.finish_reverse();
+                    return Ok(Reverse::Halted(cpu, reason))
+                }
+            }
+
+            if index == 0 {
+                break
+            }
+            end = start;
+            index -= 1;
+        }
+
+        let start = self.rec.checkpoint_pos(0);
+        self.restore(0)?;
+        self.replay_quietly(start);
+        self.finish_reverse();
+        Ok(Reverse::HistoryStart)
+    }
+}
+
+/// Logs why `cpu` stopped at `pc`, returning false if it did not halt
+fn log_halt(cpu: ActiveCpu, reason: &cpu::BreakReason, pc: u32) -> bool {
+    let name = match cpu {
+        ActiveCpu::Arm9 => "ARM9",
+        ActiveCpu::Arm11 => "ARM11",
+    };
+    match *reason {
+        cpu::BreakReason::Breakpoint => info!("{} breakpoint hit @ 0x{:X}!", name, pc),
+        cpu::BreakReason::Watchpoint { addr, kind } =>
+            info!("{} watchpoint hit ({:?} of 0x{:X}) @ 0x{:X}!", name, kind, addr, pc),
+        cpu::BreakReason::UndefinedInstruction(addr, opcode) =>
+            info!("{} undefined instruction 0x{:X} @ 0x{:X}!", name, opcode, addr),
+        cpu::BreakReason::BusError { pc, addr, access } =>
+            info!("{} bus error ({:?} of 0x{:X}) @ 0x{:X}!", name, access, addr, pc),
+        _ => return false
+    }
+    true
+}
+
+/// Runs both cores on this thread while recording, see `record`
+fn record_run(client: &msgs::Client<Message>, lockstep: &mut Lockstep) -> bool {
+    let unlimited = Position { arm9: !0, arm11: !0 };
+    let (cpu, reason) = 't: loop {
+        for msg in client.try_iter() {
+            match msg {
+                Message::Quit => return false,
+                Message::SuspendEmulation => {
+                    break 't (ActiveCpu::Arm9, cpu::BreakReason::Trapped)
+                }
+                Message::HidUpdate(btn) => lockstep.input(Input::Hid(btn)),
+                _ => {}
+            }
+        }
+
+        lockstep.checkpoint_if_due();
+        let (cpu, reason) = lockstep.run_slice(unlimited);
+        if !log_halt(cpu, &reason, lockstep.pause_addr(cpu)) {
+            continue
+        }
+        break 't (cpu, reason)
+    };
+
+    send_halts(client, cpu, reason);
+    true
+}
+
+fn interleaved_run(client: &msgs::Client<Message>, interleaved: &mut Interleaved) -> bool {
+    let unlimited = Position { arm9: !0, arm11: !0 };
+    let (cpu, reason) = 't: loop {
+        for msg in client.try_iter() {
+            match msg {
+                Message::Quit => return false,
+                Message::SuspendEmulation => {
+                    break 't (ActiveCpu::Arm9, cpu::BreakReason::Trapped)
+                }
+                Message::HidUpdate(btn) => interleaved.input_hid(btn),
+                _ => {}
+            }
+        }
+
+        let (cpu, reason) = interleaved.run_slice(unlimited);
+        if !log_halt(cpu, &reason, interleaved.pause_addr(cpu)) {
+            continue
+        }
+        break 't (cpu, reason)
+    };
+
+    send_halts(client, cpu, reason);
+    true
+}
+
+/// Reports a halt for each core like the two threads would, the one that
+/// actually halted last
+fn send_halts(client: &msgs::Client<Message>, cpu: ActiveCpu, reason: cpu::BreakReason) {
+    match cpu {
+        ActiveCpu::Arm9 => {
+            client.send(Message::Arm11Halted(cpu::BreakReason::Trapped));
+            client.send(Message::Arm9Halted(reason));
+        }
+        ActiveCpu::Arm11 => {
+            client.send(Message::Arm9Halted(cpu::BreakReason::Trapped));
+            client.send(Message::Arm11Halted(reason));
+        }
+    }
+}
+
+/// How long a thread sleeps when its core waits for an interrupt with nothing scheduled
+const IDLE_SLEEP: Duration = Duration::from_millis(1);
+
 fn arm9_run(client: &msgs::Client<Message>, hardware: &mut Hardware9) -> bool {
     let reason = 't: loop {
         for msg in client.try_iter() {
@@ -451,11 +1448,19 @@ fn arm9_run(client: &msgs::Client<Message>, hardware: &mut Hardware9) -> bool {
             }
         }
 
-        if let reason @ cpu::BreakReason::Breakpoint = hardware.arm9.run(1000) {
-            info!("Breakpoint hit @ 0x{:X}!", hardware.arm9.regs[15] - hardware.arm9.get_pc_offset());
-            client.send(Message::Arm11Halted(reason));
-            break 't reason
+        let reason = hardware.arm9.run(1000);
+        if let cpu::BreakReason::WFI = reason {
+            // Asleep until the other core sends an interrupt
+            thread::sleep(IDLE_SLEEP);
+            continue
         }
+        let pc = hardware.arm9.regs[15] - hardware.arm9.get_pc_offset();
+        if !log_halt(ActiveCpu::Arm9, &reason, pc) {
+            continue
+        }
+        // Stop the other core too; our own copy of the message is dropped once idle
+        client.send(Message::SuspendEmulation);
+        break 't reason
     };
 
     client.send(Message::Arm9Halted(reason));
@@ -478,11 +1483,22 @@ fn arm11_run(client: &msgs::Client<Message>, hardware: &mut Hardware11) -> bool
             }
         }
 
-        if let reason @ cpu::BreakReason::Breakpoint = hardware.arm11.run(1000) {
-            info!("Breakpoint hit @ 0x{:X}!", hardware.arm11.regs[15] - hardware.arm11.get_pc_offset());
-            client.send(Message::Arm9Halted(reason));
-            break 't reason
+        let reason = hardware.run(1000);
+        if let cpu::BreakReason::WFI = reason {
+            // Asleep until the other CPU sends an interrupt, unless other ARM11 cores
+            // still have work to do
+            if hardware.all_waiting_for_irq() {
+                thread::sleep(IDLE_SLEEP);
+            }
+            continue
         }
+        let pc = hardware.arm11.regs[15] - hardware.arm11.get_pc_offset();
+        if !log_halt(ActiveCpu::Arm11, &reason, pc) {
+            continue
+        }
+        // Stop the other core too; our own copy of the message is dropped once idle
+        client.send(Message::SuspendEmulation);
+        break 't reason
     };
 
     client.send(Message::Arm11Halted(reason));
@@ -509,3 +1525,139 @@ pub struct Framebuffers {
     pub top_screen_size: (usize, usize, usize),
     pub bot_screen_size: (usize, usize, usize),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// ARM9 code that counts in r0 through an undefined instruction, whose handler returns
+    /// straight after it, while the ARM11 spins
+    struct UdfLoop;
+
+    impl ldr::Loader for UdfLoop {
+        fn entrypoint9(&self) -> u32 { 0x08000100 }
+        fn entrypoint11(&self) -> u32 { 0x1FF80000 }
+        fn load9(&self, controller: &mut mem::MemController) {
+            controller.write(0x08000018, 0xE1B0F00Eu32); // movs pc, lr
+            controller.write(0x08000100, 0xE3A00000u32); // mov r0, #0
+            controller.write(0x08000104, 0xE2800001u32); // add r0, r0, #1
+            controller.write(0x08000108, 0xE7F000F0u32); // udf
+            controller.write(0x0800010C, 0xEAFFFFFCu32); // b 0x08000104
+        }
+        fn load11(&self, controller: &mut mem::MemController) {
+            controller.write(0x1FF80000, 0xEAFFFFFEu32); // b .
+        }
+        fn arm11_cores(&self) -> usize { 1 }
+    }
+
+    /// Points the config directory at blank images of the files the devices open
+    fn blank_llama_files() {
+        let home = ::std::env::temp_dir().join("llama-hwcore-test");
+        let dir = home.join(".config/llama");
+        ::std::fs::create_dir_all(&dir).unwrap();
+        for &(name, size) in [("sd.fat", 0x10000), ("nand.bin", 0x10000), ("nand-cid.bin", 16),
+                               ("otp.bin", 0x100), ("aeskeydb.bin", 0)].iter() {
+            ::std::fs::write(dir.join(name), vec![0u8; size]).unwrap();
+        }
+        ::std::env::set_var("HOME", home);
+    }
+
+    #[test]
+    fn reverse_continue_past_undefined() {
+        blank_llama_files();
+        let mut hw = HwCore::new(&UdfLoop);
+        hw.start_recording(8, 100).unwrap();
+        for _ in 0..50 {
+            hw.record_step(ActiveCpu::Arm9).unwrap();
+        }
+
+        // Halting now must not keep the replay from going past the exceptions it recorded
+        {
+            let mut hw9 = hw.hardware9.lock().unwrap();
+            hw9.arm9.halt_on_undefined = true;
+            hw9.arm9.on_bus_error = cpu::BusErrorMode::Halt;
+            hw9.arm9.breakpoints.insert(0x08000104);
+        }
+        match hw.reverse_continue().unwrap() {
+            Reverse::Halted(ActiveCpu::Arm9, cpu::BreakReason::Breakpoint) => {}
+            _ => panic!("Expected to stop at the breakpoint")
+        }
+
+        let hw9 = hw.hardware9.lock().unwrap();
+        assert_eq!(hw9.arm9.regs[15] - hw9.arm9.get_pc_offset(), 0x08000104);
+        assert!(hw9.arm9.halt_on_undefined);
+        assert_eq!(hw9.arm9.on_bus_error, cpu::BusErrorMode::Halt);
+    }
+
+    #[test]
+    fn failed_load_keeps_machine() {
+        blank_llama_files();
+        let mut hw = HwCore::new(&UdfLoop);
+        let state = write_state(&mut hw.hardware9.lock().unwrap(),
+                                &mut hw.hardware11.lock().unwrap(), &mut hw.shared_ram).unwrap();
+        hw.run_interleaved(Position { arm9: 100, arm11: !0 }, || true);
+        let running = write_state(&mut hw.hardware9.lock().unwrap(),
+                                  &mut hw.hardware11.lock().unwrap(), &mut hw.shared_ram).unwrap();
+
+        // Cut off the end of the shared RAM, after both cores have been read
+        let path = ::std::env::temp_dir().join("llama-hwcore-test").join("truncated.state");
+        ::std::fs::write(&path, &state[..state.len() - 16]).unwrap();
+        assert!(hw.load_state(&path).is_err());
+
+        let after = write_state(&mut hw.hardware9.lock().unwrap(),
+                                &mut hw.hardware11.lock().unwrap(), &mut hw.shared_ram).unwrap();
+        assert!(after == running);
+    }
+
+    /// Runs a fresh machine on one thread until both cores reach `cycles` on the shared
+    /// timeline, pressing a button along the way, and returns its state
+    fn interleaved_state(cycles: u64) -> Vec<u8> {
+        let mut hw = HwCore::new(&UdfLoop);
+        let mut hw9 = hw.hardware9.lock().unwrap();
+        let mut hw11 = hw.hardware11.lock().unwrap();
+        {
+            let mut interleaved = Interleaved {
+                hw9: &mut hw9,
+                hw11: &mut hw11,
+                quantum: 100,
+                hid: Vec::new(),
+            };
+            let mut slices = 0;
+            while interleaved.time(ActiveCpu::Arm9).min(interleaved.time(ActiveCpu::Arm11)) < cycles {
+                if slices == 5 {
+                    interleaved.input_hid(io::hid::ButtonState::Pressed(io::hid::Button::A));
+                }
+                interleaved.run_slice(Position { arm9: !0, arm11: !0 });
+                slices += 1;
+            }
+        }
+        assert_eq!(io::hid::pad(&mut hw11.io_shared().hid.lock()), 1);
+        write_state(&mut hw9, &mut hw11, &mut hw.shared_ram).unwrap()
+    }
+
+    #[test]
+    fn interleaved_is_reproducible() {
+        blank_llama_files();
+        assert!(interleaved_state(5000) == interleaved_state(5000));
+    }
+
+    #[test]
+    fn interleaved_stops_at_limit() {
+        blank_llama_files();
+        let mut hw = HwCore::new(&UdfLoop);
+        let limit = Position { arm9: 500, arm11: !0 };
+        let (_, reason) = hw.run_interleaved(limit, || true);
+        assert!(match reason { cpu::BreakReason::LimitReached => true, _ => false });
+        assert_eq!(hw.position().arm9, 500);
+    }
+
+    #[test]
+    fn quantum_order() {
+        assert_eq!(next_quantum(0, 0, 100), (ActiveCpu::Arm9, 100));
+        assert_eq!(next_quantum(100, 0, 100), (ActiveCpu::Arm11, 100));
+        assert_eq!(next_quantum(100, 104, 100), (ActiveCpu::Arm9, 200));
+        // A core that overshot waits until the other one passes it
+        assert_eq!(next_quantum(350, 200, 100), (ActiveCpu::Arm11, 300));
+        assert_eq!(next_quantum(350, 304, 100), (ActiveCpu::Arm11, 400));
+    }
+}
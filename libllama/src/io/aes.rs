@@ -7,6 +7,7 @@ use utils::bytes;
 use utils::fifo::Fifo;
 use fs;
 use io::DmaTrigger;
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 bf!(RegCnt[u32] {
     fifo_in_count: 0:4,
@@ -166,6 +167,46 @@ impl fmt::Debug for AesDeviceState {
     }
 }
 
+impl SaveState for AesDeviceState {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        if self.active_process.is_some() {
+            // OpenSSL gives us no way to extract the intermediate cipher state
+            return Err(savestate::ErrorKind::Unsupported(
+                "cannot save state while the AES engine is busy".to_owned()))
+        }
+        w.put_u32(self.active_keyslot as u32);
+        w.put_u32(self.blocks_left as u32);
+        for key in self.key_slots.iter().chain(self.keyx_slots.iter()) {
+            w.put_bytes(&key.data);
+        }
+        self.keyfifo_state.save_state(w)?;
+        self.keyxfifo_state.save_state(w)?;
+        self.keyyfifo_state.save_state(w)?;
+        self.fifo_in_buf.save_state(w)?;
+        self.fifo_out_buf.save_state(w)?;
+        w.put_bytes(&self.reg_ctr);
+        self.dma_in.save_state(w)?;
+        self.dma_out.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.active_process = None;
+        self.active_keyslot = r.get_u32()? as usize;
+        self.blocks_left = r.get_u32()? as usize;
+        for key in self.key_slots.iter_mut().chain(self.keyx_slots.iter_mut()) {
+            r.get_bytes(&mut key.data)?;
+        }
+        self.keyfifo_state.load_state(r)?;
+        self.keyxfifo_state.load_state(r)?;
+        self.keyyfifo_state.load_state(r)?;
+        self.fifo_in_buf.load_state(r)?;
+        self.fifo_out_buf.load_state(r)?;
+        r.get_bytes(&mut self.reg_ctr)?;
+        self.dma_in.load_state(r)?;
+        self.dma_out.load_state(r)
+    }
+}
+
 fn reg_cnt_onread(dev: &mut AesDevice) {
     try_drain_fifo(dev);
 
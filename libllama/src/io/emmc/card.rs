@@ -2,6 +2,7 @@ use std::fs::File;
 use std::io::{self, Seek, Read, Write};
 
 use io::emmc::TransferType;
+use savestate::{self, SaveState, StateReader, StateWriter};
 use utils::bytes;
 use utils::cache::TinyCache;
 use fs;
@@ -126,6 +127,59 @@ impl Card {
     }
 }
 
+/// The card image itself is not part of the state; it is only flushed so that it
+/// matches what the saved state expects
+impl SaveState for Card {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        self.cache.invalidate(&mut self.storage);
+        self.storage.flush()?;
+
+        w.put_u32(self.csr.val);
+        w.put_u128(self.cid.val);
+        w.put_u128(self.csd.val);
+        w.put_u16(self.rca);
+
+        w.put_bool(self.transfer.is_some());
+        if let Some(ref transfer) = self.transfer {
+            w.put_u8(transfer.loc as u8);
+            w.put_bool(transfer.ty == TransferType::Write);
+            w.put_u16(transfer.blocks_left);
+            w.put_u16(transfer.fifo_pos);
+            w.put_u64(transfer.seek_pos);
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.cache.invalidate(&mut self.storage);
+
+        self.csr.val = r.get_u32()?;
+        self.cid.val = r.get_u128()?;
+        self.csd.val = r.get_u128()?;
+        self.rca = r.get_u16()?;
+
+        self.transfer = if r.get_bool()? {
+            let loc = match r.get_u8()? {
+                0 => TransferLoc::Storage,
+                1 => TransferLoc::RegScr,
+                2 => TransferLoc::RegSsr,
+                x => return Err(savestate::ErrorKind::Mismatch(format!("invalid SDMMC transfer location {}", x)))
+            };
+            let ty = if r.get_bool()? { TransferType::Write } else { TransferType::Read };
+            Some(ActiveTransfer {
+                loc: loc,
+                ty: ty,
+                blocks_left: r.get_u16()?,
+                fifo_pos: r.get_u16()?,
+                seek_pos: r.get_u64()?
+            })
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
 impl io::Read for Card {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
         let xfer = self.transfer.as_mut()
@@ -6,10 +6,12 @@ use std::fmt;
 use std::io::{Read, Write};
 use std::mem;
 
+use clock::{Event, Scheduler};
 use io::DmaTrigger;
 use io::emmc::card::Card;
 use cpu::irq::{self, IrqClient};
 use fs;
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 bf!(RegCmd[u16] {
     command_index: 0:5,
@@ -109,10 +111,11 @@ pub struct EmmcDeviceState {
     cards: [Card; 2],
 
     dma_out: DmaTrigger,
+    sched: Scheduler,
 }
 
 impl EmmcDeviceState {
-    pub fn new(dma_out: DmaTrigger, irq_reqs: irq::IrqSyncClient) -> EmmcDeviceState {
+    pub fn new(dma_out: DmaTrigger, irq_reqs: irq::IrqSyncClient, sched: Scheduler) -> EmmcDeviceState {
         let sd_storage = fs::open_file(fs::LlamaFile::SdCardImg).unwrap();        
         let nand_storage = fs::open_file(fs::LlamaFile::NandImg).unwrap();
 
@@ -128,11 +131,32 @@ impl EmmcDeviceState {
                 Card::new(card::CardType::Mmc, nand_storage, card::nand_cid())
             ],
 
-            dma_out
+            dma_out,
+            sched
         }
     }
 }
 
+impl SaveState for EmmcDeviceState {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_u16(self.irq_statuses[0]);
+        w.put_u16(self.irq_statuses[1]);
+        for card in self.cards.iter_mut() {
+            card.save_state(w)?;
+        }
+        self.dma_out.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.irq_statuses[0] = r.get_u16()?;
+        self.irq_statuses[1] = r.get_u16()?;
+        for card in self.cards.iter_mut() {
+            card.load_state(r)?;
+        }
+        self.dma_out.load_state(r)
+    }
+}
+
 impl fmt::Debug for EmmcDeviceState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "EmmcDeviceState {{ }}")
@@ -234,7 +258,19 @@ fn clear_status<S: Into<Status>>(dev: &mut EmmcDevice, status: S) {
     };
 }
 
+/// CPU cycles it takes for the card to respond to a command
+const CMD_LATENCY: u64 = 4096;
+
 fn reg_cmd_onupdate(dev: &mut EmmcDevice) {
+    if dev._internal_state.sched.deadline(Event::Emmc).is_some() {
+        warn!("Sending SDMMC command while the previous one is still busy");
+    }
+    trigger_status(dev, Status1::CmdBusy);
+    dev._internal_state.sched.schedule_in(Event::Emmc, CMD_LATENCY);
+}
+
+/// Runs the last command written, once the card has had time to respond
+pub fn finish_cmd(dev: &mut EmmcDevice) {
     let cmd = RegCmd::new(dev.cmd.get());
     let index = cmd.command_index.get();
 
@@ -1,6 +1,9 @@
+use clock::{self, Event};
+use cpu::irq::{IrqClient, IrqType11};
 use mem;
 use msgs;
 use hwcore::Message;
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 use std::fmt;
 use std::rc::Rc;
@@ -22,6 +25,40 @@ impl HardwarePica {
     }
 }
 
+impl SaveState for HardwarePica {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        let fb_state = &self.fb_state;
+        for i in 0..2 {
+            w.put_u32(fb_state.addr_top_left[i]);
+            w.put_u32(fb_state.addr_top_right[i]);
+            w.put_u32(fb_state.addr_bot[i]);
+            w.put_u32(fb_state.color_fmt[i] as u32);
+            w.put_u32(fb_state.bg_color[i]);
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        {
+            let fb_state = &mut self.fb_state;
+            for i in 0..2 {
+                fb_state.addr_top_left[i] = r.get_u32()?;
+                fb_state.addr_top_right[i] = r.get_u32()?;
+                fb_state.addr_bot[i] = r.get_u32()?;
+                fb_state.color_fmt[i] = match r.get_u32()? {
+                    x @ 0..=4 => ColorFormat::from_index(x),
+                    x => return Err(savestate::ErrorKind::Mismatch(format!("invalid framebuffer color format {}", x)))
+                };
+                fb_state.bg_color[i] = r.get_u32()?;
+            }
+        }
+
+        // Let the UI pick up the restored framebuffers
+        self.fb_state.publish(&self.event_tx);
+        Ok(())
+    }
+}
+
 impl fmt::Debug for HardwarePica {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "HardwarePica {{ }}")
@@ -182,6 +219,22 @@ fn process_memfill(dev: &mut GpuDevice, which: usize) {
 
 
 
+/// ARM11 cycles between two VBlanks, from the LCDs' ~59.83Hz refresh rate
+pub const VBLANK_CYCLES: u64 = 4_481_136;
+
+/// Has the ARM11 clock raise the VBlank interrupts of both screens once every frame
+pub fn start_vblank(clk: &clock::SysClock) {
+    let sched = clk.sched.clone();
+    let mut irq_tx = clk.irq_tx.clone();
+    clk.set_handler(Event::VBlank, move || {
+        irq_tx.assert(IrqType11::VBlankTop);
+        irq_tx.assert(IrqType11::VBlankBottom);
+        let frame = sched.now() / VBLANK_CYCLES;
+        sched.schedule(Event::VBlank, (frame + 1) * VBLANK_CYCLES);
+    });
+    clk.sched.schedule(Event::VBlank, VBLANK_CYCLES);
+}
+
 iodevice!(LcdDevice, {
     internal_state: Rc<RefCell<HardwarePica>>;
 
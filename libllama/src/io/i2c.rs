@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 use std::fmt;
 
+use savestate::{self, SaveState, StateReader, StateWriter};
+
 
 fn mcu_power_write(dat: &mut u8) {
     if *dat & 0b111 != 0 {
@@ -70,11 +72,37 @@ impl Peripheral for PeriphMCU {
 }
 
 
+impl SaveState for PeriphMCU {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        let mut addrs: Vec<_> = self.regs.keys().cloned().collect();
+        addrs.sort();
+        for addr in addrs {
+            w.put_u8(self.regs[&addr].dat);
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        let mut addrs: Vec<_> = self.regs.keys().cloned().collect();
+        addrs.sort();
+        for addr in addrs {
+            self.regs.get_mut(&addr).unwrap().dat = r.get_u8()?;
+        }
+        Ok(())
+    }
+}
+
+
 struct PeriphLCD;
 impl PeriphLCD {
     fn new() -> Self { Self }
 }
 
+impl SaveState for PeriphLCD {
+    fn save_state(&mut self, _: &mut StateWriter) -> savestate::Result<()> { Ok(()) }
+    fn load_state(&mut self, _: &mut StateReader) -> savestate::Result<()> { Ok(()) }
+}
+
 impl Peripheral for PeriphLCD {
     fn read(&mut self, register: u8) -> Option<u8> {
         warn!("STUBBED: I2C LCD read at register {:02X}", register);
@@ -107,7 +135,7 @@ impl McuReg {
 
 
 
-trait Peripheral {
+trait Peripheral: SaveState {
     fn read(&mut self, register: u8) -> Option<u8>;
     fn write(&mut self, register: u8, dat: u8) -> bool;
 }
@@ -122,6 +150,26 @@ impl fmt::Debug for I2cPeripherals {
     }
 }
 
+impl SaveState for I2cPeripherals {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        let mut devices: Vec<_> = self.periphs.keys().cloned().collect();
+        devices.sort();
+        for device in devices {
+            self.periphs.get_mut(&device).unwrap().save_state(w)?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        let mut devices: Vec<_> = self.periphs.keys().cloned().collect();
+        devices.sort();
+        for device in devices {
+            self.periphs.get_mut(&device).unwrap().load_state(r)?;
+        }
+        Ok(())
+    }
+}
+
 impl I2cPeripherals {
     fn read(&mut self, device: u8, register: u8) -> Option<u8> {
         if let Some(p) = self.periphs.get_mut(&device) {
@@ -201,6 +249,27 @@ impl I2cDeviceState {
     }
 }
 
+impl SaveState for I2cDeviceState {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_u8(self.device);
+        w.put_u8(self.register);
+        w.put_u8(self.next_input as u8);
+        self.periphs.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.device = r.get_u8()?;
+        self.register = r.get_u8()?;
+        self.next_input = match r.get_u8()? {
+            0 => I2cByteExpected::DeviceSelect,
+            1 => I2cByteExpected::RegisterSelect,
+            2 => I2cByteExpected::DataWrite,
+            3 => I2cByteExpected::DataRead,
+            x => return Err(savestate::ErrorKind::Mismatch(format!("invalid I2C bus state {}", x)))
+        };
+        self.periphs.load_state(r)
+    }
+}
 
 fn advance_state_machine(dev: &mut I2cDevice) -> bool {
     let byte = dev.data.get();
@@ -27,11 +27,12 @@ use std::rc::Rc;
 
 use parking_lot::Mutex;
 
-use clock;
+use clock::{self, Event};
 use cpu::irq::IrqSubsys;
 use hwcore::HardwareDma9;
 use io::regs::IoRegAccess;
 use mem::MemoryBlock;
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 pub struct DmaTrigger {
     val: Arc<AtomicBool>
@@ -47,6 +48,18 @@ impl DmaTrigger {
     }
 }
 
+impl SaveState for DmaTrigger {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_bool(self.val.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.val.store(r.get_bool()?, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct DmaBus {
     val: Arc<AtomicBool>,
@@ -90,10 +103,31 @@ pub struct DmaBuses {
 
 
 
-pub fn new_devices(irq_subsys9: IrqSubsys, irq_subsys11: IrqSubsys,
-                   clk: clock::SysClock, pica_hw: gpu::HardwarePica,
+/// CPU cycles the DMA engines lag behind an access to the ARM9 IO registers
+const DMA_LATENCY: u64 = 16;
+
+/// How often DMA engines that are still busy get to run again
+const DMA_POLL_CYCLES: u64 = 1024;
+
+/// Runs the DMA engines for as long as they can make progress, returning whether any
+/// of them is still busy
+fn run_dma(xdma: &RefCell<xdma::XdmaDevice>, ndma: &RefCell<ndma::NdmaDevice>) -> bool {
+    let xdma_busy = {
+        let mut xdma = xdma.borrow_mut();
+        xdma::schedule(&mut *xdma);
+        xdma::is_busy(&*xdma)
+    };
+    let mut ndma = ndma.borrow_mut();
+    ndma::schedule(&mut *ndma);
+    xdma_busy || ndma::is_busy(&*ndma)
+}
+
+/// Creates every device, with one IRQ subsystem and clock for each ARM11 core. Interrupts
+/// from devices all go to the first core, whose clock also times the LCDs.
+pub fn new_devices(irq_subsys9: IrqSubsys, irq_subsys11: Vec<IrqSubsys>,
+                   clk: clock::SysClock, clks11: Vec<clock::SysClock>, pica_hw: gpu::HardwarePica,
                    dma9_shared: Rc<RefCell<HardwareDma9>>)
-    -> (IoRegsArm9, IoRegsShared, IoRegsArm11, IoRegsArm11Priv) {
+    -> (IoRegsArm9, IoRegsShared, IoRegsArm11, Vec<IoRegsArm11Priv>) {
     
     macro_rules! make_dev_uniq {
         ($type:ty) => { Rc::new(RefCell::new( <$type>::new() )) };
@@ -105,7 +139,7 @@ pub fn new_devices(irq_subsys9: IrqSubsys, irq_subsys11: IrqSubsys,
         ($type:ty: $($arg:expr),+) => {{ Arc::new(Mutex::new(<$type>::new($($arg),*))) }};
     }
 
-    let pxi_shared = pxi::PxiShared::make_channel(irq_subsys9.async_tx, irq_subsys11.async_tx);
+    let pxi_shared = pxi::PxiShared::make_channel(irq_subsys9.async_tx, irq_subsys11[0].async_tx.clone());
 
     let (_, dmabus_null) = DmaTrigger::new();
     let (dmatrg_aes_in, dmabus_aes_in) = DmaTrigger::new();
@@ -125,10 +159,10 @@ pub fn new_devices(irq_subsys9: IrqSubsys, irq_subsys11: IrqSubsys,
 
     let cfg    = make_dev_uniq! { config::ConfigDevice };
     let irq    = make_dev_uniq! { irq::IrqDevice:     irq_subsys9.agg };
-    let emmc   = make_dev_uniq! { emmc::EmmcDevice:   emmc::EmmcDeviceState::new(dmatrg_sdmmc_out, irq_subsys9.sync_tx) };
+    let emmc   = make_dev_uniq! { emmc::EmmcDevice:   emmc::EmmcDeviceState::new(dmatrg_sdmmc_out, irq_subsys9.sync_tx, clk.sched.clone()) };
     let otp    = make_dev_uniq! { otp::OtpDevice:     Default::default() };
     let pxi9   = make_dev_uniq! { pxi::PxiDevice:     pxi_shared.0 };
-    let timer  = make_dev_uniq! { timer::TimerDevice: clk.timer_states };
+    let timer  = make_dev_uniq! { timer::TimerDevice: clk.timer_states.clone() };
     let aes    = make_dev_uniq! { aes::AesDevice:     aes::AesDeviceState::new(dmatrg_aes_in, dmatrg_aes_out) };
     let sha    = make_dev_uniq! { sha::ShaDevice:     sha::ShaDeviceState::new(dmatrg_sha_in, dmatrg_sha_out) };
     let rsa    = make_dev_uniq! { rsa::RsaDevice:     Default::default() };
@@ -145,9 +179,36 @@ pub fn new_devices(irq_subsys9: IrqSubsys, irq_subsys11: IrqSubsys,
     let lcd    = make_dev_uniq! { gpu::LcdDevice:     pica_hw.clone() };
     let gpu    = make_dev_uniq! { gpu::GpuDevice:     pica_hw };
 
-    let irq11_agg = Rc::new(RefCell::new(irq_subsys11.agg));
-    let priv11 = make_dev_uniq! { priv11::Priv11Device: irq11_agg.clone() };
-    let gid    = make_dev_uniq! { priv11::GidDevice:  priv11::GidState::new(irq11_agg.clone()) };
+    {
+        let (xdma, ndma, sched) = (xdma.clone(), ndma.clone(), clk.sched.clone());
+        clk.set_handler(Event::Dma, move || {
+            if run_dma(&xdma, &ndma) {
+                sched.schedule_within(Event::Dma, DMA_POLL_CYCLES);
+            }
+        });
+        let emmc = emmc.clone();
+        clk.set_handler(Event::Emmc, move || emmc::finish_cmd(&mut *emmc.borrow_mut()));
+    }
+    gpu::start_vblank(&clks11[0]);
+
+    let num_cores11 = irq_subsys11.len();
+    let core_irqs11: Vec<_> = irq_subsys11.into_iter()
+        .map(|subsys| Rc::new(RefCell::new(priv11::CoreIrqs::new(subsys.agg))))
+        .collect();
+    let gid    = make_dev_uniq! { priv11::GidDevice:  priv11::GidState::new(core_irqs11.clone()) };
+    let io11_priv = core_irqs11.into_iter().zip(clks11).enumerate().map(|(core, (irqs, clk11))| {
+        let priv11 = Rc::new(RefCell::new(
+            priv11::Priv11Device::with_cores(irqs, clk11.sched.clone(), num_cores11)));
+        {
+            let priv11 = priv11.clone();
+            clk11.set_handler(Event::PrivTimer, move || priv11::update_timers(&mut *priv11.borrow_mut()));
+        }
+        IoRegsArm11Priv {
+            core:   core as u32,
+            priv11: priv11,
+            gid:    gid.clone(),
+        }
+    }).collect();
 
     (IoRegsArm9 {
         cfg:    cfg,
@@ -162,6 +223,7 @@ pub fn new_devices(irq_subsys9: IrqSubsys, irq_subsys11: IrqSubsys,
         rsa:    rsa,
         xdma:   xdma,
         cfgext: cfgext,
+        sched:  clk.sched,
     },
     IoRegsShared {
         hid:    hid,
@@ -172,10 +234,7 @@ pub fn new_devices(irq_subsys9: IrqSubsys, irq_subsys11: IrqSubsys,
         lcd:    lcd,
         gpu:    gpu,
     },
-    IoRegsArm11Priv {
-        priv11: priv11,
-        gid:    gid,
-    })
+    io11_priv)
 }
 
 macro_rules! impl_rw {
@@ -196,6 +255,14 @@ macro_rules! impl_rw {
                 _ => error!("Unimplemented IO register write at offset 0x{:X}", offset),
             };
         }
+        pub(crate) fn save_state(&self, w: &mut StateWriter) -> savestate::Result<()> {
+            $( self.$name.borrow_mut().save_state(w)?; )*
+            Ok(())
+        }
+        pub(crate) fn load_state(&self, r: &mut StateReader) -> savestate::Result<()> {
+            $( self.$name.borrow_mut().load_state(r)?; )*
+            Ok(())
+        }
     };
 }
 
@@ -217,6 +284,14 @@ macro_rules! impl_rw_locked {
                 _ => error!("Unimplemented IO register write at offset 0x{:X}", offset),
             };
         }
+        pub(crate) fn save_state(&self, w: &mut StateWriter) -> savestate::Result<()> {
+            $( self.$name.lock().save_state(w)?; )*
+            Ok(())
+        }
+        pub(crate) fn load_state(&self, r: &mut StateReader) -> savestate::Result<()> {
+            $( self.$name.lock().load_state(r)?; )*
+            Ok(())
+        }
     };
 }
 
@@ -239,6 +314,7 @@ pub struct IoRegsArm9 {
     // prng,
     pub otp:    Rc<RefCell< otp::OtpDevice >>,
     // arm7,
+    sched: clock::Scheduler,
 }
 
 impl IoRegsArm9 {
@@ -266,19 +342,13 @@ impl MemoryBlock for IoRegsArm9 {
     fn read_buf(&self, offset: usize, buf: &mut [u8]) {
         self.read_reg(offset, buf);
 
-        let mut xdma = self.xdma.borrow_mut();
-        xdma::schedule(&mut *xdma);
-        let mut ndma = self.ndma.borrow_mut();
-        ndma::schedule(&mut *ndma);
+        self.sched.schedule_within(Event::Dma, DMA_LATENCY);
     }
 
     fn write_buf(&mut self, offset: usize, buf: &[u8]) {
         self.write_reg(offset, buf);
 
-        let mut xdma = self.xdma.borrow_mut();
-        xdma::schedule(&mut *xdma);
-        let mut ndma = self.ndma.borrow_mut();
-        ndma::schedule(&mut *ndma);
+        self.sched.schedule_within(Event::Dma, DMA_LATENCY);
     }
 }
 
@@ -358,8 +428,11 @@ impl MemoryBlock for IoRegsArm11 {
 }
 
 
+/// The private region of one ARM11 core. The interrupt distributor is shared
+/// between cores.
 #[derive(Clone)]
 pub struct IoRegsArm11Priv {
+    core: u32,
     pub priv11: Rc<RefCell< priv11::Priv11Device >>,
     pub gid:    Rc<RefCell< priv11::GidDevice >>,
 }
@@ -377,10 +450,12 @@ impl MemoryBlock for IoRegsArm11Priv {
     }
 
     fn read_buf(&self, offset: usize, buf: &mut [u8]) {
+        self.gid.borrow_mut().set_accessing_core(self.core);
         self.read_reg(offset, buf)
     }
 
     fn write_buf(&mut self, offset: usize, buf: &[u8]) {
+        self.gid.borrow_mut().set_accessing_core(self.core);
         self.write_reg(offset, buf)
     }
 }
@@ -5,6 +5,7 @@ use std::fmt;
 
 use hwcore::HardwareDma9;
 use io::{DmaBus, DmaBuses};
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 bf!(RegGlobalCnt[u32] {
     enabled: 0:0,
@@ -35,6 +36,24 @@ pub struct NdmaChannelState {
     xfer_total: u32,
 }
 
+impl SaveState for NdmaChannelState {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_bool(self.started);
+        w.put_u32(self.src_addr);
+        w.put_u32(self.dst_addr);
+        w.put_u32(self.xfer_total);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.started = r.get_bool()?;
+        self.src_addr = r.get_u32()?;
+        self.dst_addr = r.get_u32()?;
+        self.xfer_total = r.get_u32()?;
+        Ok(())
+    }
+}
+
 fn startup_mode(cnt: &RegChannelCnt::Bf) -> u32 {
     cnt.startup_mode.get() | (cnt.startup_mode_ext.get() << 4)
 }
@@ -238,12 +257,34 @@ impl NdmaDeviceState {
     }
 }
 
+impl SaveState for NdmaDeviceState {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        for channel in self.channels.iter_mut() {
+            channel.save_state(w)?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        for channel in self.channels.iter_mut() {
+            channel.load_state(r)?;
+        }
+        Ok(())
+    }
+}
+
 pub fn schedule(dev: &mut NdmaDevice) {
     for channel in dev._internal_state.channels.iter_mut() {
         process_channel(channel);
     }
 }
 
+/// Whether any channel is still enabled
+pub fn is_busy(dev: &NdmaDevice) -> bool {
+    dev._internal_state.channels.iter()
+        .any(|channel| RegChannelCnt::new(channel.chan_cnt.get()).enabled.get() == 1)
+}
+
 iodevice!(NdmaDevice, {
     internal_state: NdmaDeviceState;
     regs: {
@@ -2,6 +2,7 @@ use std::fmt;
 use std::io::Read;
 
 use fs;
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 pub struct OtpDeviceState {
     otp: [u8; 0x100]
@@ -26,6 +27,17 @@ impl Default for OtpDeviceState {
     }
 }
 
+impl SaveState for OtpDeviceState {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_bytes(&self.otp);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        r.get_bytes(&mut self.otp)
+    }
+}
+
 fn reg_otp_write(dev: &mut OtpDevice, buf_pos: usize, source: &[u8]) {
     dev._internal_state.otp[buf_pos .. buf_pos + source.len()].copy_from_slice(source);
 }
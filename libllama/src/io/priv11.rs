@@ -1,15 +1,319 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::fmt;
 
+use clock::{Event, Scheduler};
 use cpu::irq::Aggregator;
+use savestate::{self, SaveState, StateReader, StateWriter};
+
+/// One ARM11 core's side of the interrupt controller
+#[derive(Debug)]
+pub struct CoreIrqs {
+    agg: Aggregator,
+    /// Core that sent each software interrupt, reported when it is acknowledged
+    sgi_sources: [u32; 16],
+}
+
+impl CoreIrqs {
+    pub fn new(agg: Aggregator) -> Self {
+        Self {
+            agg,
+            sgi_sources: [0; 16],
+        }
+    }
+
+    fn raise_sgi(&mut self, irq: u32, source: u32) {
+        self.sgi_sources[irq as usize] = source;
+        self.agg.raise(irq);
+    }
+
+    /// Acknowledge register value for the next pending interrupt, 1023 if there is none
+    fn next_pending(&mut self) -> u32 {
+        match self.agg.drain_asserts().trailing_zeros() {
+            128 => 1023,
+            irq if irq < 16 => irq | (self.sgi_sources[irq as usize] << 10),
+            irq => irq
+        }
+    }
+}
+
+impl SaveState for CoreIrqs {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        self.agg.save_state(w)?;
+        for source in self.sgi_sources.iter() {
+            w.put_u32(*source);
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.agg.load_state(r)?;
+        for source in self.sgi_sources.iter_mut() {
+            *source = r.get_u32()?;
+        }
+        Ok(())
+    }
+}
+
+/// Interrupts of a core's private timer and watchdog
+const IRQ_PRIV_TIMER: u32 = 29;
+const IRQ_WATCHDOG: u32 = 30;
+
+/// Core cycles for each tick of the private timer and watchdog before their prescalers,
+/// as the MPCore peripherals run at half the core clock speed
+const PRIV_TIMER_CYCLES: u64 = 2;
+
+/// Words that take the watchdog out of watchdog mode when written to its disable register
+/// one after the other
+const WDG_DISABLE_KEYS: [u32; 2] = [0x12345678, 0x87654321];
+
+bf!(TimerCtrl[u32] {
+    enabled: 0:0,
+    auto_reload: 1:1,
+    irq_enabled: 2:2,
+    watchdog_mode: 3:3,
+    prescaler: 8:15
+});
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Which {
+    Timer,
+    Watchdog,
+}
+
+/// A private timer or watchdog counting down. The counter is brought up to date lazily,
+/// whenever it is accessed and once it reaches zero.
+#[derive(Debug, Default)]
+struct Countdown {
+    load: u32,
+    counter: u32,
+    ctrl: u32,
+    /// Cycle of the last tick accounted for in `counter`
+    since: u64,
+    /// Set when the counter reaches zero, until cleared through the interrupt status register
+    expired: bool,
+}
+
+impl Countdown {
+    fn ctrl(&self) -> TimerCtrl::Bf {
+        TimerCtrl::new(self.ctrl)
+    }
+
+    fn tick_cycles(&self) -> u64 {
+        PRIV_TIMER_CYCLES * (self.ctrl().prescaler.get() as u64 + 1)
+    }
+
+    fn is_running(&self) -> bool {
+        self.ctrl().enabled.get() == 1 && self.counter != 0
+    }
+
+    /// Counts the ticks up to cycle `now`, returning whether the counter reached zero
+    fn advance(&mut self, now: u64) -> bool {
+        if !self.is_running() {
+            self.since = now;
+            return false
+        }
+        let tick_cycles = self.tick_cycles();
+        let ticks = (now - self.since) / tick_cycles;
+        self.since += ticks * tick_cycles;
+        if ticks < self.counter as u64 {
+            self.counter -= ticks as u32;
+            return false
+        }
+
+        let overshoot = ticks - self.counter as u64;
+        self.counter = if self.ctrl().auto_reload.get() == 1 && self.load != 0 {
+            self.load - (overshoot % self.load as u64) as u32
+        } else {
+            0
+        };
+        self.expired = true;
+        true
+    }
+
+    /// Cycle at which the counter next reaches zero
+    fn next_expiry(&self) -> Option<u64> {
+        if self.is_running() {
+            Some(self.since + self.counter as u64 * self.tick_cycles())
+        } else {
+            None
+        }
+    }
+}
+
+impl SaveState for Countdown {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_u32(self.load);
+        w.put_u32(self.counter);
+        w.put_u32(self.ctrl);
+        w.put_u64(self.since);
+        w.put_bool(self.expired);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.load = r.get_u32()?;
+        self.counter = r.get_u32()?;
+        self.ctrl = r.get_u32()?;
+        self.since = r.get_u64()?;
+        self.expired = r.get_bool()?;
+        Ok(())
+    }
+}
+
+/// State of one core's private peripherals, timed by the core's own clock
+pub struct Priv11State {
+    irqs: Rc<RefCell<CoreIrqs>>,
+    sched: Scheduler,
+    timer: Countdown,
+    watchdog: Countdown,
+    /// Set when the watchdog reaches zero in watchdog mode
+    reset_sent: bool,
+    /// Whether the first of `WDG_DISABLE_KEYS` was the last word written
+    disable_armed: bool,
+}
+
+impl Priv11State {
+    pub fn new(irqs: Rc<RefCell<CoreIrqs>>, sched: Scheduler) -> Self {
+        Self {
+            irqs,
+            sched,
+            timer: Countdown::default(),
+            watchdog: Countdown::default(),
+            reset_sent: false,
+            disable_armed: false,
+        }
+    }
+
+    fn countdown(&mut self, which: Which) -> &mut Countdown {
+        match which {
+            Which::Timer => &mut self.timer,
+            Which::Watchdog => &mut self.watchdog,
+        }
+    }
+}
+
+/// The core's scheduler is saved along with its clock
+impl SaveState for Priv11State {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        self.irqs.save_state(w)?;
+        self.timer.save_state(w)?;
+        self.watchdog.save_state(w)?;
+        w.put_bool(self.reset_sent);
+        w.put_bool(self.disable_armed);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.irqs.load_state(r)?;
+        self.timer.load_state(r)?;
+        self.watchdog.load_state(r)?;
+        self.reset_sent = r.get_bool()?;
+        self.disable_armed = r.get_bool()?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Priv11State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Priv11State {{ }}")
+    }
+}
+
+/// Brings the private timer and watchdog up to date, signalling those that reached zero,
+/// and schedules the next time one of them does
+pub fn update_timers(dev: &mut Priv11Device) {
+    let state = &mut dev._internal_state;
+    let now = state.sched.now();
+
+    if state.timer.advance(now) && state.timer.ctrl().irq_enabled.get() == 1 {
+        state.irqs.borrow_mut().agg.raise(IRQ_PRIV_TIMER);
+    }
+    if state.watchdog.advance(now) {
+        let ctrl = state.watchdog.ctrl();
+        if ctrl.watchdog_mode.get() == 1 {
+            state.reset_sent = true;
+            error!("ARM11 watchdog expired; resetting the core is not emulated");
+        } else if ctrl.irq_enabled.get() == 1 {
+            state.irqs.borrow_mut().agg.raise(IRQ_WATCHDOG);
+        }
+    }
+
+    let next = state.timer.next_expiry().into_iter()
+        .chain(state.watchdog.next_expiry())
+        .min();
+    match next {
+        Some(at) => state.sched.schedule(Event::PrivTimer, at),
+        None => state.sched.cancel(Event::PrivTimer)
+    }
+}
+
+/// Changes a countdown once it has caught up with the clock, and reschedules its expiry
+fn modify_countdown<F: FnOnce(&mut Countdown)>(dev: &mut Priv11Device, which: Which, f: F) {
+    update_timers(dev);
+    f(dev._internal_state.countdown(which));
+    update_timers(dev);
+}
+
+fn write_load(dev: &mut Priv11Device, which: Which, val: u32) {
+    modify_countdown(dev, which, |countdown| {
+        countdown.load = val;
+        countdown.counter = val;
+    });
+}
+
+fn write_counter(dev: &mut Priv11Device, which: Which, val: u32) {
+    modify_countdown(dev, which, |countdown| countdown.counter = val);
+}
+
+fn write_ctrl(dev: &mut Priv11Device, which: Which, val: u32) {
+    modify_countdown(dev, which, |countdown| {
+        countdown.ctrl = match which {
+            Which::Timer => val & 0xFF07,
+            // Only the disable sequence leaves watchdog mode
+            Which::Watchdog => (val & 0xFF0F) | (countdown.ctrl & 0x8),
+        };
+    });
+    let ctrl = dev._internal_state.countdown(which).ctrl;
+    match which {
+        Which::Timer => dev.timer_ctrl.set_unchecked(ctrl),
+        Which::Watchdog => dev.wdg_ctrl.set_unchecked(ctrl),
+    }
+}
+
+fn clear_expired(dev: &mut Priv11Device, which: Which, val: u32) {
+    update_timers(dev);
+    let countdown = dev._internal_state.countdown(which);
+    if val & 1 != 0 {
+        countdown.expired = false;
+    }
+    let expired = countdown.expired as u32;
+    match which {
+        Which::Timer => dev.timer_interrupt_stat.set_unchecked(expired),
+        Which::Watchdog => dev.wdg_interrupt_stat.set_unchecked(expired),
+    }
+}
+
+fn write_wdg_disable(dev: &mut Priv11Device, val: u32) {
+    dev.wdg_disable.set_unchecked(0);
+    if dev._internal_state.disable_armed && val == WDG_DISABLE_KEYS[1] {
+        modify_countdown(dev, Which::Watchdog, |countdown| countdown.ctrl &= !0x8);
+        let ctrl = dev._internal_state.watchdog.ctrl;
+        dev.wdg_ctrl.set_unchecked(ctrl);
+    }
+    dev._internal_state.disable_armed = val == WDG_DISABLE_KEYS[0];
+}
 
 iodevice!(Priv11Device, {
-    internal_state: Rc<RefCell<Aggregator>>;
+    internal_state: Priv11State;
     regs: {
         0x000 => scu_ctrl: u32 {
             default = 0x00001FFE;
             write_effect = |_| warn!("STUBBED: Write to ARM11 SCU ctrl register!");
         }
+        0x004 => scu_config: u32 {
+            write_bits = 0;
+        }
         0x00C => scu_invalidate_all: u32 {}
         0x100 => interrupt_ctrl: u32 {
             write_effect = |_| warn!("STUBBED: Write to ARM11 Interrupt ctrl register!");
@@ -24,20 +328,16 @@ iodevice!(Priv11Device, {
             default = 1023;
             write_bits = 0;
             read_effect = |dev: &mut Priv11Device| {
-                let mut next_interrupt = dev._internal_state.borrow_mut()
-                    .drain_asserts().trailing_zeros();
-                if next_interrupt == 128 {
-                    next_interrupt = 1023;
-                }
+                let next_interrupt = dev._internal_state.irqs.borrow_mut().next_pending();
                 dev.interrupt_ack.set_unchecked(next_interrupt);
                 warn!("STUBBED: Read from ARM11 Acknowledge Interrupt register!")
             };
         }
         0x110 => interrupt_end: u32 {
             write_effect = |dev: &mut Priv11Device| {
-                let which = dev.interrupt_end.get();
+                let which = dev.interrupt_end.get() & 0x3FF;
                 let which_mask = 1u128 << (which as usize);
-                dev._internal_state.borrow_mut().acknowledge(which_mask);
+                dev._internal_state.irqs.borrow_mut().agg.acknowledge(which_mask);
                 warn!("STUBBED: Write {} to ARM11 Interrupt end register!", which);
             };
         }
@@ -45,11 +345,7 @@ iodevice!(Priv11Device, {
             default = 1023;
             write_bits = 0;
             read_effect = |dev: &mut Priv11Device| {
-                let mut next_interrupt = dev._internal_state.borrow_mut()
-                    .drain_asserts().trailing_zeros();
-                if next_interrupt == 128 {
-                    next_interrupt = 1023;
-                }
+                let next_interrupt = dev._internal_state.irqs.borrow_mut().next_pending();
                 dev.interrupt_highest_pending.set_unchecked(next_interrupt);
                 warn!("STUBBED: Read {} from ARM11 Highest Pending Interrupt register!", next_interrupt)
             };
@@ -57,62 +353,151 @@ iodevice!(Priv11Device, {
 
         0x600 => timer_load: u32 {
             write_effect = |dev: &mut Priv11Device| {
-                warn!("STUBBED: Write {:08X} to ARM11 Timer load register!", dev.timer_load.get());
+                let val = dev.timer_load.get();
+                write_load(dev, Which::Timer, val);
             };
         }
         0x604 => timer_counter: u32 {
             read_effect = |dev: &mut Priv11Device| {
-                warn!("STUBBED: Read {:08X} from ARM11 Timer counter register!", dev.timer_counter.get());
+                update_timers(dev);
+                let counter = dev._internal_state.timer.counter;
+                dev.timer_counter.set_unchecked(counter);
             };
             write_effect = |dev: &mut Priv11Device| {
-                warn!("STUBBED: Write {:08X} to ARM11 Timer counter register!", dev.timer_counter.get());
+                let val = dev.timer_counter.get();
+                write_counter(dev, Which::Timer, val);
             };
         }
         0x608 => timer_ctrl: u32 {
             write_effect = |dev: &mut Priv11Device| {
-                warn!("STUBBED: Write {:08X} to ARM11 Timer control register!", dev.timer_ctrl.get());
+                let val = dev.timer_ctrl.get();
+                write_ctrl(dev, Which::Timer, val);
             };
         }
         0x60C => timer_interrupt_stat: u32 {
+            read_effect = |dev: &mut Priv11Device| {
+                update_timers(dev);
+                let expired = dev._internal_state.timer.expired;
+                dev.timer_interrupt_stat.set_unchecked(expired as u32);
+            };
+            write_effect = |dev: &mut Priv11Device| {
+                let val = dev.timer_interrupt_stat.get();
+                clear_expired(dev, Which::Timer, val);
+            };
+        }
+        0x620 => wdg_load: u32 {
             write_effect = |dev: &mut Priv11Device| {
-                warn!("STUBBED: Write {:08X} to ARM11 Timer interrupt status register!", dev.timer_interrupt_stat.get());
+                let val = dev.wdg_load.get();
+                write_load(dev, Which::Watchdog, val);
+            };
+        }
+        0x624 => wdg_counter: u32 {
+            read_effect = |dev: &mut Priv11Device| {
+                update_timers(dev);
+                let counter = dev._internal_state.watchdog.counter;
+                dev.wdg_counter.set_unchecked(counter);
+            };
+            write_effect = |dev: &mut Priv11Device| {
+                let val = dev.wdg_counter.get();
+                write_counter(dev, Which::Watchdog, val);
             };
         }
         0x628 => wdg_ctrl: u32 {
             write_effect = |dev: &mut Priv11Device| {
-                warn!("STUBBED: Write {:08X} to ARM11 Watchdog control register!", dev.wdg_ctrl.get());
+                let val = dev.wdg_ctrl.get();
+                write_ctrl(dev, Which::Watchdog, val);
             };
         }
         0x62C => wdg_interrupt_stat: u32 {
+            read_effect = |dev: &mut Priv11Device| {
+                update_timers(dev);
+                let expired = dev._internal_state.watchdog.expired;
+                dev.wdg_interrupt_stat.set_unchecked(expired as u32);
+            };
             write_effect = |dev: &mut Priv11Device| {
-                warn!("STUBBED: Write {:08X} to ARM11 Watchdog interrupt status register!", dev.wdg_interrupt_stat.get());
+                let val = dev.wdg_interrupt_stat.get();
+                clear_expired(dev, Which::Watchdog, val);
             };
         }
         0x630 => wdg_reset_sent: u32 {
+            read_effect = |dev: &mut Priv11Device| {
+                update_timers(dev);
+                let reset_sent = dev._internal_state.reset_sent;
+                dev.wdg_reset_sent.set_unchecked(reset_sent as u32);
+            };
             write_effect = |dev: &mut Priv11Device| {
-                warn!("STUBBED: Write {:08X} to ARM11 Watchdog reset sent register!", dev.wdg_reset_sent.get());
+                // Write 1 to clear
+                if dev.wdg_reset_sent.get() & 1 != 0 {
+                    dev._internal_state.reset_sent = false;
+                }
+                let reset_sent = dev._internal_state.reset_sent;
+                dev.wdg_reset_sent.set_unchecked(reset_sent as u32);
             };
         }
         0x634 => wdg_disable: u32 {
             write_effect = |dev: &mut Priv11Device| {
-                warn!("STUBBED: Write {:08X} to ARM11 Watchdog disable register!", dev.wdg_disable.get());
+                let val = dev.wdg_disable.get();
+                write_wdg_disable(dev, val);
             };
         }
     }
 });
 
+impl Priv11Device {
+    /// Private peripherals of a core of an MPCore with `num_cores` cores, timed by the
+    /// core's scheduler
+    pub fn with_cores(irqs: Rc<RefCell<CoreIrqs>>, sched: Scheduler, num_cores: usize) -> Self {
+        let mut dev = Self::new(Priv11State::new(irqs, sched));
+        let all_cores = (1u32 << num_cores) - 1;
+        dev.scu_config.set_unchecked((all_cores << 4) | (num_cores as u32 - 1));
+        dev
+    }
+}
+
+/// Software interrupts can't be disabled in the distributor
+const SGI_ENABLE_MASK: u128 = 0xFFFF;
+
 #[derive(Debug)]
 pub struct GidState {
-    agg: Rc<RefCell<Aggregator>>,
+    cores: Vec<Rc<RefCell<CoreIrqs>>>,
     enabled: [u32; 4],
+    /// Core making the current access, which software interrupts are sent from
+    accessing_core: u32,
 }
 
 impl GidState {
-    pub fn new(agg: Rc<RefCell<Aggregator>>) -> Self {
+    pub fn new(cores: Vec<Rc<RefCell<CoreIrqs>>>) -> Self {
+        for core in cores.iter() {
+            core.borrow().agg.set_enabled(SGI_ENABLE_MASK);
+        }
         Self {
-            agg,
+            cores,
             enabled: [0;4],
+            accessing_core: 0,
+        }
+    }
+}
+
+/// The per-core interrupt state is saved along with each core's Priv11Device
+impl SaveState for GidState {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        for word in self.enabled.iter() {
+            w.put_u32(*word);
         }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        for word in self.enabled.iter_mut() {
+            *word = r.get_u32()?;
+        }
+        Ok(())
+    }
+}
+
+impl GidDevice {
+    pub fn set_accessing_core(&mut self, core: u32) {
+        self._internal_state.accessing_core = core;
     }
 }
 
@@ -124,7 +509,29 @@ fn update_enabled(dev: &mut GidDevice) {
             | ((enabled[1] as u128) << 32)
             | (enabled[0] as u128)
     };
-    dev._internal_state.agg.borrow().set_enabled(enabled);
+    for core in dev._internal_state.cores.iter() {
+        core.borrow().agg.set_enabled(enabled | SGI_ENABLE_MASK);
+    }
+}
+
+fn send_sgi(dev: &mut GidDevice) {
+    let val = dev.software_interrupt.get();
+    let irq = bits!(val, 0:3);
+    let source = dev._internal_state.accessing_core;
+    let targets = match bits!(val, 24:25) {
+        0 => bits!(val, 16:23),
+        1 => !(1 << source),
+        2 => 1 << source,
+        _ => {
+            warn!("Reserved ARM11 software interrupt target filter in {:08X}", val);
+            0
+        }
+    };
+    for (core, irqs) in dev._internal_state.cores.iter().enumerate() {
+        if targets & (1 << core) != 0 {
+            irqs.borrow_mut().raise_sgi(irq, source);
+        }
+    }
 }
 
 macro_rules! enable_setX {
@@ -146,7 +553,9 @@ macro_rules! enable_clrX {
 macro_rules! pending_clrX {
     ($reg:ident, $i:expr) => (|dev: &mut GidDevice| {
         let which = (dev.$reg.get() as u128) << ($i * 32);
-        dev._internal_state.agg.borrow_mut().acknowledge(which);
+        for core in dev._internal_state.cores.iter() {
+            core.borrow_mut().agg.acknowledge(which);
+        }
     })
 }
 
@@ -159,8 +568,8 @@ macro_rules! pending_setX {
 
 macro_rules! pending_getX {
     ($reg:ident, $i:expr) => (|dev: &mut GidDevice| {
-        let asserted = dev._internal_state.agg
-            .borrow_mut().drain_asserts();
+        let asserted = dev._internal_state.cores.iter()
+            .fold(0, |acc, core| acc | core.borrow_mut().agg.drain_asserts());
         dev.$reg.set_unchecked((asserted >> ($i * 32)) as u32);
     })
 }
@@ -232,6 +641,10 @@ iodevice!(GidDevice, {
             read_effect = pending_getX!(pending_clr3, 3);
             write_effect = pending_clrX!(pending_clr3, 3);
         }
+
+        0xF00 => software_interrupt: u32 {
+            write_effect = send_sgi;
+        }
     }
     ranges: {
         0x400;0x100 => {
@@ -251,3 +664,119 @@ iodevice!(GidDevice, {
         }
     }
 });
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clock;
+    use cpu::irq::IrqSubsys;
+    use io::regs::IoRegAccess;
+
+    fn make_clock() -> clock::SysClock {
+        clock::make_channel(IrqSubsys::create().sync_tx)
+    }
+
+    fn make_cores(n: usize) -> Vec<Rc<RefCell<CoreIrqs>>> {
+        (0..n).map(|_| Rc::new(RefCell::new(CoreIrqs::new(IrqSubsys::create().agg))))
+            .collect()
+    }
+
+    fn ack(irqs: &Rc<RefCell<CoreIrqs>>) -> u32 {
+        let mut dev = Priv11Device::with_cores(irqs.clone(), make_clock().sched, 2);
+        let mut buf = [0u8; 4];
+        dev.read_reg(0x10C, &mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    #[test]
+    fn software_interrupts() {
+        let cores = make_cores(2);
+        let mut gid = GidDevice::new(GidState::new(cores.clone()));
+
+        // SGI 5 from core 1 to the cores in the target list
+        gid.set_accessing_core(1);
+        gid.write_reg(0xF00, &(0x00010005u32).to_le_bytes());
+        assert_eq!(ack(&cores[0]), 5 | (1 << 10));
+        assert_eq!(ack(&cores[1]), 1023);
+
+        // SGI 3 from core 0 to everyone else
+        gid.set_accessing_core(0);
+        gid.write_reg(0xF00, &(0x01000003u32).to_le_bytes());
+        assert_eq!(ack(&cores[1]), 3);
+
+        // SGI 7 from core 1 to itself
+        gid.set_accessing_core(1);
+        gid.write_reg(0xF00, &(0x02000007u32).to_le_bytes());
+        assert_eq!(ack(&cores[1]), 3);
+        cores[1].borrow_mut().agg.acknowledge(1 << 3);
+        assert_eq!(ack(&cores[1]), 7 | (1 << 10));
+    }
+
+    #[test]
+    fn scu_config() {
+        let cores = make_cores(4);
+        let mut dev = Priv11Device::with_cores(cores[2].clone(), make_clock().sched, 4);
+        let mut buf = [0u8; 4];
+        dev.read_reg(0x004, &mut buf);
+        assert_eq!(u32::from_le_bytes(buf), 0xF3);
+    }
+
+    #[test]
+    fn private_timer() {
+        let subsys = IrqSubsys::create();
+        subsys.agg.set_enabled(1u128 << IRQ_PRIV_TIMER);
+        let irqs = Rc::new(RefCell::new(CoreIrqs::new(subsys.agg)));
+        let mut clk = make_clock();
+        let dev = Rc::new(RefCell::new(Priv11Device::with_cores(irqs, clk.sched.clone(), 1)));
+        {
+            let dev = dev.clone();
+            clk.set_handler(Event::PrivTimer, move || update_timers(&mut *dev.borrow_mut()));
+        }
+        let read = |offset| {
+            let mut buf = [0u8; 4];
+            dev.borrow_mut().read_reg(offset, &mut buf);
+            u32::from_le_bytes(buf)
+        };
+
+        // 0x10 ticks with a prescaler of 2, auto-reloading and raising its IRQ
+        dev.borrow_mut().write_reg(0x600, &0x10u32.to_le_bytes());
+        dev.borrow_mut().write_reg(0x608, &0x0207u32.to_le_bytes());
+        let period = 0x10 * 3 * PRIV_TIMER_CYCLES;
+        assert_eq!(clk.sched.deadline(Event::PrivTimer), Some(period));
+
+        clk.increment(period - 1);
+        assert_eq!(read(0x604), 1);
+        assert_eq!(read(0x60C), 0);
+        assert!(!subsys.line.is_high());
+        clk.increment(1);
+        assert!(subsys.line.is_high());
+        assert_eq!(read(0x60C), 1);
+        assert_eq!(read(0x604), 0x10);
+
+        // Cleared by writing 1, and expiring again a full period later
+        dev.borrow_mut().write_reg(0x60C, &1u32.to_le_bytes());
+        assert_eq!(read(0x60C), 0);
+        assert_eq!(clk.sched.deadline(Event::PrivTimer), Some(2 * period));
+    }
+
+    #[test]
+    fn watchdog_disable_sequence() {
+        let irqs = Rc::new(RefCell::new(CoreIrqs::new(IrqSubsys::create().agg)));
+        let mut dev = Priv11Device::with_cores(irqs, make_clock().sched, 1);
+        let read_ctrl = |dev: &mut Priv11Device| {
+            let mut buf = [0u8; 4];
+            dev.read_reg(0x628, &mut buf);
+            u32::from_le_bytes(buf)
+        };
+
+        dev.write_reg(0x628, &0x9u32.to_le_bytes());
+        dev.write_reg(0x628, &0x1u32.to_le_bytes());
+        assert_eq!(read_ctrl(&mut dev), 0x9);
+
+        dev.write_reg(0x634, &WDG_DISABLE_KEYS[1].to_le_bytes());
+        assert_eq!(read_ctrl(&mut dev), 0x9);
+        dev.write_reg(0x634, &WDG_DISABLE_KEYS[0].to_le_bytes());
+        dev.write_reg(0x634, &WDG_DISABLE_KEYS[1].to_le_bytes());
+        assert_eq!(read_ctrl(&mut dev), 0x1);
+    }
+}
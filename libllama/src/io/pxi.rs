@@ -1,7 +1,11 @@
-use std::sync::{Arc, atomic, mpsc};
+use std::sync::{Arc, atomic};
 use std::fmt;
 
+use parking_lot::Mutex;
+
 use cpu::irq::{self, IrqClient};
+use savestate::{self, SaveState, StateReader, StateWriter};
+use utils::fifo::Fifo;
 
 bf!(RegCnt[u16] {
     send_empty: 0:0,
@@ -32,8 +36,8 @@ fn reg_sync_write(dev: &mut PxiDevice) {
 
 fn reg_cnt_read(dev: &mut PxiDevice) {
     let cnt = RegCnt::alias_mut(dev.cnt.ref_mut());
-    let tx_count = dev._internal_state.tx_count.load(atomic::Ordering::SeqCst);
-    let rx_count = dev._internal_state.rx_count.load(atomic::Ordering::SeqCst);
+    let tx_count = dev._internal_state.tx.lock().len();
+    let rx_count = dev._internal_state.rx.lock().len();
     cnt.send_empty.set((tx_count == 0) as u16);
     cnt.send_full.set((tx_count == 4) as u16);
     cnt.recv_empty.set((rx_count == 0) as u16);
@@ -61,10 +65,8 @@ enum PxiEnd {
 pub struct PxiShared {
     end: PxiEnd,
 
-    tx_count: Arc<atomic::AtomicUsize>,
-    rx_count: Arc<atomic::AtomicUsize>,
-    tx: mpsc::SyncSender<u32>,
-    rx: mpsc::Receiver<u32>,
+    tx: Arc<Mutex<Fifo<u32>>>,
+    rx: Arc<Mutex<Fifo<u32>>>,
     sync_tx: Arc<atomic::AtomicUsize>,
     sync_rx: Arc<atomic::AtomicUsize>,
 
@@ -75,22 +77,18 @@ pub struct PxiShared {
 
 impl PxiShared {
     pub fn make_channel(irq9: irq::IrqAsyncClient, irq11: irq::IrqAsyncClient) -> (PxiShared, PxiShared) {
-        let count_1rx_2tx = Arc::new(atomic::AtomicUsize::new(0));
-        let count_2rx_1tx = Arc::new(atomic::AtomicUsize::new(0));
         let sync_1rx_2tx = Arc::new(atomic::AtomicUsize::new(0));
         let sync_2rx_1tx = Arc::new(atomic::AtomicUsize::new(0));
-        let (pxi1_tx, pxi2_rx) = mpsc::sync_channel(4);
-        let (pxi2_tx, pxi1_rx) = mpsc::sync_channel(4);
+        let fifo_1rx_2tx = Arc::new(Mutex::new(Fifo::new(4)));
+        let fifo_2rx_1tx = Arc::new(Mutex::new(Fifo::new(4)));
         let irq_1enabled = Arc::new(atomic::AtomicBool::new(false));
         let irq_2enabled = Arc::new(atomic::AtomicBool::new(false));
 
         let pxi11 = PxiShared {
             end: PxiEnd::Arm11,
 
-            tx_count: count_2rx_1tx.clone(),
-            rx_count: count_1rx_2tx.clone(),
-            tx: pxi1_tx,
-            rx: pxi1_rx,
+            tx: fifo_2rx_1tx.clone(),
+            rx: fifo_1rx_2tx.clone(),
             sync_tx: sync_2rx_1tx.clone(),
             sync_rx: sync_1rx_2tx.clone(),
 
@@ -102,10 +100,8 @@ impl PxiShared {
         let pxi9 = PxiShared {
             end: PxiEnd::Arm9,
 
-            tx_count: count_1rx_2tx,
-            rx_count: count_2rx_1tx,
-            tx: pxi2_tx,
-            rx: pxi2_rx,
+            tx: fifo_1rx_2tx,
+            rx: fifo_2rx_1tx,
             sync_tx: sync_1rx_2tx,
             sync_rx: sync_2rx_1tx,
 
@@ -121,8 +117,6 @@ impl fmt::Debug for PxiShared {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         f.debug_struct("PxiShared")
             .field("end", &self.end)
-            .field("tx_count", &self.tx_count)
-            .field("rx_count", &self.rx_count)
             .field("tx", &self.tx)
             .field("rx", &self.rx)
             .field("sync_tx", &self.sync_tx)
@@ -132,6 +126,23 @@ impl fmt::Debug for PxiShared {
     }
 }
 
+/// Each end only saves what it receives, which together covers both directions
+impl SaveState for PxiShared {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        self.rx.lock().save_state(w)?;
+        w.put_u32(self.sync_rx.load(atomic::Ordering::SeqCst) as u32);
+        w.put_bool(self.irq_enabled.load(atomic::Ordering::SeqCst));
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.rx.lock().load_state(r)?;
+        self.sync_rx.store(r.get_u32()? as usize, atomic::Ordering::SeqCst);
+        self.irq_enabled.store(r.get_bool()?, atomic::Ordering::SeqCst);
+        Ok(())
+    }
+}
+
 fn reg_sync_ctrl_write(dev: &mut PxiDevice) {
     let cntl = RegSyncCtrl::alias_mut(dev.sync_ctrl.ref_mut());
     let irq_enabled = cntl.irq_enabled.get() == 1;
@@ -186,25 +197,19 @@ iodevice!(PxiDevice, {
         0x008 => send: u32 {
             write_effect = |dev: &mut PxiDevice| {
                 let dat = dev.send.get();
-                match dev._internal_state.tx.try_send(dat) {
-                    Ok(_) => dev._internal_state.tx_count.fetch_add(1, atomic::Ordering::SeqCst),
-                    Err(mpsc::TrySendError::Full(_)) => panic!("Attempted to send PXI word while FIFO full"),
-                    Err(e) => panic!("{:?}", e),
+                if !dev._internal_state.tx.lock().push(dat) {
+                    panic!("Attempted to send PXI word while FIFO full");
                 }
             };
         }
         0x00C => recv: u32 {
             read_effect = |dev: &mut PxiDevice| {
-                let dat = match dev._internal_state.rx.try_recv() {
-                    Ok(dat) => {
-                        dev._internal_state.rx_count.fetch_sub(1, atomic::Ordering::SeqCst);
-                        dat
-                    }
-                    Err(mpsc::TryRecvError::Empty) => {
+                let dat = match dev._internal_state.rx.lock().pop() {
+                    Some(dat) => dat,
+                    None => {
                         debug!("Attempted to receive PXI word while FIFO empty");
                         return
                     }
-                    Err(e) => panic!("{:?}", e),
                 };
                 dev.recv.set_unchecked(dat);
             };
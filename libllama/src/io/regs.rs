@@ -3,6 +3,7 @@ use std::ops::BitAndAssign;
 use std::ops::BitOrAssign;
 use std::ops::Not;
 
+use savestate::{self, StateReader, StateWriter};
 use utils::bytes;
 
 #[derive(Debug)]
@@ -48,6 +49,14 @@ impl<T> IoReg<T>
         let data = unsafe { bytes::from_mut_val(&mut self.val) };
         data.copy_from_slice(buf);
     }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.put_bytes(unsafe { bytes::from_val(&self.val) });
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        r.get_bytes(unsafe { bytes::from_mut_val(&mut self.val) })
+    }
 }
 
 pub trait IoRegAccess {
@@ -147,6 +156,22 @@ macro_rules! __iodevice__ {
                 }
             }
         }
+
+        impl $crate::savestate::SaveState for $name {
+            #[allow(unused_variables)]
+            fn save_state(&mut self, w: &mut $crate::savestate::StateWriter) -> $crate::savestate::Result<()> {
+                $( self.$reg_name.save_state(w); )*
+                $( <$instate as $crate::savestate::SaveState>::save_state(&mut self._internal_state, w)?; )*
+                Ok(())
+            }
+
+            #[allow(unused_variables)]
+            fn load_state(&mut self, r: &mut $crate::savestate::StateReader) -> $crate::savestate::Result<()> {
+                $( self.$reg_name.load_state(r)?; )*
+                $( <$instate as $crate::savestate::SaveState>::load_state(&mut self._internal_state, r)?; )*
+                Ok(())
+            }
+        }
     )
 }
 
@@ -249,4 +274,23 @@ mod test {
         let buf = vec![0xFFu8; 2];
         mmc_regs.write_reg(0x002, buf.as_slice());
     }
+
+    #[test]
+    fn save_state() {
+        use savestate::{SaveState, StateReader, StateWriter};
+
+        let mut mmc_regs = MMCRegs::new();
+        mmc_regs.write_reg(0x000, &[0x34, 0x12]);
+        mmc_regs.reg4.set_unchecked(0xBEEF);
+
+        let mut w = StateWriter::new();
+        mmc_regs.save_state(&mut w).unwrap();
+
+        let mut loaded = MMCRegs::new();
+        let mut r = StateReader::from_bytes(w.into_bytes()).unwrap();
+        loaded.load_state(&mut r).unwrap();
+        r.finish().unwrap();
+        assert_eq!(loaded.reg0.get(), 0x1234);
+        assert_eq!(loaded.reg4.get(), 0xBEEF);
+    }
 }
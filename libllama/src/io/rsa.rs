@@ -2,6 +2,8 @@ use std::fmt;
 
 use openssl::bn;
 
+use savestate::{self, SaveState, StateReader, StateWriter};
+
 bf!(RegCnt[u32] {
     busy: 0:0,
     keyslot: 4:5,
@@ -64,6 +66,29 @@ impl fmt::Debug for RsaDeviceState {
     }
 }
 
+impl SaveState for RsaDeviceState {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        for slot in self.slots.iter() {
+            w.put_u32(slot.write_pos as u32);
+            w.put_bytes(&slot.buf);
+            w.put_bytes(&slot.modulus);
+            w.put_bool(slot.ready);
+        }
+        w.put_bytes(&self.message);
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        for slot in self.slots.iter_mut() {
+            slot.write_pos = r.get_u32()? as usize;
+            r.get_bytes(&mut slot.buf)?;
+            r.get_bytes(&mut slot.modulus)?;
+            slot.ready = r.get_bool()?;
+        }
+        r.get_bytes(&mut self.message)
+    }
+}
+
 fn get_keydata(dev: &RsaDevice, keyslot: usize) -> (RegSlotCnt::Bf, u32) {
     match keyslot {
         0 => (RegSlotCnt::new(dev.slot0_cnt.get()), dev.slot0_len.get()),
@@ -3,6 +3,7 @@ use std::fmt;
 use openssl::hash::{Hasher, MessageDigest};
 
 use io::DmaTrigger;
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 bf!(RegCnt[u32] {
     busy: 0:0,
@@ -39,6 +40,26 @@ impl fmt::Debug for ShaDeviceState {
     }
 }
 
+impl SaveState for ShaDeviceState {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        if self.hasher.is_some() {
+            // OpenSSL gives us no way to extract the intermediate hash state
+            return Err(savestate::ErrorKind::Unsupported(
+                "cannot save state while the SHA engine is hashing".to_owned()))
+        }
+        w.put_bytes(&self.hash);
+        self._dma_in.save_state(w)?;
+        self.dma_out.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.hasher = None;
+        r.get_bytes(&mut self.hash)?;
+        self._dma_in.load_state(r)?;
+        self.dma_out.load_state(r)
+    }
+}
+
 // TODO: The following implementation does not yet completely work, and still needs
 // more hardware testing to determine the source of errors.
 
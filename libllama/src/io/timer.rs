@@ -1,11 +1,11 @@
-// TODO: How can we test this module?
-
 use std::cell::{RefCell, Cell};
 use std::fmt;
 use std::rc::Rc;
 
+use clock::{self, Event, Scheduler};
 use cpu::irq::{self, IrqClient};
 use io::regs::IoReg;
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 #[derive(Clone, Copy, Debug)]
 pub enum Prescaler {
@@ -51,6 +51,11 @@ fn reg_val_update(dev: &mut TimerDevice, index: usize) {
     };
     let mut state = dev._internal_state.all.borrow_mut();
     state[index].set_val(val);
+    if state[index].started {
+        // The new value holds as of now, not as of the last overflow
+        let now = ticks(&dev._internal_state);
+        dev._internal_state.start_counters[index].set(now);
+    }
     drop(state);
 
     update_deadlines(&dev._internal_state);
@@ -58,8 +63,17 @@ fn reg_val_update(dev: &mut TimerDevice, index: usize) {
 
 fn reg_val_read(dev: &mut TimerDevice, index: usize) {
     let new_val = {
-        let state = dev._internal_state.all.borrow_mut();
-        state[index].val() as u16
+        let timer_states = &dev._internal_state;
+        let state = timer_states.all.borrow();
+        let timer = &state[index];
+        match timer.val_cycles {
+            // Only brought up to date on overflows, so count the ticks since then
+            Cycles::Unscaled(cyc) if timer.started => {
+                let elapsed = ticks(timer_states) - timer_states.start_counters[index].get();
+                scale(cyc + elapsed, timer.prescaler) as u16
+            }
+            _ => timer.val() as u16
+        }
     };
     let (val, _) = get_regs(dev, index);
     val.set_unchecked(new_val);
@@ -75,7 +89,7 @@ fn reg_cnt_update(dev: &mut TimerDevice, index: usize) {
     if !state[index].started && cnt.started.get() == 1 {
         // Set baseline deadline counter for this timer so that we can
         // subtract against it later
-        let baseline = dev._internal_state.global_counter.get();
+        let baseline = ticks(&dev._internal_state);
         dev._internal_state.start_counters[index].set(baseline);
     }
     state[index].started = cnt.started.get() == 1;
@@ -212,18 +226,16 @@ impl TimerState {
 
 #[derive(Clone)]
 pub struct TimerStates {
-    deadline: Rc<Cell<Option<u64>>>,
-    pub(crate) global_counter: Rc<Cell<u64>>,
-    /// global_counter value for when each timer was started
+    sched: Scheduler,
+    /// Tick count for when each timer was started
     start_counters: Rc<[Cell<u64>; 4]>,
     all: Rc<RefCell<[TimerState; 4]>>
 }
 
 impl TimerStates {
-    pub fn new() -> TimerStates {
+    pub fn new(sched: Scheduler) -> TimerStates {
         TimerStates {
-            deadline: Rc::new(Cell::new(None)),
-            global_counter: Rc::new(Cell::new(0)),
+            sched: sched,
             start_counters: Rc::new([
                 Cell::new(0), Cell::new(0),
                 Cell::new(0), Cell::new(0),
@@ -236,6 +248,37 @@ impl TimerStates {
     }
 }
 
+impl SaveState for TimerStates {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        for counter in self.start_counters.iter() {
+            w.put_u64(counter.get());
+        }
+        for timer in self.all.borrow().iter() {
+            w.put_bool(timer.started);
+            w.put_u8(timer.prescaler as u8);
+            match timer.val_cycles {
+                Cycles::Unscaled(cyc) => { w.put_bool(false); w.put_u64(cyc) }
+                Cycles::CountUp(cyc) => { w.put_bool(true); w.put_u64(cyc) }
+            }
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        for counter in self.start_counters.iter() {
+            counter.set(r.get_u64()?);
+        }
+        for timer in self.all.borrow_mut().iter_mut() {
+            timer.started = r.get_bool()?;
+            timer.prescaler = Prescaler::new(r.get_u8()? as u16 & 0b11);
+            let count_up = r.get_bool()?;
+            let cyc = r.get_u64()?;
+            timer.val_cycles = if count_up { Cycles::CountUp(cyc) } else { Cycles::Unscaled(cyc) };
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Debug for TimerStates {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "TimerStates {{ }}")
@@ -244,17 +287,13 @@ impl fmt::Debug for TimerStates {
 
 
 
-pub fn handle_clock_update(timer_states: &TimerStates, clock_diff: u64, irq_tx: &mut irq::IrqSyncClient) {
-    // Update global counter
-    let ctr = timer_states.global_counter.get();
-    let new_ctr = ctr + clock_diff;
-    timer_states.global_counter.set(new_ctr);
-
-    // Check if we have any work to do
-    if !past_deadline(timer_states) {
-        return
-    }
+/// Timer ticks counted so far
+fn ticks(timer_states: &TimerStates) -> u64 {
+    timer_states.sched.now() / clock::CYCLES_PER_TICK
+}
 
+/// Brings the timers up to date once the clock reaches the earliest overflow
+pub fn handle_deadline(timer_states: &TimerStates, irq_tx: &mut irq::IrqSyncClient) {
     let mut timers = timer_states.all.borrow_mut();
     if let Cycles::CountUp(_) = timers[0].val_cycles {
         panic!("Don't know how to handle TIMER0 as a count-up timer!");
@@ -270,7 +309,7 @@ pub fn handle_clock_update(timer_states: &TimerStates, clock_diff: u64, irq_tx:
         let cycles = if let Cycles::CountUp(_) = timer.val_cycles {
             Cycles::CountUp(prev_overflowed as u64)
         } else {
-            let ctr = timer_states.global_counter.get();
+            let ctr = ticks(timer_states);
             let baseline = timer_states.start_counters[index].get();
             let clock_diff = ctr - baseline;
 
@@ -290,20 +329,21 @@ pub fn handle_clock_update(timer_states: &TimerStates, clock_diff: u64, irq_tx:
 
 fn update_deadlines(timer_states: &TimerStates) {
     let timers = timer_states.all.borrow();
-    let min_deadline = timers.iter()
-        .filter(|timer| timer.started)
-        .map(|timer| timer.clocks_till_overflow())
+    let ctr = ticks(timer_states);
+    let min_deadline = timers.iter().enumerate()
+        .filter(|&(_, timer)| timer.started)
+        .map(|(index, timer)| {
+            // Only brought up to date on overflows, so take off the ticks since then
+            let elapsed = ctr - timer_states.start_counters[index].get();
+            timer.clocks_till_overflow().saturating_sub(elapsed)
+        })
         .min();
-    let ctr = timer_states.global_counter.get();
-    timer_states.deadline.set(min_deadline.map(|m| m + ctr));
-}
-
-fn past_deadline(timer_states: &TimerStates) -> bool {
-    let ctr = timer_states.global_counter.get();
-    if let Some(deadline) = timer_states.deadline.get() {
-        ctr > deadline
-    } else {
-        false
+    let deadline = min_deadline
+        .and_then(|m| m.checked_add(ctr))
+        .and_then(|tick| tick.checked_mul(clock::CYCLES_PER_TICK));
+    match deadline {
+        Some(at) => timer_states.sched.schedule(Event::Timer, at),
+        None => timer_states.sched.cancel(Event::Timer)
     }
 }
 
@@ -337,3 +377,56 @@ iodevice!(TimerDevice, {
         0x00E => cnt3: u16 { write_effect = |dev: &mut TimerDevice| reg_cnt_update(dev, 3); }
     }
 });
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cpu::irq::{IrqSubsys, IrqType9};
+    use io::regs::IoRegAccess;
+
+    #[test]
+    fn overflow_deadline() {
+        let subsys = IrqSubsys::create();
+        subsys.agg.set_enabled(1u128 << (IrqType9::Timer0 as u32));
+        let mut clk = clock::make_channel(subsys.sync_tx.clone());
+        let mut dev = TimerDevice::new(clk.timer_states.clone());
+
+        // 0x10 ticks before overflowing, with the IRQ enabled
+        dev.write_reg(0x000, &0xFFF0u16.to_le_bytes());
+        dev.write_reg(0x002, &0x00C0u16.to_le_bytes());
+        assert_eq!(clk.sched.deadline(Event::Timer), Some(0x20));
+
+        clk.increment(0x1F);
+        assert!(!subsys.line.is_high());
+        let mut buf = [0u8; 2];
+        dev.read_reg(0x000, &mut buf);
+        assert_eq!(u16::from_le_bytes(buf), 0xFFFF);
+        clk.increment(1);
+        assert!(subsys.line.is_high());
+
+        // The next overflow comes a full period later
+        assert_eq!(clk.sched.deadline(Event::Timer), Some(0x20 + 0x20000));
+    }
+
+    #[test]
+    fn other_timer_keeps_deadline() {
+        let subsys = IrqSubsys::create();
+        subsys.agg.set_enabled(1u128 << (IrqType9::Timer0 as u32));
+        let mut clk = clock::make_channel(subsys.sync_tx.clone());
+        let mut dev = TimerDevice::new(clk.timer_states.clone());
+
+        dev.write_reg(0x000, &0xFFF0u16.to_le_bytes());
+        dev.write_reg(0x002, &0x00C0u16.to_le_bytes());
+        clk.increment(0x10);
+
+        // Starting timer 1 halfway must not push back timer 0
+        dev.write_reg(0x004, &0u16.to_le_bytes());
+        dev.write_reg(0x006, &0x0080u16.to_le_bytes());
+        assert_eq!(clk.sched.deadline(Event::Timer), Some(0x20));
+
+        clk.increment(0xF);
+        assert!(!subsys.line.is_high());
+        clk.increment(1);
+        assert!(subsys.line.is_high());
+    }
+}
@@ -7,6 +7,7 @@ use utils::fifo::Fifo;
 
 use hwcore::HardwareDma9;
 use io::{DmaBus, DmaBuses};
+use savestate::{self, SaveState, StateReader, StateWriter};
 
 struct DmacVer;
 pub trait Version {}
@@ -99,6 +100,62 @@ impl Default for XdmaThreadState {
     }
 }
 
+impl SaveState for XdmaThreadState {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_u32(self.pc);
+        w.put_bool(self.running);
+        w.put_bool(self.waiting);
+        w.put_u32(self.src_addr);
+        w.put_u32(self.dst_addr);
+        w.put_u32(self.chan_ctrl.val);
+        w.put_u32(self.loop_ctr[0]);
+        w.put_u32(self.loop_ctr[1]);
+        w.put_u8(self.request_type as u8);
+        self.data_fifo.save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        self.pc = r.get_u32()?;
+        self.running = r.get_bool()?;
+        self.waiting = r.get_bool()?;
+        self.src_addr = r.get_u32()?;
+        self.dst_addr = r.get_u32()?;
+        self.chan_ctrl.val = r.get_u32()?;
+        self.loop_ctr[0] = r.get_u32()?;
+        self.loop_ctr[1] = r.get_u32()?;
+        self.request_type = match r.get_u8()? {
+            0 => RequestType::Single,
+            1 => RequestType::Peripheral,
+            2 => RequestType::Burst,
+            x => return Err(savestate::ErrorKind::Mismatch(format!("invalid XDMA request type {}", x)))
+        };
+        self.data_fifo.load_state(r)
+    }
+}
+
+impl SaveState for XdmaDeviceState {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_bool(self.active_thread.is_some());
+        w.put_u32(self.active_thread.unwrap_or(0) as u32);
+        self.manager.save_state(w)?;
+        for channel in self.channels.iter_mut() {
+            channel.save_state(w)?;
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        let has_active = r.get_bool()?;
+        let active = r.get_u32()? as usize;
+        self.active_thread = if has_active { Some(active) } else { None };
+        self.manager.load_state(r)?;
+        for channel in self.channels.iter_mut() {
+            channel.load_state(r)?;
+        }
+        Ok(())
+    }
+}
+
 fn replace_active_thread(dev: &mut XdmaDevice, new: Option<usize>) -> Option<usize> {
     let res = dev._internal_state.active_thread.take();
     dev._internal_state.active_thread = new;
@@ -490,6 +547,12 @@ pub fn schedule(xdma: &mut XdmaDevice) {
     }
 }
 
+/// Whether the manager or any channel thread is still running
+pub fn is_busy(xdma: &XdmaDevice) -> bool {
+    let state = &xdma._internal_state;
+    state.manager.running || state.channels.iter().any(|thread| thread.running)
+}
+
 fn csr_alias(dev: &mut XdmaDevice, which: usize) -> &mut ChannelStatus::Bf {
     let ref_mut = match which {
         0 => dev.csr0.ref_mut(),
@@ -56,6 +56,9 @@ fn load_binfile(binfile: &DescBinfile, path: &Path, controller: &mut mem::MemCon
         controller.write_buf(vaddr, &read_buf[0..size]);
         vaddr += size as u32;
     }
+    if let Some(addr) = controller.take_bus_error() {
+        panic!("Could not load {} to unmapped address 0x{:X}", binfile.bin, addr);
+    }
 }
 
 impl ldr::Loader for Ctr9Loader {
@@ -78,12 +81,17 @@ impl ldr::Loader for Ctr9Loader {
             load_binfile(binfile, &self.path, controller);
         }
     }
+
+    fn arm11_cores(&self) -> usize {
+        self.desc.arm11_cores
+    }
 }
 
 
 struct Desc {
     entrypoint: u32,
     entry11: u32,
+    arm11_cores: usize,
     binfiles: Vec<DescBinfile>,
     binfiles11: Vec<DescBinfile>,
 }
@@ -100,6 +108,14 @@ impl Desc {
         let entrypoint11 = utils::from_hex(entrypoint11_str?)
             .or(Err(item_error("entryPoint11", DESC_FILENAME)));
 
+        let arm11_cores = if json["arm11Cores"].is_null() {
+            Ok(2)
+        } else {
+            json["arm11Cores"].as_usize()
+                .filter(|&n| n >= 1 && n <= 4)
+                .ok_or(item_error("arm11Cores", DESC_FILENAME))
+        };
+
         // Load binfiles arrays into vec, make sure >1 binfile exists
         let mut binfiles = Vec::new();
         for binfile in json["binFiles"].members() {
@@ -117,6 +133,7 @@ impl Desc {
         Ok(Desc {
             entrypoint: entrypoint?,
             entry11: entrypoint11?,
+            arm11_cores: arm11_cores?,
             binfiles: binfiles,
             binfiles11: binfiles11,
         })
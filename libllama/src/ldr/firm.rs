@@ -92,6 +92,9 @@ impl ldr::Loader for FirmLoader {
                 read_amount += read_buf_size;
                 vaddr += read_buf_size as u32;
             }
+            if let Some(addr) = controller.take_bus_error() {
+                panic!("Could not load FIRM section to unmapped address 0x{:X}", addr);
+            }
         }
     }
 
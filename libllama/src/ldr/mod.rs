@@ -10,6 +10,11 @@ pub trait Loader {
     fn entrypoint11(&self) -> u32;
     fn load9(&self, controller: &mut mem::MemController);
     fn load11(&self, controller: &mut mem::MemController);
+
+    /// Number of ARM11 MPCore cores to emulate
+    fn arm11_cores(&self) -> usize {
+        2
+    }
 }
 
 use std::path::Path;
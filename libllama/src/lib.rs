@@ -3,6 +3,7 @@
 
 #[macro_use]
 extern crate bitutils;
+extern crate capstone;
 #[macro_use]
 extern crate derive_error;
 extern crate indextree;
@@ -17,8 +18,11 @@ extern crate arraydeque;
 pub mod utils;
 
 pub mod clock;
+pub mod commands;
 pub mod cpu;
+pub mod dapserver;
 pub mod dbgcore;
+pub mod dbgexpr;
 pub mod fs;
 pub mod gdbstub;
 pub mod hwcore;
@@ -26,3 +30,5 @@ pub mod io;
 pub mod ldr;
 pub mod msgs;
 pub mod mem;
+pub mod record;
+pub mod savestate;
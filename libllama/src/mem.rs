@@ -1,13 +1,16 @@
 use std;
 use std::cmp;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
+use cpu::breakpoints::Watchpoints;
 use io;
+use savestate::{self, SaveState, StateReader, StateWriter};
 use utils::bytes;
 
 const KB_SIZE: usize = 1024;
@@ -113,6 +116,49 @@ impl MemoryBlock for SharedMemoryBlock {
     }
 }
 
+fn check_block_size(expected: usize, found: usize) -> savestate::Result<()> {
+    if expected != found {
+        return Err(savestate::ErrorKind::Mismatch(
+            format!("memory block of 0x{:X} bytes does not fit in 0x{:X} bytes", found, expected)))
+    }
+    Ok(())
+}
+
+impl SaveState for UniqueMemoryBlock {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        w.put_vec(&self.0.borrow());
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        let mut vec = self.0.borrow_mut();
+        let len = r.get_u32()? as usize;
+        check_block_size(vec.len(), len)?;
+        r.get_bytes(&mut vec[..])
+    }
+}
+
+impl SaveState for SharedMemoryBlock {
+    fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+        let nodes = &self.0;
+        w.put_u32((nodes.len() * KB_SIZE) as u32);
+        for node in nodes.iter() {
+            w.put_bytes(&*node.read());
+        }
+        Ok(())
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+        let nodes = &self.0;
+        let len = r.get_u32()? as usize;
+        check_block_size(nodes.len() * KB_SIZE, len)?;
+        for node in nodes.iter() {
+            r.get_bytes(&mut *node.write())?;
+        }
+        Ok(())
+    }
+}
+
 pub enum AddressBlock {
     UniqueRam(UniqueMemoryBlock),
     SharedRam(SharedMemoryBlock),
@@ -158,17 +204,209 @@ impl MemoryBlock for AddressBlock {
 
 pub struct AddressBlockHandle(u32);
 
+fn check_debug_range(addr: u32, len: usize) -> Result<(), String> {
+    if addr as u64 + len as u64 > 1 << 32 {
+        return Err(format!("Access of 0x{:X} bytes at 0x{:X} wraps around memory", len, addr))
+    }
+    Ok(())
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RegionKind {
+    Ram,
+    Rom,
+    Io,
+}
+
+/// A contiguous range of mapped memory, as reported to debuggers
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemRegion {
+    pub start: u32,
+    pub len: u32,
+    pub kind: RegionKind,
+}
+
+/// Exclusive reservations are tracked per doubleword, the size of the largest LDREX
+const EXCLUSIVE_GRANULE_MASK: u32 = !0x7;
+
+/// Global exclusive monitor, holding the LDREX reservation of every core sharing it
+///
+/// Each core's memory controller holds its own handle, and reservations are on physical
+/// granules. A successful STREX on any handle clears every reservation on its granule,
+/// and any other store through a controller clears the other cores' reservations on it.
+#[derive(Clone)]
+pub struct GlobalMonitor {
+    tags: Arc<Mutex<Vec<Option<u32>>>>,
+    /// How many of the tags are set, so that stores can skip the lock when none are
+    open: Arc<AtomicUsize>,
+    core: usize,
+}
+
+impl GlobalMonitor {
+    pub fn new() -> GlobalMonitor {
+        GlobalMonitor {
+            tags: Arc::new(Mutex::new(vec![None])),
+            open: Arc::new(AtomicUsize::new(0)),
+            core: 0,
+        }
+    }
+
+    /// Returns a handle for one more core sharing this monitor
+    pub fn add_core(&self) -> GlobalMonitor {
+        let mut tags = self.tags.lock();
+        tags.push(None);
+        GlobalMonitor {
+            tags: self.tags.clone(),
+            open: self.open.clone(),
+            core: tags.len() - 1,
+        }
+    }
+
+    fn count_open(&self, tags: &[Option<u32>]) {
+        self.open.store(tags.iter().filter(|tag| tag.is_some()).count(), Ordering::Relaxed);
+    }
+
+    pub fn mark(&self, addr: u32) {
+        let mut tags = self.tags.lock();
+        tags[self.core] = Some(addr & EXCLUSIVE_GRANULE_MASK);
+        self.count_open(&tags);
+    }
+
+    pub fn clear(&self) {
+        let mut tags = self.tags.lock();
+        tags[self.core] = None;
+        self.count_open(&tags);
+    }
+
+    /// Checks for a reservation on `addr` held by this core, and if there is one,
+    /// clears every core's reservation on it
+    pub fn check_and_clear(&self, addr: u32) -> bool {
+        let granule = Some(addr & EXCLUSIVE_GRANULE_MASK);
+        let mut tags = self.tags.lock();
+        if tags[self.core] != granule {
+            return false
+        }
+        for tag in tags.iter_mut().filter(|tag| **tag == granule) {
+            *tag = None;
+        }
+        self.count_open(&tags);
+        true
+    }
+
+    /// Clears the reservations other cores hold on the granules a store of `len` bytes
+    /// to `addr` touches
+    #[inline]
+    pub fn clear_stored(&self, addr: u32, len: usize) {
+        if self.open.load(Ordering::Relaxed) == 0 {
+            return
+        }
+        let first = addr & EXCLUSIVE_GRANULE_MASK;
+        let last = addr.wrapping_add(len as u32 - 1) & EXCLUSIVE_GRANULE_MASK;
+        let mut tags = self.tags.lock();
+        for (core, tag) in tags.iter_mut().enumerate() {
+            if core != self.core && tag.map_or(false, |tag| tag >= first && tag <= last) {
+                *tag = None;
+            }
+        }
+        self.count_open(&tags);
+    }
+}
+
 pub struct MemController {
     regions: BTreeMap<u32, AddressBlock>,
+    /// Addresses of regions mapped with `map_rom_region`
+    rom_regions: BTreeSet<u32>,
+    /// Checked by the owning CPU's data accesses
+    pub watchpoints: Watchpoints,
+    /// Shared with the memory controllers of the other cores of the same CPU
+    pub monitor: GlobalMonitor,
+    /// First unmapped address accessed since the last `take_bus_error`
+    bus_error: Cell<Option<u32>>,
+    /// Wait states per word accessed, by region address; unlisted regions have none
+    wait_states: BTreeMap<u32, u32>,
+    /// Wait states accumulated since the last `take_wait_cycles`
+    wait_cycles: Cell<u32>,
+    /// Whether the owning CPU stored data since the last `take_stored`
+    stored: Cell<bool>,
 }
 
 impl MemController {
     pub fn new() -> MemController {
         MemController {
             regions: BTreeMap::new(),
+            rom_regions: BTreeSet::new(),
+            watchpoints: Watchpoints::new(),
+            monitor: GlobalMonitor::new(),
+            bus_error: Cell::new(None),
+            wait_states: BTreeMap::new(),
+            wait_cycles: Cell::new(0),
+            stored: Cell::new(false),
+        }
+    }
+
+    /// Makes each word accessed in the region mapped at `address` take `cycles` more cycles
+    pub fn set_wait_states(&mut self, address: u32, cycles: u32) {
+        assert!(self.regions.contains_key(&address), "No region mapped at 0x{:X}", address);
+        self.wait_states.insert(address, cycles);
+    }
+
+    fn add_wait_states(&self, block_addr: u32, len: usize) {
+        if let Some(&cycles) = self.wait_states.get(&block_addr) {
+            let words = ((len + 3) / 4) as u32;
+            self.add_wait_cycles(cycles * words);
+        }
+    }
+
+    /// Charges `cycles` to whichever core takes the wait cycles next
+    #[inline]
+    pub fn add_wait_cycles(&self, cycles: u32) {
+        self.wait_cycles.set(self.wait_cycles.get().wrapping_add(cycles));
+    }
+
+    /// Returns the cycles accesses have spent waiting on memory since the last call
+    #[inline]
+    pub fn take_wait_cycles(&self) -> u32 {
+        self.wait_cycles.replace(0)
+    }
+
+    /// Notes a data store by the owning CPU
+    #[inline(always)]
+    pub fn mark_stored(&self) {
+        self.stored.set(true);
+    }
+
+    /// Whether the owning CPU stored data since the last `take_stored`
+    #[inline(always)]
+    pub fn stored_pending(&self) -> bool {
+        self.stored.get()
+    }
+
+    /// Whether the owning CPU stored data since the last call
+    #[inline(always)]
+    pub fn take_stored(&self) -> bool {
+        self.stored.replace(false)
+    }
+
+    fn raise_bus_error(&self, addr: u32) {
+        trace!("Access to unmapped address 0x{:X}", addr);
+        if self.bus_error.get().is_none() {
+            self.bus_error.set(Some(addr));
         }
     }
 
+    /// Whether an access made since the last `take_bus_error` hit unmapped memory
+    #[inline(always)]
+    pub fn bus_error_pending(&self) -> bool {
+        self.bus_error.get().is_some()
+    }
+
+    /// Returns the first unmapped address accessed since the last call. Reads of
+    /// unmapped memory return zeroes and writes to it are dropped.
+    #[inline(always)]
+    pub fn take_bus_error(&self) -> Option<u32> {
+        self.bus_error.take()
+    }
+
     fn match_address(&self, address: u32) -> Option<(u32, &AddressBlock)> {
         let matched = self.regions.range(..=address).next_back()?;
         let (block_addr, block) = matched;
@@ -192,6 +430,35 @@ impl MemController {
         AddressBlockHandle(address)
     }
 
+    /// Same as `map_region`, but reports the region as read-only to debuggers
+    pub fn map_rom_region(&mut self, address: u32, region: AddressBlock) -> AddressBlockHandle {
+        self.rom_regions.insert(address);
+        self.map_region(address, region)
+    }
+
+    /// All mapped memory, with adjacent regions of the same kind merged
+    pub fn region_map(&self) -> Vec<MemRegion> {
+        let mut map: Vec<MemRegion> = Vec::new();
+        for (&start, block) in self.regions.iter() {
+            let kind = match *block {
+                AddressBlock::Io9(_) | AddressBlock::Io11(_)
+                | AddressBlock::IoPriv11(_) | AddressBlock::IoShared(_) => RegionKind::Io,
+                _ if self.rom_regions.contains(&start) => RegionKind::Rom,
+                _ => RegionKind::Ram,
+            };
+            let len = block.get_bytes();
+
+            if let Some(last) = map.last_mut() {
+                if last.kind == kind && last.start as u64 + last.len as u64 == start as u64 {
+                    last.len += len;
+                    continue
+                }
+            }
+            map.push(MemRegion { start: start, len: len, kind: kind });
+        }
+        map
+    }
+
     pub(crate) fn region(&self, handle: &AddressBlockHandle) -> &AddressBlock {
         self.regions.get(&handle.0)
             .expect("Attempted to find region from non-existant handle!")
@@ -203,12 +470,9 @@ impl MemController {
     }
 
     pub fn read<T: Copy>(&self, addr: u32) -> T {
-        let (block_addr, ref block) = self.match_address(addr)
-            .unwrap_or_else(|| panic!("Could not match address 0x{:X}", addr));
-
         unsafe {
             let mut t: T = std::mem::zeroed();
-            block.read_buf((addr - block_addr) as usize, bytes::from_mut_val(&mut t));
+            self.read_buf(addr, bytes::from_mut_val(&mut t));
             t
         }
     }
@@ -223,29 +487,67 @@ impl MemController {
             | (true, AddressBlock::IoShared(_)) => return Err(format!("Cannot issue debug read for IO address 0x{:X}", addr)),
             (_, block) => block.read_buf((addr - block_addr) as usize, buf),
         }
+        if !debug {
+            self.add_wait_states(block_addr, buf.len());
+        }
         Ok(())
     }
 
     pub fn read_buf(&self, addr: u32, buf: &mut [u8]) {
-        self.try_read_buf(addr, buf, false).unwrap();
+        if self.try_read_buf(addr, buf, false).is_err() {
+            for b in buf.iter_mut() { *b = 0; }
+            self.raise_bus_error(addr);
+        }
     }
 
-    pub fn debug_read_buf(&self, addr: u32, buf: &mut [u8]) -> Result<(), String> {
-        self.try_read_buf(addr, buf, true)
+    /// Reads memory for a debugger, which may span several regions but not IO
+    pub fn debug_read_buf(&self, mut addr: u32, mut buf: &mut [u8]) -> Result<(), String> {
+        check_debug_range(addr, buf.len())?;
+        while !buf.is_empty() {
+            let chunk_len = self.chunk_len(addr, buf.len())?;
+            let (chunk, rest) = {buf}.split_at_mut(chunk_len);
+            self.try_read_buf(addr, chunk, true)?;
+            addr = addr.wrapping_add(chunk_len as u32);
+            buf = rest;
+        }
+        Ok(())
     }
 
-    pub fn write<T: Copy>(&mut self, addr: u32, data: T) {
-        let (block_addr, ref mut block) = self.match_address_mut(addr)
-            .unwrap_or_else(|| panic!("Could not match address 0x{:X}", addr));
+    /// Writes memory for a debugger, which may span several regions
+    pub fn debug_write_buf(&mut self, mut addr: u32, mut buf: &[u8]) -> Result<(), String> {
+        check_debug_range(addr, buf.len())?;
+        while !buf.is_empty() {
+            let chunk_len = self.chunk_len(addr, buf.len())?;
+            let (block_addr, block) = self.match_address_mut(addr).unwrap();
+            block.write_buf((addr - block_addr) as usize, &buf[..chunk_len]);
+            addr = addr.wrapping_add(chunk_len as u32);
+            buf = &buf[chunk_len..];
+        }
+        Ok(())
+    }
 
-        block.write_buf((addr - block_addr) as usize, unsafe { bytes::from_val(&data) });
+    /// How many of `len` bytes starting at `addr` fall into the same region
+    fn chunk_len(&self, addr: u32, len: usize) -> Result<usize, String> {
+        let (block_addr, block) = self.match_address(addr)
+            .ok_or(format!("Could not match address 0x{:X}", addr))?;
+        let remaining = (block.get_bytes() - (addr - block_addr)) as usize;
+        Ok(cmp::min(len, remaining))
     }
 
-    pub fn write_buf(&mut self, addr: u32, buf: &[u8]) {
-        let (block_addr, ref mut block) = self.match_address_mut(addr)
-            .unwrap_or_else(|| panic!("Could not match address 0x{:X}", addr));
+    pub fn write<T: Copy>(&mut self, addr: u32, data: T) {
+        self.write_buf(addr, unsafe { bytes::from_val(&data) });
+    }
 
-        block.write_buf((addr - block_addr) as usize, buf);
+    pub fn write_buf(&mut self, addr: u32, buf: &[u8]) {
+        self.monitor.clear_stored(addr, buf.len());
+        let block_addr = match self.match_address_mut(addr) {
+            Some((block_addr, block)) => {
+                block.write_buf((addr - block_addr) as usize, buf);
+                block_addr
+            }
+            None => return self.raise_bus_error(addr)
+        };
+        self.add_wait_states(block_addr, buf.len());
     }
 }
 
@@ -253,6 +555,73 @@ impl MemController {
 mod test {
     use super::*;
 
+    #[test]
+    fn global_monitor() {
+        let core0 = GlobalMonitor::new();
+        let core1 = core0.add_core();
+
+        core0.mark(0x1000);
+        core1.mark(0x1004);
+        assert!(!core0.check_and_clear(0x2000));
+        assert!(core1.check_and_clear(0x1000));
+
+        // Core 1's store took both reservations on the granule
+        assert!(!core0.check_and_clear(0x1000));
+
+        core0.mark(0x1000);
+        core0.clear();
+        assert!(!core0.check_and_clear(0x1000));
+
+        // Plain stores take the other cores' reservations on the granules they touch
+        let mut mem1 = MemController::new();
+        mem1.monitor = core1;
+        mem1.map_region(0x1000, AddressBlock::UniqueRam(UniqueMemoryBlock::new(1)));
+        core0.mark(0x1008);
+        mem1.write::<u32>(0x1000, 0);
+        assert!(core0.check_and_clear(0x1008));
+        core0.mark(0x1008);
+        mem1.monitor.mark(0x1008);
+        mem1.write::<u64>(0x1004, 0);
+        assert!(!core0.check_and_clear(0x1008));
+        assert!(mem1.monitor.check_and_clear(0x1008));
+    }
+
+    #[test]
+    fn unmapped_access() {
+        let mut mem = MemController::new();
+        mem.map_region(0x1000, AddressBlock::UniqueRam(UniqueMemoryBlock::new(1)));
+
+        mem.write::<u32>(0x2000, 0x1234);
+        assert_eq!(mem.read::<u32>(0x0FFC), 0);
+        assert_eq!(mem.take_bus_error(), Some(0x2000));
+        assert_eq!(mem.take_bus_error(), None);
+
+        mem.write::<u32>(0x1000, 0x1234);
+        assert_eq!(mem.read::<u32>(0x1000), 0x1234);
+        assert!(!mem.bus_error_pending());
+    }
+
+    #[test]
+    fn wait_states() {
+        let mut mem = MemController::new();
+        mem.map_region(0x0000, AddressBlock::UniqueRam(UniqueMemoryBlock::new(1)));
+        mem.map_region(0x1000, AddressBlock::UniqueRam(UniqueMemoryBlock::new(1)));
+        mem.set_wait_states(0x1000, 3);
+
+        mem.read::<u32>(0x0000);
+        assert_eq!(mem.take_wait_cycles(), 0);
+
+        mem.write::<u8>(0x1001, 0xFF);
+        mem.read::<[u32; 8]>(0x1020);
+        assert_eq!(mem.take_wait_cycles(), 3 + 8 * 3);
+        assert_eq!(mem.take_wait_cycles(), 0);
+
+        // Debugger accesses are free
+        let mut buf = [0u8; 4];
+        mem.debug_read_buf(0x1000, &mut buf).unwrap();
+        assert_eq!(mem.take_wait_cycles(), 0);
+    }
+
     #[test]
     fn write_intra_block() {
         let mut block = SharedMemoryBlock::new(1);
@@ -330,4 +699,59 @@ mod test {
         assert_eq!(block_mem0[0x3FE..0x400], buf[0..2]);
         assert_eq!(block_mem1[0x0..0x2], buf[2..4]);
     }
+
+    #[test]
+    fn debug_access_across_regions() {
+        let mut controller = MemController::new();
+        controller.map_region(0x0, AddressBlock::UniqueRam(UniqueMemoryBlock::new(1)));
+        controller.map_region(0x400, AddressBlock::SharedRam(SharedMemoryBlock::new(1)));
+        controller.map_rom_region(0xFFFFFC00, AddressBlock::UniqueRam(UniqueMemoryBlock::new(1)));
+
+        controller.debug_write_buf(0x3FE, &[0xFF, 0x53, 0x28, 0xC6]).unwrap();
+        let mut buf = [0u8; 4];
+        controller.debug_read_buf(0x3FE, &mut buf).unwrap();
+        assert_eq!(buf, [0xFF, 0x53, 0x28, 0xC6]);
+
+        assert!(controller.debug_read_buf(0x7FE, &mut buf).is_err());
+        assert!(controller.debug_write_buf(0xFFFFFFFE, &buf).is_err());
+
+        assert_eq!(controller.region_map(), vec![
+            MemRegion { start: 0x0, len: 0x800, kind: RegionKind::Ram },
+            MemRegion { start: 0xFFFFFC00, len: 0x400, kind: RegionKind::Rom },
+        ]);
+    }
+
+    #[test]
+    fn save_state_blocks() {
+        let mut shared = SharedMemoryBlock::new(2);
+        let mut unique = UniqueMemoryBlock::new(1);
+        shared.write_buf(0x3FE, &[0xFF, 0x53, 0x28, 0xC6]);
+        unique.write_buf(0x10, &[0xAB; 4]);
+
+        let mut w = StateWriter::new();
+        shared.save_state(&mut w).unwrap();
+        unique.save_state(&mut w).unwrap();
+
+        let mut new_shared = SharedMemoryBlock::new(2);
+        let mut new_unique = UniqueMemoryBlock::new(1);
+        let mut r = StateReader::from_bytes(w.into_bytes()).unwrap();
+        new_shared.load_state(&mut r).unwrap();
+        new_unique.load_state(&mut r).unwrap();
+        r.finish().unwrap();
+
+        let mut buf = [0u8; 4];
+        new_shared.read_buf(0x3FE, &mut buf);
+        assert_eq!(buf, [0xFF, 0x53, 0x28, 0xC6]);
+        new_unique.read_buf(0x10, &mut buf);
+        assert_eq!(buf, [0xAB; 4]);
+    }
+
+    #[test]
+    fn save_state_size_mismatch() {
+        let mut w = StateWriter::new();
+        SharedMemoryBlock::new(2).save_state(&mut w).unwrap();
+
+        let mut r = StateReader::from_bytes(w.into_bytes()).unwrap();
+        assert!(SharedMemoryBlock::new(1).load_state(&mut r).is_err());
+    }
 }
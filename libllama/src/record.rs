@@ -0,0 +1,421 @@
+//! Record mode, for running the machine backwards
+//!
+//! While recording, both cores run on a single thread following a fixed schedule (see
+//! `next_slice`), so any stretch of execution can be reproduced exactly by restoring
+//! an earlier checkpoint and running forward again. Inputs from outside the machine,
+//! like button presses or IRQs asserted by the debugger, are logged with the position
+//! they were applied at and applied again when replaying.
+//!
+//! Only the newest checkpoint is kept in full; older ones are stored as the pages of
+//! their state that differ from the checkpoint after them. Once there are too many,
+//! checkpoints are thinned out so that they get sparser the further back they go,
+//! instead of the oldest ones being forgotten.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use cpu::BreakReason;
+use cpu::irq::IrqType9;
+use dbgcore::ActiveCpu;
+use io::hid::ButtonState;
+use savestate;
+
+#[derive(Debug, Error)]
+pub enum ErrorKind {
+    State(savestate::ErrorKind),
+
+    /// Record mode is off
+    NotRecording,
+}
+
+pub type Result<T> = ::std::result::Result<T, ErrorKind>;
+
+/// Number of steps a core runs before the other one gets its turn
+pub const SLICE: u64 = 1000;
+
+pub const DEFAULT_INTERVAL: u64 = 10_000_000;
+pub const DEFAULT_MAX_CHECKPOINTS: usize = 32;
+
+const PAGE_SIZE: usize = 0x1000;
+
+/// Number of steps (instructions executed or exceptions entered) each core has taken
+/// since recording started
+///
+/// Both counts only grow as the machine runs, so for points of the same recording the
+/// derived ordering is the order in which they happened.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Position {
+    pub arm9: u64,
+    pub arm11: u64,
+}
+
+impl Position {
+    pub fn of(&self, cpu: ActiveCpu) -> u64 {
+        match cpu {
+            ActiveCpu::Arm9 => self.arm9,
+            ActiveCpu::Arm11 => self.arm11,
+        }
+    }
+
+    pub fn with(mut self, cpu: ActiveCpu, steps: u64) -> Position {
+        match cpu {
+            ActiveCpu::Arm9 => self.arm9 = steps,
+            ActiveCpu::Arm11 => self.arm11 = steps,
+        }
+        self
+    }
+}
+
+/// Picks the core that runs next from `pos`, and the step count it runs up to
+///
+/// The ARM9 runs slice N while the ARM11 waits at its start, then the ARM11 runs
+/// slice N while the ARM9 waits at its end.
+pub fn next_slice(pos: Position) -> (ActiveCpu, u64) {
+    let end = (pos.arm11 / SLICE + 1) * SLICE;
+    if pos.arm9 < end {
+        (ActiveCpu::Arm9, end)
+    } else {
+        (ActiveCpu::Arm11, end)
+    }
+}
+
+/// The last point of the schedule, no later than `limit`, at which `cpu` has taken `steps` steps
+pub fn latest_point(cpu: ActiveCpu, steps: u64, limit: Position) -> Position {
+    match cpu {
+        ActiveCpu::Arm9 => Position {
+            arm9: steps,
+            arm11: (steps / SLICE * SLICE).min(limit.arm11),
+        },
+        ActiveCpu::Arm11 => Position {
+            arm9: ((steps / SLICE + 1) * SLICE).min(limit.arm9),
+            arm11: steps,
+        },
+    }
+}
+
+/// Where running backwards ended up
+pub enum Reverse {
+    Halted(ActiveCpu, BreakReason),
+    /// Reached the oldest point still recorded
+    HistoryStart,
+}
+
+#[derive(Copy, Clone)]
+pub enum Input {
+    Hid(ButtonState),
+    Irq9(IrqType9),
+    /// New level of the FIQ line of a core
+    Fiq(ActiveCpu, bool),
+}
+
+/// Pages of a machine state that differ from another state
+struct Delta {
+    len: usize,
+    pages: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    /// The changes that turn `from` into `to`
+    fn between(from: &[u8], to: &[u8]) -> Delta {
+        let pages = to.chunks(PAGE_SIZE).enumerate()
+            .map(|(i, page)| (i * PAGE_SIZE, page))
+            .filter(|&(offset, page)| from.get(offset .. offset + page.len()) != Some(page))
+            .map(|(offset, page)| (offset, page.to_vec()))
+            .collect();
+        Delta {
+            len: to.len(),
+            pages: pages,
+        }
+    }
+
+    fn apply(&self, state: &mut Vec<u8>) {
+        state.resize(self.len, 0);
+        for &(offset, ref page) in self.pages.iter() {
+            state[offset .. offset + page.len()].copy_from_slice(page);
+        }
+    }
+
+    /// Same as applying `self` and then `later`
+    fn then(self, later: Delta) -> Delta {
+        let mut pages: BTreeMap<usize, Vec<u8>> = self.pages.into_iter()
+            .filter(|&(offset, _)| offset < later.len)
+            .map(|(offset, mut page)| {
+                page.truncate(later.len - offset);
+                (offset, page)
+            })
+            .collect();
+        pages.extend(later.pages);
+        Delta {
+            len: later.len,
+            pages: pages.into_iter().collect(),
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+}
+
+struct Checkpoint {
+    pos: Position,
+    /// Number of inputs that had been logged when the checkpoint was taken
+    inputs: usize,
+    /// Turns the state of the next checkpoint into this one's; None for the newest
+    delta: Option<Delta>,
+}
+
+/// Checkpoints and input log of a recording
+///
+/// Going back in time discards everything recorded after the point reached, so running
+/// forward from there executes live again.
+pub struct Recorder {
+    interval: u64,
+    max_checkpoints: usize,
+    checkpoints: VecDeque<Checkpoint>,
+    newest: Vec<u8>,
+    inputs: Vec<(Position, Input)>,
+    /// Number of logged inputs already applied to the machine
+    applied: usize,
+}
+
+impl Recorder {
+    /// Takes a checkpoint every `interval` steps of each core, rounded up to a whole
+    /// slice, and keeps at most `max_checkpoints` of them
+    pub fn new(interval: u64, max_checkpoints: usize) -> Recorder {
+        Recorder {
+            interval: ((interval + SLICE - 1) / SLICE).max(1) * SLICE,
+            max_checkpoints: max_checkpoints.max(1),
+            checkpoints: VecDeque::new(),
+            newest: Vec::new(),
+            inputs: Vec::new(),
+            applied: 0,
+        }
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    pub fn max_checkpoints(&self) -> usize {
+        self.max_checkpoints
+    }
+
+    pub fn num_checkpoints(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Position of the oldest point execution can go back to
+    pub fn start(&self) -> Option<Position> {
+        self.checkpoints.front().map(|cp| cp.pos)
+    }
+
+    /// Approximate memory used by checkpoints, in bytes
+    pub fn size(&self) -> usize {
+        let deltas: usize = self.checkpoints.iter()
+            .filter_map(|cp| cp.delta.as_ref())
+            .map(Delta::size)
+            .sum();
+        self.newest.len() + deltas
+    }
+
+    pub fn checkpoint_due(&self, pos: Position) -> bool {
+        pos.arm9 == pos.arm11 && pos.arm9 % self.interval == 0
+            && self.checkpoints.back().map_or(true, |cp| cp.pos < pos)
+    }
+
+    pub fn checkpoint(&mut self, pos: Position, state: Vec<u8>) {
+        if let Some(prev) = self.checkpoints.back_mut() {
+            prev.delta = Some(Delta::between(&state, &self.newest));
+        }
+        self.newest = state;
+        self.checkpoints.push_back(Checkpoint {
+            pos: pos,
+            inputs: self.inputs.len(),
+            delta: None,
+        });
+
+        if self.checkpoints.len() > self.max_checkpoints {
+            if self.checkpoints.len() > 2 {
+                self.thin_out();
+            } else {
+                self.drop_oldest();
+            }
+        }
+    }
+
+    fn drop_oldest(&mut self) {
+        self.checkpoints.pop_front();
+        let dropped = self.checkpoints[0].inputs;
+        self.inputs.drain(..dropped);
+        self.applied -= dropped;
+        for cp in self.checkpoints.iter_mut() {
+            cp.inputs -= dropped;
+        }
+    }
+
+    /// Drops the checkpoint between the oldest and the newest one that leaves the
+    /// smallest gap for how far back it is, which keeps the spacing of checkpoints
+    /// roughly proportional to their age
+    fn thin_out(&mut self) {
+        let newest = self.checkpoints.back().unwrap().pos.arm9;
+        let steps = |cp: &Checkpoint| cp.pos.arm9 as u128;
+        let index = (1..self.checkpoints.len() - 1)
+            .min_by(|&a, &b| {
+                // Compares gap / age without dividing
+                let gap = |i: usize| steps(&self.checkpoints[i + 1]) - steps(&self.checkpoints[i - 1]);
+                let age = |i: usize| newest as u128 - steps(&self.checkpoints[i]);
+                (gap(a) * age(b)).cmp(&(gap(b) * age(a)))
+            })
+            .unwrap();
+
+        // The delta of the dropped checkpoint leads to the one before it
+        let dropped = self.checkpoints.remove(index).unwrap();
+        let prev = &mut self.checkpoints[index - 1];
+        if let (Some(skipped), Some(delta)) = (dropped.delta, prev.delta.take()) {
+            prev.delta = Some(skipped.then(delta));
+        }
+    }
+
+    /// Index of the last checkpoint taken at or before `pos`
+    pub fn checkpoint_before(&self, pos: Position) -> Option<usize> {
+        self.checkpoints.iter().rposition(|cp| cp.pos <= pos)
+    }
+
+    pub fn checkpoint_pos(&self, index: usize) -> Position {
+        self.checkpoints[index].pos
+    }
+
+    /// Rebuilds the state of a checkpoint and rewinds the input log to it
+    pub fn restore(&mut self, index: usize) -> Vec<u8> {
+        let mut state = self.newest.clone();
+        for cp in self.checkpoints.iter().skip(index).rev() {
+            if let Some(ref delta) = cp.delta {
+                delta.apply(&mut state);
+            }
+        }
+        self.applied = self.checkpoints[index].inputs;
+        state
+    }
+
+    /// Records an input that was just applied live
+    pub fn log_input(&mut self, pos: Position, input: Input) {
+        self.inputs.truncate(self.applied);
+        self.inputs.push((pos, input));
+        self.applied = self.inputs.len();
+    }
+
+    /// The next logged input that has not been applied yet
+    pub fn pending_input(&self) -> Option<(Position, Input)> {
+        self.inputs.get(self.applied).cloned()
+    }
+
+    pub fn input_applied(&mut self) {
+        self.applied += 1;
+    }
+
+    /// Forgets everything recorded after `pos`, which must be the current position
+    pub fn truncate(&mut self, pos: Position) {
+        self.inputs.truncate(self.applied);
+
+        let keep = self.checkpoints.iter().take_while(|cp| cp.pos <= pos).count();
+        if keep == 0 || keep == self.checkpoints.len() {
+            return
+        }
+        self.newest = self.restore(keep - 1);
+        self.checkpoints.truncate(keep);
+        self.checkpoints[keep - 1].delta = None;
+        self.applied = self.inputs.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pos(arm9: u64, arm11: u64) -> Position {
+        Position { arm9: arm9, arm11: arm11 }
+    }
+
+    #[test]
+    fn schedule() {
+        assert_eq!(next_slice(pos(0, 0)), (ActiveCpu::Arm9, SLICE));
+        assert_eq!(next_slice(pos(SLICE, 0)), (ActiveCpu::Arm11, SLICE));
+        // A core that halted partway through its slice finishes it first
+        assert_eq!(next_slice(pos(5, 0)), (ActiveCpu::Arm9, SLICE));
+        assert_eq!(next_slice(pos(SLICE, 7)), (ActiveCpu::Arm11, SLICE));
+        assert_eq!(next_slice(pos(SLICE, SLICE)), (ActiveCpu::Arm9, 2 * SLICE));
+
+        let limit = pos(10 * SLICE, 10 * SLICE);
+        assert_eq!(latest_point(ActiveCpu::Arm9, SLICE + 5, limit), pos(SLICE + 5, SLICE));
+        assert_eq!(latest_point(ActiveCpu::Arm9, SLICE, limit), pos(SLICE, SLICE));
+        assert_eq!(latest_point(ActiveCpu::Arm11, SLICE + 5, limit), pos(2 * SLICE, SLICE + 5));
+        assert_eq!(latest_point(ActiveCpu::Arm11, SLICE, pos(SLICE + 3, SLICE + 1)),
+                   pos(SLICE + 3, SLICE));
+    }
+
+    #[test]
+    fn checkpoints() {
+        let state = |fill: u8, len: usize| {
+            let mut s = vec![0u8; len];
+            s[len - 1] = fill;
+            s
+        };
+
+        let mut rec = Recorder::new(1, 3);
+        assert_eq!(rec.interval(), SLICE);
+        assert!(rec.checkpoint_due(pos(0, 0)));
+        rec.checkpoint(pos(0, 0), state(0, 3 * PAGE_SIZE));
+        assert!(!rec.checkpoint_due(pos(0, 0)));
+        assert!(!rec.checkpoint_due(pos(SLICE, 0)));
+
+        rec.log_input(pos(5, 0), Input::Irq9(IrqType9::Timer0));
+        rec.checkpoint(pos(SLICE, SLICE), state(1, 3 * PAGE_SIZE));
+        rec.checkpoint(pos(2 * SLICE, 2 * SLICE), state(2, 2 * PAGE_SIZE + 1));
+
+        assert_eq!(rec.checkpoint_before(pos(SLICE + 5, SLICE)), Some(1));
+        assert_eq!(rec.restore(0), state(0, 3 * PAGE_SIZE));
+        assert_eq!(rec.pending_input().map(|(p, _)| p), Some(pos(5, 0)));
+        assert_eq!(rec.restore(1), state(1, 3 * PAGE_SIZE));
+        assert!(rec.pending_input().is_none());
+        assert_eq!(rec.restore(2), state(2, 2 * PAGE_SIZE + 1));
+
+        // Only the pages that changed are kept for older checkpoints
+        assert_eq!(rec.size(), 2 * PAGE_SIZE + 1 + 2 * PAGE_SIZE);
+
+        // Past the limit, the checkpoint closest to its neighbours for its age goes,
+        // keeping the start of the recording and its inputs
+        rec.checkpoint(pos(3 * SLICE, 3 * SLICE), state(3, 3 * PAGE_SIZE));
+        assert_eq!(rec.num_checkpoints(), 3);
+        assert_eq!(rec.start(), Some(pos(0, 0)));
+        assert_eq!(rec.checkpoint_pos(1), pos(2 * SLICE, 2 * SLICE));
+        assert_eq!(rec.restore(0), state(0, 3 * PAGE_SIZE));
+        assert_eq!(rec.pending_input().map(|(p, _)| p), Some(pos(5, 0)));
+        assert_eq!(rec.restore(1), state(2, 2 * PAGE_SIZE + 1));
+        assert_eq!(rec.restore(2), state(3, 3 * PAGE_SIZE));
+
+        rec.truncate(pos(SLICE + 5, SLICE));
+        assert_eq!(rec.num_checkpoints(), 1);
+        assert!(rec.checkpoint_due(pos(2 * SLICE, 2 * SLICE)));
+        assert_eq!(rec.restore(0), state(0, 3 * PAGE_SIZE));
+    }
+
+    #[test]
+    fn thinned_checkpoints() {
+        let mut rec = Recorder::new(SLICE, 4);
+        for i in 0..20 {
+            rec.checkpoint(pos(i * SLICE, i * SLICE), vec![i as u8; PAGE_SIZE + 1]);
+        }
+        assert_eq!(rec.num_checkpoints(), 4);
+        assert_eq!(rec.start(), Some(pos(0, 0)));
+        assert_eq!(rec.checkpoint_pos(3), pos(19 * SLICE, 19 * SLICE));
+
+        // Older gaps are wider, and every checkpoint still restores its own state
+        let gaps: Vec<_> = (0..3)
+            .map(|i| rec.checkpoint_pos(i + 1).arm9 - rec.checkpoint_pos(i).arm9)
+            .collect();
+        assert!(gaps[0] >= gaps[1] && gaps[1] >= gaps[2]);
+        for i in 0..4 {
+            let steps = rec.checkpoint_pos(i).arm9;
+            assert_eq!(rec.restore(i), vec![(steps / SLICE) as u8; PAGE_SIZE + 1]);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Machine save states
+//!
+//! A save state is a little-endian byte stream made of a header (magic and format
+//! version) followed by the state of every component, in a fixed order. Components
+//! open their state with a short tag so that a mismatched layout fails loudly
+//! instead of silently loading garbage.
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+const MAGIC: &'static [u8; 8] = b"LLAMASAV";
+
+/// Must be bumped whenever the layout of any component's state changes
+pub const VERSION: u32 = 13;
+
+#[derive(Debug, Error)]
+pub enum ErrorKind {
+    Io(io::Error),
+
+    /// File is not a llama save state
+    BadMagic,
+    /// Save state was written by an incompatible version of llama
+    BadVersion,
+    /// Save state ended unexpectedly
+    Truncated,
+    #[error(non_std, no_from, msg_embedded)]
+    Mismatch(String),
+    #[error(non_std, no_from, msg_embedded)]
+    Unsupported(String),
+}
+
+pub type Result<T> = ::std::result::Result<T, ErrorKind>;
+
+pub trait SaveState {
+    fn save_state(&mut self, w: &mut StateWriter) -> Result<()>;
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()>;
+}
+
+impl<T: SaveState> SaveState for Rc<RefCell<T>> {
+    fn save_state(&mut self, w: &mut StateWriter) -> Result<()> {
+        self.borrow_mut().save_state(w)
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<()> {
+        self.borrow_mut().load_state(r)
+    }
+}
+
+pub struct StateWriter {
+    buf: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        StateWriter { buf: buf }
+    }
+
+    pub fn tag(&mut self, tag: &[u8; 4]) {
+        self.buf.extend_from_slice(tag);
+    }
+
+    pub fn put_bool(&mut self, val: bool) {
+        self.put_u8(val as u8);
+    }
+
+    pub fn put_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Writes a length-prefixed byte buffer
+    pub fn put_vec(&mut self, bytes: &[u8]) {
+        self.put_u32(bytes.len() as u32);
+        self.put_bytes(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct StateReader {
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl StateReader {
+    pub fn from_bytes(buf: Vec<u8>) -> Result<StateReader> {
+        let mut reader = StateReader { buf: buf, pos: 0 };
+
+        let mut magic = [0u8; 8];
+        reader.get_bytes(&mut magic).map_err(|_| ErrorKind::BadMagic)?;
+        if &magic != MAGIC {
+            return Err(ErrorKind::BadMagic)
+        }
+        if reader.get_u32()? != VERSION {
+            return Err(ErrorKind::BadVersion)
+        }
+        Ok(reader)
+    }
+
+    pub fn expect_tag(&mut self, tag: &[u8; 4]) -> Result<()> {
+        let mut found = [0u8; 4];
+        self.get_bytes(&mut found)?;
+        if &found != tag {
+            return Err(ErrorKind::Mismatch(format!("expected section `{}`, found `{}`",
+                String::from_utf8_lossy(tag), String::from_utf8_lossy(&found))))
+        }
+        Ok(())
+    }
+
+    pub fn get_bool(&mut self) -> Result<bool> {
+        Ok(self.get_u8()? != 0)
+    }
+
+    pub fn get_bytes(&mut self, out: &mut [u8]) -> Result<()> {
+        let end = self.pos + out.len();
+        if end > self.buf.len() {
+            return Err(ErrorKind::Truncated)
+        }
+        out.copy_from_slice(&self.buf[self.pos..end]);
+        self.pos = end;
+        Ok(())
+    }
+
+    /// Reads a length-prefixed byte buffer
+    pub fn get_vec(&mut self) -> Result<Vec<u8>> {
+        let len = self.get_u32()? as usize;
+        // Check before allocating, as a corrupt length could ask for gigabytes
+        if len > self.buf.len() - self.pos {
+            return Err(ErrorKind::Truncated)
+        }
+        let out = self.buf[self.pos..self.pos + len].to_vec();
+        self.pos += len;
+        Ok(out)
+    }
+
+    /// Fails if any state was left unread
+    pub fn finish(self) -> Result<()> {
+        if self.pos != self.buf.len() {
+            return Err(ErrorKind::Mismatch(format!("{} trailing bytes after state",
+                self.buf.len() - self.pos)))
+        }
+        Ok(())
+    }
+}
+
+macro_rules! impl_primitives {
+    ($($put:ident, $get:ident: $ty:ty, $size:expr;)*) => {
+        impl StateWriter {
+            $( pub fn $put(&mut self, val: $ty) {
+                self.buf.extend_from_slice(&val.to_le_bytes());
+            } )*
+        }
+
+        impl StateReader {
+            $( pub fn $get(&mut self) -> Result<$ty> {
+                let mut bytes = [0u8; $size];
+                self.get_bytes(&mut bytes)?;
+                Ok(<$ty>::from_le_bytes(bytes))
+            } )*
+        }
+    };
+}
+
+impl_primitives! {
+    put_u8, get_u8: u8, 1;
+    put_u16, get_u16: u16, 2;
+    put_u32, get_u32: u32, 4;
+    put_u64, get_u64: u64, 8;
+    put_u128, get_u128: u128, 16;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut w = StateWriter::new();
+        w.tag(b"TEST");
+        w.put_u8(0x12);
+        w.put_bool(true);
+        w.put_u32(0xDEADBEEF);
+        w.put_u64(!0);
+        w.put_u128(1 << 100);
+        w.put_vec(&[1, 2, 3]);
+
+        let mut r = StateReader::from_bytes(w.into_bytes()).unwrap();
+        r.expect_tag(b"TEST").unwrap();
+        assert_eq!(r.get_u8().unwrap(), 0x12);
+        assert_eq!(r.get_bool().unwrap(), true);
+        assert_eq!(r.get_u32().unwrap(), 0xDEADBEEF);
+        assert_eq!(r.get_u64().unwrap(), !0);
+        assert_eq!(r.get_u128().unwrap(), 1 << 100);
+        assert_eq!(r.get_vec().unwrap(), vec![1, 2, 3]);
+        r.finish().unwrap();
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut bytes = StateWriter::new().into_bytes();
+        bytes[0] = b'X';
+        assert!(match StateReader::from_bytes(bytes) { Err(ErrorKind::BadMagic) => true, _ => false });
+
+        let mut bytes = StateWriter::new().into_bytes();
+        bytes[8] = bytes[8].wrapping_add(1);
+        assert!(match StateReader::from_bytes(bytes) { Err(ErrorKind::BadVersion) => true, _ => false });
+    }
+
+    #[test]
+    fn truncated() {
+        let mut w = StateWriter::new();
+        w.put_u16(0x1234);
+        let mut r = StateReader::from_bytes(w.into_bytes()).unwrap();
+        assert!(match r.get_u32() { Err(ErrorKind::Truncated) => true, _ => false });
+
+        let mut w = StateWriter::new();
+        w.put_u32(!0);
+        w.put_u8(0);
+        let mut r = StateReader::from_bytes(w.into_bytes()).unwrap();
+        assert!(match r.get_vec() { Err(ErrorKind::Truncated) => true, _ => false });
+    }
+
+    #[test]
+    fn wrong_tag() {
+        let mut w = StateWriter::new();
+        w.tag(b"CPU9");
+        let mut r = StateReader::from_bytes(w.into_bytes()).unwrap();
+        assert!(r.expect_tag(b"CP11").is_err());
+    }
+}
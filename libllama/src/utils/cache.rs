@@ -61,6 +61,29 @@ impl<T: Copy, C> TinyCache<T, C> {
         self.map_keys = [!0; CACHE_SIZE];
     }
 
+    pub fn contains(&self, key: u32) -> bool {
+        self.map_keys[Self::key_to_index(key)] == key
+    }
+
+    /// Drops the entry for `key` without writing it back
+    pub fn discard(&mut self, key: u32) {
+        let idx = Self::key_to_index(key);
+        if self.map_keys[idx] == key {
+            self.map_keys[idx] = !0;
+            self.dirty[idx] = false;
+        }
+    }
+
+    /// Looks up `key` without filling or evicting anything
+    pub fn peek(&self, key: u32) -> Option<&T> {
+        let idx = Self::key_to_index(key);
+        if self.map_keys[idx] == key {
+            Some(&*self.map_vals[idx])
+        } else {
+            None
+        }
+    }
+
     pub fn key_to_index(key: u32) -> usize {
         let hash = (key.wrapping_mul(2654435761)) as usize;
         hash >> (32 - CACHE_SIZE_BITS) // shifted by 32 - log2(# buckets)
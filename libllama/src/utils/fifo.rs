@@ -1,5 +1,7 @@
 use std::collections::VecDeque;
 
+use savestate::{self, SaveState, StateReader, StateWriter};
+
 #[derive(Debug, Clone)]
 pub struct Fifo<T> {
     inner: VecDeque<T>,
@@ -70,6 +72,38 @@ impl<T: Clone> Fifo<T> {
     }
 }
 
+macro_rules! impl_fifo_state {
+    ($($ty:ty: $put:ident, $get:ident;)*) => {$(
+        impl SaveState for Fifo<$ty> {
+            fn save_state(&mut self, w: &mut StateWriter) -> savestate::Result<()> {
+                w.put_u32(self.len() as u32);
+                for item in self.inner.iter() {
+                    w.$put(*item);
+                }
+                Ok(())
+            }
+
+            fn load_state(&mut self, r: &mut StateReader) -> savestate::Result<()> {
+                let len = r.get_u32()? as usize;
+                if len > self.max_len {
+                    return Err(savestate::ErrorKind::Mismatch(
+                        format!("{} items do not fit in FIFO of size {}", len, self.max_len)))
+                }
+                self.clear();
+                for _ in 0..len {
+                    self.push(r.$get()?);
+                }
+                Ok(())
+            }
+        }
+    )*};
+}
+
+impl_fifo_state! {
+    u8: put_u8, get_u8;
+    u32: put_u32, get_u32;
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -0,0 +1,34 @@
+extern crate log;
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, _: &log::LogMetadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::LogRecord) {
+        if !self.enabled(record.metadata()) { return }
+
+        let thread = ::std::thread::current();
+        let thread_name: String = if let Some(name) = thread.name() {
+            name.into()
+        } else {
+            format!("{:?}", thread.id())
+        };
+        eprintln!("[[{}]] ## {}: {}", thread_name, record.level(), record.args());
+    }
+}
+
+pub fn init(allow_trace: bool) -> Result<(), log::SetLoggerError> {
+    let max_level = if allow_trace {
+        log::LogLevelFilter::Trace
+    } else {
+        log::LogLevelFilter::Debug
+    };
+
+    log::set_logger(|max| {
+        max.set(max_level);
+        Box::new(StderrLogger)
+    })
+}
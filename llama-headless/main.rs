@@ -0,0 +1,194 @@
+#![deny(warnings)]
+
+#[macro_use]
+extern crate log;
+extern crate libllama;
+
+mod logger;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::process::exit;
+use std::time::{Duration, Instant};
+
+use libllama::{dbgcore, hwcore, ldr};
+use libllama::commands::{self, LogOutput};
+use libllama::cpu::BreakReason;
+use libllama::dbgcore::ActiveCpu;
+use libllama::record::Position;
+
+/// Emulation stopped at a breakpoint or watchpoint
+const EXIT_BREAKPOINT: i32 = 0;
+/// Invalid command line arguments or unreadable script
+const EXIT_USAGE: i32 = 1;
+/// Instruction limit was reached without hitting a breakpoint
+const EXIT_LIMIT: i32 = 2;
+/// Timeout elapsed without hitting a breakpoint
+const EXIT_TIMEOUT: i32 = 3;
+/// Emulation halted on an undefined instruction or bus error
+const EXIT_FAULT: i32 = 4;
+
+struct Options {
+    path: String,
+    script: Option<String>,
+    max_instrs: Option<u64>,
+    timeout: Option<Duration>,
+    trace: bool,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: llama-headless <payload.ctr9|payload.firm> [options]\n\
+               \n\
+               Options:\n  \
+                 --script <file>   Read debugger commands from <file> instead of stdin\n  \
+                 --instrs <n>      Stop after the ARM9 executes <n> instructions\n  \
+                 --timeout <secs>  Stop after <secs> seconds of emulation\n  \
+                 --trace           Print trace-level logs");
+    exit(EXIT_USAGE)
+}
+
+fn parse_args() -> Options {
+    let mut args = env::args().skip(1);
+    let mut opts = Options {
+        path: String::new(),
+        script: None,
+        max_instrs: None,
+        timeout: None,
+        trace: false,
+    };
+
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--script" => opts.script = Some(args.next().unwrap_or_else(|| usage())),
+            "--instrs" => {
+                let n = args.next().and_then(|n| n.parse().ok());
+                opts.max_instrs = Some(n.unwrap_or_else(|| usage()));
+            }
+            "--timeout" => {
+                let secs = args.next().and_then(|s| s.parse().ok());
+                opts.timeout = Some(Duration::from_secs(secs.unwrap_or_else(|| usage())));
+            }
+            "--trace" => opts.trace = true,
+            "-h" | "--help" => usage(),
+            _ if path.is_none() => path = Some(arg),
+            _ => usage()
+        }
+    }
+
+    opts.path = path.unwrap_or_else(|| usage());
+    opts
+}
+
+#[derive(Clone, Copy)]
+enum StopReason {
+    Breakpoint,
+    InstrLimit,
+    Timeout,
+    Fault,
+}
+
+impl StopReason {
+    fn exit_code(&self) -> i32 {
+        match *self {
+            StopReason::Breakpoint => EXIT_BREAKPOINT,
+            StopReason::InstrLimit => EXIT_LIMIT,
+            StopReason::Timeout => EXIT_TIMEOUT,
+            StopReason::Fault => EXIT_FAULT,
+        }
+    }
+}
+
+/// Interleaves both CPUs on the current thread with the quantum from `sched single`, until
+/// one of them halts or until the instruction limit/timeout from `opts` is reached
+fn run_emulation(debugger: &mut dbgcore::DbgCore, opts: &Options) -> StopReason {
+    let start = Instant::now();
+    let mut ctx = debugger.ctx(ActiveCpu::Arm9);
+    let hwcore = ctx.hwcore_mut();
+
+    let start_steps = hwcore.position().arm9;
+    let limit = Position {
+        arm9: opts.max_instrs.map_or(!0, |max| start_steps.saturating_add(max)),
+        arm11: !0,
+    };
+    let (_, reason) = hwcore.run_interleaved(limit, || match opts.timeout {
+        Some(timeout) => start.elapsed() < timeout,
+        None => true
+    });
+
+    let executed = hwcore.position().arm9 - start_steps;
+    match reason {
+        BreakReason::Breakpoint | BreakReason::Watchpoint { .. } => StopReason::Breakpoint,
+        BreakReason::UndefinedInstruction(..) | BreakReason::BusError { .. } => {
+            error!("Emulation halted after {} instructions", executed);
+            StopReason::Fault
+        }
+        _ if executed >= limit.arm9 - start_steps => {
+            info!("Instruction limit of {} reached", executed);
+            StopReason::InstrLimit
+        }
+        _ => {
+            info!("Timed out after {} instructions", executed);
+            StopReason::Timeout
+        }
+    }
+}
+
+fn main() {
+    let opts = parse_args();
+    logger::init(opts.trace).unwrap();
+
+    let input: Box<dyn BufRead> = match opts.script {
+        Some(ref script) => match File::open(script) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("Could not open script `{}`: {}", script, e);
+                exit(EXIT_USAGE)
+            }
+        },
+        None => Box::new(BufReader::new(io::stdin()))
+    };
+
+    let loader = ldr::make_loader(Path::new(&opts.path));
+    let hwcore = hwcore::HwCore::new(loader.as_ref());
+    let mut debugger = dbgcore::DbgCore::bind(hwcore);
+    let mut active_cpu = ActiveCpu::Arm9;
+
+    let mut last_stop = None;
+    for line in input.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => { error!("Could not read command: {}", e); break }
+        };
+
+        for cmd in line.split(';') {
+            let cmd = cmd.trim();
+            if cmd.is_empty() || cmd.starts_with('#') {
+                continue
+            }
+
+            info!("> {}", cmd);
+            match cmd.split_whitespace().next() {
+                // `run` blocks until a stop condition instead of resuming the CPU threads
+                Some("run") => last_stop = Some(run_emulation(&mut debugger, &opts)),
+                Some("quit") | Some("exit") => {
+                    exit(last_stop.map_or(EXIT_BREAKPOINT, |stop| stop.exit_code()))
+                }
+                _ => {
+                    let mut ctx = debugger.ctx(active_cpu);
+                    commands::handle(&mut ctx, &mut LogOutput, cmd.split_whitespace());
+                    active_cpu = ctx.active_cpu();
+                }
+            }
+        }
+    }
+
+    // Scripts that never issue `run` still get one run at the end
+    let stop = match last_stop {
+        Some(stop) => stop,
+        None => run_emulation(&mut debugger, &opts)
+    };
+    exit(stop.exit_code());
+}
@@ -2,7 +2,6 @@
 
 #[macro_use]
 extern crate log;
-extern crate capstone;
 extern crate lgl;
 extern crate libllama;
 
@@ -12,7 +11,7 @@ mod uilog;
 use std::env;
 use std::path::Path;
 
-use libllama::{dbgcore, gdbstub, hwcore, ldr, msgs, io::gpu};
+use libllama::{dapserver, dbgcore, gdbstub, hwcore, ldr, msgs, io::gpu};
 
 mod c {
     #![allow(warnings)]
@@ -24,6 +23,7 @@ struct Backend<'a> {
     debugger: dbgcore::DbgCore,
     cmd_active_cpu: dbgcore::ActiveCpu,
     gdb: gdbstub::GdbStub,
+    dap: dapserver::DapServer,
     fbs: hwcore::Framebuffers,
     fb_state: Option<gpu::FramebufState>,
     msg_client: msgs::Client<hwcore::Message>,
@@ -200,6 +200,7 @@ mod cbs {
         let backend = Backend::from_c(backend);
         backend.msg_client.send(Message::Quit);
         backend.gdb.wait(); // Need to wait because the GDB thread owns the port
+        backend.dap.wait();
         *backend = super::load_game(backend.loader);
     }
 
@@ -226,6 +227,7 @@ fn load_game<'a>(loader: &'a dyn ldr::Loader) -> Backend<'a> {
 
     let mut hwcore = hwcore::HwCore::new(loader);
     let client_gdb = hwcore.take_client_gdb().unwrap();
+    let client_dap = hwcore.take_client_dap().unwrap();
     let client_user = hwcore.take_client_user().unwrap();
 
     let debugger = dbgcore::DbgCore::bind(hwcore);
@@ -236,7 +238,8 @@ fn load_game<'a>(loader: &'a dyn ldr::Loader) -> Backend<'a> {
         loader: loader,
         debugger: debugger.clone(),
         cmd_active_cpu: dbgcore::ActiveCpu::Arm9,
-        gdb: gdbstub::GdbStub::new(client_gdb, debugger),
+        gdb: gdbstub::GdbStub::new(client_gdb, debugger.clone()),
+        dap: dapserver::DapServer::new(client_dap, debugger),
         fbs: fbs,
         fb_state: None,
         msg_client: client_user,